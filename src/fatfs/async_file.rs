@@ -0,0 +1,156 @@
+//! Reads and writes that await the driver for the whole sectors they transfer.
+//!
+//! ```ignore
+//! let mut locked_fs = fatfs::FS.lock().await;
+//! let mut file = locked_fs.open_raw("capture.bin", FileOptions::CreateAlways | FileOptions::Write)?;
+//! locked_fs.write_async(&mut file, &samples).await?;
+//! locked_fs.close(file)?;
+//! ```
+
+use crate::fatfs::*;
+
+//Flags of `FIL::flag` that are private to FatFs.
+const FA_MODIFIED: u8 = 0x40;
+const FA_DIRTY: u8 = 0x80;
+
+impl RawFileSystem {
+    /// Checks that the file is open on the mounted volume with the given access, as FatFs
+    /// does at the start of a read or write.
+    fn check_access(&self, file: &File, access: u32) -> Result<(), Error> {
+        if self.fs().fs_type == 0 || file.obj.fs != self.fs.get() || file.obj.id != self.fs().id {
+            return Err(Error::InvalidObject)
+        }
+        if file.err != 0 {
+            return Err(Error::from_result(file.err as u32))
+        }
+        if file.flag as u32 & access == 0 {
+            return Err(Error::Denied)
+        }
+        Ok(())
+    }
+
+    /// Moves the sector aligned position of the file forward by up to `count` whole sectors,
+    /// stopping at the end of the cluster holding the position. A cluster is allocated if the
+    /// file is written past its end. Returns the first sector moved over and the number of
+    /// sectors, which is 0 if the volume is full.
+    fn advance(&self, file: &mut File, count: u32) -> Result<(u32, u32), Error> {
+        let cluster_sectors = self.fs().csize as u32;
+        let offset = file.fptr / FF_MAX_SS % cluster_sectors;
+        let count = count.min(cluster_sectors - offset);
+        let position = file.fptr;
+        let target = position + count * FF_MAX_SS;
+        //FatFs leaves `clust` at the cluster holding the byte before the new position, which
+        //holds all of the sectors moved over.
+        self.seek(file, target)?;
+        if file.fptr == position {
+            return Ok((0, 0))
+        }
+        if file.fptr != target || file.clust < 2 || file.clust >= self.fs().n_fatent {
+            return Err(Error::IntError)
+        }
+        Ok((self.cluster_sector(file.clust) + offset, count))
+    }
+
+    /// Reads from the file in the same way as `read()`, awaiting the driver for each run of
+    /// whole sectors in the same cluster if it was installed with `install_async()`, so that
+    /// other tasks run while the data is transferred. The partial sectors at either end are
+    /// read through FatFs, and the FAT is followed from one cluster to the next without
+    /// awaiting. With a blocking driver, the sectors are read without awaiting.
+    ///
+    /// The lock on the file system is held until the read completes. If the future is dropped
+    /// before then, the position of the file is left within the data requested.
+    pub async fn read_async(&self, file: &mut File, buffer: &mut [u8]) -> Result<u32, Error> {
+        self.check_access(file, FA_READ)?;
+        let sector_size = FF_MAX_SS as usize;
+        let mut total = 0;
+        while total < buffer.len() {
+            let remaining = (buffer.len() - total).min((file.obj.objsize - file.fptr) as usize);
+            let offset = (file.fptr % FF_MAX_SS) as usize;
+            if remaining < sector_size || offset != 0 {
+                //A partial sector is read through the sector buffer of the file.
+                let len = remaining.min(sector_size - offset);
+                let read = self.read(file, &mut buffer[total..total + len])? as usize;
+                if read == 0 {
+                    break
+                }
+                total += read;
+                continue
+            }
+            let (sector, count) = self.advance(file, (remaining / sector_size) as u32)?;
+            if count == 0 {
+                break
+            }
+            let data = &mut buffer[total..total + count as usize * sector_size];
+            if let Err(error) = diskio::read_sectors_async(self.fs().pdrv, data, sector).await {
+                file.err = FRESULT_FR_DISK_ERR as u8;
+                return Err(error)
+            }
+            //The sector buffer of the file may hold data not yet written to the drive.
+            if file.flag & FA_DIRTY != 0 && file.sect.wrapping_sub(sector) < count {
+                let start = (file.sect - sector) as usize * sector_size;
+                data[start..start + sector_size].copy_from_slice(&file.buf);
+            }
+            total += data.len();
+        }
+        Ok(total as u32)
+    }
+
+    /// Writes to the file in the same way as `write()`, awaiting the driver for each run of
+    /// whole sectors in the same cluster as `read_async()` does. Clusters are allocated and
+    /// the partial sectors at either end are written through FatFs without awaiting. Returns
+    /// the number of bytes written, which is less than the data length if the volume is full.
+    ///
+    /// The lock on the file system is held until the write completes. The size of the file is
+    /// extended before each run of sectors is written, so if the future is dropped before the
+    /// write completes, the file may end in sectors that hold stale data.
+    pub async fn write_async(&self, file: &mut File, data: &[u8]) -> Result<u32, Error> {
+        self.check_access(file, FA_WRITE)?;
+        self.invalidate_stat_cache();
+        let sector_size = FF_MAX_SS as usize;
+        //As with FatFs, a file is not written past 4 GiB.
+        let data = &data[..data.len().min((u32::MAX - file.fptr) as usize)];
+        let mut total = 0;
+        while total < data.len() {
+            let remaining = data.len() - total;
+            let offset = (file.fptr % FF_MAX_SS) as usize;
+            let mut count = (remaining / sector_size) as u32;
+            //A file with a fast seek table cannot be extended by seeking, only by FatFs.
+            if !file.cltbl.is_null() {
+                count = count.min((file.obj.objsize - file.fptr) / FF_MAX_SS);
+            }
+            if count == 0 || offset != 0 {
+                let len = if offset == 0 { remaining } else { remaining.min(sector_size - offset) };
+                let written = self.write(file, &data[total..total + len])? as usize;
+                total += written;
+                if written < len {
+                    break
+                }
+                continue
+            }
+            let position = file.fptr;
+            let size = file.obj.objsize;
+            let (sector, count) = self.advance(file, count)?;
+            if count == 0 {
+                break
+            }
+            let sectors = &data[total..total + count as usize * sector_size];
+            if let Err(error) = diskio::write_sectors_async(self.fs().pdrv, sectors, sector).await {
+                //As when FatFs fails to write, the clusters allocated stay in the chain.
+                file.obj.objsize = size;
+                file.err = FRESULT_FR_DISK_ERR as u8;
+                self.registry.borrow_mut().set_dirty(file.handle, true);
+                return Err(error)
+            }
+            //FatFs would otherwise write the sector buffer of the file over the new data.
+            if file.sect.wrapping_sub(sector) < count {
+                let start = (file.sect - sector) as usize * sector_size;
+                file.buf.copy_from_slice(&sectors[start..start + sector_size]);
+                file.flag &= !FA_DIRTY;
+            }
+            file.flag |= FA_MODIFIED;
+            self.registry.borrow_mut().record_write(file.handle, position, sectors);
+            total += sectors.len();
+        }
+        Ok(total as u32)
+    }
+}
@@ -0,0 +1,32 @@
+use crate::fatfs::*;
+use alloc::vec::Vec;
+
+impl RawFileSystem {
+    /// Opens the file at the given path and returns it along with its information, so that
+    /// the information always describes the file that was opened.
+    pub fn open_with_info(&self, path: &str, mode: FileOptions) -> Result<(File, FileInfo), Error> {
+        let file = self.open_raw(path, mode)?;
+        match self.stat(path) {
+            Ok(info) => Ok((file, info)),
+            Err(error) => {
+                let _ = self.close(file);
+                Err(error)
+            }
+        }
+    }
+
+    /// Returns the information of every item in the directory at the given path.
+    pub fn stat_children(&self, path: &str) -> Result<Vec<FileInfo>, Error> {
+        let mut dir = self.opendir(path)?;
+        let mut children = Vec::new();
+        let result = loop {
+            match self.readdir(&mut dir) {
+                Ok(info) if info.fname[0] == 0 => break Ok(()),
+                Ok(info) => children.push(info),
+                Err(error) => break Err(error)
+            }
+        };
+        self.closedir(dir)?;
+        result.map(|_| children)
+    }
+}
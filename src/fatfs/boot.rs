@@ -0,0 +1,237 @@
+//! Decoding of the boot sector and FSInfo sector of a FAT volume, for diagnostics and for
+//! checking media before it is mounted, and setting the identity of a newly formatted volume.
+//!
+//! ```ignore
+//! let boot = boot::read(&locked_fs)?;
+//! if boot.fat_type != FatType::Fat32 {
+//!     return Err(Error::NoFileSystem)
+//! }
+//! ```
+
+use crate::fatfs::*;
+use crate::fatfs::partition;
+
+/// Signature at the end of a boot sector.
+const SIGNATURE: [u8; 2] = [0x55, 0xAA];
+/// Offset of the partition table in a master boot record.
+const PARTITION_TABLE: usize = 446;
+/// Value of the extended boot signature when the serial number and label are present.
+const EXTENDED_SIGNATURE: u8 = 0x29;
+const FSINFO_LEAD_SIGNATURE: u32 = 0x41615252;
+const FSINFO_STRUCT_SIGNATURE: u32 = 0x61417272;
+/// Value of an FSInfo field that has not been set.
+const UNKNOWN: u32 = 0xFFFFFFFF;
+
+/// The type of FAT, which depends on the number of clusters in the volume.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32
+}
+
+/// The free cluster hints held in the FSInfo sector of a FAT32 volume. Either may be absent,
+/// and neither is guaranteed to be accurate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FsInfo {
+    pub free_clusters: Option<u32>,
+    pub next_free: Option<u32>
+}
+
+/// The fields of a boot sector.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BootSector {
+    pub oem_name: [u8; 8],
+    pub bytes_per_sector: u16,
+    pub sectors_per_cluster: u8,
+    pub reserved_sectors: u16,
+    pub fat_count: u8,
+    /// Number of entries in the root directory, which is 0 on FAT32.
+    pub root_entries: u16,
+    pub total_sectors: u32,
+    pub media: u8,
+    pub sectors_per_fat: u32,
+    /// Number of sectors on the drive ahead of the volume.
+    pub hidden_sectors: u32,
+    pub fat_type: FatType,
+    pub cluster_count: u32,
+    /// First cluster of the root directory on FAT32.
+    pub root_cluster: Option<u32>,
+    /// Sector of the FSInfo structure on FAT32, relative to the boot sector.
+    pub fs_info_sector: Option<u16>,
+    /// Volume serial number, if the extended boot signature is present.
+    pub serial: Option<u32>,
+    /// Volume label as stored in the boot sector, if the extended boot signature is present.
+    /// The label in the root directory, returned by `getlabel()`, takes precedence.
+    pub label: Option<[u8; 11]>,
+    /// Contents of the FSInfo sector, filled in by `read()` and `read_at()` on FAT32.
+    pub fs_info: Option<FsInfo>
+}
+
+fn u16_at(sector: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([sector[offset], sector[offset + 1]])
+}
+
+fn u32_at(sector: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([sector[offset], sector[offset + 1], sector[offset + 2], sector[offset + 3]])
+}
+
+fn known(value: u32) -> Option<u32> {
+    (value != UNKNOWN).then_some(value)
+}
+
+impl BootSector {
+    /// Decodes a boot sector. Fails with `Error::NoFileSystem` if the sector is not the boot
+    /// sector of a FAT volume.
+    pub fn parse(sector: &[u8]) -> Result<Self, Error> {
+        if sector.len() < 512 || sector[510..512] != SIGNATURE || !matches!(sector[0], 0xEB | 0xE9) {
+            return Err(Error::NoFileSystem)
+        }
+        let bytes_per_sector = u16_at(sector, 11);
+        let sectors_per_cluster = sector[13];
+        let reserved_sectors = u16_at(sector, 14);
+        let fat_count = sector[16];
+        let root_entries = u16_at(sector, 17);
+        let total_sectors = match u16_at(sector, 19) {
+            0 => u32_at(sector, 32),
+            total => total as u32
+        };
+        let fat32 = u16_at(sector, 22) == 0;
+        let sectors_per_fat = if fat32 { u32_at(sector, 36) } else { u16_at(sector, 22) as u32 };
+        if !(512..=4096).contains(&bytes_per_sector) || !bytes_per_sector.is_power_of_two() ||
+            !sectors_per_cluster.is_power_of_two() || reserved_sectors == 0 || fat_count == 0 ||
+            total_sectors == 0 || sectors_per_fat == 0 {
+            return Err(Error::NoFileSystem)
+        }
+        let root_sectors = (root_entries as u32 * 32).div_ceil(bytes_per_sector as u32);
+        let data_start = (fat_count as u32).checked_mul(sectors_per_fat)
+            .and_then(|fats| fats.checked_add(reserved_sectors as u32 + root_sectors))
+            .ok_or(Error::NoFileSystem)?;
+        let cluster_count = total_sectors.checked_sub(data_start).ok_or(Error::NoFileSystem)? / sectors_per_cluster as u32;
+        let fat_type = match cluster_count {
+            count if count < 4085 => FatType::Fat12,
+            count if count < 65525 => FatType::Fat16,
+            _ => FatType::Fat32
+        };
+        //The extended fields follow the FAT32 fields on FAT32 volumes.
+        let extended = if fat32 { 64 } else { 36 };
+        let (serial, label) = if sector[extended + 2] == EXTENDED_SIGNATURE {
+            let mut label = [0; 11];
+            label.copy_from_slice(&sector[extended + 7..extended + 18]);
+            (Some(u32_at(sector, extended + 3)), Some(label))
+        } else {
+            (None, None)
+        };
+        let mut oem_name = [0; 8];
+        oem_name.copy_from_slice(&sector[3..11]);
+        Ok(Self {
+            oem_name,
+            bytes_per_sector,
+            sectors_per_cluster,
+            reserved_sectors,
+            fat_count,
+            root_entries,
+            total_sectors,
+            media: sector[21],
+            sectors_per_fat,
+            hidden_sectors: u32_at(sector, 28),
+            fat_type,
+            cluster_count,
+            root_cluster: fat32.then(|| u32_at(sector, 44)),
+            fs_info_sector: fat32.then(|| u16_at(sector, 48)),
+            serial,
+            label,
+            fs_info: None
+        })
+    }
+
+    /// Returns the volume label from the boot sector without its trailing spaces, or `None`
+    /// if there is no label or it is not valid UTF-8.
+    pub fn label_str(&self) -> Option<&str> {
+        let label = self.label.as_ref()?;
+        core::str::from_utf8(label).ok().map(|label| label.trim_end_matches(' '))
+    }
+}
+
+/// Decodes an FSInfo sector, returning `None` if its signatures do not match.
+fn parse_fs_info(sector: &[u8]) -> Option<FsInfo> {
+    if u32_at(sector, 0) != FSINFO_LEAD_SIGNATURE || u32_at(sector, 484) != FSINFO_STRUCT_SIGNATURE {
+        return None
+    }
+    Some(FsInfo { free_clusters: known(u32_at(sector, 488)), next_free: known(u32_at(sector, 492)) })
+}
+
+/// Returns the sector holding the boot sector of a volume. Partition `0` selects sector 0 if
+/// it is a boot sector, and the first entry of the partition table otherwise, while partitions
+/// `1` to `4` select the entries of the partition table.
+fn volume_start(fs: &RawFileSystem, partition: u8) -> Result<u32, Error> {
+    let mut buffer = [0; FF_MAX_SS as usize];
+    fs.read_sectors(0, &mut buffer)?;
+    if partition == 0 && BootSector::parse(&buffer).is_ok() {
+        return Ok(0)
+    }
+    //Each entry of the partition table holds the type and the start of the partition.
+    let entry = PARTITION_TABLE + 16 * (partition.max(1) as usize - 1);
+    if partition > 4 || buffer[510..512] != SIGNATURE || buffer[entry + 4] == 0 {
+        return Err(Error::NoFileSystem)
+    }
+    Ok(u32_at(&buffer, entry + 8))
+}
+
+/// Reads and decodes the boot sector of the first volume on the drive, along with the FSInfo
+/// sector on FAT32. If sector 0 is not a boot sector, it is read as a master boot record and
+/// the first partition is used. The volume need not be mounted.
+pub fn read(fs: &RawFileSystem) -> Result<BootSector, Error> {
+    read_at(fs, volume_start(fs, 0)?)
+}
+
+/// Reads and decodes the boot sector of the volume that `mkfs()` formats and `mount()` mounts.
+pub(crate) fn read_selected(fs: &RawFileSystem) -> Result<BootSector, Error> {
+    read_at(fs, volume_start(fs, partition::selected_partition())?)
+}
+
+/// Reads and decodes the boot sector at the given logical block address, along with the
+/// FSInfo sector on FAT32.
+pub fn read_at(fs: &RawFileSystem, sector: u32) -> Result<BootSector, Error> {
+    let mut buffer = [0; FF_MAX_SS as usize];
+    fs.read_sectors(sector, &mut buffer)?;
+    let mut boot = BootSector::parse(&buffer)?;
+    if let Some(offset) = boot.fs_info_sector.filter(|offset| *offset != 0 && *offset != 0xFFFF) {
+        fs.read_sectors(sector.checked_add(offset as u32).ok_or(Error::NoFileSystem)?, &mut buffer)?;
+        boot.fs_info = parse_fs_info(&buffer);
+    }
+    Ok(boot)
+}
+
+/// Sets the OEM name and volume serial number in the boot sector of the volume that `mkfs()`
+/// formats and `mount()` mounts, along with the backup boot sector on FAT32. FatFs writes
+/// `MSDOS5.0` as the OEM name and derives the serial number from the time of formatting, so
+/// this is called after `mkfs()` to give a provisioned device a serial number that identifies
+/// it. Values given as `None` are left unchanged. Fails with `Error::InvalidParameter` if a
+/// serial number is given and the boot sector has no field for it.
+pub fn set_identity(fs: &RawFileSystem, oem_name: Option<&[u8; 8]>, serial: Option<u32>) -> Result<(), Error> {
+    let start = volume_start(fs, partition::selected_partition())?;
+    let mut buffer = [0; FF_MAX_SS as usize];
+    fs.read_sectors(start, &mut buffer)?;
+    let boot = BootSector::parse(&buffer)?;
+    if serial.is_some() && boot.serial.is_none() {
+        return Err(Error::InvalidParameter)
+    }
+    //The extended fields follow the FAT32 fields on FAT32 volumes.
+    let serial_offset = if boot.root_cluster.is_some() { 67 } else { 39 };
+    let backup = match u16_at(&buffer, 50) {
+        offset if boot.root_cluster.is_some() && offset != 0 && offset != 0xFFFF => Some(start + offset as u32),
+        _ => None
+    };
+    for sector in [Some(start), backup].into_iter().flatten() {
+        fs.read_sectors(sector, &mut buffer)?;
+        if let Some(oem_name) = oem_name {
+            buffer[3..11].copy_from_slice(oem_name);
+        }
+        if let Some(serial) = serial {
+            buffer[serial_offset..serial_offset + 4].copy_from_slice(&serial.to_le_bytes());
+        }
+        fs.write_sectors(sector, &buffer)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,317 @@
+//! Buffered readers and writers that turn many small reads and writes into fewer, larger
+//! FatFs calls. Each FatFs call has a fixed cost regardless of its length, so parsing a file
+//! a byte at a time through `read()` is slow, particularly on SD cards. Line iterators for
+//! text files such as configuration or G-code are built on the buffered reader.
+//!
+//! ```ignore
+//! let file = locked_fs.open_raw("data.csv", FileOptions::Read)?;
+//! let mut reader: BufReader<'_, 512> = BufReader::new(&locked_fs, file);
+//! while let Some(byte) = reader.read_byte()? {
+//!     parser.push(byte);
+//! }
+//! locked_fs.close(reader.into_inner())?;
+//! ```
+//!
+//! A reader or writer that is dropped closes its file, discarding any error. `AsyncLines`,
+//! which cannot reach the file system without waiting for the lock, leaves the file to be
+//! closed the next time the lock is acquired, as an `AutoCloseFile` is.
+
+use core::fmt;
+use core::mem;
+use alloc::vec::Vec;
+use crate::fatfs::*;
+
+/// A reader that fills a buffer of `N` bytes from the file at a time.
+pub struct BufReader<'a, const N: usize = 512> {
+    fs: &'a RawFileSystem,
+    file: File,
+    buffer: [u8; N],
+    position: usize,
+    filled: usize
+}
+
+impl<'a, const N: usize> BufReader<'a, N> {
+    pub fn new(fs: &'a RawFileSystem, file: File) -> Self {
+        Self { fs, file, buffer: [0; N], position: 0, filled: 0 }
+    }
+
+    /// Returns the buffered data, reading more from the file if the buffer is empty. An
+    /// empty slice is returned at the end of the file.
+    pub fn fill_buf(&mut self) -> Result<&[u8], Error> {
+        if self.position >= self.filled {
+            self.filled = self.fs.read(&mut self.file, &mut self.buffer)? as usize;
+            self.position = 0;
+        }
+        Ok(&self.buffer[self.position..self.filled])
+    }
+
+    /// Marks the given number of bytes returned by `fill_buf()` as read.
+    pub fn consume(&mut self, len: usize) {
+        self.position = (self.position + len).min(self.filled);
+    }
+
+    /// Reads data into the buffer and returns the number of bytes read. Reads at least as
+    /// large as the internal buffer bypass it when it is empty.
+    pub fn read(&mut self, buffer: &mut [u8]) -> Result<u32, Error> {
+        if self.position >= self.filled && buffer.len() >= N {
+            return self.fs.read(&mut self.file, buffer)
+        }
+        let available = self.fill_buf()?;
+        let len = available.len().min(buffer.len());
+        buffer[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len as u32)
+    }
+
+    /// Reads the next byte, or returns `None` at the end of the file.
+    pub fn read_byte(&mut self) -> Result<Option<u8>, Error> {
+        let byte = self.fill_buf()?.first().copied();
+        self.consume(1);
+        Ok(byte)
+    }
+
+    /// Reads bytes up to and including the delimiter, or the end of the file, appending them
+    /// to the buffer. Returns the number of bytes read, which is 0 at the end of the file.
+    pub fn read_until(&mut self, delimiter: u8, buffer: &mut Vec<u8>) -> Result<usize, Error> {
+        let mut total = 0;
+        loop {
+            let available = self.fill_buf()?;
+            if available.is_empty() {
+                return Ok(total)
+            }
+            let (len, found) = match available.iter().position(|byte| *byte == delimiter) {
+                Some(index) => (index + 1, true),
+                None => (available.len(), false)
+            };
+            buffer.extend_from_slice(&available[..len]);
+            self.consume(len);
+            total += len;
+            if found {
+                return Ok(total)
+            }
+        }
+    }
+
+    /// Returns an iterator over the lines of the file, which end with either `\n` or `\r\n`.
+    pub fn lines(self) -> Lines<'a, N> {
+        Lines { reader: self, done: false }
+    }
+
+    /// Returns the file, which must then be closed manually. Its position is moved back to
+    /// the first byte that has not been read from the buffer.
+    pub fn into_inner(mut self) -> File {
+        let unread = (self.filled - self.position) as u32;
+        let position = self.file.position().saturating_sub(unread);
+        let _ = self.fs.seek(&mut self.file, position);
+        mem::take(&mut self.file)
+    }
+}
+
+impl<const N: usize> Drop for BufReader<'_, N> {
+    fn drop(&mut self) {
+        //FatFs clears the file system pointer of a file when it is closed.
+        if !self.file.obj.fs.is_null() {
+            let _ = self.fs.close(mem::take(&mut self.file));
+        }
+    }
+}
+
+/// Strips the line ending from a line and checks that it is valid UTF-8.
+fn line_to_string(mut line: Vec<u8>) -> Result<String, Error> {
+    if line.last() == Some(&b'\n') {
+        line.pop();
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+    }
+    String::from_utf8(line).map_err(|_| Error::InvalidParameter)
+}
+
+/// An iterator over the lines of a file, returned by `BufReader::lines()`. Lines are
+/// returned without their line ending. A line that is not valid UTF-8 results in
+/// `Error::InvalidParameter`. The iterator ends after the first error.
+pub struct Lines<'a, const N: usize = 512> {
+    reader: BufReader<'a, N>,
+    done: bool
+}
+
+impl<'a, const N: usize> Lines<'a, N> {
+    /// Returns the file, positioned at the start of the next line, which must then be closed
+    /// manually.
+    pub fn into_inner(self) -> File {
+        self.reader.into_inner()
+    }
+}
+
+impl<const N: usize> Iterator for Lines<'_, N> {
+    type Item = Result<String, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None
+        }
+        let mut line = Vec::new();
+        match self.reader.read_until(b'\n', &mut line) {
+            Ok(0) => {
+                self.done = true;
+                None
+            },
+            Ok(_) => Some(line_to_string(line)),
+            Err(error) => {
+                self.done = true;
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+/// Reads the lines of a file from another task. Exclusive access to the file system is
+/// acquired each time the buffer of `N` bytes is refilled, and released again afterwards.
+/// If the iterator is dropped, its file is closed the next time the lock is acquired.
+pub struct AsyncLines<'a, const N: usize = 512> {
+    fs: &'a FileSystem,
+    file: AutoCloseFile,
+    buffer: [u8; N],
+    position: usize,
+    filled: usize
+}
+
+impl<'a, const N: usize> AsyncLines<'a, N> {
+    pub fn new(fs: &'a FileSystem, file: File) -> Self {
+        Self { fs, file: AutoCloseFile::new(file), buffer: [0; N], position: 0, filled: 0 }
+    }
+
+    /// Returns the next line without its line ending, or `None` at the end of the file.
+    pub async fn next_line(&mut self) -> Option<Result<String, Error>> {
+        let mut line = Vec::new();
+        loop {
+            if self.position >= self.filled {
+                let fs = self.fs.lock().await;
+                match fs.read(&mut self.file, &mut self.buffer) {
+                    Ok(0) if line.is_empty() => return None,
+                    Ok(0) => return Some(line_to_string(line)),
+                    Ok(len) => self.filled = len as usize,
+                    Err(error) => return Some(Err(error))
+                }
+                self.position = 0;
+            }
+            let available = &self.buffer[self.position..self.filled];
+            match available.iter().position(|byte| *byte == b'\n') {
+                Some(index) => {
+                    line.extend_from_slice(&available[..=index]);
+                    self.position += index + 1;
+                    return Some(line_to_string(line))
+                },
+                None => {
+                    line.extend_from_slice(available);
+                    self.position = self.filled;
+                }
+            }
+        }
+    }
+
+    /// Returns the file, which must still be closed. Its position is moved back to the start
+    /// of the next line.
+    pub async fn into_inner(mut self) -> File {
+        let unread = (self.filled - self.position) as u32;
+        let position = self.file.position().saturating_sub(unread);
+        let fs = self.fs.lock().await;
+        let _ = fs.seek(&mut self.file, position);
+        self.file.into_inner()
+    }
+}
+
+/// A writer that collects data in a buffer of `N` bytes and writes it to the file once the
+/// buffer is full. Buffered data is written and the file closed when the writer is dropped,
+/// discarding any error. Use `flush()` or `into_inner()` to report errors.
+pub struct BufWriter<'a, const N: usize = 512> {
+    fs: &'a RawFileSystem,
+    file: File,
+    buffer: [u8; N],
+    len: usize
+}
+
+impl<'a, const N: usize> BufWriter<'a, N> {
+    pub fn new(fs: &'a RawFileSystem, file: File) -> Self {
+        Self { fs, file, buffer: [0; N], len: 0 }
+    }
+
+    /// Writes all of the data, through the buffer. Writes at least as large as the internal
+    /// buffer bypass it once it has been flushed.
+    pub fn write(&mut self, data: &[u8]) -> Result<(), WriteAllError> {
+        if self.len + data.len() > N {
+            self.flush()?;
+        }
+        if data.len() >= N {
+            return self.fs.write_all(&mut self.file, data)
+        }
+        self.buffer[self.len..self.len + data.len()].copy_from_slice(data);
+        self.len += data.len();
+        Ok(())
+    }
+
+    /// Writes the buffered data to the file.
+    pub fn flush(&mut self) -> Result<(), WriteAllError> {
+        let result = self.fs.write_all(&mut self.file, &self.buffer[..self.len]);
+        match result {
+            Ok(()) => self.len = 0,
+            //Keep whatever part of the buffer was not written.
+            Err(WriteAllError::DiskFull { written }) => {
+                self.buffer.copy_within(written as usize..self.len, 0);
+                self.len -= written as usize;
+            },
+            Err(_) => ()
+        }
+        result
+    }
+
+    /// Writes the buffered data to the file, then syncs the file.
+    pub fn sync(&mut self) -> Result<(), WriteAllError> {
+        self.flush()?;
+        Ok(self.fs.sync(&mut self.file)?)
+    }
+
+    /// Writes the buffered data to the file and returns the file, which must then be closed
+    /// manually. If writing fails, the file is returned along with the error, and the data
+    /// that was not written is discarded.
+    #[allow(clippy::result_large_err)]
+    pub fn into_inner(mut self) -> Result<File, (WriteAllError, File)> {
+        let result = self.flush();
+        self.len = 0;
+        let file = mem::take(&mut self.file);
+        match result {
+            Ok(()) => Ok(file),
+            Err(error) => Err((error, file))
+        }
+    }
+}
+
+/// Formatted writes with `write!()`, in place of `f_printf()`. Errors writing to the file
+/// are reported as `fmt::Error`.
+impl<const N: usize> fmt::Write for BufWriter<'_, N> {
+    fn write_str(&mut self, string: &str) -> fmt::Result {
+        self.write(string.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+/// Formatted writes with `uwrite!()`, without pulling in `core::fmt`.
+#[cfg(feature = "ufmt")]
+impl<const N: usize> ufmt_write::uWrite for BufWriter<'_, N> {
+    type Error = WriteAllError;
+
+    fn write_str(&mut self, string: &str) -> Result<(), WriteAllError> {
+        self.write(string.as_bytes())
+    }
+}
+
+impl<const N: usize> Drop for BufWriter<'_, N> {
+    fn drop(&mut self) {
+        if self.file.obj.fs.is_null() {
+            return
+        }
+        if self.len > 0 {
+            let _ = self.flush();
+        }
+        let _ = self.fs.close(mem::take(&mut self.file));
+    }
+}
@@ -0,0 +1,51 @@
+use crate::fatfs::*;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// A small least-recently-used cache keyed by path, most recent first.
+pub(crate) struct PathCache<V> {
+    entries: Vec<(String, V)>,
+    capacity: usize
+}
+
+/// Recently used `stat()` results. A `None` entry records that the path does not exist.
+pub(crate) type StatCache = PathCache<Option<FileInfo>>;
+
+/// Start clusters of recently used directories, keyed by absolute path.
+pub(crate) type DirCache = PathCache<DWORD>;
+
+impl<V: Clone> PathCache<V> {
+    pub(crate) const fn new() -> Self {
+        Self { entries: Vec::new(), capacity: 0 }
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub(crate) fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.entries.truncate(capacity);
+        self.entries.shrink_to_fit();
+    }
+
+    pub(crate) fn get(&mut self, path: &str) -> Option<V> {
+        let index = self.entries.iter().position(|(cached_path, _)| cached_path == path)?;
+        let entry = self.entries.remove(index);
+        let value = entry.1.clone();
+        self.entries.insert(0, entry);
+        Some(value)
+    }
+
+    pub(crate) fn insert(&mut self, path: &str, value: V) {
+        if self.capacity == 0 {
+            return
+        }
+        self.entries.truncate(self.capacity - 1);
+        self.entries.insert(0, (String::from(path), value));
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
@@ -0,0 +1,37 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::fatfs::*;
+
+/// A flag used to stop a long-running operation, such as a copy of a large file, from
+/// another task or an interrupt. The operation checks the token between chunks of work and
+/// returns `Error::Cancelled` once it has been cancelled, after closing any files it opened.
+/// A token may be placed in a `static` and shared, and stays cancelled until it is reset.
+#[derive(Debug, Default)]
+pub struct CancelToken(AtomicBool);
+
+impl CancelToken {
+    pub const fn new() -> Self {
+        Self(AtomicBool::new(false))
+    }
+
+    /// Requests that operations checking this token stop.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Clears a cancellation, so that the token may be used for another operation.
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Returns `Error::Cancelled` if the token has been cancelled.
+    pub(crate) fn check(&self) -> Result<(), Error> {
+        if self.is_cancelled() {
+            return Err(Error::Cancelled)
+        }
+        Ok(())
+    }
+}
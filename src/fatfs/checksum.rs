@@ -0,0 +1,246 @@
+use core::ptr;
+use crate::fatfs::*;
+use crate::fatfs::crc::crc32;
+use crate::fatfs::path::PathBuffer;
+#[cfg(feature = "sha256")]
+use sha2::{Digest, Sha256};
+
+/// Sidecar files are named after their data file, with a leading `.` and this suffix.
+const SIDECAR_SUFFIX: &str = ".crc";
+const SIDECAR_LEN: usize = 8;
+/// Longest name FatFs accepts, `FF_MAX_LFN` in ffconf.h.
+const MAX_NAME_LEN: usize = 255;
+
+/// Returns the path of the sidecar file for a normalized absolute path, or `None` if the name
+/// of the file leaves no room for the prefix and suffix of its sidecar.
+fn sidecar_path(path: &str) -> Option<PathBuffer> {
+    let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+    if name.chars().count() + 1 + SIDECAR_SUFFIX.len() > MAX_NAME_LEN {
+        return None
+    }
+    let mut sidecar = PathBuffer::new(if dir.is_empty() { "/" } else { dir });
+    sidecar.push(&alloc::format!(".{}{}", name, SIDECAR_SUFFIX));
+    Some(sidecar)
+}
+
+fn is_sidecar(path: &str) -> bool {
+    let name = path.trim_end_matches('\0').rsplit(['/', '\\']).next().unwrap_or(path);
+    name.starts_with('.') && name.ends_with(SIDECAR_SUFFIX)
+}
+
+/// Algorithms for `checksum()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    /// CRC-32 (IEEE 802.3).
+    Crc32,
+    #[cfg(feature = "sha256")]
+    Sha256
+}
+
+/// A checksum returned by `checksum()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Checksum {
+    Crc32(u32),
+    #[cfg(feature = "sha256")]
+    Sha256([u8; 32])
+}
+
+/// A checksum being computed over a stream of data.
+pub(crate) enum Hasher {
+    Crc32(u32),
+    #[cfg(feature = "sha256")]
+    Sha256(Sha256)
+}
+
+impl Hasher {
+    pub(crate) fn new(algorithm: ChecksumAlgorithm) -> Self {
+        match algorithm {
+            ChecksumAlgorithm::Crc32 => Hasher::Crc32(0),
+            #[cfg(feature = "sha256")]
+            ChecksumAlgorithm::Sha256 => Hasher::Sha256(Sha256::new())
+        }
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Crc32(crc) => *crc = crc32(*crc, data),
+            #[cfg(feature = "sha256")]
+            Hasher::Sha256(hasher) => hasher.update(data)
+        }
+    }
+
+    pub(crate) fn finish(self) -> Checksum {
+        match self {
+            Hasher::Crc32(crc) => Checksum::Crc32(crc),
+            #[cfg(feature = "sha256")]
+            Hasher::Sha256(hasher) => Checksum::Sha256(hasher.finalize().into())
+        }
+    }
+}
+
+impl RawFileSystem {
+    /// Computes a checksum of the content of the file at the given path, reading it a
+    /// sector at a time so that the file is never held in memory.
+    pub fn checksum(&self, path: &str, algorithm: ChecksumAlgorithm) -> Result<Checksum, Error> {
+        self.checksum_cancellable(path, algorithm, &CancelToken::new())
+    }
+
+    /// Computes a checksum in the same way as `checksum()`, checking the token before each
+    /// sector is read and returning `Error::Cancelled` once it has been cancelled.
+    pub fn checksum_cancellable(&self, path: &str, algorithm: ChecksumAlgorithm, cancel: &CancelToken) -> Result<Checksum, Error> {
+        let mut file = self.open_raw(path, FileOptions::Read | FileOptions::OpenExisting)?;
+        let mut hasher = Hasher::new(algorithm);
+        let mut buffer = [0; FF_MAX_SS as usize];
+        let result = loop {
+            if let Err(error) = cancel.check() {
+                break Err(error)
+            }
+            match self.read(&mut file, &mut buffer) {
+                Ok(0) => break Ok(()),
+                Ok(len) => hasher.update(&buffer[..len as usize]),
+                Err(error) => break Err(error)
+            }
+        };
+        self.close(file)?;
+        result?;
+        Ok(hasher.finish())
+    }
+
+    /// Enables maintaining a hidden sidecar file holding the CRC-32 and size of each data
+    /// file. The sidecar of a file is updated whenever the file is synced or closed after
+    /// being written to, and removed or renamed along with the file. `verify()` then detects
+    /// media that silently corrupts data. Files written while checksums were disabled have
+    /// no sidecar, or a stale one, until they are next written. Files with names too long to
+    /// leave room for the `.` and `.crc` of a sidecar are given none. Disabled by default.
+    pub fn set_checksums(&mut self, enabled: bool) {
+        self.checksums = enabled;
+    }
+
+    /// Checks the content of the file at the given path against its sidecar. Returns `false`
+    /// if the content has changed since the sidecar was last updated, or `Error::NoFile` if
+    /// the file does not exist. `Error::ChecksumMissing` is returned for a file without a
+    /// sidecar, because it was written while checksums were disabled, its sidecar could not be
+    /// written or its name is too long for one.
+    pub fn verify(&self, path: &str) -> Result<bool, Error> {
+        let absolute = self.resolve(self.fs().cdir, path).ok_or(Error::NoPath)?;
+        let data_path = PathBuffer::new(&absolute);
+        self.stat(data_path.as_str())?;
+        let sidecar = sidecar_path(&absolute).ok_or(Error::ChecksumMissing)?;
+        let mut file = match self.open_raw(sidecar.as_str(), FileOptions::Read) {
+            Err(Error::NoFile) => return Err(Error::ChecksumMissing),
+            file => file?
+        };
+        let mut bytes = [0; SIDECAR_LEN];
+        let result = self.read(&mut file, &mut bytes);
+        self.close(file)?;
+        if result? as usize != SIDECAR_LEN {
+            return Ok(false)
+        }
+        let file = self.open_raw(data_path.as_str(), FileOptions::Read)?;
+        let size = file.obj.objsize;
+        let result = self.file_crc(&file);
+        self.close(file)?;
+        let crc = result?;
+        let expected_crc = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let expected_size = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+        Ok(crc == expected_crc && size == expected_size)
+    }
+
+    /// Returns the CRC-32 of the whole content of the file, which must have been synced. FatFs
+    /// refuses to open a file a second time while it is open for writing, so the file is read
+    /// through a copy of its file object opened for reading only, leaving the file untouched.
+    fn file_crc(&self, file: &File) -> Result<u32, Error> {
+        //The copy shares the lock slot and handle of the file, so it is never closed.
+        let mut reader = unsafe { ptr::read(file) };
+        reader.flag = FA_READ as BYTE;
+        reader.err = 0;
+        reader.fptr = 0;
+        reader.clust = 0;
+        reader.sect = 0;
+        let mut crc = 0;
+        let mut buffer = [0; 512];
+        loop {
+            match self.read(&mut reader, &mut buffer)? {
+                0 => return Ok(crc),
+                len => crc = crc32(crc, &buffer[..len as usize])
+            }
+        }
+    }
+
+    /// Rewrites the sidecar of the given file, which has just been synced. If the sidecar
+    /// cannot be written, for example because the directory or the volume is full, any stale
+    /// sidecar is removed so that the file is not reported as changed by `verify()`, and
+    /// `Error::ChecksumMissing` is returned.
+    pub(crate) fn update_checksum(&self, file: &File) -> Result<(), Error> {
+        let (path, cdir) = match self.registry.borrow().get(file.handle) {
+            Some(open) => (open.path.clone(), open.cdir),
+            None => return Ok(())
+        };
+        if is_sidecar(&path) {
+            return Ok(())
+        }
+        let absolute = self.resolve(cdir, &path).ok_or(Error::ChecksumMissing)?;
+        let sidecar = match sidecar_path(&absolute) {
+            Some(sidecar) => sidecar,
+            None => return Ok(())
+        };
+        if self.write_sidecar(file, &sidecar).is_err() {
+            unsafe { f_unlink(sidecar.as_str().as_ptr().cast()); }
+            return Err(Error::ChecksumMissing)
+        }
+        Ok(())
+    }
+
+    fn write_sidecar(&self, file: &File, sidecar: &PathBuffer) -> Result<(), Error> {
+        let size = file.obj.objsize;
+        let running = self.registry.borrow().get(file.handle).and_then(|open| open.crc);
+        let crc = match running {
+            Some((crc, len)) if len == size => crc,
+            _ => self.file_crc(file)?
+        };
+        self.registry.borrow_mut().set_crc(file.handle, Some((crc, size)));
+        let mut bytes = [0; SIDECAR_LEN];
+        bytes[..4].copy_from_slice(&crc.to_le_bytes());
+        bytes[4..].copy_from_slice(&size.to_le_bytes());
+        let mut sidecar_file = self.open_raw(sidecar.as_str(), FileOptions::CreateAlways | FileOptions::Write)?;
+        let result = self.write(&mut sidecar_file, &bytes);
+        self.close(sidecar_file)?;
+        if result? as usize != SIDECAR_LEN {
+            return Err(Error::Denied)
+        }
+        let hidden = FileAttributes::Hidden | FileAttributes::System;
+        self.chmod(sidecar.as_str(), hidden, hidden)
+    }
+
+    /// Removes the sidecar of a file that has been deleted.
+    pub(crate) fn remove_checksum(&self, path: &str) {
+        if let Some(absolute) = self.resolve(self.fs().cdir, path) {
+            if let Some(sidecar) = sidecar_path(&absolute) {
+                unsafe { f_unlink(sidecar.as_str().as_ptr().cast()); }
+            }
+        }
+    }
+
+    /// Moves the sidecar of a file that has been renamed, replacing any stale sidecar at the new path.
+    pub(crate) fn rename_checksum(&self, old_path: &str, new_path: &str) {
+        if let (Some(old_path), Some(new_path)) = (self.resolve(self.fs().cdir, old_path), self.resolve(self.fs().cdir, new_path)) {
+            let old_sidecar = sidecar_path(&old_path);
+            let new_sidecar = sidecar_path(&new_path);
+            unsafe {
+                if let Some(new_sidecar) = &new_sidecar {
+                    f_unlink(new_sidecar.as_str().as_ptr().cast());
+                }
+                match (old_sidecar, new_sidecar) {
+                    (Some(old_sidecar), Some(new_sidecar)) => {
+                        f_rename(old_sidecar.as_str().as_ptr().cast(), new_sidecar.as_str().as_ptr().cast());
+                    },
+                    //The new name is too long for a sidecar.
+                    (Some(old_sidecar), None) => {
+                        f_unlink(old_sidecar.as_str().as_ptr().cast());
+                    },
+                    _ => ()
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use crate::fatfs::*;
+use crate::fatfs::diskio::{DiskResult, DiskStatus, FatFsDriver, IoctlCommand};
+
+/// Selects which sectors `clone_volume()` copies.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CloneMode {
+    /// Copy the sectors ahead of the data area, including the partition table, boot sector
+    /// and FATs, and the clusters that are in use. Requires the volume to be mounted.
+    UsedSectors,
+    /// Copy every sector of the device.
+    AllSectors
+}
+
+impl RawFileSystem {
+    /// Copies the device of the installed driver to the given destination device, for example
+    /// to duplicate cards in manufacturing or back up to a second card. Cached data is written
+    /// back to the source first. When `verify` is set, each sector is read back from the
+    /// destination and compared, with a mismatch reported as `Error::DiskError`. The progress
+    /// callback receives the number of sectors copied so far and the total to copy.
+    /// Returns the number of sectors copied.
+    pub fn clone_volume(&self, destination: &mut dyn FatFsDriver, mode: CloneMode, verify: bool, mut progress: impl FnMut(u32, u32)) -> Result<u32, Error> {
+        let drive = self.fs().pdrv;
+        if self.fs().fs_type != 0 {
+            self.sync_fs()?;
+        } else if mode == CloneMode::UsedSectors {
+            return Err(Error::NotEnabled)
+        }
+        if destination.disk_initialize(0) & DiskStatus::NotInitialized as u8 != 0 {
+            return Err(Error::NotReady)
+        }
+        let sector_count = diskio::sector_count(drive)?;
+        let mut destination_count = IoctlCommand::GetSectorCount(0);
+        destination.disk_ioctl(&mut destination_count);
+        if let IoctlCommand::GetSectorCount(count) = destination_count {
+            if count < sector_count {
+                return Err(Error::Denied)
+            }
+        }
+        let total = match mode {
+            CloneMode::AllSectors => sector_count,
+            CloneMode::UsedSectors => {
+                let mut used = 0;
+                self.used_sector_runs(|_, count| -> Result<(), Error> {
+                    used += count;
+                    Ok(())
+                })?;
+                used
+            }
+        };
+        let mut buffer = [0; FF_MAX_SS as usize];
+        let mut readback = [0; FF_MAX_SS as usize];
+        let mut copied = 0;
+        let mut copy = |sector: u32| -> Result<(), Error> {
+            diskio::read_sectors(drive, &mut buffer, sector)?;
+            if !matches!(destination.disk_write(0, &buffer, sector), DiskResult::Ok) {
+                return Err(Error::DiskError)
+            }
+            if verify && (!matches!(destination.disk_read(0, &mut readback, sector), DiskResult::Ok) || readback != buffer) {
+                return Err(Error::DiskError)
+            }
+            copied += 1;
+            progress(copied, total);
+            Ok(())
+        };
+        match mode {
+            CloneMode::AllSectors => {
+                for sector in 0..sector_count {
+                    copy(sector)?;
+                }
+            },
+            CloneMode::UsedSectors => {
+                self.used_sector_runs(|first, count| -> Result<(), Error> {
+                    for sector in first..first + count {
+                        copy(sector)?;
+                    }
+                    Ok(())
+                })?;
+            }
+        }
+        Ok(copied)
+    }
+}
@@ -0,0 +1,149 @@
+//! Settings files protected by a CRC, so that a corrupted file is detected when it is read
+//! rather than loaded as valid settings.
+//!
+//! Each file holds a header of the length of the data and a CRC-32 of it, both little
+//! endian, followed by the data. Files are written with `write_atomic()`, so a power loss
+//! while writing leaves either the old or the new settings.
+//!
+//! Settings may instead be kept in two files, A and B, written in turn. Each copy carries a
+//! generation number that increases with every write, and the newest valid copy is read, so
+//! the previous settings remain if a write is interrupted or one copy is corrupted.
+//!
+//! ```ignore
+//! config::write(&locked_fs, "settings.bin", &settings)?;
+//! let settings = match config::read(&locked_fs, "settings.bin") {
+//!     Ok(data) => parse(&data),
+//!     Err(ConfigError::Corrupt) | Err(ConfigError::Other(Error::NoFile)) => defaults(),
+//!     Err(error) => return Err(error.into())
+//! };
+//! ```
+
+use alloc::vec::Vec;
+use crate::fatfs::*;
+use crate::fatfs::crc::crc32;
+
+/// Length of the header preceding the data in a settings file.
+pub const HEADER_LEN: usize = 8;
+
+/// Error returned when reading a settings file.
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum ConfigError {
+    /// The file is too short, its length does not match its header, or its data does not
+    /// match its CRC.
+    Corrupt,
+    Other(Error)
+}
+
+impl From<Error> for ConfigError {
+    fn from(error: Error) -> Self {
+        ConfigError::Other(error)
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(error: ConfigError) -> Self {
+        match error {
+            ConfigError::Corrupt => Error::IntError,
+            ConfigError::Other(error) => error
+        }
+    }
+}
+
+/// Returns the data held in the contents of a settings file.
+pub(crate) fn decode(contents: &[u8]) -> Result<&[u8], ConfigError> {
+    let (header, data) = match contents.split_first_chunk::<HEADER_LEN>() {
+        Some(split) => split,
+        None => return Err(ConfigError::Corrupt)
+    };
+    let [l0, l1, l2, l3, c0, c1, c2, c3] = *header;
+    let len = u32::from_le_bytes([l0, l1, l2, l3]);
+    let crc = u32::from_le_bytes([c0, c1, c2, c3]);
+    if len as usize != data.len() || crc != crc32(0, data) {
+        return Err(ConfigError::Corrupt)
+    }
+    Ok(data)
+}
+
+/// Returns the contents of a settings file holding the given data.
+pub(crate) fn encode(data: &[u8]) -> Vec<u8> {
+    let mut contents = Vec::with_capacity(HEADER_LEN + data.len());
+    contents.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    contents.extend_from_slice(&crc32(0, data).to_le_bytes());
+    contents.extend_from_slice(data);
+    contents
+}
+
+/// Writes the data to the settings file at the given path, replacing any existing file.
+pub fn write(fs: &RawFileSystem, path: &str, data: &[u8]) -> Result<(), Error> {
+    fs.write_atomic(path, &encode(data))
+}
+
+/// Reads the data from the settings file at the given path, checking it against its header.
+pub fn read(fs: &RawFileSystem, path: &str) -> Result<Vec<u8>, ConfigError> {
+    let mut contents = fs.read_file(path)?;
+    let len = decode(&contents)?.len();
+    contents.drain(..HEADER_LEN);
+    contents.truncate(len);
+    Ok(contents)
+}
+
+/// Writes the contents of a file in place and syncs it.
+fn write_synced(fs: &RawFileSystem, path: &str, contents: &[u8]) -> Result<(), Error> {
+    let mut file = fs.open_raw(path, FileOptions::Write | FileOptions::CreateAlways)?;
+    let result = fs.write_all(&mut file, contents).map_err(Error::from)
+        .and_then(|_| fs.sync(&mut file));
+    let closed = fs.close(file).map_err(Error::from);
+    result.and(closed)
+}
+
+/// Reads one copy of dual settings, returning its generation and data, or `None` if it is
+/// missing or corrupt.
+fn read_copy(fs: &RawFileSystem, path: &str) -> Result<Option<(u32, Vec<u8>)>, Error> {
+    let mut contents = match read(fs, path) {
+        Ok(contents) => contents,
+        Err(ConfigError::Corrupt) | Err(ConfigError::Other(Error::NoFile)) => return Ok(None),
+        Err(ConfigError::Other(error)) => return Err(error)
+    };
+    let generation = match contents.first_chunk::<4>() {
+        Some(generation) => u32::from_le_bytes(*generation),
+        None => return Ok(None)
+    };
+    contents.drain(..4);
+    Ok(Some((generation, contents)))
+}
+
+/// Reads the newest valid copy of settings written with `write_dual()`, returning its data
+/// and generation. Fails with `ConfigError::Corrupt` if neither copy is valid, or with
+/// `Error::NoFile` if neither exists.
+pub fn read_dual(fs: &RawFileSystem, path_a: &str, path_b: &str) -> Result<(Vec<u8>, u32), ConfigError> {
+    let a = read_copy(fs, path_a)?;
+    let b = read_copy(fs, path_b)?;
+    match (a, b) {
+        (Some((generation_a, data_a)), Some((generation_b, _))) if generation_a >= generation_b => Ok((data_a, generation_a)),
+        (Some(_), Some((generation_b, data_b))) => Ok((data_b, generation_b)),
+        (Some((generation, data)), None) | (None, Some((generation, data))) => Ok((data, generation)),
+        (None, None) if fs.exists(path_a)? || fs.exists(path_b)? => Err(ConfigError::Corrupt),
+        (None, None) => Err(ConfigError::Other(Error::NoFile))
+    }
+}
+
+/// Writes settings to whichever of the two files does not hold the newest valid copy, with
+/// a generation one greater than it. Returns the generation written, which starts at 1.
+pub fn write_dual(fs: &RawFileSystem, path_a: &str, path_b: &str, data: &[u8]) -> Result<u32, Error> {
+    let a = read_copy(fs, path_a)?.map(|(generation, _)| generation);
+    let b = read_copy(fs, path_b)?.map(|(generation, _)| generation);
+    //Overwrite the older copy, so that the newest one survives an interrupted write.
+    let (path, newest) = match (a, b) {
+        (Some(a), Some(b)) if a >= b => (path_b, a),
+        (Some(a), None) => (path_b, a),
+        (_, Some(b)) => (path_a, b),
+        (None, None) => (path_a, 0)
+    };
+    let generation = newest.wrapping_add(1);
+    let mut payload = Vec::with_capacity(4 + data.len());
+    payload.extend_from_slice(&generation.to_le_bytes());
+    payload.extend_from_slice(data);
+    write_synced(fs, path, &encode(&payload))?;
+    Ok(generation)
+}
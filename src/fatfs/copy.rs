@@ -0,0 +1,99 @@
+use core::ptr;
+use crate::fatfs::*;
+use crate::fatfs::path::{self, PathBuffer};
+
+/// Attributes carried over to the copy of a file.
+const COPIED_ATTRIBUTES: FileAttributes = FileAttributes::ReadOnly.union(FileAttributes::Hidden)
+    .union(FileAttributes::System).union(FileAttributes::Archive);
+
+impl RawFileSystem {
+    /// Copies the file at the source path to the destination path, replacing any existing
+    /// file, and returns the number of bytes copied. The attributes and modification time of
+    /// the source are applied to the copy. If copying fails, the partial copy is deleted.
+    pub fn copy(&self, src: &str, dst: &str) -> Result<u32, Error> {
+        let mut buffer = [0; FF_MAX_SS as usize];
+        self.copy_with_buffer(src, dst, &mut buffer)
+    }
+
+    /// Copies a file in the same way as `copy()`, through the given buffer. Larger buffers
+    /// need fewer calls to FatFs.
+    pub fn copy_with_buffer(&self, src: &str, dst: &str, buffer: &mut [u8]) -> Result<u32, Error> {
+        self.copy_cancellable(src, dst, buffer, &CancelToken::new())
+    }
+
+    /// Copies a file in the same way as `copy_with_buffer()`, checking the token after each
+    /// chunk. Once cancelled, the partial copy is deleted and `Error::Cancelled` returned.
+    pub fn copy_cancellable(&self, src: &str, dst: &str, buffer: &mut [u8], cancel: &CancelToken) -> Result<u32, Error> {
+        if buffer.is_empty() {
+            return Err(Error::InvalidParameter)
+        }
+        let info = self.stat(src)?;
+        let mut source = self.open_raw(src, FileOptions::Read | FileOptions::OpenExisting)?;
+        let mut destination = match self.open_raw(dst, FileOptions::Write | FileOptions::CreateAlways) {
+            Ok(file) => file,
+            Err(error) => {
+                let _ = self.close(source);
+                return Err(error)
+            }
+        };
+        let mut copied = 0;
+        let result = loop {
+            if let Err(error) = cancel.check() {
+                break Err(error)
+            }
+            let len = match self.read(&mut source, buffer) {
+                Ok(0) => break Ok(()),
+                Ok(len) => len,
+                Err(error) => break Err(error)
+            };
+            if let Err(error) = self.write_all(&mut destination, &buffer[..len as usize]) {
+                break Err(error.into())
+            }
+            copied += len;
+        };
+        let _ = self.close(source);
+        let closed = self.close(destination).map_err(Error::from);
+        let result = result.and(closed)
+            .and_then(|_| self.set_timestamp(dst, info.fdate, info.ftime))
+            .and_then(|_| self.chmod(dst, FileAttributes::from_bits_retain(info.fattrib), COPIED_ATTRIBUTES));
+        match result {
+            Ok(()) => Ok(copied),
+            Err(error) => {
+                let _ = self.unlink(dst);
+                Err(error)
+            }
+        }
+    }
+
+    /// Moves the file at the source path to the destination path. Within a volume the file is
+    /// renamed, which moves it between directories without copying any data. FatFs ignores
+    /// the drive of the new name when renaming, so a file moved to another volume is copied
+    /// with `copy()` instead and the source deleted. If the source cannot be deleted, the copy
+    /// is deleted again and the error returned.
+    pub fn move_file(&self, src: &str, dst: &str) -> Result<(), Error> {
+        //Paths without a drive are on the current drive, which is always drive 0 as only one
+        //volume is configured.
+        if path::drive(src).unwrap_or("0") == path::drive(dst).unwrap_or("0") {
+            return self.rename(src, dst)
+        }
+        self.copy(src, dst)?;
+        if let Err(error) = self.unlink(src) {
+            let _ = self.unlink(dst);
+            return Err(error)
+        }
+        Ok(())
+    }
+
+    /// Applies a timestamp in FAT format to the given path.
+    fn set_timestamp(&self, path: &str, fdate: u16, ftime: u16) -> Result<(), Error> {
+        self.invalidate_stat_cache();
+        let mut info = FileInfo { fdate, ftime, ..Default::default() };
+        let result;
+        unsafe { result = f_utime(PathBuffer::new(path).as_str().as_ptr().cast(), ptr::addr_of_mut!(info)); }
+        if result == FRESULT_FR_OK {
+            return Ok(())
+        } else {
+            return Err(Error::from_result(result))
+        }
+    }
+}
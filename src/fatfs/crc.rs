@@ -0,0 +1,12 @@
+/// Continues a CRC-32 (IEEE 802.3) over the given data. Start with a `crc` of 0.
+/// Computed bitwise to avoid the flash cost of a lookup table.
+pub(crate) fn crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
@@ -0,0 +1,110 @@
+//! Files that are closed automatically when dropped.
+//!
+//! `Drop` cannot wait for the file system lock, so a dropped `AutoCloseFile` is pushed onto a
+//! queue of pending closes instead. The queue is drained the next time a task acquires the
+//! lock with `FileSystem::lock()`, or by an explicit call to `process_pending_closes()`.
+//!
+//! ```ignore
+//! let mut file = AutoCloseFile::new(locked_fs.open_raw("log.txt", FileOptions::Write)?);
+//! locked_fs.write(&mut file, b"boot\n")?;
+//! //Closed on the next call to `fatfs::FS.lock()`, even if this function returns early.
+//! ```
+
+use core::mem;
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+use alloc::boxed::Box;
+use crate::fatfs::*;
+
+struct PendingClose {
+    file: File,
+    next: *mut PendingClose
+}
+
+/// Files dropped without being closed, as a lock-free stack of pending closes.
+static PENDING: AtomicPtr<PendingClose> = AtomicPtr::new(ptr::null_mut());
+
+/// A file that is closed when dropped. Closing the file explicitly with `close()` reports
+/// any error, which is otherwise discarded.
+pub struct AutoCloseFile {
+    file: File
+}
+
+impl AutoCloseFile {
+    pub fn new(file: File) -> Self {
+        Self { file }
+    }
+
+    /// Closes the file now. If closing fails, the file is closed again once dropped.
+    pub fn close(mut self, fs: &RawFileSystem) -> Result<(), Error> {
+        fs.close(mem::take(&mut self.file)).map_err(|(error, file)| {
+            self.file = file;
+            error
+        })
+    }
+
+    /// Returns the file, which must then be closed manually.
+    pub fn into_inner(mut self) -> File {
+        mem::take(&mut self.file)
+    }
+}
+
+impl From<File> for AutoCloseFile {
+    fn from(file: File) -> Self {
+        Self::new(file)
+    }
+}
+
+impl Deref for AutoCloseFile {
+    type Target = File;
+
+    fn deref(&self) -> &File {
+        &self.file
+    }
+}
+
+impl DerefMut for AutoCloseFile {
+    fn deref_mut(&mut self) -> &mut File {
+        &mut self.file
+    }
+}
+
+impl Drop for AutoCloseFile {
+    fn drop(&mut self) {
+        //FatFs clears the file system pointer of a file when it is closed.
+        if self.file.obj.fs.is_null() {
+            return
+        }
+        let file = mem::take(&mut self.file);
+        let node = Box::into_raw(Box::new(PendingClose { file, next: ptr::null_mut() }));
+        let mut head = PENDING.load(Ordering::Relaxed);
+        loop {
+            unsafe { (*node).next = head; }
+            match PENDING.compare_exchange_weak(head, node, Ordering::Release, Ordering::Relaxed) {
+                Ok(_) => break,
+                Err(current) => head = current
+            }
+        }
+    }
+}
+
+impl RawFileSystem {
+    /// Closes the files dropped as `AutoCloseFile`s since the queue was last drained, and
+    /// returns the number of files closed. Files that fail to close, for example because the
+    /// volume has since been unmounted, are discarded. This is called whenever the lock is
+    /// acquired with `FileSystem::lock()`.
+    pub fn process_pending_closes(&self) -> usize {
+        //Taking the whole stack at once leaves droppers free to push onto an empty one.
+        let mut node = PENDING.swap(ptr::null_mut(), Ordering::Acquire);
+        let mut closed = 0;
+        while !node.is_null() {
+            let pending = unsafe { Box::from_raw(node) };
+            node = pending.next;
+            if self.close(pending.file).is_ok() {
+                closed += 1;
+            }
+        }
+        closed
+    }
+}
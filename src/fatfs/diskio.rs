@@ -1,10 +1,27 @@
 mod diskio_bindings;
+/// Timeouts for block device operations.
+#[cfg(feature = "time")]
+pub mod timeout;
+/// Latency histograms for block device operations.
+pub mod latency;
+/// Adapters from `embedded-storage` and `embedded-storage-async` devices.
+#[cfg(feature = "embedded-storage")]
+pub mod storage;
+/// SD card driver over SPI.
+#[cfg(feature = "sd-spi")]
+pub mod sd_spi;
 
 use crate::fatfs::diskio::diskio_bindings::*;
 use crate::fatfs::*;
 use core::ptr;
+use core::cell::Cell;
+use core::future::Future;
+use core::marker::PhantomData;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU32, Ordering};
 use alloc::boxed::Box;
-use embassy_sync::{mutex::Mutex, blocking_mutex::raw::ThreadModeRawMutex};
+use embassy_futures::block_on;
+use embassy_sync::{mutex::Mutex, blocking_mutex::{self, raw::ThreadModeRawMutex}};
 
 #[cfg(feature = "chrono")]
 use chrono::{ Datelike, NaiveDateTime, Timelike };
@@ -13,7 +30,10 @@ pub enum IoctlCommand {
     CtrlSync(()),
     GetSectorCount(DWORD),
     GetSectorSize(WORD),
-    GetBlockSize(DWORD)
+    GetBlockSize(DWORD),
+    /// Informs the device that the data in the given inclusive range of sectors is no
+    /// longer used. Issued by `trim_free()`.
+    CtrlTrim(DWORD, DWORD)
 }
 
 pub enum DiskResult {
@@ -44,15 +64,286 @@ pub trait FatFsDriver: Send + Sync {
     fn get_fattime(&self) -> NaiveDateTime;
 }
 
+/// Asynchronous variant of `FatFsDriver`, for drivers that wait on DMA or interrupt driven
+/// completion futures. Install it with `install_async()`.
+///
+/// The whole sectors transferred by `read_async()` and `write_async()` are read and written
+/// by awaiting the driver, so that other tasks run while the transfer is in flight. FatFs
+/// itself is synchronous, so every other operation, including the partial sectors at either
+/// end of those transfers and all FAT and directory updates, is driven to completion with
+/// `embassy_futures::block_on` from within the FatFs call. The futures must therefore be
+/// completed by interrupts or hardware rather than by other tasks on the same executor,
+/// which do not run until the file system call returns.
+#[allow(async_fn_in_trait)]
+pub trait AsyncFatFsDriver: Send + Sync {
+    async fn disk_status(&self, drive: u8) -> u8;
+    async fn disk_initialize(&mut self, drive: u8) -> u8;
+    async fn disk_read(&mut self, drive: u8, buffer: &mut [u8], sector: u32) -> DiskResult;
+    async fn disk_write(&mut self, drive: u8, buffer: &[u8], sector: u32) -> DiskResult;
+    async fn disk_ioctl(&self, data: &mut IoctlCommand) -> DiskResult;
+
+    #[cfg(feature = "chrono")]
+    fn get_fattime(&self) -> NaiveDateTime;
+}
+
+/// Adapts an `AsyncFatFsDriver` to the blocking `FatFsDriver` interface by driving each
+/// operation to completion. Created by `install_async()`.
+pub struct BlockingAdapter<D: AsyncFatFsDriver>(D);
+
+impl<D: AsyncFatFsDriver> BlockingAdapter<D> {
+    pub fn new(driver: D) -> Self {
+        Self(driver)
+    }
+
+    /// Returns the wrapped driver.
+    pub fn into_inner(self) -> D {
+        self.0
+    }
+}
+
+impl<D: AsyncFatFsDriver> FatFsDriver for BlockingAdapter<D> {
+    fn disk_status(&self, drive: u8) -> u8 {
+        block_on(self.0.disk_status(drive))
+    }
+
+    fn disk_initialize(&mut self, drive: u8) -> u8 {
+        block_on(self.0.disk_initialize(drive))
+    }
+
+    fn disk_read(&mut self, drive: u8, buffer: &mut [u8], sector: u32) -> DiskResult {
+        block_on(self.0.disk_read(drive, buffer, sector))
+    }
+
+    fn disk_write(&mut self, drive: u8, buffer: &[u8], sector: u32) -> DiskResult {
+        block_on(self.0.disk_write(drive, buffer, sector))
+    }
+
+    fn disk_ioctl(&self, data: &mut IoctlCommand) -> DiskResult {
+        block_on(self.0.disk_ioctl(data))
+    }
+
+    #[cfg(feature = "chrono")]
+    fn get_fattime(&self) -> NaiveDateTime {
+        self.0.get_fattime()
+    }
+}
+
 /// Installed driver singleton. A call to `install()` places the driver here.
 /// Only one driver instance is supported.
 static DRIVER: Mutex<ThreadModeRawMutex, Option<Box<dyn FatFsDriver>>> = Mutex::new(None);
 
+type DiskFuture<'a> = Pin<Box<dyn Future<Output = DiskResult> + 'a>>;
+
+/// The sector transfers of a driver installed with `install_async()`, through which
+/// `read_sectors_async()` and `write_sectors_async()` await the driver rather than block on it.
+#[derive(Clone, Copy)]
+struct AsyncTransfers {
+    /// The `DRIVER_ID` of the driver, which the transfers are only used with.
+    id: u32,
+    read: for<'a> fn(&'a mut dyn FatFsDriver, u8, &'a mut [u8], u32) -> DiskFuture<'a>,
+    write: for<'a> fn(&'a mut dyn FatFsDriver, u8, &'a [u8], u32) -> DiskFuture<'a>
+}
+
+static ASYNC_TRANSFERS: blocking_mutex::Mutex<ThreadModeRawMutex, Cell<Option<AsyncTransfers>>> = blocking_mutex::Mutex::new(Cell::new(None));
+
+fn read_with<'a, D: AsyncFatFsDriver + 'static>(driver: &'a mut dyn FatFsDriver, drive: u8, buffer: &'a mut [u8], sector: u32) -> DiskFuture<'a> {
+    //The transfers of `D` are only used while the driver installed with them is in `DRIVER`.
+    let adapter = unsafe { &mut *(driver as *mut dyn FatFsDriver as *mut BlockingAdapter<D>) };
+    Box::pin(adapter.0.disk_read(drive, buffer, sector))
+}
+
+fn write_with<'a, D: AsyncFatFsDriver + 'static>(driver: &'a mut dyn FatFsDriver, drive: u8, buffer: &'a [u8], sector: u32) -> DiskFuture<'a> {
+    let adapter = unsafe { &mut *(driver as *mut dyn FatFsDriver as *mut BlockingAdapter<D>) };
+    Box::pin(adapter.0.disk_write(drive, buffer, sector))
+}
+
+/// Returns the transfers of the installed driver, if it was installed with `install_async()`.
+fn async_transfers() -> Option<AsyncTransfers> {
+    ASYNC_TRANSFERS.lock(Cell::get).filter(|transfers| transfers.id == DRIVER_ID.load(Ordering::Relaxed))
+}
+
+/// Awaits a driver operation against the configured timeout, in the same way as the blocking
+/// operations made by FatFs.
+#[cfg(feature = "time")]
+async fn timed_async(operation: impl Future<Output = DiskResult>) -> DiskResult {
+    timeout::begin();
+    let result = operation.await;
+    if timeout::end() { DiskResult::Error } else { result }
+}
+
+#[cfg(not(feature = "time"))]
+async fn timed_async(operation: impl Future<Output = DiskResult>) -> DiskResult {
+    operation.await
+}
+
+fn disk_result(result: DiskResult) -> Result<(), Error> {
+    match result {
+        DiskResult::Ok => Ok(()),
+        _ => Err(Error::from_result(FRESULT_FR_DISK_ERR))
+    }
+}
+
+/// Reads whole sectors into the buffer, awaiting the installed driver if it was installed with
+/// `install_async()`, and in the same way as `read_sectors()` otherwise.
+pub(crate) async fn read_sectors_async(drive: u8, buffer: &mut [u8], sector: u32) -> Result<(), Error> {
+    let mut installed = DRIVER.lock().await;
+    match (async_transfers(), installed.as_deref_mut()) {
+        (Some(transfers), Some(driver)) => disk_result(timed_async((transfers.read)(driver, drive, buffer, sector)).await),
+        _ => {
+            //The blocking callbacks take the driver themselves.
+            drop(installed);
+            read_sectors(drive, buffer, sector)
+        }
+    }
+}
+
+/// Writes whole sectors from the buffer in the same way as `read_sectors_async()`.
+pub(crate) async fn write_sectors_async(drive: u8, buffer: &[u8], sector: u32) -> Result<(), Error> {
+    let mut installed = DRIVER.lock().await;
+    match (async_transfers(), installed.as_deref_mut()) {
+        (Some(transfers), Some(driver)) => disk_result(timed_async((transfers.write)(driver, drive, buffer, sector)).await),
+        _ => {
+            drop(installed);
+            write_sectors(drive, buffer, sector)
+        }
+    }
+}
+
+/// Reads whole sectors from the installed driver into the buffer, in the same way as FatFs.
+pub(crate) fn read_sectors(drive: u8, buffer: &mut [u8], sector: u32) -> Result<(), Error> {
+    let count = (buffer.len() / SECTOR_SIZE) as UINT;
+    let result = unsafe { disk_read(drive, buffer.as_mut_ptr(), sector, count) };
+    if result == DRESULT_RES_OK {
+        Ok(())
+    } else {
+        Err(Error::from_result(FRESULT_FR_DISK_ERR))
+    }
+}
+
+/// Writes whole sectors from the buffer to the installed driver, in the same way as FatFs.
+pub(crate) fn write_sectors(drive: u8, buffer: &[u8], sector: u32) -> Result<(), Error> {
+    let count = (buffer.len() / SECTOR_SIZE) as UINT;
+    let result = unsafe { disk_write(drive, buffer.as_ptr(), sector, count) };
+    if result == DRESULT_RES_OK {
+        Ok(())
+    } else {
+        Err(Error::from_result(FRESULT_FR_DISK_ERR))
+    }
+}
+
+/// Asks the installed driver to complete any pending writes.
+pub(crate) fn sync_drive(drive: u8) -> Result<(), Error> {
+    let result = unsafe { disk_ioctl(drive, CTRL_SYNC, ptr::null_mut()) };
+    if result == DRESULT_RES_OK {
+        Ok(())
+    } else {
+        Err(Error::from_result(FRESULT_FR_DISK_ERR))
+    }
+}
+
+/// Returns the current time from the installed driver in the packed FAT format.
+pub(crate) fn fattime() -> u32 {
+    unsafe { get_fattime() }
+}
+
+/// Initializes the installed driver if it is not yet, as FatFs does before formatting a drive.
+pub(crate) fn initialize(drive: u8) -> Result<(), Error> {
+    let status = unsafe { disk_initialize(drive) };
+    if status & STA_NOINIT == 0 {
+        Ok(())
+    } else {
+        Err(Error::NotReady)
+    }
+}
+
+/// Returns the number of sectors reported by the installed driver.
+pub(crate) fn sector_count(drive: u8) -> Result<u32, Error> {
+    let mut count: LBA_t = 0;
+    let result = unsafe { disk_ioctl(drive, GET_SECTOR_COUNT, ptr::addr_of_mut!(count).cast()) };
+    if result == DRESULT_RES_OK {
+        Ok(count)
+    } else {
+        Err(Error::from_result(FRESULT_FR_DISK_ERR))
+    }
+}
+
+/// Returns the erase block size in sectors reported by the installed driver.
+pub(crate) fn block_size(drive: u8) -> Result<u32, Error> {
+    let mut size: DWORD = 0;
+    let result = unsafe { disk_ioctl(drive, GET_BLOCK_SIZE, ptr::addr_of_mut!(size).cast()) };
+    if result == DRESULT_RES_OK {
+        Ok(size)
+    } else {
+        Err(Error::from_result(FRESULT_FR_DISK_ERR))
+    }
+}
+
+/// Informs the installed driver that the given inclusive range of sectors is no longer used.
+pub(crate) fn trim_sectors(drive: u8, start: u32, end: u32) -> Result<(), Error> {
+    let mut range: [LBA_t; 2] = [start, end];
+    let result = unsafe { disk_ioctl(drive, CTRL_TRIM, range.as_mut_ptr().cast()) };
+    if result == DRESULT_RES_OK {
+        Ok(())
+    } else {
+        Err(Error::from_result(FRESULT_FR_DISK_ERR))
+    }
+}
+
+/// Identifies the driver currently held in `DRIVER`. Incremented on every call to
+/// `install()` so that a stale `InstalledDriver` handle cannot reclaim a driver that
+/// replaced it.
+static DRIVER_ID: AtomicU32 = AtomicU32::new(0);
+
 /// Installs a driver for the file system. Only one driver can be installed at a time.
 /// The driver must implement the `FatFsDriver` trait.
 /// The driver is placed on the heap using `Box` so that it lives for the lifetime of 
-/// the program.
-pub async fn install(driver: impl FatFsDriver + 'static) {
+/// the program, or until it is reclaimed through the returned `InstalledDriver` handle.
+pub async fn install<D: FatFsDriver + 'static>(driver: D) -> InstalledDriver<D> {
     let boxed_driver = Box::new(driver);
-    (*(DRIVER.lock().await)).replace(boxed_driver);
-}
\ No newline at end of file
+    let mut installed = DRIVER.lock().await;
+    let id = DRIVER_ID.load(Ordering::Relaxed).wrapping_add(1);
+    DRIVER_ID.store(id, Ordering::Relaxed);
+    (*installed).replace(boxed_driver);
+    InstalledDriver { id, _driver: PhantomData }
+}
+
+/// Installs an asynchronous driver for the file system, in the same way as `install()`.
+/// The driver is awaited by `read_async()` and `write_async()`.
+pub async fn install_async<D: AsyncFatFsDriver + 'static>(driver: D) -> InstalledDriver<BlockingAdapter<D>> {
+    let installed = install(BlockingAdapter::new(driver)).await;
+    ASYNC_TRANSFERS.lock(|transfers| transfers.set(Some(AsyncTransfers {
+        id: installed.id,
+        read: read_with::<D>,
+        write: write_with::<D>
+    })));
+    installed
+}
+
+/// Handle returned by `install()`. It may be used to remove the driver from the file
+/// system again, for example to reclaim a shared SPI bus or to hot-swap drivers.
+/// The volume should be unmounted before the driver is removed.
+pub struct InstalledDriver<D: FatFsDriver + 'static> {
+    id: u32,
+    _driver: PhantomData<D>
+}
+
+impl<D: FatFsDriver + 'static> InstalledDriver<D> {
+    /// Removes the driver from the file system and returns it.
+    /// Returns `None` if the driver has since been replaced by another call to `install()`.
+    pub async fn take(self) -> Option<D> {
+        let mut installed = DRIVER.lock().await;
+        if DRIVER_ID.load(Ordering::Relaxed) != self.id {
+            return None
+        }
+        let boxed_driver = (*installed).take()?;
+        //The ID matches, so the installed driver is the `D` placed there by `install()`.
+        let raw = Box::into_raw(boxed_driver) as *mut D;
+        Some(*unsafe { Box::from_raw(raw) })
+    }
+
+    /// Removes the driver from the file system and drops it.
+    /// Has no effect if the driver has since been replaced by another call to `install()`.
+    pub async fn uninstall(self) {
+        self.take().await;
+    }
+}
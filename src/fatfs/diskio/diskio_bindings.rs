@@ -1,6 +1,10 @@
-use embassy_futures::block_on;
 use super::*;
 
+//The callbacks take the installed driver with `try_lock()` rather than waiting for it. FatFs
+//calls run to completion in thread mode, and `install()` and `InstalledDriver::take()` never
+//await while holding the driver, so the lock is free whenever FatFs calls in. Should that
+//ever not be the case, the operation fails instead of blocking the executor.
+
 pub type DSTATUS = BYTE;
 pub const STA_NOINIT: DSTATUS =	0x01;	/* Drive not initialized */
 pub const STA_NODISK: DSTATUS =	0x02;	/* No medium in the drive */
@@ -16,15 +20,29 @@ pub const DRESULT_RES_NOTRDY: DRESULT = 3;
 pub const DRESULT_RES_PARERR: DRESULT = 4;
 
 /* Generic command (Used by FatFs) */
-const CTRL_SYNC: BYTE = 0;	/* Complete pending write process (needed at FF_FS_READONLY == 0) */
-const GET_SECTOR_COUNT: BYTE = 1;	/* Get media size (needed at FF_USE_MKFS == 1) */
+pub(super) const CTRL_SYNC: BYTE = 0;	/* Complete pending write process (needed at FF_FS_READONLY == 0) */
+pub(super) const GET_SECTOR_COUNT: BYTE = 1;	/* Get media size (needed at FF_USE_MKFS == 1) */
 const GET_SECTOR_SIZE: BYTE = 2;	/* Get sector size (needed at FF_MAX_SS != FF_MIN_SS) */
-const GET_BLOCK_SIZE: BYTE = 3;	/* Get erase block size (needed at FF_USE_MKFS == 1) */
-const CTRL_TRIM: BYTE = 4;	/* Inform device that the data on the block of sectors is no longer used (needed at FF_USE_TRIM == 1) */
+pub(super) const GET_BLOCK_SIZE: BYTE = 3;	/* Get erase block size (needed at FF_USE_MKFS == 1) */
+pub(super) const CTRL_TRIM: BYTE = 4;	/* Inform device that the data on the block of sectors is no longer used (needed at FF_USE_TRIM == 1) */
+
+/// Runs a driver operation against the configured timeout. If the operation overruns
+/// its deadline the `expired` value is returned to FatFs in place of the driver's result.
+#[cfg(feature = "time")]
+fn timed<T>(expired: T, operation: impl FnOnce() -> T) -> T {
+    timeout::begin();
+    let result = operation();
+    if timeout::end() { expired } else { result }
+}
+
+#[cfg(not(feature = "time"))]
+fn timed<T>(_expired: T, operation: impl FnOnce() -> T) -> T {
+    operation()
+}
 
 #[no_mangle]
 pub unsafe extern fn disk_status(pdrv: BYTE) -> DSTATUS {
-    if let Some(driver) = &*block_on(DRIVER.lock()) {
+    if let Some(Some(driver)) = DRIVER.try_lock().ok().as_deref() {
         driver.disk_status(pdrv)
     } else {
         STA_NOINIT
@@ -33,8 +51,8 @@ pub unsafe extern fn disk_status(pdrv: BYTE) -> DSTATUS {
 
 #[no_mangle]
 pub unsafe extern fn disk_initialize(pdrv: BYTE) -> DSTATUS {
-    if let Some(driver) = &mut *block_on(DRIVER.lock()) {
-        driver.disk_initialize(pdrv)
+    if let Some(Some(driver)) = DRIVER.try_lock().ok().as_deref_mut() {
+        timed(STA_NOINIT, || driver.disk_initialize(pdrv))
     } else {
         STA_NOINIT
     }
@@ -42,9 +60,12 @@ pub unsafe extern fn disk_initialize(pdrv: BYTE) -> DSTATUS {
 
 #[no_mangle]
 pub unsafe extern fn disk_read(pdrv: BYTE, buff: *mut BYTE, sector: LBA_t, count: UINT) -> DRESULT {
-    if let Some(driver) = &mut *block_on(DRIVER.lock()) {
-        let buffer = &mut *ptr::slice_from_raw_parts_mut(buff, (count as usize) * SECTOR_SIZE);
-        driver.disk_read(pdrv, buffer, sector) as DRESULT
+    if let Some(Some(driver)) = DRIVER.try_lock().ok().as_deref_mut() {
+        let Some(len) = (count as usize).checked_mul(SECTOR_SIZE) else {
+            return DRESULT_RES_PARERR
+        };
+        let buffer = &mut *ptr::slice_from_raw_parts_mut(buff, len);
+        timed(DRESULT_RES_ERROR, || driver.disk_read(pdrv, buffer, sector) as DRESULT)
     } else {
         DRESULT_RES_ERROR
     }
@@ -52,9 +73,12 @@ pub unsafe extern fn disk_read(pdrv: BYTE, buff: *mut BYTE, sector: LBA_t, count
 
 #[no_mangle]
 pub unsafe extern fn disk_write(pdrv: BYTE, buff: *const BYTE, sector: LBA_t, count: UINT) -> DRESULT {
-    if let Some(driver) = &mut *block_on(DRIVER.lock()) {
-        let buffer = &*ptr::slice_from_raw_parts(buff, (count as usize) * SECTOR_SIZE);
-        driver.disk_write(pdrv, buffer, sector) as DRESULT
+    if let Some(Some(driver)) = DRIVER.try_lock().ok().as_deref_mut() {
+        let Some(len) = (count as usize).checked_mul(SECTOR_SIZE) else {
+            return DRESULT_RES_PARERR
+        };
+        let buffer = &*ptr::slice_from_raw_parts(buff, len);
+        timed(DRESULT_RES_ERROR, || driver.disk_write(pdrv, buffer, sector) as DRESULT)
     } else {
         DRESULT_RES_ERROR
     }
@@ -62,16 +86,22 @@ pub unsafe extern fn disk_write(pdrv: BYTE, buff: *const BYTE, sector: LBA_t, co
 
 #[no_mangle]
 pub unsafe extern fn disk_ioctl(_lun: BYTE, cmd: BYTE, buff: *mut cty::c_void) -> DRESULT {
-    if let Some(driver) = &*block_on(DRIVER.lock()) {
+    if let Some(Some(driver)) = DRIVER.try_lock().ok().as_deref() {
         let mut data = match cmd {
             CTRL_SYNC => IoctlCommand::CtrlSync(()),
             GET_SECTOR_COUNT => IoctlCommand::GetSectorCount(0),
             GET_SECTOR_SIZE => IoctlCommand::GetSectorSize(0),
             GET_BLOCK_SIZE => IoctlCommand::GetBlockSize(0),
-            CTRL_TRIM => panic!("CTRL_TRIM is not implemented."),
-            _ => panic!("An invalid FatFS IOCTL command was received.")
+            CTRL_TRIM if !buff.is_null() => {
+                let range = buff.cast::<LBA_t>();
+                IoctlCommand::CtrlTrim(range.read_unaligned(), range.add(1).read_unaligned())
+            },
+            _ => return DRESULT_RES_PARERR
         };
-        driver.disk_ioctl(&mut data);
+        let result = timed(DRESULT_RES_ERROR, || driver.disk_ioctl(&mut data) as DRESULT);
+        if result != DRESULT_RES_OK {
+            return result
+        }
         match data {
             IoctlCommand::GetBlockSize(value) => buff.copy_from(ptr::addr_of!(value).cast(), 4),
             IoctlCommand::GetSectorSize(value) => buff.copy_from(ptr::addr_of!(value).cast(), 2),
@@ -88,15 +118,16 @@ pub unsafe extern fn disk_ioctl(_lun: BYTE, cmd: BYTE, buff: *mut cty::c_void) -
 pub unsafe extern fn get_fattime() -> DWORD {
     
     #[cfg(feature = "chrono")]
-    if let Some(driver) = &*block_on(DRIVER.lock()) {
+    if let Some(Some(driver)) = DRIVER.try_lock().ok().as_deref() {
         let timestamp = driver.get_fattime();
-        let year = timestamp.year() as u32;
+        //Times outside of the years 1980 to 2107 that FAT can represent are clamped.
+        let year = (timestamp.year() - 1980).clamp(0, 127) as u32;
         let month = timestamp.month();
         let day = timestamp.day();
         let hour = timestamp.hour();
         let minute = timestamp.minute();
-        let second = timestamp.second();
-        let result = (year - 80) << 25 | month << 21 | day << 16 | hour << 11 | minute << 5 | second << 1;
+        let second = timestamp.second().min(59);
+        let result = year << 25 | month << 21 | day << 16 | hour << 11 | minute << 5 | (second / 2);
         return result
     } else {
         return 0
@@ -0,0 +1,154 @@
+//! Latency histograms for block device operations.
+//!
+//! Wrap a driver in `Instrumented` before installing it to record how long each read,
+//! write and sync takes. Rising latencies are an early sign of a worn or counterfeit
+//! SD card, so the histograms may be reported with other telemetry from the field.
+//!
+//! ```ignore
+//! fatfs::diskio::install(Instrumented::new(driver)).await;
+//! //...
+//! let stats = latency::stats();
+//! defmt::info!("slowest write: {}us", stats.write.max_us);
+//! ```
+
+use core::cell::Cell;
+use embassy_sync::blocking_mutex::{Mutex, raw::ThreadModeRawMutex};
+use super::{DiskResult, FatFsDriver, IoctlCommand};
+
+#[cfg(feature = "chrono")]
+use chrono::NaiveDateTime;
+
+/// Number of buckets in each histogram.
+pub const BUCKETS: usize = 16;
+
+/// Latencies of one type of operation. Bucket `0` counts operations that took less than
+/// 2us, bucket `i` those that took from `2^i` up to `2^(i + 1)` microseconds, and the
+/// last bucket all longer operations.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Histogram {
+    pub buckets: [u32; BUCKETS],
+    /// Number of operations recorded.
+    pub count: u32,
+    /// Number of operations the driver reported as failed.
+    pub errors: u32,
+    pub max_us: u32,
+    pub total_us: u64
+}
+
+impl Histogram {
+    const EMPTY: Self = Self { buckets: [0; BUCKETS], count: 0, errors: 0, max_us: 0, total_us: 0 };
+
+    fn record(&mut self, micros: u32, ok: bool) {
+        let bucket = ((31 - (micros | 1).leading_zeros()) as usize).min(BUCKETS - 1);
+        self.buckets[bucket] = self.buckets[bucket].saturating_add(1);
+        self.count = self.count.saturating_add(1);
+        if !ok {
+            self.errors = self.errors.saturating_add(1);
+        }
+        self.max_us = self.max_us.max(micros);
+        self.total_us = self.total_us.saturating_add(micros as u64);
+    }
+
+    /// Returns the mean latency in microseconds, or 0 if nothing has been recorded.
+    pub fn mean_us(&self) -> u32 {
+        if self.count == 0 { 0 } else { (self.total_us / self.count as u64) as u32 }
+    }
+}
+
+/// Latency histograms per type of operation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LatencyStats {
+    pub read: Histogram,
+    pub write: Histogram,
+    pub sync: Histogram
+}
+
+static STATS: Mutex<ThreadModeRawMutex, Cell<LatencyStats>> = Mutex::new(Cell::new(
+    LatencyStats { read: Histogram::EMPTY, write: Histogram::EMPTY, sync: Histogram::EMPTY }
+));
+
+fn update(f: impl FnOnce(&mut LatencyStats)) {
+    STATS.lock(|cell| {
+        let mut stats = cell.get();
+        f(&mut stats);
+        cell.set(stats);
+    })
+}
+
+/// Returns the latencies recorded by the installed `Instrumented` driver.
+pub fn stats() -> LatencyStats {
+    STATS.lock(|cell| cell.get())
+}
+
+/// Clears the recorded latencies.
+pub fn reset() {
+    update(|stats| *stats = LatencyStats::default());
+}
+
+/// Runs a driver operation and records its latency in the selected histogram.
+fn measure(clock: fn() -> u64, select: fn(&mut LatencyStats) -> &mut Histogram, operation: impl FnOnce() -> DiskResult) -> DiskResult {
+    let start = clock();
+    let result = operation();
+    let micros = clock().saturating_sub(start).min(u32::MAX as u64) as u32;
+    update(|stats| select(stats).record(micros, matches!(result, DiskResult::Ok)));
+    result
+}
+
+/// Driver wrapper that records the latency of every read, write and sync of the wrapped
+/// driver. The latencies are retrieved with `stats()`.
+pub struct Instrumented<D: FatFsDriver> {
+    driver: D,
+    clock: fn() -> u64
+}
+
+impl<D: FatFsDriver> Instrumented<D> {
+    /// Wraps the driver, timing operations with `embassy_time`.
+    #[cfg(feature = "time")]
+    pub fn new(driver: D) -> Self {
+        Self::with_clock(driver, || embassy_time::Instant::now().as_micros())
+    }
+
+    /// Wraps the driver, timing operations with the given clock, which returns a
+    /// monotonic time in microseconds.
+    pub fn with_clock(driver: D, clock: fn() -> u64) -> Self {
+        Self { driver, clock }
+    }
+
+    /// Returns the wrapped driver.
+    pub fn into_inner(self) -> D {
+        self.driver
+    }
+}
+
+impl<D: FatFsDriver> FatFsDriver for Instrumented<D> {
+    fn disk_status(&self, drive: u8) -> u8 {
+        self.driver.disk_status(drive)
+    }
+
+    fn disk_initialize(&mut self, drive: u8) -> u8 {
+        self.driver.disk_initialize(drive)
+    }
+
+    fn disk_read(&mut self, drive: u8, buffer: &mut [u8], sector: u32) -> DiskResult {
+        let driver = &mut self.driver;
+        measure(self.clock, |stats| &mut stats.read, || driver.disk_read(drive, buffer, sector))
+    }
+
+    fn disk_write(&mut self, drive: u8, buffer: &[u8], sector: u32) -> DiskResult {
+        let driver = &mut self.driver;
+        measure(self.clock, |stats| &mut stats.write, || driver.disk_write(drive, buffer, sector))
+    }
+
+    fn disk_ioctl(&self, data: &mut IoctlCommand) -> DiskResult {
+        if let IoctlCommand::CtrlSync(_) = data {
+            measure(self.clock, |stats| &mut stats.sync, || self.driver.disk_ioctl(data))
+        } else {
+            self.driver.disk_ioctl(data)
+        }
+    }
+
+    #[cfg(feature = "chrono")]
+    fn get_fattime(&self) -> NaiveDateTime {
+        self.driver.get_fattime()
+    }
+}
@@ -0,0 +1,369 @@
+//! SD card driver over an `embedded-hal` SPI bus and chip select pin. Requires the `sd-spi`
+//! feature.
+//!
+//! The SPI bus must run at 400kHz or less until the card has been initialized by FatFs,
+//! which happens when the volume is mounted. The clock may then be raised through
+//! `spi_mut()`, up to 25MHz for most cards.
+//!
+//! ```ignore
+//! let driver = SdSpi::new(spi_bus, cs_pin, delay);
+//! fatfs::diskio::install(driver).await;
+//! locked_fs.mount()?;
+//! ```
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::OutputPin;
+use embedded_hal::spi::SpiBus;
+use super::{DiskResult, DiskStatus, FatFsDriver, IoctlCommand};
+use super::diskio_bindings::SECTOR_SIZE;
+
+#[cfg(feature = "chrono")]
+use chrono::NaiveDateTime;
+
+const CMD0: u8 = 0;     //GO_IDLE_STATE
+const CMD8: u8 = 8;     //SEND_IF_COND
+const CMD9: u8 = 9;     //SEND_CSD
+const CMD12: u8 = 12;   //STOP_TRANSMISSION
+const CMD16: u8 = 16;   //SET_BLOCKLEN
+const CMD17: u8 = 17;   //READ_SINGLE_BLOCK
+const CMD18: u8 = 18;   //READ_MULTIPLE_BLOCK
+const CMD24: u8 = 24;   //WRITE_BLOCK
+const CMD25: u8 = 25;   //WRITE_MULTIPLE_BLOCK
+const CMD55: u8 = 55;   //APP_CMD
+const CMD58: u8 = 58;   //READ_OCR
+const ACMD41: u8 = 41;  //SD_SEND_OP_COND
+
+const R1_IDLE: u8 = 0x01;
+const TOKEN_START_BLOCK: u8 = 0xFE;
+const TOKEN_START_MULTIPLE: u8 = 0xFC;
+const TOKEN_STOP_MULTIPLE: u8 = 0xFD;
+const DATA_ACCEPTED: u8 = 0x05;
+/// Host capacity support bit of ACMD41, and card capacity status bit of the OCR.
+const HIGH_CAPACITY: u32 = 1 << 30;
+
+/// Number of bytes to wait for the card to become ready or to start a data block.
+const WAIT_BYTES: u32 = 50_000;
+/// Number of milliseconds to wait for the card to leave the idle state.
+const INIT_TIMEOUT_MS: u32 = 1000;
+
+/// An error communicating with the card. The detail is not reported to FatFs.
+struct CardError;
+
+/// A FatFs driver for SD and SDHC/SDXC cards in SPI mode, on a bus shared with no other
+/// device while the driver is installed. The card is initialized with `CMD0`, `CMD8` and
+/// `ACMD41`, and its capacity read from the CSD register. Transfers of more than one sector
+/// use the multiple block read and write commands. Each write waits until the card has
+/// finished programming, so syncing requires no further action. Trimming is not supported,
+/// as `disk_ioctl()` cannot reach the bus to send the erase commands, so `CtrlTrim` fails
+/// with `DiskResult::ParameterError`.
+pub struct SdSpi<SPI: SpiBus, CS: OutputPin, D: DelayNs> {
+    spi: SPI,
+    cs: CS,
+    delay: D,
+    initialized: bool,
+    /// Set for SDHC/SDXC cards, which are addressed by block rather than by byte.
+    block_addressing: bool,
+    sector_count: u32,
+    #[cfg(feature = "chrono")]
+    clock: fn() -> NaiveDateTime
+}
+
+impl<SPI: SpiBus, CS: OutputPin, D: DelayNs> SdSpi<SPI, CS, D> {
+    /// Creates the driver. Files are timestamped with the earliest time FAT can represent
+    /// until a clock is supplied with `with_clock()`.
+    pub fn new(spi: SPI, cs: CS, delay: D) -> Self {
+        Self {
+            spi,
+            cs,
+            delay,
+            initialized: false,
+            block_addressing: false,
+            sector_count: 0,
+            #[cfg(feature = "chrono")]
+            clock: NaiveDateTime::default
+        }
+    }
+
+    /// Sets the source of the time used to timestamp files.
+    #[cfg(feature = "chrono")]
+    pub fn with_clock(mut self, clock: fn() -> NaiveDateTime) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Returns the SPI bus, for example to raise its clock once the card is initialized.
+    pub fn spi_mut(&mut self) -> &mut SPI {
+        &mut self.spi
+    }
+
+    /// Returns the bus, chip select pin and delay.
+    pub fn release(self) -> (SPI, CS, D) {
+        (self.spi, self.cs, self.delay)
+    }
+
+    fn transfer_byte(&mut self, byte: u8) -> Result<u8, CardError> {
+        let mut buffer = [byte];
+        self.spi.transfer_in_place(&mut buffer).map_err(|_| CardError)?;
+        Ok(buffer[0])
+    }
+
+    /// Clocks in bytes, sending `0xFF` as the card requires.
+    fn receive(&mut self, buffer: &mut [u8]) -> Result<(), CardError> {
+        buffer.fill(0xFF);
+        self.spi.transfer_in_place(buffer).map_err(|_| CardError)
+    }
+
+    fn select(&mut self) -> Result<(), CardError> {
+        self.cs.set_low().map_err(|_| CardError)
+    }
+
+    /// Releases the card, clocking out one more byte so that it releases the data line.
+    fn deselect(&mut self) {
+        let _ = self.spi.flush();
+        let _ = self.cs.set_high();
+        let _ = self.transfer_byte(0xFF);
+    }
+
+    /// Waits until the card is no longer busy.
+    fn wait_ready(&mut self) -> Result<(), CardError> {
+        for _ in 0..WAIT_BYTES {
+            if self.transfer_byte(0xFF)? == 0xFF {
+                return Ok(())
+            }
+        }
+        Err(CardError)
+    }
+
+    /// Sends a command to the selected card and returns its R1 response.
+    fn command(&mut self, command: u8, argument: u32) -> Result<u8, CardError> {
+        if command != CMD0 {
+            self.wait_ready()?;
+        }
+        //The CRC is only checked for CMD0 and CMD8 while the card is in SPI mode.
+        let crc = match command {
+            CMD0 => 0x95,
+            CMD8 => 0x87,
+            _ => 0x01
+        };
+        let [a, b, c, d] = argument.to_be_bytes();
+        self.spi.write(&[0x40 | command, a, b, c, d, crc]).map_err(|_| CardError)?;
+        if command == CMD12 {
+            //Skip the stuff byte that follows a stop command.
+            self.transfer_byte(0xFF)?;
+        }
+        for _ in 0..10 {
+            let response = self.transfer_byte(0xFF)?;
+            if response & 0x80 == 0 {
+                return Ok(response)
+            }
+        }
+        Err(CardError)
+    }
+
+    fn app_command(&mut self, command: u8, argument: u32) -> Result<u8, CardError> {
+        self.command(CMD55, 0)?;
+        self.command(command, argument)
+    }
+
+    /// Receives a data block following a read command.
+    fn receive_block(&mut self, buffer: &mut [u8]) -> Result<(), CardError> {
+        let mut token = 0xFF;
+        for _ in 0..WAIT_BYTES {
+            token = self.transfer_byte(0xFF)?;
+            if token != 0xFF {
+                break
+            }
+        }
+        if token != TOKEN_START_BLOCK {
+            return Err(CardError)
+        }
+        self.receive(buffer)?;
+        let mut crc = [0; 2];
+        self.receive(&mut crc)
+    }
+
+    /// Sends a data block following a write command and waits for it to be programmed.
+    fn send_block(&mut self, token: u8, buffer: &[u8]) -> Result<(), CardError> {
+        self.wait_ready()?;
+        self.spi.write(&[token]).map_err(|_| CardError)?;
+        self.spi.write(buffer).map_err(|_| CardError)?;
+        self.spi.write(&[0xFF, 0xFF]).map_err(|_| CardError)?;
+        if self.transfer_byte(0xFF)? & 0x1F != DATA_ACCEPTED {
+            return Err(CardError)
+        }
+        self.wait_ready()
+    }
+
+    fn initialize(&mut self) -> Result<(), CardError> {
+        //At least 74 clocks with the card deselected switch it to SPI mode.
+        let _ = self.cs.set_high();
+        for _ in 0..10 {
+            self.transfer_byte(0xFF)?;
+        }
+        self.select()?;
+        if self.command(CMD0, 0)? != R1_IDLE {
+            return Err(CardError)
+        }
+        //Cards implementing version 2 of the specification echo the check pattern of CMD8.
+        let version2 = if self.command(CMD8, 0x1AA)? == R1_IDLE {
+            let mut r7 = [0; 4];
+            self.receive(&mut r7)?;
+            if r7[2] & 0x0F != 0x01 || r7[3] != 0xAA {
+                return Err(CardError)
+            }
+            true
+        } else {
+            false
+        };
+        let argument = if version2 { HIGH_CAPACITY } else { 0 };
+        let mut ready = false;
+        for _ in 0..INIT_TIMEOUT_MS {
+            if self.app_command(ACMD41, argument)? == 0 {
+                ready = true;
+                break
+            }
+            self.delay.delay_ms(1);
+        }
+        if !ready {
+            return Err(CardError)
+        }
+        self.block_addressing = false;
+        if version2 {
+            if self.command(CMD58, 0)? != 0 {
+                return Err(CardError)
+            }
+            let mut ocr = [0; 4];
+            self.receive(&mut ocr)?;
+            self.block_addressing = u32::from_be_bytes(ocr) & HIGH_CAPACITY != 0;
+        }
+        if !self.block_addressing && self.command(CMD16, SECTOR_SIZE as u32)? != 0 {
+            return Err(CardError)
+        }
+        if self.command(CMD9, 0)? != 0 {
+            return Err(CardError)
+        }
+        let mut csd = [0; 16];
+        self.receive_block(&mut csd)?;
+        self.sector_count = csd_sector_count(&csd).ok_or(CardError)?;
+        Ok(())
+    }
+
+    /// Returns the address of the sector in the units the card expects.
+    fn address(&self, sector: u32) -> u32 {
+        if self.block_addressing { sector } else { sector.wrapping_mul(SECTOR_SIZE as u32) }
+    }
+
+    fn read(&mut self, buffer: &mut [u8], sector: u32) -> Result<(), CardError> {
+        let address = self.address(sector);
+        if buffer.len() == SECTOR_SIZE {
+            if self.command(CMD17, address)? != 0 {
+                return Err(CardError)
+            }
+            return self.receive_block(buffer)
+        }
+        if self.command(CMD18, address)? != 0 {
+            return Err(CardError)
+        }
+        let result = buffer.chunks_mut(SECTOR_SIZE).try_for_each(|block| self.receive_block(block));
+        let stopped = self.command(CMD12, 0);
+        result?;
+        stopped.map(|_| ())
+    }
+
+    fn write(&mut self, buffer: &[u8], sector: u32) -> Result<(), CardError> {
+        let address = self.address(sector);
+        if buffer.len() == SECTOR_SIZE {
+            if self.command(CMD24, address)? != 0 {
+                return Err(CardError)
+            }
+            return self.send_block(TOKEN_START_BLOCK, buffer)
+        }
+        if self.command(CMD25, address)? != 0 {
+            return Err(CardError)
+        }
+        let result = buffer.chunks(SECTOR_SIZE).try_for_each(|block| self.send_block(TOKEN_START_MULTIPLE, block));
+        self.wait_ready()?;
+        self.spi.write(&[TOKEN_STOP_MULTIPLE]).map_err(|_| CardError)?;
+        self.transfer_byte(0xFF)?;
+        self.wait_ready()?;
+        result
+    }
+
+    /// Runs a transfer with the card selected.
+    fn transaction(&mut self, transfer: impl FnOnce(&mut Self) -> Result<(), CardError>) -> DiskResult {
+        if !self.initialized {
+            return DiskResult::NotReady
+        }
+        let result = self.select().and_then(|_| transfer(self));
+        self.deselect();
+        match result {
+            Ok(()) => DiskResult::Ok,
+            Err(CardError) => DiskResult::Error
+        }
+    }
+}
+
+/// Returns the number of 512 byte sectors described by a CSD register.
+fn csd_sector_count(csd: &[u8; 16]) -> Option<u32> {
+    match csd[0] >> 6 {
+        //CSD version 1.0, standard capacity cards.
+        0 => {
+            let read_bl_len = (csd[5] & 0x0F) as u32;
+            let c_size = ((csd[6] & 0x03) as u32) << 10 | (csd[7] as u32) << 2 | (csd[8] >> 6) as u32;
+            let c_size_mult = ((csd[9] & 0x03) as u32) << 1 | (csd[10] >> 7) as u32;
+            let shift = (c_size_mult + 2 + read_bl_len).checked_sub(9)?;
+            (c_size + 1).checked_shl(shift)
+        },
+        //CSD version 2.0, high and extended capacity cards.
+        1 => {
+            let c_size = ((csd[7] & 0x3F) as u32) << 16 | (csd[8] as u32) << 8 | csd[9] as u32;
+            (c_size + 1).checked_mul(1024)
+        },
+        _ => None
+    }
+}
+
+impl<SPI, CS, D> FatFsDriver for SdSpi<SPI, CS, D>
+where
+    SPI: SpiBus + Send + Sync,
+    CS: OutputPin + Send + Sync,
+    D: DelayNs + Send + Sync
+{
+    fn disk_status(&self, _drive: u8) -> u8 {
+        if self.initialized { DiskStatus::Ok as u8 } else { DiskStatus::NotInitialized as u8 }
+    }
+
+    fn disk_initialize(&mut self, _drive: u8) -> u8 {
+        let result = self.initialize();
+        self.deselect();
+        self.initialized = result.is_ok();
+        self.disk_status(0)
+    }
+
+    fn disk_read(&mut self, _drive: u8, buffer: &mut [u8], sector: u32) -> DiskResult {
+        self.transaction(|card| card.read(buffer, sector))
+    }
+
+    fn disk_write(&mut self, _drive: u8, buffer: &[u8], sector: u32) -> DiskResult {
+        self.transaction(|card| card.write(buffer, sector))
+    }
+
+    fn disk_ioctl(&self, data: &mut IoctlCommand) -> DiskResult {
+        if !self.initialized {
+            return DiskResult::NotReady
+        }
+        match data {
+            IoctlCommand::GetSectorCount(count) => *count = self.sector_count,
+            IoctlCommand::GetSectorSize(size) => *size = SECTOR_SIZE as u16,
+            IoctlCommand::GetBlockSize(size) => *size = 1,
+            IoctlCommand::CtrlSync(_) => (),
+            IoctlCommand::CtrlTrim(_, _) => return DiskResult::ParameterError
+        }
+        DiskResult::Ok
+    }
+
+    #[cfg(feature = "chrono")]
+    fn get_fattime(&self) -> NaiveDateTime {
+        (self.clock)()
+    }
+}
@@ -0,0 +1,234 @@
+//! Adapters from `embedded-storage` devices to `FatFsDriver`, and from `embedded-storage-async`
+//! NOR flash to `AsyncFatFsDriver`. Require the `embedded-storage` and `embedded-storage-async`
+//! features respectively.
+//!
+//! The adapters are built on the `Storage` and `NorFlash` traits rather than a block device
+//! trait, because `embedded-storage` 0.3, the release this crate depends on, has no `block`
+//! module.
+//!
+//! ```ignore
+//! let flash = ExternalFlash::new(spi);
+//! fatfs::diskio::install(StorageDriver::new(flash)).await;
+//! ```
+
+use embedded_storage::Storage;
+use super::{DiskResult, DiskStatus, FatFsDriver, IoctlCommand};
+use super::diskio_bindings::SECTOR_SIZE;
+
+#[cfg(feature = "embedded-storage-async")]
+use embedded_storage_async::nor_flash::NorFlash;
+#[cfg(feature = "embedded-storage-async")]
+use super::AsyncFatFsDriver;
+#[cfg(feature = "embedded-storage-async")]
+use crate::fatfs::alloc::{self, vec::Vec};
+
+#[cfg(feature = "chrono")]
+use chrono::NaiveDateTime;
+
+/// Exposes a byte addressed `embedded_storage::Storage` device as a block device of
+/// 512 byte sectors. Sector `n` is stored at byte offset `n * 512`, and the sector count
+/// is derived from the capacity of the device. Any erasing is left to the `Storage`
+/// implementation.
+pub struct StorageDriver<S: Storage> {
+    storage: S,
+    #[cfg(feature = "chrono")]
+    clock: fn() -> NaiveDateTime
+}
+
+impl<S: Storage> StorageDriver<S> {
+    /// Wraps the device. Files are timestamped with the earliest time FAT can represent
+    /// until a clock is supplied with `with_clock()`.
+    pub fn new(storage: S) -> Self {
+        Self {
+            storage,
+            #[cfg(feature = "chrono")]
+            clock: NaiveDateTime::default
+        }
+    }
+
+    /// Sets the source of the time used to timestamp files.
+    #[cfg(feature = "chrono")]
+    pub fn with_clock(mut self, clock: fn() -> NaiveDateTime) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Returns the wrapped device.
+    pub fn into_inner(self) -> S {
+        self.storage
+    }
+
+    /// Returns the byte offset of the sector, if the whole transfer fits on the device.
+    fn offset(&self, sector: u32, len: usize) -> Option<u32> {
+        let offset = sector.checked_mul(SECTOR_SIZE as u32)?;
+        let end = (offset as usize).checked_add(len)?;
+        if end <= self.storage.capacity() { Some(offset) } else { None }
+    }
+}
+
+impl<S: Storage + Send + Sync> FatFsDriver for StorageDriver<S> {
+    fn disk_status(&self, _drive: u8) -> u8 {
+        DiskStatus::Ok as u8
+    }
+
+    fn disk_initialize(&mut self, _drive: u8) -> u8 {
+        DiskStatus::Ok as u8
+    }
+
+    fn disk_read(&mut self, _drive: u8, buffer: &mut [u8], sector: u32) -> DiskResult {
+        let Some(offset) = self.offset(sector, buffer.len()) else {
+            return DiskResult::ParameterError
+        };
+        match self.storage.read(offset, buffer) {
+            Ok(()) => DiskResult::Ok,
+            Err(_) => DiskResult::Error
+        }
+    }
+
+    fn disk_write(&mut self, _drive: u8, buffer: &[u8], sector: u32) -> DiskResult {
+        let Some(offset) = self.offset(sector, buffer.len()) else {
+            return DiskResult::ParameterError
+        };
+        match self.storage.write(offset, buffer) {
+            Ok(()) => DiskResult::Ok,
+            Err(_) => DiskResult::Error
+        }
+    }
+
+    fn disk_ioctl(&self, data: &mut IoctlCommand) -> DiskResult {
+        match data {
+            IoctlCommand::GetSectorCount(count) => *count = (self.storage.capacity() / SECTOR_SIZE) as u32,
+            IoctlCommand::GetSectorSize(size) => *size = SECTOR_SIZE as u16,
+            IoctlCommand::GetBlockSize(size) => *size = 1,
+            IoctlCommand::CtrlSync(_) | IoctlCommand::CtrlTrim(_, _) => ()
+        }
+        DiskResult::Ok
+    }
+
+    #[cfg(feature = "chrono")]
+    fn get_fattime(&self) -> NaiveDateTime {
+        (self.clock)()
+    }
+}
+
+/// Exposes an `embedded_storage_async::nor_flash::NorFlash` device as an `AsyncFatFsDriver`
+/// of 512 byte sectors, to be installed with `install_async()`. Flash is erased in blocks of
+/// `ERASE_SIZE` bytes, so a write that covers part of a block reads the block, merges the
+/// sectors into it, and erases and writes it back whole. The block size is reported to FatFs,
+/// which aligns the data area to it when formatting. `ERASE_SIZE` must be a power of two, as it
+/// is on NOR flash devices, and any trailing partial block of the device is left unused.
+#[cfg(feature = "embedded-storage-async")]
+pub struct FlashDriver<F: NorFlash> {
+    flash: F,
+    //Holds a block while it is rewritten, of `ERASE_SIZE` or a sector if that is larger.
+    block: Vec<u8>,
+    #[cfg(feature = "chrono")]
+    clock: fn() -> NaiveDateTime
+}
+
+#[cfg(feature = "embedded-storage-async")]
+impl<F: NorFlash> FlashDriver<F> {
+    /// Wraps the device. Files are timestamped with the earliest time FAT can represent
+    /// until a clock is supplied with `with_clock()`.
+    pub fn new(flash: F) -> Self {
+        debug_assert!(F::ERASE_SIZE.is_power_of_two());
+        Self {
+            flash,
+            block: alloc::vec![0; F::ERASE_SIZE.max(SECTOR_SIZE)],
+            #[cfg(feature = "chrono")]
+            clock: NaiveDateTime::default
+        }
+    }
+
+    /// Sets the source of the time used to timestamp files.
+    #[cfg(feature = "chrono")]
+    pub fn with_clock(mut self, clock: fn() -> NaiveDateTime) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Returns the wrapped device.
+    pub fn into_inner(self) -> F {
+        self.flash
+    }
+
+    /// Returns the number of bytes of the device in whole blocks.
+    fn capacity(&self) -> usize {
+        self.flash.capacity() / self.block.len() * self.block.len()
+    }
+
+    /// Returns the byte offset of the sector, if the whole transfer fits on the device.
+    fn offset(&self, sector: u32, len: usize) -> Option<usize> {
+        let offset = (sector as usize).checked_mul(SECTOR_SIZE)?;
+        let end = offset.checked_add(len)?;
+        if end <= self.capacity() { Some(offset) } else { None }
+    }
+}
+
+#[cfg(feature = "embedded-storage-async")]
+impl<F: NorFlash + Send + Sync> AsyncFatFsDriver for FlashDriver<F> {
+    async fn disk_status(&self, _drive: u8) -> u8 {
+        DiskStatus::Ok as u8
+    }
+
+    async fn disk_initialize(&mut self, _drive: u8) -> u8 {
+        DiskStatus::Ok as u8
+    }
+
+    async fn disk_read(&mut self, _drive: u8, buffer: &mut [u8], sector: u32) -> DiskResult {
+        let Some(offset) = self.offset(sector, buffer.len()) else {
+            return DiskResult::ParameterError
+        };
+        match self.flash.read(offset as u32, buffer).await {
+            Ok(()) => DiskResult::Ok,
+            Err(_) => DiskResult::Error
+        }
+    }
+
+    async fn disk_write(&mut self, _drive: u8, buffer: &[u8], sector: u32) -> DiskResult {
+        let Some(mut offset) = self.offset(sector, buffer.len()) else {
+            return DiskResult::ParameterError
+        };
+        let block_len = self.block.len();
+        let mut data = buffer;
+        while !data.is_empty() {
+            let block_start = offset - offset % block_len;
+            let start = offset - block_start;
+            let len = data.len().min(block_len - start);
+            let (sectors, rest) = data.split_at(len);
+            let block = if len == block_len {
+                sectors
+            } else {
+                if self.flash.read(block_start as u32, &mut self.block).await.is_err() {
+                    return DiskResult::Error
+                }
+                self.block[start..start + len].copy_from_slice(sectors);
+                &self.block[..]
+            };
+            if self.flash.erase(block_start as u32, (block_start + block_len) as u32).await.is_err() {
+                return DiskResult::Error
+            }
+            if self.flash.write(block_start as u32, block).await.is_err() {
+                return DiskResult::Error
+            }
+            offset += len;
+            data = rest;
+        }
+        DiskResult::Ok
+    }
+
+    async fn disk_ioctl(&self, data: &mut IoctlCommand) -> DiskResult {
+        match data {
+            IoctlCommand::GetSectorCount(count) => *count = (self.capacity() / SECTOR_SIZE) as u32,
+            IoctlCommand::GetSectorSize(size) => *size = SECTOR_SIZE as u16,
+            IoctlCommand::GetBlockSize(size) => *size = (self.block.len() / SECTOR_SIZE) as u32,
+            IoctlCommand::CtrlSync(_) | IoctlCommand::CtrlTrim(_, _) => ()
+        }
+        DiskResult::Ok
+    }
+
+    #[cfg(feature = "chrono")]
+    fn get_fattime(&self) -> NaiveDateTime {
+        (self.clock)()
+    }
+}
@@ -0,0 +1,74 @@
+//! Timeouts for block device operations. Requires the `time` feature.
+//!
+//! A deadline is armed before each call into the installed driver. Because
+//! `FatFsDriver` methods are blocking, the timeout is cooperative: drivers that wait
+//! on hardware should poll `expired()` and give up when it returns `true`. Any driver
+//! operation that completes after its deadline is reported to FatFs as a disk error
+//! and surfaced to the caller as `Error::Timeout`.
+//!
+//! The timeout cannot interrupt a driver that is blocked. A driver operation that never
+//! returns hangs the calling task, with the file system locked, however short the timeout.
+
+use core::cell::Cell;
+use embassy_sync::blocking_mutex::{Mutex, raw::ThreadModeRawMutex};
+use embassy_time::{Duration, Instant};
+
+#[derive(Clone, Copy)]
+struct TimeoutState {
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    expired: bool
+}
+
+static STATE: Mutex<ThreadModeRawMutex, Cell<TimeoutState>> = Mutex::new(Cell::new(
+    TimeoutState { timeout: None, deadline: None, expired: false }
+));
+
+fn update<R>(f: impl FnOnce(&mut TimeoutState) -> R) -> R {
+    STATE.lock(|cell| {
+        let mut state = cell.get();
+        let result = f(&mut state);
+        cell.set(state);
+        result
+    })
+}
+
+/// Sets the timeout applied to every driver operation. `None` disables the timeout.
+pub fn set_timeout(timeout: Option<Duration>) {
+    update(|state| state.timeout = timeout);
+}
+
+/// Returns the timeout applied to every driver operation.
+pub fn timeout() -> Option<Duration> {
+    update(|state| state.timeout)
+}
+
+/// Returns `true` if the driver operation in progress has exceeded its deadline.
+/// Drivers should poll this while waiting on the block device.
+pub fn expired() -> bool {
+    update(|state| match state.deadline {
+        Some(deadline) => Instant::now() >= deadline,
+        None => false
+    })
+}
+
+/// Arms the deadline for a driver operation.
+pub(crate) fn begin() {
+    update(|state| state.deadline = state.timeout.map(|timeout| Instant::now() + timeout));
+}
+
+/// Disarms the deadline. Returns `true` if the operation overran it.
+pub(crate) fn end() -> bool {
+    let expired = expired();
+    update(|state| {
+        state.deadline = None;
+        state.expired |= expired;
+    });
+    expired
+}
+
+/// Returns `true` if a driver operation has timed out since the last call, and clears
+/// the flag.
+pub(crate) fn take_expired() -> bool {
+    update(|state| core::mem::replace(&mut state.expired, false))
+}
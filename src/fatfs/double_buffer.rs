@@ -0,0 +1,170 @@
+//! A double-buffered writer for logging at sustained high rates.
+//!
+//! One buffer is filled by the producer, such as an interrupt handler collecting ADC or IMU
+//! samples, while the other is written to the file by a task. When the buffer being filled
+//! is full, the buffers swap without blocking, so the producer never waits for storage as
+//! long as each buffer is written before the next one fills. Whole buffers are written, so
+//! with a buffer size that is a multiple of the sector size, FatFs writes directly from the
+//! buffer to the driver.
+//!
+//! ```ignore
+//! static SAMPLES: DoubleBuffer<4096> = DoubleBuffer::new();
+//!
+//! //From an interrupt handler:
+//! let _ = SAMPLES.push(&sample.to_le_bytes());
+//!
+//! //From a task:
+//! loop {
+//!     SAMPLES.write_next(&fatfs::FS, &mut log_file).await?;
+//! }
+//! ```
+
+use core::cell::UnsafeCell;
+use core::future::poll_fn;
+use core::ptr;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use core::task::Poll;
+use embassy_sync::waitqueue::AtomicWaker;
+use crate::fatfs::{Error, File, FileSystem, RawFileSystem};
+use crate::fatfs::write_queue::QueueFull;
+
+/// Two buffers of `N` bytes, one filled by a single producer while the other is written
+/// to a file by a single consumer.
+///
+/// Only one context may push at a time. If records are pushed from more than one interrupt
+/// priority, the caller must serialize the calls to `push()` and `seal()`.
+pub struct DoubleBuffer<const N: usize> {
+    buffers: UnsafeCell<[[u8; N]; 2]>,
+    //Owned by the producer.
+    active: AtomicUsize,
+    filled: AtomicUsize,
+    //Number of bytes in each buffer handed to the consumer, or 0 if the buffer is free.
+    pending: [AtomicUsize; 2],
+    dropped: AtomicU32,
+    waker: AtomicWaker
+}
+
+unsafe impl<const N: usize> Sync for DoubleBuffer<N> {}
+
+impl<const N: usize> DoubleBuffer<N> {
+    pub const fn new() -> Self {
+        Self {
+            buffers: UnsafeCell::new([[0; N]; 2]),
+            active: AtomicUsize::new(0),
+            filled: AtomicUsize::new(0),
+            pending: [AtomicUsize::new(0), AtomicUsize::new(0)],
+            dropped: AtomicU32::new(0),
+            waker: AtomicWaker::new()
+        }
+    }
+
+    fn buffer(&self, index: usize) -> *mut u8 {
+        self.buffers.get().cast::<u8>().wrapping_add(index * N)
+    }
+
+    /// Hands the active buffer to the consumer and makes the other one active.
+    fn swap(&self, active: usize, len: usize) {
+        self.pending[active].store(len, Ordering::Release);
+        self.active.store(1 - active, Ordering::Relaxed);
+        self.filled.store(0, Ordering::Relaxed);
+        self.waker.wake();
+    }
+
+    /// Adds a record to the active buffer, swapping buffers when it fills. This never blocks
+    /// and is safe to call from an interrupt handler. A full buffer is handed to the consumer
+    /// once the other buffer has been written. Records longer than `N` bytes, or that do not
+    /// fit in the space left in the buffers, are discarded and counted in `dropped()`.
+    pub fn push(&self, record: &[u8]) -> Result<(), QueueFull> {
+        let mut active = self.active.load(Ordering::Relaxed);
+        let mut filled = self.filled.load(Ordering::Relaxed);
+        if filled == N && self.pending[1 - active].load(Ordering::Acquire) == 0 {
+            self.swap(active, N);
+            active = 1 - active;
+            filled = 0;
+        }
+        let other_free = self.pending[1 - active].load(Ordering::Acquire) == 0;
+        let space = if other_free { N } else { N - filled };
+        if record.len() > space {
+            //Only the producer updates the counter, so a load and store is sufficient.
+            self.dropped.store(self.dropped.load(Ordering::Relaxed).wrapping_add(1), Ordering::Relaxed);
+            return Err(QueueFull)
+        }
+        let first = record.len().min(N - filled);
+        //The consumer never accesses the active buffer, nor a buffer that is not pending.
+        unsafe { ptr::copy_nonoverlapping(record.as_ptr(), self.buffer(active).add(filled), first); }
+        if filled + first < N || !other_free {
+            self.filled.store(filled + first, Ordering::Relaxed);
+            return Ok(())
+        }
+        self.swap(active, N);
+        let rest = record.len() - first;
+        unsafe { ptr::copy_nonoverlapping(record[first..].as_ptr(), self.buffer(1 - active), rest); }
+        self.filled.store(rest, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Hands a partly filled active buffer to the consumer, for example when logging stops.
+    /// Called by the producer. Returns false if the other buffer has not been written yet, or
+    /// the active buffer is empty.
+    pub fn seal(&self) -> bool {
+        let active = self.active.load(Ordering::Relaxed);
+        let filled = self.filled.load(Ordering::Relaxed);
+        if filled == 0 || self.pending[1 - active].load(Ordering::Acquire) != 0 {
+            return false
+        }
+        self.swap(active, filled);
+        true
+    }
+
+    /// Returns true if a buffer is waiting to be written.
+    pub fn is_pending(&self) -> bool {
+        self.pending.iter().any(|pending| pending.load(Ordering::Acquire) != 0)
+    }
+
+    /// Returns the number of records discarded because both buffers were full.
+    pub fn dropped(&self) -> u32 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Writes the buffer waiting to be written, if any, to the given file and frees it for
+    /// the producer. Returns the number of bytes written. If the buffer cannot be written in
+    /// full, for example because the drive is full, it remains pending and `Error::Denied`
+    /// is returned.
+    pub fn write_pending(&self, fs: &RawFileSystem, file: &mut File) -> Result<u32, Error> {
+        for (index, pending) in self.pending.iter().enumerate() {
+            let len = pending.load(Ordering::Acquire);
+            if len == 0 {
+                continue
+            }
+            //The producer does not touch a pending buffer until it is freed.
+            let buffer = unsafe { &*ptr::slice_from_raw_parts(self.buffer(index), len) };
+            if fs.write(file, buffer)? as usize != len {
+                return Err(Error::Denied)
+            }
+            pending.store(0, Ordering::Release);
+            return Ok(len as u32)
+        }
+        Ok(0)
+    }
+
+    /// Waits until a buffer is waiting to be written, then acquires a lock on the file
+    /// system and writes it to the given file.
+    pub async fn write_next(&self, fs: &FileSystem, file: &mut File) -> Result<u32, Error> {
+        poll_fn(|cx| {
+            self.waker.register(cx.waker());
+            if self.is_pending() {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        }).await;
+        let locked_fs = fs.lock().await;
+        self.write_pending(&locked_fs, file)
+    }
+}
+
+impl<const N: usize> Default for DoubleBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,260 @@
+use crate::fatfs::*;
+use alloc::vec::Vec;
+
+#[cfg(feature = "chrono")]
+use chrono::{NaiveDate, NaiveDateTime};
+
+/// Metadata of a directory entry, without its name.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EntryMeta {
+    /// Size of the file in bytes.
+    pub size: u32,
+    pub attributes: FileAttributes,
+    /// Date of last modification, in FAT format.
+    pub date: u16,
+    /// Time of last modification, in FAT format.
+    pub time: u16
+}
+
+impl From<&FileInfo> for EntryMeta {
+    fn from(info: &FileInfo) -> Self {
+        Self {
+            size: info.fsize,
+            attributes: FileAttributes::from_bits_truncate(info.fattrib),
+            date: info.fdate,
+            time: info.ftime
+        }
+    }
+}
+
+/// Converts a date and time in FAT format to a timestamp, or `None` if they are invalid.
+#[cfg(feature = "chrono")]
+pub(crate) fn fat_timestamp(date: u16, time: u16) -> Option<NaiveDateTime> {
+    let date = NaiveDate::from_ymd_opt(1980 + (date >> 9) as i32, ((date >> 5) & 0x0F) as u32, (date & 0x1F) as u32)?;
+    date.and_hms_opt((time >> 11) as u32, ((time >> 5) & 0x3F) as u32, (time & 0x1F) as u32 * 2)
+}
+
+/// An item within a directory, with its name and metadata held by value.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub attributes: FileAttributes,
+    /// Size of the file in bytes.
+    pub size: u32,
+    /// Date of last modification, in FAT format.
+    pub date: u16,
+    /// Time of last modification, in FAT format.
+    pub time: u16
+}
+
+impl DirEntry {
+    /// Creates an entry from the information of an item. Names that are not valid UTF-8
+    /// result in `Error::InvalidName`.
+    pub fn new(info: &FileInfo) -> Result<Self, Error> {
+        let meta = EntryMeta::from(info);
+        Ok(Self {
+            name: String::from(info.name()?),
+            attributes: meta.attributes,
+            size: meta.size,
+            date: meta.date,
+            time: meta.time
+        })
+    }
+
+    /// Returns true if the item is a directory.
+    pub fn is_dir(&self) -> bool {
+        self.attributes.contains(FileAttributes::Directory)
+    }
+
+    /// Returns the time of last modification, or `None` if the stored date is invalid.
+    #[cfg(feature = "chrono")]
+    pub fn modified(&self) -> Option<NaiveDateTime> {
+        fat_timestamp(self.date, self.time)
+    }
+}
+
+/// Iterator over the items of a directory, created by `iter_dir()`. The directory is closed
+/// when the last item has been returned, after an error, or when the iterator is dropped.
+pub struct DirIter<'a> {
+    fs: &'a RawFileSystem,
+    dir: Option<Directory>,
+    /// Items with any of these attributes are skipped.
+    exclude: u8
+}
+
+impl DirIter<'_> {
+    fn close(&mut self) {
+        if let Some(dir) = self.dir.take() {
+            let _ = self.fs.closedir(dir);
+        }
+    }
+}
+
+impl Iterator for DirIter<'_> {
+    type Item = Result<FileInfo, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let dir = self.dir.as_mut()?;
+            match self.fs.readdir(dir) {
+                Ok(info) if info.fname[0] == 0 => {
+                    self.close();
+                    return None
+                },
+                Ok(info) if info.fattrib & self.exclude != 0 => continue,
+                Ok(info) => return Some(Ok(info)),
+                Err(error) => {
+                    self.close();
+                    return Some(Err(error))
+                }
+            }
+        }
+    }
+}
+
+impl Drop for DirIter<'_> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl RawFileSystem {
+    /// Opens the directory at the given path and iterates over its items.
+    pub fn iter_dir(&self, path: &str) -> Result<DirIter<'_>, Error> {
+        self.iter_dir_filtered(path, FileAttributes::empty())
+    }
+
+    /// Opens the directory at the given path and iterates over its items, skipping items
+    /// with any of the given attributes. For example, a file browser may exclude `Hidden |
+    /// System`. FatFs never returns the `.` and `..` entries of a directory.
+    pub fn iter_dir_filtered(&self, path: &str, exclude: FileAttributes) -> Result<DirIter<'_>, Error> {
+        let dir = self.opendir(path)?;
+        Ok(DirIter { fs: self, dir: Some(dir), exclude: exclude.as_u8() })
+    }
+
+    /// Fills the page with the information of the items of the directory at the given path,
+    /// starting from the item at `offset`, and returns the number of items filled in. Fewer
+    /// items than fit in the page are returned at the end of the directory. The items before
+    /// `offset` are read and skipped, so only one page of items is held in memory.
+    pub fn list_page(&self, path: &str, offset: usize, page: &mut [FileInfo]) -> Result<usize, Error> {
+        let mut items = self.iter_dir(path)?.skip(offset);
+        let mut count = 0;
+        for slot in page.iter_mut() {
+            match items.next() {
+                Some(info) => *slot = info?,
+                None => break
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Returns an entry for every item in the directory at the given path.
+    pub fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, Error> {
+        self.iter_dir(path)?.map(|info| DirEntry::new(&info?)).collect()
+    }
+}
+
+/// Iterator over the items of a directory that match a pattern, created by `find()`. The
+/// directory is closed in the same way as by `DirIter`.
+pub struct FindIter<'a> {
+    fs: &'a RawFileSystem,
+    dir: Option<Directory>,
+    first: Option<FileInfo>
+}
+
+impl FindIter<'_> {
+    fn close(&mut self) {
+        if let Some(dir) = self.dir.take() {
+            let _ = self.fs.closedir(dir);
+        }
+    }
+}
+
+impl Iterator for FindIter<'_> {
+    type Item = Result<FileInfo, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = match self.first.take() {
+            Some(info) => Ok(info),
+            None => self.fs.findnext(self.dir.as_mut()?)
+        };
+        match result {
+            Ok(info) if info.fname[0] == 0 => {
+                self.close();
+                None
+            },
+            Ok(info) => Some(Ok(info)),
+            Err(error) => {
+                self.close();
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+impl Drop for FindIter<'_> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl RawFileSystem {
+    /// Iterates over the items of the directory at the given path whose names match the
+    /// pattern, in the same way as `findfirst()` and `findnext()`. The pattern is copied, so
+    /// it need not outlive the iterator.
+    pub fn find(&self, path: &str, pattern: &str) -> Result<FindIter<'_>, Error> {
+        let (dir, info) = self.findfirst(path, pattern)?;
+        Ok(FindIter { fs: self, dir: Some(dir), first: Some(info) })
+    }
+}
+
+/// Iterator over the items of a directory that yields each name as a fixed capacity
+/// `heapless::String`. Created by `readdir_names()`.
+#[cfg(feature = "heapless")]
+pub struct DirNames<'a, const N: usize> {
+    fs: &'a RawFileSystem,
+    dir: &'a mut Directory,
+    done: bool
+}
+
+#[cfg(feature = "heapless")]
+impl<'a, const N: usize> Iterator for DirNames<'a, N> {
+    type Item = Result<(heapless::String<N>, EntryMeta), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None
+        }
+        let info = match self.fs.readdir(self.dir) {
+            Ok(info) => info,
+            Err(error) => {
+                self.done = true;
+                return Some(Err(error))
+            }
+        };
+        if info.fname[0] == 0 {
+            self.done = true;
+            return None
+        }
+        let mut name = heapless::String::new();
+        let result = match info.name() {
+            Ok(item_name) => match name.push_str(item_name) {
+                Ok(()) => Ok((name, EntryMeta::from(&info))),
+                Err(()) => Err(Error::NotEnoughCore)
+            },
+            Err(error) => Err(error)
+        };
+        Some(result)
+    }
+}
+
+#[cfg(feature = "heapless")]
+impl RawFileSystem {
+    /// Iterates over the remaining items of the given directory, yielding the name and metadata
+    /// of each. Names longer than `N` bytes are reported as `Error::NotEnoughCore` and
+    /// iteration continues with the next item.
+    pub fn readdir_names<'a, const N: usize>(&'a self, dir: &'a mut Directory) -> DirNames<'a, N> {
+        DirNames { fs: self, dir, done: false }
+    }
+}
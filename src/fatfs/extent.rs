@@ -0,0 +1,144 @@
+use alloc::vec::Vec;
+use crate::fatfs::*;
+use crate::fatfs::fat::FatReader;
+use crate::fatfs::path;
+
+/// A run of consecutive sectors on the drive holding part of a file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SectorExtent {
+    /// Offset in the file of the first byte held in the run.
+    pub offset: u32,
+    /// Logical block address of the first sector, counted from the start of the drive.
+    pub first_sector: u32,
+    /// Number of sectors in the run.
+    pub sector_count: u32
+}
+
+/// A run of consecutive clusters in a cluster chain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClusterRun {
+    pub first_cluster: u32,
+    pub cluster_count: u32
+}
+
+/// How the cluster chain of a file or directory is split into fragments.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Fragmentation {
+    /// Number of clusters in the chain.
+    pub clusters: u32,
+    /// Number of runs of consecutive clusters. A contiguous chain has 1, and an empty one 0.
+    pub fragments: u32,
+    /// Number of clusters in the longest run.
+    pub largest_fragment: u32,
+    /// Size of a cluster in bytes.
+    pub cluster_size: u32
+}
+
+impl RawFileSystem {
+    /// Calls the given function with the first cluster and the number of clusters of each
+    /// run of consecutive clusters in the chain starting at the given cluster, in order.
+    pub(crate) fn chain_runs(&self, start: u32, mut f: impl FnMut(u32, u32) -> Result<(), Error>) -> Result<(), Error> {
+        if start == 0 {
+            return Ok(())
+        }
+        let mut fat = FatReader::new(self);
+        let mut run_start = start;
+        let mut cluster = start;
+        //A chain cannot hold more clusters than the volume, so a longer one has a loop.
+        for _ in 0..self.fs().n_fatent {
+            if cluster < 2 || cluster >= self.fs().n_fatent {
+                return Err(Error::IntError)
+            }
+            let next = fat.entry(cluster)?;
+            if next != cluster + 1 {
+                f(run_start, cluster + 1 - run_start)?;
+                //Any value beyond the last cluster marks the end of the chain.
+                if next >= self.fs().n_fatent {
+                    return Ok(())
+                }
+                run_start = next;
+            }
+            cluster = next;
+        }
+        Err(Error::IntError)
+    }
+
+    /// Returns true if the clusters of the given file occupy consecutive sectors, for example
+    /// before handing the file to hardware that streams to or from the sectors directly. An
+    /// empty file is contiguous.
+    pub fn is_contiguous(&self, file: &mut File) -> Result<bool, Error> {
+        let mut runs = 0;
+        self.chain_runs(file.obj.sclust, |_, _| {
+            runs += 1;
+            Ok(())
+        })?;
+        Ok(runs <= 1)
+    }
+
+    /// Returns the runs of consecutive sectors holding the data of the given file, in file
+    /// order, so that the data may be transferred by DMA or other hardware directly to or from
+    /// the drive while FatFs still manages the directory and FAT. The runs cover the size of
+    /// the file rounded up to whole sectors. Data written to the file but not yet synced may
+    /// still be held in the file object rather than on the drive, so the file should be synced
+    /// first. The sectors are only valid while the file is neither truncated nor deleted.
+    pub fn sector_extents(&self, file: &mut File) -> Result<Vec<SectorExtent>, Error> {
+        let cluster_sectors = self.fs().csize as u32;
+        let mut remaining = file.obj.objsize.div_ceil(FF_MAX_SS);
+        let mut offset = 0;
+        let mut extents = Vec::new();
+        self.chain_runs(file.obj.sclust, |start, count| {
+            let sector_count = (count * cluster_sectors).min(remaining);
+            if sector_count > 0 {
+                extents.push(SectorExtent { offset, first_sector: self.cluster_sector(start), sector_count });
+            }
+            remaining -= sector_count;
+            offset += sector_count * FF_MAX_SS;
+            Ok(())
+        })?;
+        Ok(extents)
+    }
+
+    /// Returns the first cluster of the file or directory at the given path, or 0 if it has
+    /// no cluster chain.
+    fn first_cluster(&self, path: &str) -> Result<u32, Error> {
+        if path::is_root(path) {
+            //Only the root directory of a FAT32 volume is held in a cluster chain.
+            return Ok(if self.fs().fs_type == FS_FAT32 as u8 { self.fs().dirbase } else { 0 })
+        }
+        if self.stat(path)?.fattrib & FileAttributes::Directory.as_u8() != 0 {
+            let dir = self.opendir(path)?;
+            let cluster = dir.obj.sclust;
+            self.closedir(dir)?;
+            return Ok(cluster)
+        }
+        let file = self.open_raw(path, FileOptions::Read | FileOptions::OpenExisting)?;
+        let cluster = file.obj.sclust;
+        self.close(file)?;
+        Ok(cluster)
+    }
+
+    /// Returns the cluster chain of the file or directory at the given path, as runs of
+    /// consecutive clusters in chain order, for diagnosing the performance of streaming
+    /// workloads. A file that is open for writing cannot be inspected, as FatFs locks it.
+    pub fn cluster_chain(&self, path: &str) -> Result<Vec<ClusterRun>, Error> {
+        let mut runs = Vec::new();
+        self.chain_runs(self.first_cluster(path)?, |first_cluster, cluster_count| {
+            runs.push(ClusterRun { first_cluster, cluster_count });
+            Ok(())
+        })?;
+        Ok(runs)
+    }
+
+    /// Returns how the cluster chain of the file or directory at the given path is
+    /// fragmented, without holding the chain in memory.
+    pub fn fragmentation(&self, path: &str) -> Result<Fragmentation, Error> {
+        let mut stats = Fragmentation { cluster_size: self.fs().csize as u32 * FF_MAX_SS, ..Default::default() };
+        self.chain_runs(self.first_cluster(path)?, |_, count| {
+            stats.clusters += count;
+            stats.fragments += 1;
+            stats.largest_fragment = stats.largest_fragment.max(count);
+            Ok(())
+        })?;
+        Ok(stats)
+    }
+}
@@ -0,0 +1,51 @@
+use core::ptr;
+use alloc::vec::Vec;
+use crate::fatfs::*;
+
+/// Number of entries in the first table tried, enough for a file of up to 7 fragments.
+const INITIAL_TABLE_LEN: usize = 16;
+
+/// Value of `CREATE_LINKMAP`, the offset passed to `f_lseek()` to build the table.
+const CREATE_LINKMAP: FSIZE_t = FSIZE_t::MAX;
+
+impl RawFileSystem {
+    /// Enables fast seek on the given file. A cluster link map table of the fragments of the
+    /// file is built and held until the file is closed or fast seek is disabled, so that
+    /// seeking and random reads no longer follow the cluster chain through the FAT. The table
+    /// needs two words per fragment of the file. While fast seek is enabled, the file cannot
+    /// grow: writes stop at the end of the file, and a file opened for writing should be
+    /// expanded to its final size beforehand, for example with `expand()`.
+    pub fn enable_fast_seek(&self, file: &mut File) -> Result<(), Error> {
+        let mut table: Vec<DWORD> = alloc::vec![0; INITIAL_TABLE_LEN];
+        loop {
+            table[0] = table.len() as DWORD;
+            file.cltbl = table.as_mut_ptr();
+            let result;
+            unsafe { result = f_lseek(ptr::addr_of_mut!(file.fil), CREATE_LINKMAP); }
+            match result {
+                FRESULT_FR_OK => break,
+                //The first entry now holds the number of entries needed.
+                FRESULT_FR_NOT_ENOUGH_CORE if table[0] as usize > table.len() => {
+                    let required = table[0] as usize;
+                    table.resize(required, 0);
+                },
+                _ => {
+                    file.cltbl = ptr::null_mut();
+                    return Err(Error::from_result(result))
+                }
+            }
+        }
+        //The table is owned by the registry, so it lives as long as the file is open.
+        if !self.registry.borrow_mut().set_link_map(file.handle, Some(table)) {
+            file.cltbl = ptr::null_mut();
+            return Err(Error::InvalidObject)
+        }
+        Ok(())
+    }
+
+    /// Disables fast seek on the given file and releases its cluster link map table.
+    pub fn disable_fast_seek(&self, file: &mut File) {
+        file.cltbl = ptr::null_mut();
+        self.registry.borrow_mut().set_link_map(file.handle, None);
+    }
+}
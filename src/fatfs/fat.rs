@@ -0,0 +1,169 @@
+use crate::fatfs::*;
+
+/// Reads entries of the FAT directly from storage, and sets them for repairs. The most
+/// recently used FAT sector is kept, while a sector currently held in the window of the file
+/// system object is read from there, as it may not have been written back yet. Entries set
+/// are written when another sector is needed, or by `flush()`, which must be called once the
+/// last entry is set.
+pub(crate) struct FatReader<'a> {
+    fs: &'a RawFileSystem,
+    sector: [u8; FF_MAX_SS as usize],
+    loaded: Option<u32>,
+    /// Whether the loaded sector holds entries set but not yet written to the drive.
+    dirty: bool
+}
+
+impl<'a> FatReader<'a> {
+    pub(crate) fn new(fs: &'a RawFileSystem) -> Self {
+        Self { fs, sector: [0; FF_MAX_SS as usize], loaded: None, dirty: false }
+    }
+
+    /// Loads the given sector of the first FAT, writing back the sector loaded before it.
+    fn load(&mut self, number: u32) -> Result<(), Error> {
+        if self.loaded != Some(number) {
+            self.flush()?;
+            self.loaded = None;
+            self.fs.read_sectors(number, &mut self.sector)?;
+            self.loaded = Some(number);
+        }
+        Ok(())
+    }
+
+    fn byte(&mut self, offset: u32) -> Result<u8, Error> {
+        let fs = self.fs.fs();
+        let number = fs.fatbase + offset / FF_MAX_SS;
+        let index = (offset % FF_MAX_SS) as usize;
+        //Entries set but not yet written are newer than the window.
+        if !self.dirty && fs.winsect == number {
+            return Ok(fs.win[index])
+        }
+        self.load(number)?;
+        Ok(self.sector[index])
+    }
+
+    /// Returns the FAT entry for the given cluster. A value of 0 marks a free cluster.
+    pub(crate) fn entry(&mut self, cluster: u32) -> Result<u32, Error> {
+        match self.fs.fs().fs_type as u32 {
+            FS_FAT12 => {
+                let offset = cluster + cluster / 2;
+                let value = u16::from_le_bytes([self.byte(offset)?, self.byte(offset + 1)?]) as u32;
+                Ok(if cluster & 1 != 0 { value >> 4 } else { value & 0xFFF })
+            },
+            FS_FAT16 => {
+                let offset = cluster * 2;
+                Ok(u16::from_le_bytes([self.byte(offset)?, self.byte(offset + 1)?]) as u32)
+            },
+            FS_FAT32 => {
+                let offset = cluster * 4;
+                let value = u32::from_le_bytes([self.byte(offset)?, self.byte(offset + 1)?, self.byte(offset + 2)?, self.byte(offset + 3)?]);
+                Ok(value & 0x0FFF_FFFF)
+            },
+            _ => Err(Error::NoFileSystem)
+        }
+    }
+
+    /// Sets a byte of the FAT in the loaded sector, to be written to the drive later.
+    fn set_byte(&mut self, offset: u32, value: u8) -> Result<(), Error> {
+        self.load(self.fs.fs().fatbase + offset / FF_MAX_SS)?;
+        self.sector[(offset % FF_MAX_SS) as usize] = value;
+        self.dirty = true;
+        Ok(())
+    }
+
+    /// Writes the entries set since the last call to every copy of the FAT on the drive,
+    /// bypassing FatFs. The window of the file system object must have been written back
+    /// before the first entry was set.
+    pub(crate) fn flush(&mut self) -> Result<(), Error> {
+        let number = match self.loaded {
+            Some(number) if self.dirty => number,
+            _ => return Ok(())
+        };
+        let (n_fats, fsize) = (self.fs.fs().n_fats, self.fs.fs().fsize);
+        for copy in 0..n_fats as u32 {
+            self.fs.write_sectors(number + copy * fsize, &self.sector)?;
+        }
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Sets the FAT entry for the given cluster, in every copy of the FAT once written. Used
+    /// by repairs, which write back the window of the file system object before they begin.
+    pub(crate) fn set_entry(&mut self, cluster: u32, value: u32) -> Result<(), Error> {
+        match self.fs.fs().fs_type as u32 {
+            FS_FAT12 => {
+                let offset = cluster + cluster / 2;
+                let [low, high] = ((value & 0xFFF) as u16).to_le_bytes();
+                //Odd entries start in the upper half of their first byte.
+                if cluster & 1 != 0 {
+                    let first = self.byte(offset)?;
+                    self.set_byte(offset, (first & 0x0F) | (low << 4))?;
+                    self.set_byte(offset + 1, (low >> 4) | (high << 4))
+                } else {
+                    let second = self.byte(offset + 1)?;
+                    self.set_byte(offset, low)?;
+                    self.set_byte(offset + 1, (second & 0xF0) | high)
+                }
+            },
+            FS_FAT16 => {
+                let [low, high] = (value as u16).to_le_bytes();
+                self.set_byte(cluster * 2, low)?;
+                self.set_byte(cluster * 2 + 1, high)
+            },
+            FS_FAT32 => {
+                //The upper 4 bits of an entry are reserved and kept.
+                let reserved = self.byte(cluster * 4 + 3)? & 0xF0;
+                let bytes = (value & 0x0FFF_FFFF).to_le_bytes();
+                for (index, byte) in bytes.iter().enumerate() {
+                    let byte = if index == 3 { byte | reserved } else { *byte };
+                    self.set_byte(cluster * 4 + index as u32, byte)?;
+                }
+                Ok(())
+            },
+            _ => Err(Error::NoFileSystem)
+        }
+    }
+
+    /// Returns the value marking the end of a cluster chain.
+    pub(crate) fn end_of_chain(&self) -> u32 {
+        match self.fs.fs().fs_type as u32 {
+            FS_FAT12 => 0xFFF,
+            FS_FAT16 => 0xFFFF,
+            _ => 0x0FFF_FFFF
+        }
+    }
+
+    /// Returns the value marking a bad cluster.
+    pub(crate) fn bad_cluster(&self) -> u32 {
+        self.end_of_chain() - 8
+    }
+}
+
+impl RawFileSystem {
+    /// Returns the first sector of the given cluster.
+    pub(crate) fn cluster_sector(&self, cluster: u32) -> u32 {
+        self.fs().database + (cluster - 2) * self.fs().csize as u32
+    }
+
+    /// Calls the given function with the first sector and the number of sectors of each run
+    /// of sectors in use: the sectors ahead of the data area, followed by each run of
+    /// consecutive clusters that are allocated.
+    pub(crate) fn used_sector_runs<E: From<Error>>(&self, mut f: impl FnMut(u32, u32) -> Result<(), E>) -> Result<(), E> {
+        f(0, self.fs().database)?;
+        let mut fat = FatReader::new(self);
+        let mut run_start = None;
+        for cluster in 2..self.fs().n_fatent {
+            match (fat.entry(cluster)? != 0, run_start) {
+                (true, None) => run_start = Some(cluster),
+                (false, Some(start)) => {
+                    f(self.cluster_sector(start), (cluster - start) * self.fs().csize as u32)?;
+                    run_start = None;
+                }
+                _ => ()
+            }
+        }
+        if let Some(start) = run_start {
+            f(self.cluster_sector(start), (self.fs().n_fatent - start) * self.fs().csize as u32)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,245 @@
+use crate::fatfs::*;
+use alloc::vec::Vec;
+
+/// A position to seek to within a file, following `std::io::SeekFrom`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SeekFrom {
+    /// An offset from the start of the file.
+    Start(u64),
+    /// An offset from the end of the file.
+    End(i64),
+    /// An offset from the current position.
+    Current(i64)
+}
+
+/// Error returned by `write_all()`.
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum WriteAllError {
+    /// The volume became full after the given number of bytes had been written.
+    DiskFull { written: u32 },
+    Other(Error)
+}
+
+impl From<Error> for WriteAllError {
+    fn from(error: Error) -> Self {
+        WriteAllError::Other(error)
+    }
+}
+
+/// Reports a full volume as `Error::Denied`, as FatFs does when creating items.
+impl From<WriteAllError> for Error {
+    fn from(error: WriteAllError) -> Self {
+        match error {
+            WriteAllError::DiskFull { .. } => Error::Denied,
+            WriteAllError::Other(error) => error
+        }
+    }
+}
+
+/// Error returned by `read_exact()`.
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum ReadExactError {
+    /// The end of the file was reached after the given number of bytes had been read.
+    UnexpectedEof { read: u32 },
+    Other(Error)
+}
+
+impl From<Error> for ReadExactError {
+    fn from(error: Error) -> Self {
+        ReadExactError::Other(error)
+    }
+}
+
+impl File {
+    /// Returns the current offset from the start of the file, as `f_tell()` does.
+    pub fn position(&self) -> u32 {
+        self.fptr
+    }
+
+    /// Returns the size of the file in bytes, as `f_size()` does.
+    pub fn len(&self) -> u32 {
+        self.obj.objsize
+    }
+
+    /// Returns true if the file is empty.
+    pub fn is_empty(&self) -> bool {
+        self.obj.objsize == 0
+    }
+
+    /// Returns true if the current position is at or beyond the end of the file, as `f_eof()` does.
+    pub fn is_eof(&self) -> bool {
+        self.fptr >= self.obj.objsize
+    }
+}
+
+impl RawFileSystem {
+    /// Moves to a position in the given file relative to its start, end or current position,
+    /// and returns the new offset from the start of the file. Fails with
+    /// `Error::InvalidParameter` if the position is before the start of the file or beyond
+    /// the largest size of a FAT file.
+    pub fn seek_from(&self, file: &mut File, position: SeekFrom) -> Result<u32, Error> {
+        let (base, offset) = match position {
+            SeekFrom::Start(offset) => (0, offset as i64),
+            SeekFrom::End(offset) => (file.len(), offset),
+            SeekFrom::Current(offset) => (file.position(), offset)
+        };
+        let offset = (base as i64).checked_add(offset).ok_or(Error::InvalidParameter)?;
+        let offset = u32::try_from(offset).map_err(|_| Error::InvalidParameter)?;
+        self.seek(file, offset)?;
+        Ok(file.position())
+    }
+
+    /// Reads data from the given offset in the file, then moves back to the previous position.
+    /// Returns the number of bytes read.
+    pub fn read_at(&self, file: &mut File, offset: u32, buffer: &mut [u8]) -> Result<u32, Error> {
+        self.at(file, offset, |fs, file| fs.read(file, buffer))
+    }
+
+    /// Writes data at the given offset in the file, then moves back to the previous position.
+    /// Writing beyond the end of the file extends it. Returns the number of bytes written.
+    pub fn write_at(&self, file: &mut File, offset: u32, buffer: &[u8]) -> Result<u32, Error> {
+        self.at(file, offset, |fs, file| fs.write(file, buffer))
+    }
+
+    /// Reads data from the file into each buffer in turn, stopping early at the end of the
+    /// file. Returns the total number of bytes read.
+    pub fn read_vectored(&self, file: &mut File, buffers: &mut [&mut [u8]]) -> Result<u32, Error> {
+        let mut total = 0;
+        for buffer in buffers.iter_mut() {
+            let count = self.read(file, buffer)?;
+            total += count;
+            if (count as usize) < buffer.len() {
+                break
+            }
+        }
+        Ok(total)
+    }
+
+    /// Writes the data of each buffer to the file in turn, stopping early if the volume
+    /// becomes full. Returns the total number of bytes written.
+    pub fn write_vectored(&self, file: &mut File, buffers: &[&[u8]]) -> Result<u32, Error> {
+        let mut total = 0;
+        for buffer in buffers {
+            let count = self.write(file, buffer)?;
+            total += count;
+            if (count as usize) < buffer.len() {
+                break
+            }
+        }
+        Ok(total)
+    }
+
+    /// Writes all of the buffer to the file. FatFs reports a full volume by writing fewer
+    /// bytes than requested without an error, which is returned as `WriteAllError::DiskFull`.
+    pub fn write_all(&self, file: &mut File, buffer: &[u8]) -> Result<(), WriteAllError> {
+        let mut written = 0;
+        while (written as usize) < buffer.len() {
+            match self.write(file, &buffer[written as usize..])? {
+                0 => return Err(WriteAllError::DiskFull { written }),
+                count => written += count
+            }
+        }
+        Ok(())
+    }
+
+    /// Fills the buffer with data read from the file. Fails with `ReadExactError::UnexpectedEof`
+    /// if the end of the file is reached first, leaving the file position at the end of the file.
+    pub fn read_exact(&self, file: &mut File, buffer: &mut [u8]) -> Result<(), ReadExactError> {
+        let mut read = 0;
+        while (read as usize) < buffer.len() {
+            match self.read(file, &mut buffer[read as usize..])? {
+                0 => return Err(ReadExactError::UnexpectedEof { read }),
+                count => read += count
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads from the current position to the end of the file, appending the data to the
+    /// buffer. Space for the data is reserved up front from the size of the file, failing
+    /// with `Error::NotEnoughCore` if it cannot be allocated. Returns the number of bytes read.
+    pub fn read_to_end(&self, file: &mut File, buffer: &mut Vec<u8>) -> Result<u32, Error> {
+        let start = buffer.len();
+        let remaining = file.len().saturating_sub(file.position()) as usize;
+        buffer.try_reserve_exact(remaining).map_err(|_| Error::NotEnoughCore)?;
+        buffer.resize(start + remaining, 0);
+        match self.read(file, &mut buffer[start..]) {
+            Ok(count) => {
+                buffer.truncate(start + count as usize);
+                Ok(count)
+            },
+            Err(error) => {
+                buffer.truncate(start);
+                Err(error)
+            }
+        }
+    }
+
+    /// Reads from the current position to the end of the file, appending the data to the
+    /// string. Fails with `Error::InvalidParameter`, leaving the string unchanged, if the
+    /// data is not valid UTF-8. Returns the number of bytes read.
+    pub fn read_to_string(&self, file: &mut File, string: &mut String) -> Result<u32, Error> {
+        let mut buffer = Vec::new();
+        let count = self.read_to_end(file, &mut buffer)?;
+        string.push_str(core::str::from_utf8(&buffer).map_err(|_| Error::InvalidParameter)?);
+        Ok(count)
+    }
+
+    /// Reads the whole file at the given path.
+    pub fn read_file(&self, path: &str) -> Result<Vec<u8>, Error> {
+        let mut file = self.open_raw(path, FileOptions::Read)?;
+        let mut buffer = Vec::new();
+        let result = self.read_to_end(&mut file, &mut buffer);
+        self.close(file)?;
+        result.map(|_| buffer)
+    }
+
+    /// Reads the whole file at the given path, which must contain valid UTF-8.
+    pub fn read_file_to_string(&self, path: &str) -> Result<String, Error> {
+        let buffer = self.read_file(path)?;
+        String::from_utf8(buffer).map_err(|_| Error::InvalidParameter)
+    }
+
+    /// Reads the file at the given path in chunks of up to `chunk_size` bytes, passing each
+    /// chunk to the callback along with the percentage of the file read so far. An error from
+    /// the callback stops the transfer and is returned. Fails with `Error::NotEnoughCore`,
+    /// before the file is opened, if the chunk cannot be allocated. Returns the number of
+    /// bytes read.
+    pub fn stream_file(&self, path: &str, chunk_size: usize, mut callback: impl FnMut(&[u8], u8) -> Result<(), Error>) -> Result<u32, Error> {
+        if chunk_size == 0 {
+            return Err(Error::InvalidParameter)
+        }
+        let mut buffer = Vec::new();
+        buffer.try_reserve_exact(chunk_size).map_err(|_| Error::NotEnoughCore)?;
+        buffer.resize(chunk_size, 0);
+        let mut file = self.open_raw(path, FileOptions::Read)?;
+        let size = file.len() as u64;
+        let mut total = 0;
+        let result = loop {
+            let len = match self.read(&mut file, &mut buffer) {
+                Ok(0) => break Ok(total),
+                Ok(len) => len,
+                Err(error) => break Err(error)
+            };
+            total += len;
+            let percent = (total as u64 * 100).checked_div(size).unwrap_or(100).min(100) as u8;
+            if let Err(error) = callback(&buffer[..len as usize], percent) {
+                break Err(error)
+            }
+        };
+        self.close(file)?;
+        result
+    }
+
+    fn at(&self, file: &mut File, offset: u32, f: impl FnOnce(&Self, &mut File) -> Result<u32, Error>) -> Result<u32, Error> {
+        let position = file.position();
+        let result = self.seek(file, offset).and_then(|_| f(self, file));
+        //Restore the position even if the transfer failed.
+        let restored = self.seek(file, position);
+        let count = result?;
+        restored?;
+        Ok(count)
+    }
+}
@@ -0,0 +1,92 @@
+use sha2::{Digest, Sha256};
+use crate::fatfs::*;
+
+/// Length of the SHA-256 digest ending an image read by `load_firmware()`.
+const DIGEST_LEN: usize = 32;
+
+/// Error returned when loading a firmware image.
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum FirmwareError {
+    /// The image is shorter than its trailer, or does not match it.
+    Verification,
+    /// Reading the image failed, or the callback returned an error.
+    Other(Error)
+}
+
+impl From<Error> for FirmwareError {
+    fn from(error: Error) -> Self {
+        FirmwareError::Other(error)
+    }
+}
+
+impl From<ReadExactError> for FirmwareError {
+    fn from(error: ReadExactError) -> Self {
+        match error {
+            //The image ended before its trailer.
+            ReadExactError::UnexpectedEof { .. } => FirmwareError::Verification,
+            ReadExactError::Other(error) => FirmwareError::Other(error)
+        }
+    }
+}
+
+impl From<FirmwareError> for Error {
+    fn from(error: FirmwareError) -> Self {
+        match error {
+            FirmwareError::Verification => Error::IntError,
+            FirmwareError::Other(error) => error
+        }
+    }
+}
+
+impl RawFileSystem {
+    /// Reads the firmware image at the given path, which ends with a SHA-256 digest of the
+    /// rest of the file, and passes it to the callback a sector at a time, along with the
+    /// offset of each chunk. The digest is computed as the image is read, and compared once
+    /// the whole image has been passed to the callback. An error from the callback stops
+    /// the load. Returns the length of the image, without the digest.
+    ///
+    /// As the image has already been passed on when a mismatch is found, the callback should
+    /// only stage the image, for example in an inactive flash bank that is only booted once
+    /// this returns successfully. Alternatively, call this first with a callback that does
+    /// nothing to verify the image, then again to program it.
+    pub fn load_firmware(&self, path: &str, program: impl FnMut(u32, &[u8]) -> Result<(), Error>) -> Result<u32, FirmwareError> {
+        self.load_firmware_signed(path, DIGEST_LEN, program, |digest, trailer| digest[..] == *trailer)
+    }
+
+    /// Loads a firmware image in the same way as `load_firmware()`, for an image ending with
+    /// a trailer of the given length, such as a signature. Once the image has been passed to
+    /// the callback, `verify` is given the SHA-256 digest of the image and the trailer, and
+    /// returns whether the image is valid.
+    pub fn load_firmware_signed(&self, path: &str, trailer_len: usize, program: impl FnMut(u32, &[u8]) -> Result<(), Error>,
+        verify: impl FnOnce(&[u8; 32], &[u8]) -> bool) -> Result<u32, FirmwareError> {
+        let mut file = self.open_raw(path, FileOptions::Read | FileOptions::OpenExisting)?;
+        let result = self.stream_firmware(&mut file, trailer_len, program, verify);
+        self.close(file).map_err(Error::from)?;
+        result
+    }
+
+    fn stream_firmware(&self, file: &mut File, trailer_len: usize, mut program: impl FnMut(u32, &[u8]) -> Result<(), Error>,
+        verify: impl FnOnce(&[u8; 32], &[u8]) -> bool) -> Result<u32, FirmwareError> {
+        let image_len = match file.len().checked_sub(trailer_len as u32) {
+            Some(len) => len,
+            None => return Err(FirmwareError::Verification)
+        };
+        let mut hasher = Sha256::new();
+        let mut buffer = [0; FF_MAX_SS as usize];
+        let mut offset = 0;
+        while offset < image_len {
+            let len = (image_len - offset).min(FF_MAX_SS) as usize;
+            self.read_exact(file, &mut buffer[..len])?;
+            hasher.update(&buffer[..len]);
+            program(offset, &buffer[..len])?;
+            offset += len as u32;
+        }
+        let mut trailer = alloc::vec![0; trailer_len];
+        self.read_exact(file, &mut trailer)?;
+        if !verify(&hasher.finalize().into(), &trailer) {
+            return Err(FirmwareError::Verification)
+        }
+        Ok(image_len)
+    }
+}
@@ -0,0 +1,566 @@
+use alloc::vec::Vec;
+use crate::fatfs::*;
+use crate::fatfs::fat::FatReader;
+use crate::fatfs::path::{self, PathBuffer};
+
+/// Size of a directory entry.
+const ENTRY_LEN: usize = 32;
+const ATTR_VOLUME: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_LONG_NAME: u8 = 0x0F;
+const DELETED: u8 = 0xE5;
+
+/// A problem found by `fsck()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FsckIssue {
+    /// An item starts at, or its cluster chain leads to, a cluster that is free, marked bad,
+    /// or beyond the end of the volume.
+    InvalidCluster { cluster: u32 },
+    /// The cluster chain of an item leads to a cluster that already belongs to another item.
+    CrossLinked { cluster: u32 },
+    /// The size of a file does not match the number of clusters allocated to it.
+    SizeMismatch { size: u32, allocated: u32 },
+    /// A chain of clusters that is allocated in the FAT but does not belong to any item.
+    LostChain { first_cluster: u32, clusters: u32 }
+}
+
+/// The outcome of a call to `fsck()`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FsckReport {
+    pub files: u32,
+    pub directories: u32,
+    pub invalid_clusters: u32,
+    pub cross_links: u32,
+    pub size_mismatches: u32,
+    pub lost_chains: u32,
+    /// Number of clusters in lost chains, and in cycles of clusters that no item refers to.
+    pub lost_clusters: u32,
+    /// Number of issues repaired.
+    pub repaired: u32
+}
+
+impl FsckReport {
+    /// Returns true if no issues were found.
+    pub fn is_clean(&self) -> bool {
+        self.invalid_clusters == 0 && self.cross_links == 0 && self.size_mismatches == 0 && self.lost_clusters == 0
+    }
+}
+
+/// What `recover_lost()` does with lost chains.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LostChainAction<'a> {
+    /// Frees the clusters of lost chains.
+    Free,
+    /// Saves each lost chain as a file named `FILEnnnn.CHK` in the directory at the given
+    /// path, which is created if it does not exist.
+    Save(&'a str)
+}
+
+/// The outcome of a call to `recover_lost()`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RecoveryReport {
+    pub lost_chains: u32,
+    pub lost_clusters: u32,
+    /// Number of files created for lost chains.
+    pub saved: u32,
+    /// Number of clusters freed.
+    pub freed: u32
+}
+
+/// A set of clusters, one bit per cluster of the volume.
+pub(crate) struct ClusterSet(Vec<u32>);
+
+impl ClusterSet {
+    pub(crate) fn new(clusters: u32) -> Result<Self, Error> {
+        let mut words = Vec::new();
+        words.try_reserve_exact(clusters.div_ceil(32) as usize).map_err(|_| Error::NotEnoughCore)?;
+        words.resize(clusters.div_ceil(32) as usize, 0);
+        Ok(Self(words))
+    }
+
+    pub(crate) fn contains(&self, cluster: u32) -> bool {
+        self.0.get((cluster / 32) as usize).is_some_and(|word| word & (1 << (cluster % 32)) != 0)
+    }
+
+    pub(crate) fn insert(&mut self, cluster: u32) {
+        if let Some(word) = self.0.get_mut((cluster / 32) as usize) {
+            *word |= 1 << (cluster % 32);
+        }
+    }
+
+    pub(crate) fn remove(&mut self, cluster: u32) {
+        if let Some(word) = self.0.get_mut((cluster / 32) as usize) {
+            *word &= !(1 << (cluster % 32));
+        }
+    }
+}
+
+/// The state of a check in progress.
+struct Check<'a> {
+    fat: FatReader<'a>,
+    used: ClusterSet,
+    repair: bool,
+    report: FsckReport
+}
+
+/// The fate of the chain of a directory entry after it has been checked.
+enum Entry {
+    /// The chain is valid, or was repaired, and holds the given number of clusters.
+    Valid(u32),
+    /// The entry should start no chain, as its first cluster is invalid or cross-linked.
+    Clear
+}
+
+/// Returns the checksum of the short name of a directory entry, which each of its long name
+/// entries holds.
+fn short_name_checksum(entry: &[u8]) -> u8 {
+    entry[..11].iter().fold(0, |sum: u8, byte| sum.rotate_right(1).wrapping_add(*byte))
+}
+
+/// Returns the short name of a directory entry in the form `NAME.EXT`.
+fn short_name(entry: &[u8]) -> String {
+    let mut name = String::new();
+    for (index, byte) in entry[..11].iter().enumerate() {
+        let byte = if index == 0 && *byte == 0x05 { DELETED } else { *byte };
+        if index == 8 && entry[8] != b' ' {
+            name.push('.');
+        }
+        if byte != b' ' {
+            name.push(if byte.is_ascii() { byte as char } else { '?' });
+        }
+    }
+    name
+}
+
+impl RawFileSystem {
+    /// Checks the consistency of the mounted volume, which FatFs does not do itself, for
+    /// example after removable media was pulled out during a write. Every directory is read
+    /// and the cluster chain of each item followed through the FAT, then the FAT is scanned
+    /// for allocated clusters that no item refers to. Each issue found is passed to the
+    /// callback along with the path of the item, formed from short names, or an empty path for
+    /// lost chains. Memory is allocated for one bit per cluster of the volume, plus the paths
+    /// of directories waiting to be read.
+    ///
+    /// With `repair` set, the volume must have no open files, or `Error::Denied` is returned.
+    /// A chain leading to an invalid or cross-linked cluster is cut short before it. An item
+    /// whose first cluster is invalid or cross-linked is emptied if it is a file, or deleted
+    /// along with its long name if it is a directory, so the item read first keeps clusters
+    /// shared with another. The size of a file is then set to fit its chain, while clusters
+    /// beyond its size are cut off. Clusters cut off from chains, like other lost chains, stay
+    /// allocated and are reported as lost.
+    pub fn fsck(&self, repair: bool, mut issue: impl FnMut(&str, &FsckIssue)) -> Result<FsckReport, Error> {
+        if self.fs().fs_type == 0 {
+            return Err(Error::NotEnabled)
+        }
+        if repair {
+            if self.registry.borrow().iter().next().is_some() {
+                return Err(Error::Denied)
+            }
+            //Repairs write to the drive directly, so pending metadata is written first.
+            self.sync_fs()?;
+        }
+        let mut check = self.scan(repair, &mut issue)?;
+        check.fat.flush()?;
+        self.find_lost(&mut check, &mut issue)?;
+        Ok(check.report)
+    }
+
+    /// Reads every directory, following the cluster chain of each item and marking its
+    /// clusters as used.
+    fn scan(&self, repair: bool, issue: &mut impl FnMut(&str, &FsckIssue)) -> Result<Check<'_>, Error> {
+        let mut check = Check {
+            fat: FatReader::new(self),
+            used: ClusterSet::new(self.fs().n_fatent)?,
+            repair,
+            report: FsckReport::default()
+        };
+        //Directories waiting to be read, with the number of clusters in their chain. The root
+        //directory of a FAT12 or FAT16 volume is outside the data area, and is given as 0.
+        let mut pending: Vec<(String, u32, u32)> = Vec::new();
+        if self.fs().fs_type == FS_FAT32 as u8 {
+            if let Entry::Valid(clusters) = self.check_chain(&mut check, "/", self.fs().dirbase, None, issue)? {
+                pending.push((String::from("/"), self.fs().dirbase, clusters));
+            }
+        } else {
+            pending.push((String::from("/"), 0, 0));
+        }
+        while let Some((path, cluster, clusters)) = pending.pop() {
+            self.check_dir(&mut check, &path, cluster, clusters, &mut pending, issue)?;
+        }
+        Ok(check)
+    }
+
+    /// Returns the sectors of a directory, given its first cluster and the number of clusters
+    /// in its chain.
+    fn dir_sectors(&self, fat: &mut FatReader, cluster: u32, clusters: u32) -> Result<Vec<u32>, Error> {
+        if cluster == 0 {
+            let count = (self.fs().n_rootdir as u32 * ENTRY_LEN as u32).div_ceil(FF_MAX_SS);
+            return Ok((self.fs().dirbase..self.fs().dirbase + count).collect())
+        }
+        let mut sectors = Vec::new();
+        let mut cluster = cluster;
+        for _ in 0..clusters {
+            let first = self.cluster_sector(cluster);
+            sectors.extend(first..first + self.fs().csize as u32);
+            cluster = fat.entry(cluster)?;
+        }
+        Ok(sectors)
+    }
+
+    /// Checks each item in a directory, queueing its sub-directories.
+    fn check_dir(&self, check: &mut Check, path: &str, cluster: u32, clusters: u32,
+        pending: &mut Vec<(String, u32, u32)>, issue: &mut impl FnMut(&str, &FsckIssue)) -> Result<(), Error> {
+        let fat32 = self.fs().fs_type == FS_FAT32 as u8;
+        let mut buffer = [0; FF_MAX_SS as usize];
+        //The sector and offset of each long name entry since the last short name entry.
+        let mut long_name: Vec<(u32, usize)> = Vec::new();
+        for sector in self.dir_sectors(&mut check.fat, cluster, clusters)? {
+            self.read_sectors(sector, &mut buffer)?;
+            for offset in (0..FF_MAX_SS as usize).step_by(ENTRY_LEN) {
+                let entry = &buffer[offset..offset + ENTRY_LEN];
+                let attr = entry[11];
+                if entry[0] == 0 {
+                    return Ok(())
+                }
+                if entry[0] != DELETED && attr & ATTR_LONG_NAME == ATTR_LONG_NAME {
+                    long_name.try_reserve(1).map_err(|_| Error::NotEnoughCore)?;
+                    long_name.push((sector, offset));
+                    continue
+                }
+                let long_entries = core::mem::take(&mut long_name);
+                if entry[0] == DELETED || entry[0] == b'.' || attr & ATTR_VOLUME != 0 {
+                    continue
+                }
+                let high = if fat32 { u16::from_le_bytes([entry[20], entry[21]]) as u32 } else { 0 };
+                let first = high << 16 | u16::from_le_bytes([entry[26], entry[27]]) as u32;
+                let size = u32::from_le_bytes([entry[28], entry[29], entry[30], entry[31]]);
+                let directory = attr & ATTR_DIRECTORY != 0;
+                let mut item = String::from(path.trim_end_matches('/'));
+                item.push('/');
+                item.push_str(&short_name(entry));
+                let result = if directory {
+                    check.report.directories += 1;
+                    self.check_chain(check, &item, first, None, issue)?
+                } else {
+                    check.report.files += 1;
+                    self.check_chain(check, &item, first, Some(size), issue)?
+                };
+                //Rewrite the entry if its chain was repaired.
+                let (new_first, new_size) = match result {
+                    Entry::Valid(clusters) if directory => {
+                        pending.push((item, first, clusters));
+                        continue
+                    },
+                    Entry::Valid(clusters) => (first, size.min(clusters * self.fs().csize as u32 * FF_MAX_SS)),
+                    Entry::Clear => (0, 0)
+                };
+                if !check.repair || (new_first == first && new_size == size) {
+                    continue
+                }
+                let entry = &mut buffer[offset..offset + ENTRY_LEN];
+                if directory {
+                    let checksum = short_name_checksum(entry);
+                    entry[0] = DELETED;
+                    self.delete_long_name(&long_entries, checksum, sector, &mut buffer)?;
+                } else {
+                    entry[20..22].copy_from_slice(&((new_first >> 16) as u16).to_le_bytes());
+                    entry[26..28].copy_from_slice(&(new_first as u16).to_le_bytes());
+                    entry[28..32].copy_from_slice(&new_size.to_le_bytes());
+                }
+                self.write_sectors(sector, &buffer)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Deletes the long name entries of an item along with its short name entry, which is in
+    /// the given sector, already read into the buffer. Entries that do not match the checksum
+    /// of the short name belong to no item, and are left for FatFs to ignore.
+    fn delete_long_name(&self, entries: &[(u32, usize)], checksum: u8, sector: u32, buffer: &mut [u8; FF_MAX_SS as usize]) -> Result<(), Error> {
+        let mut other = [0; FF_MAX_SS as usize];
+        //The entries before the short name entry may start in an earlier sector.
+        let mut loaded = None;
+        for (entry_sector, offset) in entries {
+            let target = if *entry_sector == sector {
+                &mut buffer[..]
+            } else {
+                if loaded != Some(*entry_sector) {
+                    if let Some(number) = loaded {
+                        self.write_sectors(number, &other)?;
+                    }
+                    self.read_sectors(*entry_sector, &mut other)?;
+                    loaded = Some(*entry_sector);
+                }
+                &mut other[..]
+            };
+            if target[offset + 13] == checksum {
+                target[*offset] = DELETED;
+            }
+        }
+        if let Some(number) = loaded {
+            self.write_sectors(number, &other)?;
+        }
+        Ok(())
+    }
+
+    /// Follows the cluster chain of an item, marking its clusters as used. For files, the size
+    /// is compared with the chain. With repairs enabled, the chain is cut short as described
+    /// for `fsck()`.
+    fn check_chain(&self, check: &mut Check, path: &str, first: u32, size: Option<u32>,
+        issue: &mut impl FnMut(&str, &FsckIssue)) -> Result<Entry, Error> {
+        let cluster_size = self.fs().csize as u32 * FF_MAX_SS;
+        let valid = |cluster: u32| cluster >= 2 && cluster < self.fs().n_fatent;
+        if first == 0 && size.is_some_and(|size| size == 0) {
+            return Ok(Entry::Valid(0))
+        }
+        if first == 0 || !valid(first) || check.fat.entry(first)? == 0 || check.used.contains(first) {
+            let found = if first != 0 && valid(first) && check.used.contains(first) {
+                check.report.cross_links += 1;
+                FsckIssue::CrossLinked { cluster: first }
+            } else if first == 0 && size.is_some() {
+                check.report.size_mismatches += 1;
+                FsckIssue::SizeMismatch { size: size.unwrap_or(0), allocated: 0 }
+            } else {
+                check.report.invalid_clusters += 1;
+                FsckIssue::InvalidCluster { cluster: first }
+            };
+            issue(path, &found);
+            if check.repair {
+                check.report.repaired += 1;
+            }
+            return Ok(Entry::Clear)
+        }
+        let end = check.fat.end_of_chain();
+        let mut clusters = 0;
+        let mut cluster = first;
+        loop {
+            check.used.insert(cluster);
+            clusters += 1;
+            let next = check.fat.entry(cluster)?;
+            if next >= end - 7 && next != end - 8 {
+                break
+            }
+            let found = if !valid(next) || check.fat.entry(next)? == 0 {
+                check.report.invalid_clusters += 1;
+                FsckIssue::InvalidCluster { cluster: next }
+            } else if check.used.contains(next) {
+                check.report.cross_links += 1;
+                FsckIssue::CrossLinked { cluster: next }
+            } else {
+                cluster = next;
+                continue
+            };
+            issue(path, &found);
+            if check.repair {
+                check.fat.set_entry(cluster, end)?;
+                check.report.repaired += 1;
+            }
+            break
+        }
+        let size = match size {
+            Some(size) => size,
+            None => return Ok(Entry::Valid(clusters))
+        };
+        let needed = size.div_ceil(cluster_size);
+        if needed != clusters {
+            check.report.size_mismatches += 1;
+            issue(path, &FsckIssue::SizeMismatch { size, allocated: clusters.saturating_mul(cluster_size) });
+            if check.repair {
+                check.report.repaired += 1;
+                if needed < clusters {
+                    return self.cut_chain(check, first, needed)
+                }
+            }
+        }
+        Ok(Entry::Valid(clusters))
+    }
+
+    /// Cuts a chain after the given number of clusters, leaving the clusters after it
+    /// allocated but no longer marked as used, so that they are found as a lost chain.
+    fn cut_chain(&self, check: &mut Check, first: u32, keep: u32) -> Result<Entry, Error> {
+        let end = check.fat.end_of_chain();
+        let mut cluster = first;
+        for _ in 1..keep {
+            cluster = check.fat.entry(cluster)?;
+        }
+        let mut next = if keep == 0 { first } else { check.fat.entry(cluster)? };
+        if keep > 0 {
+            check.fat.set_entry(cluster, end)?;
+        }
+        while next >= 2 && next < self.fs().n_fatent && check.used.contains(next) {
+            check.used.remove(next);
+            next = check.fat.entry(next)?;
+        }
+        Ok(if keep == 0 { Entry::Clear } else { Entry::Valid(keep) })
+    }
+
+    /// Scans the FAT for allocated clusters that no item refers to, reporting each chain of
+    /// them by its first cluster.
+    fn find_lost(&self, check: &mut Check, issue: &mut impl FnMut(&str, &FsckIssue)) -> Result<(), Error> {
+        let bad = check.fat.bad_cluster();
+        //Lost clusters that another lost cluster links to, so are not the start of a chain.
+        let mut linked = ClusterSet::new(self.fs().n_fatent)?;
+        for cluster in 2..self.fs().n_fatent {
+            let next = check.fat.entry(cluster)?;
+            if next != 0 && next != bad && !check.used.contains(cluster) {
+                check.report.lost_clusters += 1;
+                linked.insert(next);
+            }
+        }
+        for cluster in 2..self.fs().n_fatent {
+            let next = check.fat.entry(cluster)?;
+            if next == 0 || next == bad || check.used.contains(cluster) || linked.contains(cluster) {
+                continue
+            }
+            let mut clusters = 1;
+            let mut next = next;
+            while next >= 2 && next < self.fs().n_fatent && !check.used.contains(next) && clusters < self.fs().n_fatent {
+                clusters += 1;
+                next = check.fat.entry(next)?;
+            }
+            check.report.lost_chains += 1;
+            issue("", &FsckIssue::LostChain { first_cluster: cluster, clusters });
+        }
+        Ok(())
+    }
+
+    /// Frees lost chains, which `fsck()` reports as allocated in the FAT but not belonging to
+    /// any item, or saves each of them as a file so that their data can be inspected, restoring
+    /// the free space they take up. Lost chains are typically left behind when power is lost
+    /// while files are written or deleted. A saved file holds every cluster of its chain, so
+    /// its data is followed by whatever the last cluster held. Cycles of lost clusters, which
+    /// have no first cluster, are freed in either case.
+    ///
+    /// The volume must have no open files, or `Error::Denied` is returned. Issues other than
+    /// lost chains are left alone, so `fsck()` should be run with repairs enabled first.
+    pub fn recover_lost(&self, action: LostChainAction) -> Result<RecoveryReport, Error> {
+        if self.fs().fs_type == 0 {
+            return Err(Error::NotEnabled)
+        }
+        if self.registry.borrow().iter().next().is_some() {
+            return Err(Error::Denied)
+        }
+        self.sync_fs()?;
+        let mut check = self.scan(false, &mut |_, _| ())?;
+        let mut report = RecoveryReport::default();
+        let bad = check.fat.bad_cluster();
+        let end = check.fat.end_of_chain();
+        let valid = |cluster: u32| cluster >= 2 && cluster < self.fs().n_fatent;
+        let mut linked = ClusterSet::new(self.fs().n_fatent)?;
+        for cluster in 2..self.fs().n_fatent {
+            let next = check.fat.entry(cluster)?;
+            if next != 0 && next != bad && !check.used.contains(cluster) {
+                report.lost_clusters += 1;
+                linked.insert(next);
+            }
+        }
+        //The first cluster and length of each chain to be saved.
+        let mut chains: Vec<(u32, u32)> = Vec::new();
+        for first in 2..self.fs().n_fatent {
+            let next = check.fat.entry(first)?;
+            if next == 0 || next == bad || check.used.contains(first) || linked.contains(first) {
+                continue
+            }
+            report.lost_chains += 1;
+            if action == LostChainAction::Free {
+                continue
+            }
+            //Follow the chain, marking it as used, and end it before any cluster that is not
+            //lost, or that already belongs to it.
+            let mut cluster = first;
+            let mut clusters = 1;
+            check.used.insert(first);
+            loop {
+                let next = check.fat.entry(cluster)?;
+                if next >= end - 7 {
+                    break
+                }
+                if !valid(next) || check.used.contains(next) || check.fat.entry(next)? == 0 {
+                    check.fat.set_entry(cluster, end)?;
+                    break
+                }
+                check.used.insert(next);
+                cluster = next;
+                clusters += 1;
+            }
+            chains.try_reserve(1).map_err(|_| Error::NotEnoughCore)?;
+            chains.push((first, clusters));
+        }
+        //Free the clusters that are still lost.
+        for cluster in 2..self.fs().n_fatent {
+            let next = check.fat.entry(cluster)?;
+            if next != 0 && next != bad && !check.used.contains(cluster) {
+                check.fat.set_entry(cluster, 0)?;
+                report.freed += 1;
+            }
+        }
+        check.fat.flush()?;
+        drop(check);
+        if report.freed > 0 {
+            //The free cluster count is no longer known, so is counted again by `getfree()`.
+            let fs = self.fs.get();
+            unsafe {
+                (*fs).free_clst = 0xFFFFFFFF;
+                (*fs).fsi_flag |= 1;
+            }
+            self.sync_fs()?;
+        }
+        if let LostChainAction::Save(dir) = action {
+            if !chains.is_empty() {
+                report.saved = self.save_chains(dir, &chains)?;
+            }
+        }
+        Ok(report)
+    }
+
+    /// Creates a file for each of the given chains, then points its directory entry at the
+    /// chain. Returns the number of files created.
+    fn save_chains(&self, dir: &str, chains: &[(u32, u32)]) -> Result<u32, Error> {
+        let mut path = PathBuffer::new(dir);
+        if !path::is_root(dir) {
+            match self.mkdir(path.as_str()) {
+                Ok(()) | Err(Error::Exists) => (),
+                Err(error) => return Err(error)
+            }
+        }
+        let cluster_size = self.fs().csize as u32 * FF_MAX_SS;
+        let mut number = 0;
+        let mut saved = 0;
+        for (first, clusters) in chains {
+            let file = loop {
+                if number > 9999 {
+                    return Err(Error::Denied)
+                }
+                let len = path.push(&alloc::format!("FILE{:04}.CHK", number));
+                number += 1;
+                let result = self.open_raw(path.as_str(), FileOptions::CreateNew | FileOptions::Write);
+                path.truncate(len);
+                match result {
+                    Ok(file) => break file,
+                    Err(Error::Exists) => continue,
+                    Err(error) => return Err(error)
+                }
+            };
+            //The directory entry of the file was in the window of the file system object when
+            //it was opened.
+            let sector_number = file.dir_sect;
+            let offset = (file.dir_ptr as usize).wrapping_sub(ptr::addr_of!(self.fs().win) as usize);
+            self.close(file).map_err(|(error, _)| error)?;
+            if offset.checked_add(ENTRY_LEN).is_none_or(|end| end > FF_MAX_SS as usize) {
+                return Err(Error::IntError)
+            }
+            self.sync_fs()?;
+            let mut sector = [0; FF_MAX_SS as usize];
+            self.read_sectors(sector_number, &mut sector)?;
+            let entry = &mut sector[offset..offset + ENTRY_LEN];
+            if self.fs().fs_type == FS_FAT32 as u8 {
+                entry[20..22].copy_from_slice(&((first >> 16) as u16).to_le_bytes());
+            }
+            entry[26..28].copy_from_slice(&(*first as u16).to_le_bytes());
+            entry[28..32].copy_from_slice(&clusters.saturating_mul(cluster_size).to_le_bytes());
+            self.write_sectors(sector_number, &sector)?;
+            saved += 1;
+        }
+        Ok(saved)
+    }
+}
@@ -0,0 +1,150 @@
+use crate::fatfs::*;
+use embedded_io::{Read, Write};
+
+/// Marks the start of a sparse image, followed by the sector size.
+const SPARSE_MAGIC: &[u8; 4] = b"FSIM";
+
+/// Error returned when streaming a volume image.
+#[derive(Debug, PartialEq)]
+pub enum ImageError<E> {
+    /// The reader or writer failed.
+    Io(E),
+    /// The image ended part way through a sector or run.
+    UnexpectedEof,
+    /// The image is not a sparse image of the expected sector size.
+    InvalidImage,
+    Other(Error)
+}
+
+impl<E> From<Error> for ImageError<E> {
+    fn from(error: Error) -> Self {
+        ImageError::Other(error)
+    }
+}
+
+/// Fills the buffer from the reader, returning the number of bytes read before the end of the stream.
+fn fill<R: Read>(reader: &mut R, buffer: &mut [u8]) -> Result<usize, ImageError<R::Error>> {
+    let mut len = 0;
+    while len < buffer.len() {
+        match reader.read(&mut buffer[len..]).map_err(ImageError::Io)? {
+            0 => break,
+            count => len += count
+        }
+    }
+    Ok(len)
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32, ImageError<R::Error>> {
+    let mut bytes = [0; 4];
+    if fill(reader, &mut bytes)? != 4 {
+        return Err(ImageError::UnexpectedEof)
+    }
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn write_all<W: Write>(writer: &mut W, data: &[u8]) -> Result<(), ImageError<W::Error>> {
+    writer.write_all(data).map_err(ImageError::Io)
+}
+
+/// Copies a run of sectors from the installed driver to the writer.
+fn export_sectors<W: Write>(drive: u8, writer: &mut W, buffer: &mut [u8; FF_MAX_SS as usize], first: u32, count: u32) -> Result<u32, ImageError<W::Error>> {
+    for sector in first..first + count {
+        diskio::read_sectors(drive, buffer, sector)?;
+        write_all(writer, buffer)?;
+    }
+    Ok(count)
+}
+
+impl RawFileSystem {
+    /// Streams an image of the device of the installed driver to the writer, for example
+    /// to back it up over a network. With `CloneMode::AllSectors` the image is a raw copy
+    /// of every sector. With `CloneMode::UsedSectors` only the sectors in use are written,
+    /// in a sparse format of runs that `import_image()` restores. Cached data is written
+    /// back first. Returns the number of sectors written.
+    pub fn export_image<W: Write>(&self, writer: &mut W, mode: CloneMode) -> Result<u32, ImageError<W::Error>> {
+        let drive = self.fs().pdrv;
+        if self.fs().fs_type != 0 {
+            self.sync_fs()?;
+        } else if mode == CloneMode::UsedSectors {
+            return Err(Error::NotEnabled.into())
+        }
+        let mut buffer = [0; FF_MAX_SS as usize];
+        let mut exported = 0;
+        match mode {
+            CloneMode::AllSectors => {
+                exported = export_sectors(drive, writer, &mut buffer, 0, diskio::sector_count(drive)?)?;
+            },
+            CloneMode::UsedSectors => {
+                //Each run is written as its first sector and number of sectors, followed by its data.
+                //A run of zero sectors ends the image.
+                write_all(writer, SPARSE_MAGIC)?;
+                write_all(writer, &FF_MAX_SS.to_le_bytes())?;
+                self.used_sector_runs(|first, count| -> Result<(), ImageError<W::Error>> {
+                    write_all(writer, &first.to_le_bytes())?;
+                    write_all(writer, &count.to_le_bytes())?;
+                    exported += export_sectors(drive, writer, &mut buffer, first, count)?;
+                    Ok(())
+                })?;
+                write_all(writer, &[0; 8])?;
+            }
+        }
+        writer.flush().map_err(ImageError::Io)?;
+        Ok(exported)
+    }
+
+    /// Restores an image written by `export_image()` with the same mode to the device of
+    /// the installed driver. The volume is unmounted first and must be mounted again once
+    /// the import is complete. Returns the number of sectors restored.
+    pub fn import_image<R: Read>(&mut self, reader: &mut R, mode: CloneMode) -> Result<u32, ImageError<R::Error>> {
+        let drive = self.fs().pdrv;
+        if self.fs().fs_type != 0 {
+            self.unmount("")?;
+        }
+        let mut buffer = [0; FF_MAX_SS as usize];
+        let mut imported = 0;
+        match mode {
+            CloneMode::AllSectors => {
+                let sector_count = diskio::sector_count(drive)?;
+                loop {
+                    match fill(reader, &mut buffer)? {
+                        0 => break,
+                        len if len < buffer.len() => return Err(ImageError::UnexpectedEof),
+                        _ => ()
+                    }
+                    if imported >= sector_count {
+                        return Err(Error::Denied.into())
+                    }
+                    diskio::write_sectors(drive, &buffer, imported)?;
+                    imported += 1;
+                }
+            },
+            CloneMode::UsedSectors => {
+                let mut magic = [0; 4];
+                if fill(reader, &mut magic)? != 4 || &magic != SPARSE_MAGIC || read_u32(reader)? != FF_MAX_SS {
+                    return Err(ImageError::InvalidImage)
+                }
+                let sector_count = diskio::sector_count(drive)?;
+                loop {
+                    let first = read_u32(reader)?;
+                    let count = read_u32(reader)?;
+                    if count == 0 {
+                        break
+                    }
+                    //A run that does not fit on the device is rejected before any of it is written.
+                    let end = match first.checked_add(count) {
+                        Some(end) if end <= sector_count => end,
+                        _ => return Err(ImageError::InvalidImage)
+                    };
+                    for sector in first..end {
+                        if fill(reader, &mut buffer)? != buffer.len() {
+                            return Err(ImageError::UnexpectedEof)
+                        }
+                        diskio::write_sectors(drive, &buffer, sector)?;
+                        imported += 1;
+                    }
+                }
+            }
+        }
+        Ok(imported)
+    }
+}
@@ -0,0 +1,159 @@
+//! `embedded-io` and `embedded-io-async` adapters for files, so that files may be handed
+//! to protocol stacks and other code written against those traits.
+//!
+//! ```ignore
+//! let file = locked_fs.open_raw("upload.bin", FileOptions::Read)?;
+//! let mut reader = IoFile::new(&locked_fs, file);
+//! http_client.post(&mut reader)?;
+//! locked_fs.close(reader.into_inner())?;
+//! ```
+
+use core::mem;
+use crate::fatfs::*;
+use embedded_io::{ErrorKind, ErrorType};
+
+impl embedded_io::Error for Error {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Error::NoFile | Error::NoPath => ErrorKind::NotFound,
+            Error::Denied | Error::WriteProtected | Error::Locked => ErrorKind::PermissionDenied,
+            Error::Exists => ErrorKind::AlreadyExists,
+            Error::InvalidName | Error::InvalidParameter => ErrorKind::InvalidInput,
+            Error::Timeout => ErrorKind::TimedOut,
+            Error::NotEnoughCore => ErrorKind::OutOfMemory,
+            Error::Cancelled => ErrorKind::Interrupted,
+            _ => ErrorKind::Other
+        }
+    }
+}
+
+impl From<embedded_io::SeekFrom> for SeekFrom {
+    fn from(position: embedded_io::SeekFrom) -> Self {
+        match position {
+            embedded_io::SeekFrom::Start(offset) => SeekFrom::Start(offset),
+            embedded_io::SeekFrom::End(offset) => SeekFrom::End(offset),
+            embedded_io::SeekFrom::Current(offset) => SeekFrom::Current(offset)
+        }
+    }
+}
+
+/// A file together with the file system it is read from and written to, implementing the
+/// blocking `embedded-io` traits. Flushing syncs the file. The file is closed when the
+/// adapter is dropped, discarding any error.
+pub struct IoFile<'a> {
+    fs: &'a RawFileSystem,
+    file: File
+}
+
+impl<'a> IoFile<'a> {
+    pub fn new(fs: &'a RawFileSystem, file: File) -> Self {
+        Self { fs, file }
+    }
+
+    /// Returns the file, which must then be closed manually.
+    pub fn into_inner(mut self) -> File {
+        mem::take(&mut self.file)
+    }
+}
+
+impl Drop for IoFile<'_> {
+    fn drop(&mut self) {
+        //FatFs clears the file system pointer of a file when it is closed.
+        if !self.file.obj.fs.is_null() {
+            let _ = self.fs.close(mem::take(&mut self.file));
+        }
+    }
+}
+
+impl ErrorType for IoFile<'_> {
+    type Error = Error;
+}
+
+impl embedded_io::Read for IoFile<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        Ok(self.fs.read(&mut self.file, buf)? as usize)
+    }
+}
+
+impl embedded_io::Write for IoFile<'_> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        let written = self.fs.write(&mut self.file, buf)? as usize;
+        //FatFs writes nothing without an error when the volume is full, which an `embedded-io`
+        //writer must not return for a non-empty buffer.
+        if written == 0 && !buf.is_empty() {
+            return Err(Error::Denied)
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.fs.sync(&mut self.file)
+    }
+}
+
+impl embedded_io::Seek for IoFile<'_> {
+    fn seek(&mut self, position: embedded_io::SeekFrom) -> Result<u64, Error> {
+        Ok(self.fs.seek_from(&mut self.file, position.into())? as u64)
+    }
+}
+
+/// A file together with the locked file system, implementing the `embedded-io-async`
+/// traits. Exclusive access to the file system is acquired for each operation and released
+/// again afterwards, so that other tasks may use the file system between operations. Reads
+/// and writes are made with `read_async()` and `write_async()`. If the adapter is dropped,
+/// its file is closed the next time the lock is acquired, as an `AutoCloseFile` is.
+#[cfg(feature = "embedded-io-async")]
+pub struct AsyncIoFile<'a> {
+    fs: &'a FileSystem,
+    file: AutoCloseFile
+}
+
+#[cfg(feature = "embedded-io-async")]
+impl<'a> AsyncIoFile<'a> {
+    pub fn new(fs: &'a FileSystem, file: File) -> Self {
+        Self { fs, file: AutoCloseFile::new(file) }
+    }
+
+    /// Returns the file, which must then be closed manually.
+    pub fn into_inner(self) -> File {
+        self.file.into_inner()
+    }
+}
+
+#[cfg(feature = "embedded-io-async")]
+impl ErrorType for AsyncIoFile<'_> {
+    type Error = Error;
+}
+
+#[cfg(feature = "embedded-io-async")]
+impl embedded_io_async::Read for AsyncIoFile<'_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let fs = self.fs.lock().await;
+        Ok(fs.read_async(&mut self.file, buf).await? as usize)
+    }
+}
+
+#[cfg(feature = "embedded-io-async")]
+impl embedded_io_async::Write for AsyncIoFile<'_> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        let fs = self.fs.lock().await;
+        let written = fs.write_async(&mut self.file, buf).await? as usize;
+        if written == 0 && !buf.is_empty() {
+            return Err(Error::Denied)
+        }
+        Ok(written)
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        let fs = self.fs.lock().await;
+        fs.sync(&mut self.file)
+    }
+}
+
+#[cfg(feature = "embedded-io-async")]
+impl embedded_io_async::Seek for AsyncIoFile<'_> {
+    async fn seek(&mut self, position: embedded_io::SeekFrom) -> Result<u64, Error> {
+        let fs = self.fs.lock().await;
+        Ok(fs.seek_from(&mut self.file, position.into())? as u64)
+    }
+}
@@ -0,0 +1,257 @@
+//! A small persistent key-value store, with string keys and byte values, kept in a single
+//! file on the volume.
+//!
+//! The file is a log of records, each setting or removing a key, so an update only appends
+//! to the file. Each record carries a CRC-32, and a partial record left at the end of the file
+//! by a power loss is discarded when the store is opened. Records made obsolete by later ones
+//! are reclaimed by `compact()`, which rewrites the file through a temporary file.
+//!
+//! ```ignore
+//! let mut store = KvStore::open(&locked_fs, "settings.kv")?;
+//! store.set(&locked_fs, "volume", &[7])?;
+//! let volume = store.get(&locked_fs, "volume")?;
+//! if store.garbage() > 4096 {
+//!     store.compact(&locked_fs)?;
+//! }
+//! store.close(&locked_fs)?;
+//! ```
+
+use core::mem;
+use alloc::vec::Vec;
+use crate::fatfs::*;
+use crate::fatfs::crc::crc32;
+use crate::fatfs::path::{self, PathBuffer};
+
+/// Length of the header of a record: the key length, the kind of record, the value length
+/// and the CRC.
+const HEADER_LEN: usize = 8;
+const SET: u8 = 0;
+const REMOVE: u8 = 1;
+
+/// Largest length of a key in bytes.
+pub const MAX_KEY_LEN: usize = u8::MAX as usize;
+/// Largest length of a value in bytes.
+pub const MAX_VALUE_LEN: usize = u16::MAX as usize;
+
+/// A key and the location of its value in the file.
+struct Entry {
+    key: String,
+    offset: u32,
+    len: u16
+}
+
+impl Entry {
+    /// Size of the record that set the value.
+    fn record_len(&self) -> u32 {
+        (HEADER_LEN + self.key.len() + self.len as usize) as u32
+    }
+}
+
+/// Returns a record setting or removing a key.
+fn encode(kind: u8, key: &str, value: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(HEADER_LEN + key.len() + value.len());
+    record.push(key.len() as u8);
+    record.push(kind);
+    record.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    let crc = crc32(crc32(crc32(0, &record), key.as_bytes()), value);
+    record.extend_from_slice(&crc.to_le_bytes());
+    record.extend_from_slice(key.as_bytes());
+    record.extend_from_slice(value);
+    record
+}
+
+/// A key-value store open on a file. The keys are held in memory, along with the location of
+/// their values in the file, while values are read from the file when requested.
+pub struct KvStore {
+    path: PathBuffer,
+    file: File,
+    entries: Vec<Entry>,
+    garbage: u32
+}
+
+impl KvStore {
+    /// Opens the store in the file at the given path, creating it if it does not exist. A
+    /// partial or corrupt record, and everything after it, is removed from the file.
+    pub fn open(fs: &RawFileSystem, path: &str) -> Result<Self, Error> {
+        let file = fs.open_raw(path, FileOptions::OpenAlways | FileOptions::Read | FileOptions::Write)?;
+        let mut store = Self { path: PathBuffer::new(path), file, entries: Vec::new(), garbage: 0 };
+        match store.load(fs) {
+            Ok(()) => Ok(store),
+            Err(error) => {
+                let _ = fs.close(store.file);
+                Err(error)
+            }
+        }
+    }
+
+    /// Reads the keys from the file, truncating it after the last valid record.
+    fn load(&mut self, fs: &RawFileSystem) -> Result<(), Error> {
+        self.entries.clear();
+        self.garbage = 0;
+        let size = self.file.len();
+        let mut offset = 0;
+        let mut header = [0; HEADER_LEN];
+        while offset + HEADER_LEN as u32 <= size {
+            if fs.read_at(&mut self.file, offset, &mut header)? as usize != HEADER_LEN {
+                break
+            }
+            let [key_len, kind, len_low, len_high, c0, c1, c2, c3] = header;
+            let len = u16::from_le_bytes([len_low, len_high]);
+            let record_len = (HEADER_LEN + key_len as usize + len as usize) as u32;
+            if offset + record_len > size || (kind != SET && kind != REMOVE) {
+                break
+            }
+            let mut data = alloc::vec![0; key_len as usize + len as usize];
+            if fs.read_at(&mut self.file, offset + HEADER_LEN as u32, &mut data)? as usize != data.len() ||
+                u32::from_le_bytes([c0, c1, c2, c3]) != crc32(crc32(0, &header[..4]), &data) {
+                break
+            }
+            data.truncate(key_len as usize);
+            let key = match String::from_utf8(data) {
+                Ok(key) => key,
+                Err(_) => break
+            };
+            if let Some(index) = self.find(&key) {
+                self.garbage += self.entries.swap_remove(index).record_len();
+            }
+            if kind == SET {
+                self.entries.push(Entry { key, offset: offset + HEADER_LEN as u32 + key_len as u32, len });
+            } else {
+                self.garbage += record_len;
+            }
+            offset += record_len;
+        }
+        if offset < size {
+            fs.seek(&mut self.file, offset)?;
+            fs.truncate(&mut self.file)?;
+            fs.sync(&mut self.file)?;
+        }
+        Ok(())
+    }
+
+    fn find(&self, key: &str) -> Option<usize> {
+        self.entries.iter().position(|entry| entry.key == key)
+    }
+
+    /// Appends a record to the file and syncs it, returning the offset of the record. A
+    /// record that is only partly written is removed again.
+    fn append(&mut self, fs: &RawFileSystem, record: &[u8]) -> Result<u32, Error> {
+        let offset = self.file.len();
+        fs.seek(&mut self.file, offset)?;
+        let result = fs.write_all(&mut self.file, record).map_err(Error::from)
+            .and_then(|_| fs.sync(&mut self.file));
+        if result.is_err() && fs.seek(&mut self.file, offset).is_ok() {
+            let _ = fs.truncate(&mut self.file);
+        }
+        result.map(|_| offset)
+    }
+
+    /// Returns the value of the given key, or `None` if it is not set.
+    pub fn get(&mut self, fs: &RawFileSystem, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        let (offset, len) = match self.find(key) {
+            Some(index) => (self.entries[index].offset, self.entries[index].len),
+            None => return Ok(None)
+        };
+        let mut value = alloc::vec![0; len as usize];
+        if fs.read_at(&mut self.file, offset, &mut value)? as usize != value.len() {
+            return Err(Error::IntError)
+        }
+        Ok(Some(value))
+    }
+
+    /// Sets the value of the given key. Keys must not be empty, and are limited to
+    /// `MAX_KEY_LEN` bytes and values to `MAX_VALUE_LEN` bytes, or `Error::InvalidParameter`
+    /// results. The record is synced to storage before returning.
+    pub fn set(&mut self, fs: &RawFileSystem, key: &str, value: &[u8]) -> Result<(), Error> {
+        if key.is_empty() || key.len() > MAX_KEY_LEN || value.len() > MAX_VALUE_LEN {
+            return Err(Error::InvalidParameter)
+        }
+        let offset = self.append(fs, &encode(SET, key, value))?;
+        if let Some(index) = self.find(key) {
+            self.garbage += self.entries.swap_remove(index).record_len();
+        }
+        let offset = offset + (HEADER_LEN + key.len()) as u32;
+        self.entries.push(Entry { key: String::from(key), offset, len: value.len() as u16 });
+        Ok(())
+    }
+
+    /// Removes the given key, returning true if it was set.
+    pub fn remove(&mut self, fs: &RawFileSystem, key: &str) -> Result<bool, Error> {
+        let index = match self.find(key) {
+            Some(index) => index,
+            None => return Ok(false)
+        };
+        let record = encode(REMOVE, key, &[]);
+        self.append(fs, &record)?;
+        self.garbage += self.entries.swap_remove(index).record_len() + record.len() as u32;
+        Ok(true)
+    }
+
+    /// Returns true if the given key is set.
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.find(key).is_some()
+    }
+
+    /// Returns an iterator over the keys that are set, in no particular order.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|entry| entry.key.as_str())
+    }
+
+    /// Returns the number of keys that are set.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the number of bytes in the file held by records that have been replaced or
+    /// removed, which `compact()` would reclaim.
+    pub fn garbage(&self) -> u32 {
+        self.garbage
+    }
+
+    /// Rewrites the file with only the current value of each key. The new file is written
+    /// and synced as a temporary file in the same directory, then renamed over the old one
+    /// with `rename_replace()`, so a power loss leaves either the old or the new file.
+    pub fn compact(&mut self, fs: &RawFileSystem) -> Result<(), Error> {
+        let (mut temp, temp_path) = fs.create_temp(path::parent(self.path.as_str()), "KV")?;
+        let mut result = Ok(());
+        for index in 0..self.entries.len() {
+            let key = self.entries[index].key.clone();
+            result = match self.get(fs, &key) {
+                Ok(Some(value)) => fs.write_all(&mut temp, &encode(SET, &key, &value)).map_err(Error::from),
+                Ok(None) => Err(Error::IntError),
+                Err(error) => Err(error)
+            };
+            if result.is_err() {
+                break
+            }
+        }
+        let result = result.and_then(|_| fs.sync(&mut temp));
+        let closed = fs.close(temp).map_err(Error::from);
+        if let Err(error) = result.and(closed) {
+            let _ = fs.unlink(&temp_path);
+            return Err(error)
+        }
+        //The old file must be closed before it can be replaced.
+        if let Err((error, file)) = fs.close(mem::take(&mut self.file)) {
+            self.file = file;
+            let _ = fs.unlink(&temp_path);
+            return Err(error)
+        }
+        let renamed = fs.rename_replace(&temp_path, self.path.as_str());
+        if renamed.is_err() {
+            let _ = fs.unlink(&temp_path);
+        }
+        self.file = fs.open_raw(self.path.as_str(), FileOptions::OpenAlways | FileOptions::Read | FileOptions::Write)?;
+        self.load(fs)?;
+        renamed
+    }
+
+    /// Closes the file holding the store.
+    pub fn close(self, fs: &RawFileSystem) -> Result<(), Error> {
+        fs.close(self.file).map_err(Error::from)
+    }
+}
@@ -0,0 +1,524 @@
+use core::cell::{RefCell, UnsafeCell};
+use core::future::poll_fn;
+use core::ops::{Deref, DerefMut};
+use core::task::Poll;
+use alloc::string::String;
+use alloc::vec::Vec;
+use embassy_futures::yield_now;
+use embassy_sync::blocking_mutex::{Mutex, raw::ThreadModeRawMutex};
+use embassy_sync::waitqueue::MultiWakerRegistration;
+use crate::fatfs::*;
+
+struct LockState {
+    readers: usize,
+    writer: bool,
+    //Tasks waiting in `lock()`, which new readers give way to.
+    writers_waiting: usize,
+    waiters: MultiWakerRegistration<4>
+}
+
+/// A read-write lock around the file system. Any number of tasks may hold read access
+/// at once, while write access is exclusive. Tasks waiting for write access take
+/// priority over new readers so that writers are not starved.
+///
+/// FatFs calls are never interleaved with one another, because they run to completion
+/// in thread mode. Read access therefore only restricts which operations are available
+/// through `ReadOnlyFileSystem`.
+pub struct FileSystem {
+    state: Mutex<ThreadModeRawMutex, RefCell<LockState>>,
+    inner: UnsafeCell<RawFileSystem>
+}
+
+unsafe impl Sync for FileSystem {}
+
+impl FileSystem {
+    pub(crate) const fn new(fs: RawFileSystem) -> Self {
+        Self {
+            state: Mutex::new(RefCell::new(LockState {
+                readers: 0,
+                writer: false,
+                writers_waiting: 0,
+                waiters: MultiWakerRegistration::new()
+            })),
+            inner: UnsafeCell::new(fs)
+        }
+    }
+
+    /// Acquires exclusive access to the file system, waiting until all other tasks have
+    /// released their locks. Files dropped as `AutoCloseFile`s are closed first.
+    pub async fn lock(&self) -> FileSystemGuard<'_> {
+        let mut waiting = WaitingWriter { lock: self, counted: false };
+        let guard = poll_fn(|cx| {
+            self.state.lock(|state| {
+                let mut state = state.borrow_mut();
+                if state.writer || state.readers > 0 {
+                    if !waiting.counted {
+                        state.writers_waiting += 1;
+                        waiting.counted = true;
+                    }
+                    state.waiters.register(cx.waker());
+                    Poll::Pending
+                } else {
+                    if waiting.counted {
+                        state.writers_waiting -= 1;
+                        waiting.counted = false;
+                    }
+                    state.writer = true;
+                    Poll::Ready(FileSystemGuard { lock: self })
+                }
+            })
+        }).await;
+        //Close the files dropped as `AutoCloseFile`s since the lock was last held.
+        guard.process_pending_closes();
+        guard
+    }
+
+    /// Acquires shared access to the file system for read-only operations. Other tasks may
+    /// hold read access at the same time.
+    pub async fn read(&self) -> ReadGuard<'_> {
+        poll_fn(|cx| {
+            self.state.lock(|state| {
+                let mut state = state.borrow_mut();
+                if state.writer || state.writers_waiting > 0 {
+                    state.waiters.register(cx.waker());
+                    Poll::Pending
+                } else {
+                    state.readers += 1;
+                    Poll::Ready(ReadGuard { lock: self })
+                }
+            })
+        }).await
+    }
+
+    /// Runs a batch of operations with exclusive access to the file system, acquiring the
+    /// lock once for the whole batch. No other task can change the volume between the
+    /// operations, for example between checking that a file exists and opening it.
+    pub async fn with_fs<R>(&self, batch: impl FnOnce(&mut RawFileSystem) -> R) -> R {
+        let mut fs = self.lock().await;
+        batch(&mut fs)
+    }
+
+    /// Runs a batch of read-only operations with shared access to the file system, acquiring
+    /// the lock once for the whole batch.
+    pub async fn with_fs_read<R>(&self, batch: impl FnOnce(&ReadOnlyFileSystem) -> R) -> R {
+        let fs = self.read().await;
+        batch(&fs)
+    }
+
+    /// Reads from the file into the buffer in chunks of at most `chunk_size` bytes, acquiring
+    /// shared access for each chunk and yielding to the executor between them, so that a
+    /// large read neither blocks other tasks nor holds the lock for its whole length. Returns
+    /// the number of bytes read, which is less than the buffer length at the end of the file.
+    pub async fn read_chunked(&self, file: &mut File, buffer: &mut [u8], chunk_size: usize) -> Result<u32, Error> {
+        if chunk_size == 0 {
+            return Err(Error::InvalidParameter)
+        }
+        let mut total = 0;
+        for chunk in buffer.chunks_mut(chunk_size) {
+            let read = self.read().await.read(file, chunk)?;
+            total += read;
+            if (read as usize) < chunk.len() {
+                break
+            }
+            yield_now().await;
+        }
+        Ok(total)
+    }
+
+    /// Writes the data to the file in chunks of at most `chunk_size` bytes, acquiring
+    /// exclusive access for each chunk and yielding to the executor between them. Returns the
+    /// number of bytes written, which is less than the data length if the volume is full.
+    pub async fn write_chunked(&self, file: &mut File, data: &[u8], chunk_size: usize) -> Result<u32, Error> {
+        if chunk_size == 0 {
+            return Err(Error::InvalidParameter)
+        }
+        let mut total = 0;
+        for chunk in data.chunks(chunk_size) {
+            let written = self.lock().await.write(file, chunk)?;
+            total += written;
+            if (written as usize) < chunk.len() {
+                break
+            }
+            yield_now().await;
+        }
+        Ok(total)
+    }
+
+    fn release(&self, writer: bool) {
+        self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            if writer {
+                state.writer = false;
+            } else {
+                state.readers -= 1;
+            }
+            state.waiters.wake();
+        });
+    }
+}
+
+/// A task waiting in `lock()`. It is counted among the waiting writers from the first time it
+/// has to wait until it acquires the lock, or until the future is dropped without acquiring it.
+struct WaitingWriter<'a> {
+    lock: &'a FileSystem,
+    counted: bool
+}
+
+impl Drop for WaitingWriter<'_> {
+    fn drop(&mut self) {
+        if self.counted {
+            self.lock.state.lock(|state| {
+                let mut state = state.borrow_mut();
+                state.writers_waiting -= 1;
+                state.waiters.wake();
+            });
+        }
+    }
+}
+
+/// Exclusive access to the file system. The lock is released when this guard is dropped.
+pub struct FileSystemGuard<'a> {
+    lock: &'a FileSystem
+}
+
+impl Deref for FileSystemGuard<'_> {
+    type Target = RawFileSystem;
+
+    fn deref(&self) -> &RawFileSystem {
+        unsafe { &*self.lock.inner.get() }
+    }
+}
+
+impl DerefMut for FileSystemGuard<'_> {
+    fn deref_mut(&mut self) -> &mut RawFileSystem {
+        unsafe { &mut *self.lock.inner.get() }
+    }
+}
+
+impl Drop for FileSystemGuard<'_> {
+    fn drop(&mut self) {
+        self.lock.release(true);
+    }
+}
+
+/// Shared access to the file system. The lock is released when this guard is dropped.
+pub struct ReadGuard<'a> {
+    lock: &'a FileSystem
+}
+
+impl Deref for ReadGuard<'_> {
+    type Target = ReadOnlyFileSystem;
+
+    fn deref(&self) -> &ReadOnlyFileSystem {
+        let fs: *const RawFileSystem = self.lock.inner.get();
+        unsafe { &*fs.cast() }
+    }
+}
+
+impl Drop for ReadGuard<'_> {
+    fn drop(&mut self) {
+        self.lock.release(false);
+    }
+}
+
+/// The subset of the file system API that does not modify the volume. Obtained by
+/// acquiring read access with `FileSystem::read()`.
+#[repr(transparent)]
+pub struct ReadOnlyFileSystem(RawFileSystem);
+
+impl ReadOnlyFileSystem {
+    /// Opens the file at the given path for reading. The returned file borrows the file
+    /// system, and so cannot outlive the lock guard.
+    pub fn open(&self, path: &str) -> Result<ScopedFile<'_>, Error> {
+        self.0.open(path, FileOptions::Read)
+    }
+
+    /// Opens the file at the given path for reading, returning the bare file object. It must
+    /// be closed with `close()` while the lock is held.
+    pub fn open_raw(&self, path: &str) -> Result<File, Error> {
+        self.0.open_raw(path, FileOptions::Read)
+    }
+
+    /// Closes the given file. If closing fails, the file is returned along with the error.
+    #[allow(clippy::result_large_err)]
+    pub fn close(&self, file: File) -> Result<(), (Error, File)> {
+        self.0.close(file)
+    }
+
+    /// Read data from the given file. The length of the provided buffer determines the length of data read.
+    pub fn read(&self, file: &mut File, buffer: &mut [u8]) -> Result<u32, Error> {
+        self.0.read(file, buffer)
+    }
+
+    /// Move to an offset in the given file.
+    pub fn seek(&self, file: &mut File, offset: u32) -> Result<(), Error> {
+        self.0.seek(file, offset)
+    }
+
+    /// Move to a position relative to the start, end or current position of the given file.
+    pub fn seek_from(&self, file: &mut File, position: SeekFrom) -> Result<u32, Error> {
+        self.0.seek_from(file, position)
+    }
+
+    /// Enables fast seek on the given file, building its cluster link map table.
+    pub fn enable_fast_seek(&self, file: &mut File) -> Result<(), Error> {
+        self.0.enable_fast_seek(file)
+    }
+
+    /// Disables fast seek on the given file and releases its cluster link map table.
+    pub fn disable_fast_seek(&self, file: &mut File) {
+        self.0.disable_fast_seek(file)
+    }
+
+    /// Returns true if the clusters of the given file occupy consecutive sectors.
+    pub fn is_contiguous(&self, file: &mut File) -> Result<bool, Error> {
+        self.0.is_contiguous(file)
+    }
+
+    /// Returns the runs of consecutive sectors holding the data of the given file.
+    pub fn sector_extents(&self, file: &mut File) -> Result<Vec<SectorExtent>, Error> {
+        self.0.sector_extents(file)
+    }
+
+    /// Returns the cluster chain of the file or directory at the given path.
+    pub fn cluster_chain(&self, path: &str) -> Result<Vec<ClusterRun>, Error> {
+        self.0.cluster_chain(path)
+    }
+
+    /// Returns how the cluster chain of the file or directory at the given path is fragmented.
+    pub fn fragmentation(&self, path: &str) -> Result<Fragmentation, Error> {
+        self.0.fragmentation(path)
+    }
+
+    /// Reads whole sectors from the drive, starting at the given logical block address.
+    pub fn read_sectors(&self, sector: u32, buffer: &mut [u8]) -> Result<(), Error> {
+        self.0.read_sectors(sector, buffer)
+    }
+
+    /// Reads data from the given offset in the file, then moves back to the previous position.
+    pub fn read_at(&self, file: &mut File, offset: u32, buffer: &mut [u8]) -> Result<u32, Error> {
+        self.0.read_at(file, offset, buffer)
+    }
+
+    /// Reads data from the file into each buffer in turn.
+    pub fn read_vectored(&self, file: &mut File, buffers: &mut [&mut [u8]]) -> Result<u32, Error> {
+        self.0.read_vectored(file, buffers)
+    }
+
+    /// Fills the buffer with data read from the file.
+    pub fn read_exact(&self, file: &mut File, buffer: &mut [u8]) -> Result<(), ReadExactError> {
+        self.0.read_exact(file, buffer)
+    }
+
+    /// Reads from the current position to the end of the file, appending the data to the buffer.
+    pub fn read_to_end(&self, file: &mut File, buffer: &mut Vec<u8>) -> Result<u32, Error> {
+        self.0.read_to_end(file, buffer)
+    }
+
+    /// Reads from the current position to the end of the file, appending the data to the string.
+    pub fn read_to_string(&self, file: &mut File, string: &mut String) -> Result<u32, Error> {
+        self.0.read_to_string(file, string)
+    }
+
+    /// Reads the whole file at the given path.
+    pub fn read_file(&self, path: &str) -> Result<Vec<u8>, Error> {
+        self.0.read_file(path)
+    }
+
+    /// Reads the whole file at the given path, which must contain valid UTF-8.
+    pub fn read_file_to_string(&self, path: &str) -> Result<String, Error> {
+        self.0.read_file_to_string(path)
+    }
+
+    /// Reads the file at the given path in chunks, passing each to the callback with the progress.
+    pub fn stream_file(&self, path: &str, chunk_size: usize, callback: impl FnMut(&[u8], u8) -> Result<(), Error>) -> Result<u32, Error> {
+        self.0.stream_file(path, chunk_size, callback)
+    }
+
+    /// Computes a checksum of the content of the file at the given path.
+    pub fn checksum(&self, path: &str, algorithm: ChecksumAlgorithm) -> Result<Checksum, Error> {
+        self.0.checksum(path, algorithm)
+    }
+
+    /// Computes a checksum of a file, stopping once the token is cancelled.
+    pub fn checksum_cancellable(&self, path: &str, algorithm: ChecksumAlgorithm, cancel: &CancelToken) -> Result<Checksum, Error> {
+        self.0.checksum_cancellable(path, algorithm, cancel)
+    }
+
+    /// Reads the file at the given path and deserializes it with `postcard`.
+    #[cfg(feature = "postcard")]
+    pub fn read_struct<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<T, StructError> {
+        self.0.read_struct(path)
+    }
+
+    /// Reads the file at the given path and deserializes it as JSON.
+    #[cfg(feature = "json")]
+    pub fn read_json<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<T, StructError> {
+        self.0.read_json(path)
+    }
+
+    /// Opens a directory. On success, the Directory object is returned.
+    pub fn opendir(&self, path: &str) -> Result<Directory, Error> {
+        self.0.opendir(path)
+    }
+
+    /// Closes the given directory. If closing fails, the directory is returned along with the error.
+    pub fn closedir(&self, dir: Directory) -> Result<(), (Error, Directory)> {
+        self.0.closedir(dir)
+    }
+
+    /// Gets information about items within the given directory.
+    /// Each call to this function returns the next item in sequence, until a null string is returned.
+    pub fn readdir(&self, dir: &mut Directory) -> Result<FileInfo, Error> {
+        self.0.readdir(dir)
+    }
+
+    /// Opens the directory at the given path and iterates over its items.
+    pub fn iter_dir(&self, path: &str) -> Result<DirIter<'_>, Error> {
+        self.0.iter_dir(path)
+    }
+
+    /// Opens the directory at the given path and iterates over its items, skipping items
+    /// with any of the given attributes.
+    pub fn iter_dir_filtered(&self, path: &str, exclude: FileAttributes) -> Result<DirIter<'_>, Error> {
+        self.0.iter_dir_filtered(path, exclude)
+    }
+
+    /// Fills the page with the information of the items of the directory at the given path,
+    /// starting from the item at `offset`.
+    pub fn list_page(&self, path: &str, offset: usize, page: &mut [FileInfo]) -> Result<usize, Error> {
+        self.0.list_page(path, offset, page)
+    }
+
+    /// Returns an entry for every item in the directory at the given path.
+    pub fn read_dir(&self, path: &str) -> Result<Vec<DirEntry>, Error> {
+        self.0.read_dir(path)
+    }
+
+    /// Walks the tree beneath the directory at the given path, depth first.
+    pub fn walk(&self, path: &str) -> Result<Walk<'_>, Error> {
+        self.0.walk(path)
+    }
+
+    /// Adds up the sizes of the files beneath the directory at the given path.
+    pub fn du(&self, path: &str) -> Result<DiskUsage, Error> {
+        self.0.du(path)
+    }
+
+    /// Walks the tree beneath the directory at the given path, yielding the items with the given attributes.
+    pub fn find_with_attr(&self, path: &str, attr: FileAttributes, mask: FileAttributes) -> Result<impl Iterator<Item = Result<WalkEntry, Error>> + '_, Error> {
+        self.0.find_with_attr(path, attr, mask)
+    }
+
+    /// Walks the tree beneath the directory at the given path, yielding the files modified since the given time.
+    #[cfg(feature = "chrono")]
+    pub fn find_modified_since(&self, path: &str, timestamp: chrono::NaiveDateTime) -> Result<impl Iterator<Item = Result<WalkEntry, Error>> + '_, Error> {
+        self.0.find_modified_since(path, timestamp)
+    }
+
+    /// Moves back to the first item of the given directory.
+    pub fn rewinddir(&self, dir: &mut Directory) -> Result<(), Error> {
+        self.0.rewinddir(dir)
+    }
+
+    /// Iterates over the remaining items of the given directory, yielding the name and metadata of each.
+    #[cfg(feature = "heapless")]
+    pub fn readdir_names<'a, const N: usize>(&'a self, dir: &'a mut Directory) -> DirNames<'a, N> {
+        self.0.readdir_names(dir)
+    }
+
+    /// Find the first item that matches the given pattern.
+    pub fn findfirst(&self, path: &str, pattern: &str) -> Result<(Directory, FileInfo), Error> {
+        self.0.findfirst(path, pattern)
+    }
+
+    /// Iterates over the items of the directory at the given path whose names match the pattern.
+    pub fn find(&self, path: &str, pattern: &str) -> Result<FindIter<'_>, Error> {
+        self.0.find(path, pattern)
+    }
+
+    /// Returns the next item that matches a pattern following a call to `findfirst()`.
+    pub fn findnext(&self, dir: &mut Directory) -> Result<FileInfo, Error> {
+        self.0.findnext(dir)
+    }
+
+    /// Returns information about a file at the given path.
+    pub fn stat(&self, path: &str) -> Result<FileInfo, Error> {
+        self.0.stat(path)
+    }
+
+    /// Opens the file at the given path for reading and returns it along with its information.
+    pub fn open_with_info(&self, path: &str) -> Result<(File, FileInfo), Error> {
+        self.0.open_with_info(path, FileOptions::Read)
+    }
+
+    /// Returns the information of every item in the directory at the given path.
+    pub fn stat_children(&self, path: &str) -> Result<Vec<FileInfo>, Error> {
+        self.0.stat_children(path)
+    }
+
+    /// Returns information about the item at the given path, or `None` if it does not exist.
+    pub fn try_stat(&self, path: &str) -> Result<Option<FileInfo>, Error> {
+        self.0.try_stat(path)
+    }
+
+    /// Returns true if a file or directory exists at the given path.
+    pub fn exists(&self, path: &str) -> Result<bool, Error> {
+        self.0.exists(path)
+    }
+
+    /// Returns true if a file exists at the given path.
+    pub fn is_file(&self, path: &str) -> Result<bool, Error> {
+        self.0.is_file(path)
+    }
+
+    /// Returns true if a directory exists at the given path.
+    pub fn is_dir(&self, path: &str) -> Result<bool, Error> {
+        self.0.is_dir(path)
+    }
+
+    /// Checks the content of the file at the given path against its checksum sidecar.
+    pub fn verify(&self, path: &str) -> Result<bool, Error> {
+        self.0.verify(path)
+    }
+
+    /// Returns the mount metadata read and updated when the volume was mounted.
+    pub fn mount_info(&self) -> Option<MountInfo> {
+        self.0.mount_info()
+    }
+
+    /// Retrieves full path name of the current directory of the current drive.
+    pub fn getcwd(&self, buffer: &mut String) -> Result<(), Error> {
+        self.0.getcwd(buffer)
+    }
+
+    /// Get number of free clusters on the drive.
+    pub fn getfree(&self, path: &str) -> Result<u32, Error> {
+        self.0.getfree(path)
+    }
+
+    /// Returns the total and free space of the volume in bytes.
+    pub fn stats(&self, path: &str) -> Result<FsStats, Error> {
+        self.0.stats(path)
+    }
+
+    /// Returns the serial number of the volume.
+    pub fn volume_serial(&self, path: &str) -> Result<u32, Error> {
+        self.0.volume_serial(path)
+    }
+
+    /// Returns the label, serial number, type of FAT, capacity and sector and cluster sizes of the volume.
+    pub fn volume_info(&self, path: &str) -> Result<VolumeInfo, Error> {
+        self.0.volume_info(path)
+    }
+
+    /// Get the volume label.
+    pub fn getlabel(&self, path: &str, label: &mut String) -> Result<u32, Error> {
+        self.0.getlabel(path, label)
+    }
+
+    /// Read a string from the file.
+    pub fn gets(&self, file: &mut File, buffer: &mut String) -> Result<(), Error> {
+        self.0.gets(file, buffer)
+    }
+}
@@ -0,0 +1,213 @@
+//! A builder for the parameters given to `mkfs()`.
+//!
+//! ```ignore
+//! let report = locked_fs.mkfs("", MkfsOptions::new().format(FormatOptions::FAT32).au_size(32768))?;
+//! assert_eq!(report.cluster_size, 32768);
+//! ```
+
+use crate::fatfs::*;
+use crate::fatfs::boot::FatType;
+
+/// Error returned by `MkfsOptions::validate()`. FatFs silently replaces invalid parameters
+/// with its defaults, so they are reported before it is called.
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum MkfsOptionsError {
+    /// The number of FATs is not 1 or 2.
+    InvalidFatCopies,
+    /// The alignment is not 0 or a power of 2 no greater than 32768 sectors.
+    InvalidAlignment,
+    /// The allocation unit size is not 0 or a power of 2 from the sector size to 16 MiB.
+    InvalidAuSize,
+    /// The number of root directory entries is not a multiple of the number of entries in
+    /// a sector, from 1 to 32768.
+    InvalidRootEntries
+}
+
+impl From<MkfsOptionsError> for Error {
+    fn from(_: MkfsOptionsError) -> Self {
+        Error::InvalidParameter
+    }
+}
+
+/// The layout of a volume formatted by `mkfs()`, showing what was chosen for parameters
+/// left at 0.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MkfsReport {
+    pub fat_type: FatType,
+    /// Size of a cluster in bytes, chosen by FatFs from the size of the volume if the
+    /// allocation unit size was 0.
+    pub cluster_size: u32,
+    /// Alignment of the data area in sectors, the erase block size reported by the driver
+    /// if the alignment was 0.
+    pub alignment: u32,
+    /// Number of sectors in the volume.
+    pub sector_count: u32,
+    pub cluster_count: u32
+}
+
+/// Parameters for formatting a volume. The defaults are those FatFs uses when it is given
+/// none: the FAT type is chosen from the size of the volume, with one FAT, 512 root directory
+/// entries on FAT12 and FAT16, and the allocation unit size and alignment chosen by FatFs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MkfsOptions {
+    format: FormatOptions,
+    fat_copies: u8,
+    alignment: u32,
+    au_size: u32,
+    root_entries: u32,
+    oem_name: Option<[u8; 8]>,
+    serial: Option<u32>
+}
+
+impl Default for MkfsOptions {
+    fn default() -> Self {
+        Self {
+            format: FormatOptions::Any,
+            fat_copies: 1,
+            alignment: 0,
+            au_size: 0,
+            root_entries: 512,
+            oem_name: None,
+            serial: None
+        }
+    }
+}
+
+impl MkfsOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the options the SD Association's file system specification gives for a card
+    /// of the given number of 512 byte sectors, as used by its SD Formatter: the FAT type,
+    /// cluster size and alignment of the data area to the card's boundary unit follow the
+    /// capacity class of the card, with two FATs. Cards over 32 GB are specified as exFAT,
+    /// which is not enabled in this configuration of FatFs, so they are formatted as FAT32
+    /// with the settings of the largest SDHC cards.
+    pub fn sd_standard(sector_count: u32) -> Self {
+        const MB: u32 = 2048;
+        //Cluster size in bytes and boundary unit in sectors for each capacity class.
+        let (format, au_size, alignment) = match sector_count {
+            count if count <= 8 * MB => (FormatOptions::FAT, 8192, 16),
+            count if count <= 64 * MB => (FormatOptions::FAT, 16384, 32),
+            count if count <= 256 * MB => (FormatOptions::FAT, 16384, 64),
+            count if count <= 1024 * MB => (FormatOptions::FAT, 16384, 128),
+            count if count <= 2048 * MB => (FormatOptions::FAT, 32768, 128),
+            _ => (FormatOptions::FAT32, 32768, 8192)
+        };
+        Self { format, fat_copies: 2, alignment, au_size, ..Self::default() }
+    }
+
+    /// Selects the FAT types that may be used. FatFs picks among them from the size of the volume.
+    pub fn format(&mut self, format: FormatOptions) -> &mut Self {
+        self.format = format;
+        self
+    }
+
+    /// Sets the number of FATs, 1 or 2. A second FAT is a copy of the first.
+    pub fn fat_copies(&mut self, fat_copies: u8) -> &mut Self {
+        self.fat_copies = fat_copies;
+        self
+    }
+
+    /// Aligns the data area to a multiple of the given number of sectors, a power of 2. With
+    /// 0, the erase block size reported by the driver for `GET_BLOCK_SIZE` is used, or 1 if
+    /// the driver reports an invalid size.
+    pub fn alignment(&mut self, alignment: u32) -> &mut Self {
+        self.alignment = alignment;
+        self
+    }
+
+    /// Sets the size of a cluster in bytes, a power of 2 from the sector size to 16 MiB. With
+    /// 0, FatFs chooses the size from the size of the volume.
+    pub fn au_size(&mut self, au_size: u32) -> &mut Self {
+        self.au_size = au_size;
+        self
+    }
+
+    /// Sets the number of entries in the root directory of a FAT12 or FAT16 volume. It is
+    /// ignored on FAT32, where the root directory grows as needed.
+    pub fn root_entries(&mut self, root_entries: u32) -> &mut Self {
+        self.root_entries = root_entries;
+        self
+    }
+
+    /// Sets the OEM name written to the boot sector in place of `MSDOS5.0`.
+    pub fn oem_name(&mut self, oem_name: [u8; 8]) -> &mut Self {
+        self.oem_name = Some(oem_name);
+        self
+    }
+
+    /// Sets the volume serial number, which FatFs otherwise derives from the time.
+    pub fn serial(&mut self, serial: u32) -> &mut Self {
+        self.serial = Some(serial);
+        self
+    }
+
+    /// Checks the options, returning the first that is invalid.
+    pub fn validate(&self) -> Result<(), MkfsOptionsError> {
+        if !(1..=2).contains(&self.fat_copies) {
+            return Err(MkfsOptionsError::InvalidFatCopies)
+        }
+        if self.alignment > 0x8000 || (self.alignment != 0 && !self.alignment.is_power_of_two()) {
+            return Err(MkfsOptionsError::InvalidAlignment)
+        }
+        if self.au_size != 0 && (!self.au_size.is_power_of_two() || !(FF_MAX_SS..=0x1000000).contains(&self.au_size)) {
+            return Err(MkfsOptionsError::InvalidAuSize)
+        }
+        if !(1..=32768).contains(&self.root_entries) || !self.root_entries.is_multiple_of(FF_MAX_SS / 32) {
+            return Err(MkfsOptionsError::InvalidRootEntries)
+        }
+        Ok(())
+    }
+
+    /// Returns the parameters given to FatFs for the given drive, querying the driver for an
+    /// alignment of 0 so that the alignment used is known.
+    pub(crate) fn parameters(&self, drive: u8) -> Result<MKFS_PARM, Error> {
+        self.validate()?;
+        let alignment = match self.alignment {
+            0 => {
+                diskio::initialize(drive)?;
+                //FatFs uses 1 when the driver does not report the size.
+                diskio::block_size(drive).unwrap_or(1)
+            },
+            alignment => alignment
+        };
+        Ok(MKFS_PARM {
+            fmt: self.format.as_u8(),
+            n_fat: self.fat_copies,
+            //FatFs falls back to 1 in the same way.
+            align: if alignment.is_power_of_two() && alignment <= 0x8000 { alignment } else { 1 },
+            n_root: self.root_entries,
+            au_size: self.au_size
+        })
+    }
+
+    /// Sets the OEM name and serial number chosen, if any, on the volume formatted, then
+    /// returns its layout.
+    pub(crate) fn finish(&self, fs: &RawFileSystem, parameters: &MKFS_PARM) -> Result<MkfsReport, Error> {
+        if self.oem_name.is_some() || self.serial.is_some() {
+            boot::set_identity(fs, self.oem_name.as_ref(), self.serial)?;
+        }
+        let boot = boot::read_selected(fs)?;
+        Ok(MkfsReport {
+            fat_type: boot.fat_type,
+            cluster_size: boot.sectors_per_cluster as u32 * boot.bytes_per_sector as u32,
+            alignment: parameters.align,
+            sector_count: boot.total_sectors,
+            cluster_count: boot.cluster_count
+        })
+    }
+}
+
+impl RawFileSystem {
+    /// Formats the drive with `MkfsOptions::sd_standard()` for the number of sectors the
+    /// driver reports, so that the card performs as well as when it left the factory and is
+    /// recognized by hosts.
+    pub fn mkfs_sd_standard(&self, path: &str) -> Result<MkfsReport, Error> {
+        let drive = self.fs().pdrv;
+        diskio::initialize(drive)?;
+        self.mkfs(path, &MkfsOptions::sd_standard(diskio::sector_count(drive)?))
+    }
+}
@@ -0,0 +1,119 @@
+use crate::fatfs::*;
+use crate::fatfs::crc::crc32;
+use crate::fatfs::path::PathBuffer;
+
+/// Hidden file holding the mount metadata record.
+const MOUNT_INFO_PATH: &str = "/.mountinfo";
+const MAGIC: u32 = 0x3154_4E4D; //"MNT1"
+const RECORD_LEN: usize = 28;
+
+/// Metadata maintained on the volume when mount tracking is enabled with
+/// `set_mount_tracking()`. Timestamps are in the packed FAT format returned by
+/// `get_fattime()`, and are 0 when the `chrono` feature is disabled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MountInfo {
+    /// Number of times the volume has been mounted while tracking was enabled.
+    pub mount_count: u32,
+    /// Number of mounts that were not followed by a call to `unmount()`, such as after a power loss.
+    pub unclean_unmounts: u32,
+    /// Time of the current mount.
+    pub last_mount: u32,
+    /// Time of the last call to `unmount()`.
+    pub last_clean_unmount: u32,
+    /// Whether the previous mount ended with a call to `unmount()`.
+    pub previous_unmount_clean: bool
+}
+
+impl RawFileSystem {
+    /// Enables maintaining a small metadata record on the volume, updated at each mount and
+    /// unmount, that counts mounts and unclean unmounts. Failures to update the record, for
+    /// example on a write protected card, do not cause mounting to fail; `mount_info()` then
+    /// returns `None`. Disabled by default.
+    pub fn set_mount_tracking(&mut self, enabled: bool) {
+        self.mount_tracking = enabled;
+    }
+
+    /// Returns the mount metadata read and updated when the volume was mounted.
+    pub fn mount_info(&self) -> Option<MountInfo> {
+        self.mount_info.get()
+    }
+
+    /// Reads the metadata record, counts the new mount and marks the volume as mounted.
+    pub(crate) fn track_mount(&self) {
+        self.mount_info.set(None);
+        if !self.mount_tracking {
+            return
+        }
+        let now = diskio::fattime();
+        let info = match self.read_mount_record() {
+            Ok(Some((mut info, mounted))) => {
+                info.mount_count = info.mount_count.wrapping_add(1);
+                info.previous_unmount_clean = !mounted;
+                if mounted {
+                    info.unclean_unmounts = info.unclean_unmounts.wrapping_add(1);
+                }
+                info.last_mount = now;
+                info
+            },
+            Ok(None) => MountInfo { mount_count: 1, last_mount: now, previous_unmount_clean: true, ..Default::default() },
+            Err(_) => return
+        };
+        if self.write_mount_record(&info, true).is_ok() {
+            self.mount_info.set(Some(info));
+        }
+    }
+
+    /// Marks the volume as cleanly unmounted.
+    pub(crate) fn track_unmount(&self) {
+        if let Some(mut info) = self.mount_info.take() {
+            info.last_clean_unmount = diskio::fattime();
+            let _ = self.write_mount_record(&info, false);
+        }
+    }
+
+    /// Returns the stored record and whether the volume was marked as mounted, or `None` if
+    /// there is no valid record.
+    fn read_mount_record(&self) -> Result<Option<(MountInfo, bool)>, Error> {
+        let path = PathBuffer::new(MOUNT_INFO_PATH);
+        let mut file = match self.open_raw(path.as_str(), FileOptions::Read) {
+            Ok(file) => file,
+            Err(Error::NoFile) => return Ok(None),
+            Err(error) => return Err(error)
+        };
+        let mut bytes = [0; RECORD_LEN];
+        let result = self.read(&mut file, &mut bytes);
+        self.close(file)?;
+        if result? as usize != RECORD_LEN {
+            return Ok(None)
+        }
+        let field = |index: usize| u32::from_le_bytes([bytes[index * 4], bytes[index * 4 + 1], bytes[index * 4 + 2], bytes[index * 4 + 3]]);
+        if field(0) != MAGIC || field(6) != crc32(0, &bytes[..RECORD_LEN - 4]) {
+            return Ok(None)
+        }
+        let info = MountInfo {
+            mount_count: field(1),
+            unclean_unmounts: field(2),
+            last_mount: field(3),
+            last_clean_unmount: field(4),
+            previous_unmount_clean: true
+        };
+        Ok(Some((info, field(5) != 0)))
+    }
+
+    fn write_mount_record(&self, info: &MountInfo, mounted: bool) -> Result<(), Error> {
+        let path = PathBuffer::new(MOUNT_INFO_PATH);
+        let mut bytes = [0; RECORD_LEN];
+        let fields = [MAGIC, info.mount_count, info.unclean_unmounts, info.last_mount, info.last_clean_unmount, mounted as u32];
+        for (chunk, field) in bytes.chunks_exact_mut(4).zip(fields) {
+            chunk.copy_from_slice(&field.to_le_bytes());
+        }
+        let crc = crc32(0, &bytes[..RECORD_LEN - 4]);
+        bytes[RECORD_LEN - 4..].copy_from_slice(&crc.to_le_bytes());
+        let mut file = self.open_raw(path.as_str(), FileOptions::OpenAlways | FileOptions::Write)?;
+        let result = self.write(&mut file, &bytes).and_then(|_| self.sync(&mut file));
+        self.close(file)?;
+        result?;
+        let hidden = FileAttributes::Hidden | FileAttributes::System;
+        self.chmod(path.as_str(), hidden, hidden)
+    }
+}
@@ -0,0 +1,84 @@
+use core::cell::RefCell;
+use core::future::poll_fn;
+use core::task::Poll;
+use alloc::string::String;
+use alloc::vec::Vec;
+use embassy_sync::blocking_mutex::{Mutex, raw::ThreadModeRawMutex};
+use embassy_sync::waitqueue::MultiWakerRegistration;
+use crate::fatfs::*;
+
+struct LockTable {
+    held: Vec<String>,
+    waiters: MultiWakerRegistration<4>
+}
+
+/// Paths currently held by a `NamedLock`.
+static LOCKS: Mutex<ThreadModeRawMutex, RefCell<LockTable>> = Mutex::new(RefCell::new(LockTable {
+    held: Vec::new(),
+    waiters: MultiWakerRegistration::new()
+}));
+
+/// FAT names are case insensitive, so paths are compared the same way.
+fn same_path(a: &str, b: &str) -> bool {
+    a.trim_end_matches('/').eq_ignore_ascii_case(b.trim_end_matches('/'))
+}
+
+/// An advisory lock on a path, held in RAM. Tasks that coordinate on the same file, such
+/// as a producer and a consumer of a log, acquire a lock on its path before using it.
+/// The lock does not prevent access to the file system itself and is independent of the
+/// file system lock, so it may be held across many acquisitions of `FS`.
+/// The lock is released when this object is dropped.
+pub struct NamedLock {
+    path: String
+}
+
+impl NamedLock {
+    /// Acquires a lock on the given path, waiting until any other task releases it.
+    pub async fn acquire(path: &str) -> NamedLock {
+        poll_fn(|cx| {
+            LOCKS.lock(|table| {
+                let mut table = table.borrow_mut();
+                if table.held.iter().any(|held| same_path(held, path)) {
+                    table.waiters.register(cx.waker());
+                    Poll::Pending
+                } else {
+                    table.held.push(String::from(path));
+                    Poll::Ready(NamedLock { path: String::from(path) })
+                }
+            })
+        }).await
+    }
+
+    /// Acquires a lock on the given path if no other task holds it.
+    pub fn try_acquire(path: &str) -> Option<NamedLock> {
+        LOCKS.lock(|table| {
+            let mut table = table.borrow_mut();
+            if table.held.iter().any(|held| same_path(held, path)) {
+                None
+            } else {
+                table.held.push(String::from(path));
+                Some(NamedLock { path: String::from(path) })
+            }
+        })
+    }
+
+    /// Returns the path this lock is held on.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Releases the lock. Equivalent to dropping it.
+    pub fn release(self) {}
+}
+
+impl Drop for NamedLock {
+    fn drop(&mut self) {
+        LOCKS.lock(|table| {
+            let mut table = table.borrow_mut();
+            if let Some(index) = table.held.iter().position(|held| *held == self.path) {
+                table.held.swap_remove(index);
+            }
+            table.waiters.wake();
+        });
+    }
+}
@@ -0,0 +1,141 @@
+//! A builder for the mode files are opened in, following `std::fs::OpenOptions`.
+//!
+//! ```ignore
+//! let file = OpenOptions::new().write(true).create_new(true).open(&locked_fs, "/lock")?;
+//! ```
+
+use crate::fatfs::*;
+
+/// Error returned by `OpenOptions::open()`. Combinations of options that cannot be satisfied
+/// are reported before FatFs is called.
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum OpenOptionsError {
+    /// None of read, write or append access was requested.
+    NoAccessMode,
+    /// Creating or truncating a file was requested without write or append access.
+    WriteRequired,
+    /// Both truncating and appending to a file were requested.
+    TruncateWithAppend,
+    Other(Error)
+}
+
+impl From<Error> for OpenOptionsError {
+    fn from(error: Error) -> Self {
+        OpenOptionsError::Other(error)
+    }
+}
+
+impl From<OpenOptionsError> for Error {
+    fn from(error: OpenOptionsError) -> Self {
+        match error {
+            OpenOptionsError::Other(error) => error,
+            _ => Error::InvalidParameter
+        }
+    }
+}
+
+/// Options for opening a file, mapped to the `FileOptions` flags given to FatFs. All options
+/// are initially false.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct OpenOptions {
+    read: bool,
+    write: bool,
+    append: bool,
+    truncate: bool,
+    create: bool,
+    create_new: bool
+}
+
+impl OpenOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens the file for reading.
+    pub fn read(&mut self, read: bool) -> &mut Self {
+        self.read = read;
+        self
+    }
+
+    /// Opens the file for writing.
+    pub fn write(&mut self, write: bool) -> &mut Self {
+        self.write = write;
+        self
+    }
+
+    /// Opens the file for writing, starting at the end of the file.
+    pub fn append(&mut self, append: bool) -> &mut Self {
+        self.append = append;
+        self
+    }
+
+    /// Truncates an existing file to zero length. Requires write access.
+    pub fn truncate(&mut self, truncate: bool) -> &mut Self {
+        self.truncate = truncate;
+        self
+    }
+
+    /// Creates the file if it does not exist. Requires write or append access.
+    pub fn create(&mut self, create: bool) -> &mut Self {
+        self.create = create;
+        self
+    }
+
+    /// Creates the file, failing with `Error::Exists` if it already exists. `create()` and
+    /// `truncate()` are ignored when set. Requires write or append access.
+    pub fn create_new(&mut self, create_new: bool) -> &mut Self {
+        self.create_new = create_new;
+        self
+    }
+
+    /// Returns the `FileOptions` flags for these options, or the reason they are invalid.
+    /// Truncating or appending to a file that is not created is done after it is opened, so
+    /// is not reflected in the flags.
+    pub fn mode(&self) -> Result<FileOptions, OpenOptionsError> {
+        if !self.read && !self.write && !self.append {
+            return Err(OpenOptionsError::NoAccessMode)
+        }
+        if (self.create || self.create_new || self.truncate) && !self.write && !self.append {
+            return Err(OpenOptionsError::WriteRequired)
+        }
+        if self.truncate && self.append && !self.create_new {
+            return Err(OpenOptionsError::TruncateWithAppend)
+        }
+        let mut mode = FileOptions::empty();
+        if self.read {
+            mode |= FileOptions::Read;
+        }
+        if self.write || self.append {
+            mode |= FileOptions::Write;
+        }
+        mode |= match (self.create_new, self.create, self.truncate, self.append) {
+            (true, _, _, _) => FileOptions::CreateNew,
+            (false, true, true, _) => FileOptions::CreateAlways,
+            (false, true, false, true) => FileOptions::OpenAppend,
+            (false, true, false, false) => FileOptions::OpenAlways,
+            (false, false, _, _) => FileOptions::OpenExisting
+        };
+        Ok(mode)
+    }
+
+    /// Opens the file at the given path with these options.
+    pub fn open(&self, fs: &RawFileSystem, path: &str) -> Result<File, OpenOptionsError> {
+        let mode = self.mode()?;
+        let mut file = fs.open_raw(path, mode)?;
+        let size = file.obj.objsize;
+        //FatFs only truncates or appends to files it is allowed to create.
+        let result = match (self.create || self.create_new, self.truncate, self.append) {
+            (false, true, _) => fs.truncate(&mut file),
+            (false, false, true) => fs.seek(&mut file, size),
+            _ => Ok(())
+        };
+        match result {
+            Ok(()) => Ok(file),
+            Err(error) => {
+                let _ = fs.close(file);
+                Err(error.into())
+            }
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use crate::fatfs::*;
+use crate::fatfs::path::PathBuffer;
+
+/// The size of a partition created by `partition()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PartitionSpec {
+    /// A percentage of the drive, from 1 to 100.
+    Percent(u8),
+    /// A number of sectors, which must be over 100 as FatFs reads smaller sizes as percentages.
+    Sectors(u32)
+}
+
+/// Volume to partition mapping table read by FatFs. Partition `0` selects the first FAT
+/// volume found on the drive, and partitions `1` to `4` the entries of its partition table.
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+static mut VolToPart: [PARTITION; FF_VOLUMES as usize] = [PARTITION { pd: 0, pt: 0 }];
+
+/// Selects the partition mounted by subsequent calls to `mount()`.
+fn set_partition(partition: u8) {
+    unsafe { VolToPart[0].pt = partition; }
+}
+
+/// Returns the partition mounted or formatted by FatFs.
+pub(crate) fn selected_partition() -> u8 {
+    unsafe { VolToPart[0].pt }
+}
+
+impl RawFileSystem {
+    /// Mounts the volume with the given label, comparing labels without regard to case.
+    /// Each partition of the drive is probed in turn, followed by the drive as a whole if it
+    /// has no partition table. Returns the partition number of the mounted volume, which
+    /// is `0` for a drive without a partition table. Subsequent calls to `mount()` mount the
+    /// same partition. As with `mount()`, files and directories opened on the previous volume
+    /// are invalidated. Fails with `Error::NoFileSystem` if no volume matches, in which case
+    /// the partition selected before is selected again and mounted if it was mounted.
+    pub fn mount_by_label(&mut self, label: &str) -> Result<u8, Error> {
+        self.mount_matching(|volume_label, _| volume_label.eq_ignore_ascii_case(label.trim_end_matches('\0').as_bytes()))
+    }
+
+    /// Mounts the volume with the given serial number, in the same way as `mount_by_label()`.
+    pub fn mount_by_serial(&mut self, serial: u32) -> Result<u8, Error> {
+        self.mount_matching(|_, volume_serial| volume_serial == serial)
+    }
+
+    fn mount_matching(&mut self, matches: impl Fn(&[u8], u32) -> bool) -> Result<u8, Error> {
+        let previous = selected_partition();
+        let was_mounted = self.fs().fs_type != 0;
+        //Probing replaces the mounted volume, so nothing opened on it stays usable.
+        self.invalidate_stat_cache();
+        self.invalidate_dir_cache();
+        self.registry.borrow_mut().clear();
+        self.find_patterns.borrow_mut().clear();
+        let root = PathBuffer::new("");
+        for partition in [1, 2, 3, 4, 0] {
+            set_partition(partition);
+            //Probe without `mount()`, so that volumes that do not match are not written to.
+            *self.fs.get_mut() = FATFS::default();
+            let mut result;
+            unsafe { result = f_mount(self.fs.get(), root.as_str().as_ptr().cast(), 1); }
+            if result != FRESULT_FR_OK {
+                continue
+            }
+            let mut label = [0u8; 34];
+            let mut serial = 0;
+            unsafe { result = f_getlabel(root.as_str().as_ptr().cast(), label.as_mut_ptr().cast(), ptr::addr_of_mut!(serial)); }
+            let len = label.iter().position(|c| *c == 0).unwrap_or(label.len());
+            if result == FRESULT_FR_OK && matches(&label[..len], serial) {
+                return self.mount().map(|_| partition)
+            }
+        }
+        set_partition(previous);
+        if was_mounted {
+            //The volume may have been removed since, which is reported by the next operation.
+            let _ = self.mount();
+        } else {
+            *self.fs.get_mut() = FATFS::default();
+            unsafe { f_mount(ptr::null_mut(), root.as_str().as_ptr().cast(), 0); }
+        }
+        Err(Error::NoFileSystem)
+    }
+
+    /// Selects the partition of the drive that `mkfs()` formats and `mount()` mounts: `0` for
+    /// the first FAT volume on the drive, or `1` to `4` for an entry of its partition table.
+    /// Formatting a partition requires the drive to have a partition table, created with
+    /// `partition()`, and leaves the other partitions intact. Fails with
+    /// `Error::InvalidParameter` for other partition numbers.
+    pub fn select_partition(&self, partition: u8) -> Result<(), Error> {
+        if partition > 4 {
+            return Err(Error::InvalidParameter)
+        }
+        set_partition(partition);
+        Ok(())
+    }
+
+    /// Writes a new partition table to the drive, dividing it into up to four primary
+    /// partitions of the given sizes in turn. Each partition is aligned to the erase block size
+    /// reported by the driver, and is cut short at the end of the drive. The partitions are
+    /// then formatted one at a time by selecting each with `select_partition()` and calling
+    /// `mkfs()`. Any volume on the drive is lost, and a mounted volume must be mounted again
+    /// once formatted. Fails with `Error::InvalidParameter` if no sizes or more than four are
+    /// given, or a size is out of range.
+    pub fn partition(&self, drive: u8, sizes: &[PartitionSpec]) -> Result<(), Error> {
+        if sizes.is_empty() || sizes.len() > 4 {
+            return Err(Error::InvalidParameter)
+        }
+        //FatFs reads up to four sizes, stopping at the first that is 0.
+        let mut table: [LBA_t; 4] = [0; 4];
+        for (entry, size) in table.iter_mut().zip(sizes) {
+            *entry = match *size {
+                PartitionSpec::Percent(percent) if (1..=100).contains(&percent) => percent as LBA_t,
+                PartitionSpec::Sectors(sectors) if sectors > 100 => sectors,
+                _ => return Err(Error::InvalidParameter)
+            };
+        }
+        self.invalidate_stat_cache();
+        self.invalidate_dir_cache();
+        self.registry.borrow_mut().clear();
+        self.find_patterns.borrow_mut().clear();
+        let result;
+        let mut work: [u8; FF_MAX_SS as usize] = [0; FF_MAX_SS as usize];
+        unsafe { result = f_fdisk(drive, table.as_ptr(), work.as_mut_ptr().cast()); }
+        if result != FRESULT_FR_OK {
+            return Err(Error::from_result(result))
+        }
+        //The volume mounted from the old layout is gone, as after `mkfs()`.
+        if drive == self.fs().pdrv {
+            let fs = self.fs.get();
+            unsafe { (*fs).fs_type = 0; }
+        }
+        Ok(())
+    }
+}
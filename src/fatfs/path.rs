@@ -0,0 +1,90 @@
+use crate::fatfs::*;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// A path built up by the crate. The path is always followed by a NUL terminator in
+/// memory, so that `as_str()` may be passed directly to the FatFs API.
+pub(crate) struct PathBuffer {
+    buffer: String
+}
+
+impl PathBuffer {
+    pub(crate) fn new(path: &str) -> Self {
+        let path = path.trim_end_matches('\0');
+        let mut buffer = String::with_capacity(path.len() + 1);
+        buffer.push_str(path);
+        buffer.push('\0');
+        Self { buffer }
+    }
+
+    pub(crate) fn as_str(&self) -> &str {
+        &self.buffer[..self.len()]
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.buffer.len() - 1
+    }
+
+    /// Appends a name to the path and returns the previous length of the path, to be
+    /// passed to `truncate()` to remove the name again.
+    pub(crate) fn push(&mut self, name: &str) -> usize {
+        let len = self.len();
+        self.buffer.pop();
+        if len > 0 && !self.buffer.ends_with(['/', '\\']) {
+            self.buffer.push('/');
+        }
+        self.buffer.push_str(name);
+        self.buffer.push('\0');
+        len
+    }
+
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.buffer.truncate(len);
+        self.buffer.push('\0');
+    }
+}
+
+/// Returns true if the path refers to the root directory of a volume.
+pub(crate) fn is_root(path: &str) -> bool {
+    let path = path.trim_end_matches(['/', '\\', '\0']);
+    path.is_empty() || path.ends_with(':')
+}
+
+/// Returns the drive prefix of a path, such as `0` for `0:/log.txt`, or `None` if the path
+/// is on the current drive.
+pub(crate) fn drive(path: &str) -> Option<&str> {
+    path.split_once(':').map(|(drive, _)| drive)
+}
+
+/// Returns the path of the directory holding the item at the given path. The separator is
+/// kept for an item in the root directory, as `0:` alone refers to the current directory.
+pub(crate) fn parent(path: &str) -> &str {
+    let path = path.trim_end_matches('\0');
+    match path.rfind(['/', '\\']) {
+        Some(index) if is_root(&path[..index]) => &path[..=index],
+        Some(index) => &path[..index],
+        None => path.rfind(':').map_or("", |index| &path[..=index])
+    }
+}
+
+/// Joins a relative path onto an absolute base path, resolving `.` and `..` components,
+/// and returns the result as an absolute path using `/` as the separator.
+pub(crate) fn normalize(base: &str, path: &str) -> String {
+    let mut components: Vec<&str> = Vec::new();
+    for component in base.split(['/', '\\']).chain(path.split(['/', '\\'])) {
+        match component {
+            "" | "." => (),
+            ".." => { components.pop(); }
+            component => components.push(component)
+        }
+    }
+    let mut normalized = String::new();
+    for component in components {
+        normalized.push('/');
+        normalized.push_str(component);
+    }
+    if normalized.is_empty() {
+        normalized.push('/');
+    }
+    normalized
+}
@@ -0,0 +1,85 @@
+use crate::fatfs::*;
+
+/// A file being written sequentially, such as an audio or video recording, that is extended
+/// in large chunks ahead of the write pointer. Clusters are allocated a chunk at a time
+/// instead of as each write crosses a cluster boundary, so most writes do not touch the FAT.
+/// An empty file is given its first chunk with `expand()`, which is contiguous, and FatFs
+/// extends a file from its last cluster where that is free, so later chunks follow on when
+/// free space is not fragmented.
+///
+/// The file's size covers the whole allocation while recording. `finish()` truncates it to
+/// the data written.
+pub struct PreallocatedFile {
+    file: File,
+    chunk_size: u32,
+    end: u32
+}
+
+impl PreallocatedFile {
+    /// Wraps a file opened for writing, which is extended `chunk_size` bytes at a time, or a
+    /// sector at a time if the chunk size is smaller. Writing continues from the current
+    /// position of the file, and data already in the file is kept.
+    pub fn new(file: File, chunk_size: u32) -> Self {
+        let end = file.len();
+        Self { file, chunk_size: chunk_size.max(FF_MAX_SS), end }
+    }
+
+    /// Returns the number of bytes allocated to the file ahead of the data written.
+    pub fn reserved(&self) -> u32 {
+        self.file.len().saturating_sub(self.end)
+    }
+
+    /// Extends the file by whole chunks until it holds at least `size` bytes, then returns to
+    /// the current position. Fails with `Error::Denied` if the volume is full.
+    fn reserve(&mut self, fs: &RawFileSystem, size: u32) -> Result<(), Error> {
+        let allocated = self.file.len();
+        if size <= allocated {
+            return Ok(())
+        }
+        let chunks = (size - allocated).div_ceil(self.chunk_size);
+        let target = chunks.checked_mul(self.chunk_size).and_then(|len| allocated.checked_add(len))
+            .ok_or(Error::InvalidParameter)?;
+        let position = self.file.position();
+        if allocated == 0 {
+            fs.expand(&mut self.file, target)?;
+        } else {
+            //Seeking beyond the end of a file opened for writing extends it, stopping short
+            //if the volume is full.
+            fs.seek(&mut self.file, target)?;
+            if self.file.position() != target {
+                fs.seek(&mut self.file, position)?;
+                return Err(Error::Denied)
+            }
+        }
+        fs.seek(&mut self.file, position)
+    }
+
+    /// Writes the data at the current position, first extending the file if the allocation
+    /// does not cover it.
+    pub fn write(&mut self, fs: &RawFileSystem, data: &[u8]) -> Result<u32, Error> {
+        let size = self.file.position().checked_add(data.len() as u32).ok_or(Error::InvalidParameter)?;
+        self.reserve(fs, size)?;
+        let written = fs.write(&mut self.file, data)?;
+        self.end = self.end.max(self.file.position());
+        Ok(written)
+    }
+
+    /// Syncs the file, including the allocation ahead of the data, so that the data written
+    /// so far survives a power loss. The file then appears to hold the whole allocation.
+    pub fn sync(&mut self, fs: &RawFileSystem) -> Result<(), Error> {
+        fs.sync(&mut self.file)
+    }
+
+    /// Truncates the file to the data written, releasing the unused allocation, and returns
+    /// the file, which must still be closed. On failure the file is returned with the error.
+    #[allow(clippy::result_large_err)]
+    pub fn finish(mut self, fs: &RawFileSystem) -> Result<File, (Error, File)> {
+        let result = fs.seek(&mut self.file, self.end)
+            .and_then(|_| fs.truncate(&mut self.file))
+            .and_then(|_| fs.sync(&mut self.file));
+        match result {
+            Ok(()) => Ok(self.file),
+            Err(error) => Err((error, self.file))
+        }
+    }
+}
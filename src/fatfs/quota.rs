@@ -0,0 +1,141 @@
+//! Byte budgets for directories, so that one subsystem cannot consume the whole volume.
+//!
+//! ```ignore
+//! let mut logs = Quota::new(&locked_fs, "/logs", 4 * 1024 * 1024)?
+//!     .with_eviction(quota::evict_oldest);
+//! logs.write(&locked_fs, &mut log_file, b"boot\n")?;
+//! ```
+
+use crate::fatfs::*;
+use crate::fatfs::path::PathBuffer;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+/// Called when a write would exceed the budget, with the directory and the number of bytes
+/// that must be freed beneath it.
+pub type Eviction = Box<dyn FnMut(&RawFileSystem, &str, u64) -> Result<(), Error>>;
+
+/// Tracks the total size of the files beneath a directory against a byte budget. Writes made
+/// through the quota that would exceed the budget are rejected with `Error::Denied`, unless an
+/// eviction callback is set that frees enough space first. Changes made to the directory other
+/// than through the quota are picked up by `rescan()`.
+pub struct Quota {
+    dir: PathBuffer,
+    budget: u64,
+    used: u64,
+    eviction: Option<Eviction>
+}
+
+/// Returns the total size of the files beneath the directory.
+fn tree_size(fs: &RawFileSystem, dir: &mut PathBuffer) -> Result<u64, Error> {
+    let mut used = 0;
+    fs.visit_tree(dir, &mut |_, info| {
+        if let Ok(info) = info {
+            used += info.fsize as u64;
+        }
+    })?;
+    Ok(used)
+}
+
+impl Quota {
+    /// Creates a quota of `budget` bytes for the directory at the given path, which is scanned
+    /// to find the size of the files already beneath it.
+    pub fn new(fs: &RawFileSystem, dir: &str, budget: u64) -> Result<Self, Error> {
+        let mut dir = PathBuffer::new(dir);
+        let used = tree_size(fs, &mut dir)?;
+        Ok(Self { dir, budget, used, eviction: None })
+    }
+
+    /// Sets the callback used to free space when a write would exceed the budget.
+    /// `evict_oldest` may be used to delete the oldest files first.
+    pub fn with_eviction(mut self, eviction: impl FnMut(&RawFileSystem, &str, u64) -> Result<(), Error> + 'static) -> Self {
+        self.eviction = Some(Box::new(eviction));
+        self
+    }
+
+    /// Scans the directory again to find the size of the files beneath it.
+    pub fn rescan(&mut self, fs: &RawFileSystem) -> Result<u64, Error> {
+        self.used = tree_size(fs, &mut self.dir)?;
+        Ok(self.used)
+    }
+
+    /// Returns the total size of the files beneath the directory.
+    pub fn used(&self) -> u64 {
+        self.used
+    }
+
+    pub fn budget(&self) -> u64 {
+        self.budget
+    }
+
+    /// Returns the number of bytes that may be added before the budget is reached.
+    pub fn remaining(&self) -> u64 {
+        self.budget.saturating_sub(self.used)
+    }
+
+    /// Accounts for `len` bytes about to be added beneath the directory. If they do not fit
+    /// in the budget, the eviction callback is given the chance to free space, after which the
+    /// directory is rescanned. Fails with `Error::Denied` if the bytes still do not fit.
+    pub fn reserve(&mut self, fs: &RawFileSystem, len: u64) -> Result<(), Error> {
+        if self.used + len > self.budget {
+            let needed = self.used + len - self.budget;
+            match self.eviction.as_mut() {
+                Some(eviction) => eviction(fs, self.dir.as_str(), needed)?,
+                None => return Err(Error::Denied)
+            }
+            self.rescan(fs)?;
+            if self.used + len > self.budget {
+                return Err(Error::Denied)
+            }
+        }
+        self.used += len;
+        Ok(())
+    }
+
+    /// Writes data to a file beneath the directory, accounting for the growth of the file.
+    /// Returns the number of bytes written.
+    pub fn write(&mut self, fs: &RawFileSystem, file: &mut File, data: &[u8]) -> Result<u32, Error> {
+        let size = file.obj.objsize as u64;
+        let growth = (file.fptr as u64 + data.len() as u64).saturating_sub(size);
+        self.reserve(fs, growth)?;
+        let result = fs.write(file, data);
+        //Return whatever part of the reservation the write did not use.
+        self.used -= growth - (file.obj.objsize as u64).saturating_sub(size).min(growth);
+        result
+    }
+
+    /// Deletes the file at the given path, which should be beneath the directory.
+    pub fn unlink(&mut self, fs: &RawFileSystem, path: &str) -> Result<(), Error> {
+        let size = fs.stat(path)?.fsize as u64;
+        fs.unlink(path)?;
+        self.used = self.used.saturating_sub(size);
+        Ok(())
+    }
+}
+
+/// Eviction callback that deletes the least recently modified files beneath the directory
+/// until at least `needed` bytes have been freed. Files that cannot be deleted, such as
+/// files that are open, are skipped.
+pub fn evict_oldest(fs: &RawFileSystem, dir: &str, needed: u64) -> Result<(), Error> {
+    let mut files: Vec<(u32, u32, String)> = Vec::new();
+    let mut path = PathBuffer::new(dir);
+    fs.visit_tree(&mut path, &mut |path, info| {
+        if let Ok(info) = info {
+            if info.fattrib & FileAttributes::Directory.as_u8() == 0 {
+                let modified = (info.fdate as u32) << 16 | info.ftime as u32;
+                files.push((modified, info.fsize, String::from(path)));
+            }
+        }
+    })?;
+    files.sort_by_key(|(modified, _, _)| *modified);
+    let mut freed = 0;
+    for (_, size, path) in files {
+        if freed >= needed {
+            break
+        }
+        if fs.unlink(PathBuffer::new(&path).as_str()).is_ok() {
+            freed += size as u64;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,51 @@
+use crate::fatfs::*;
+
+impl RawFileSystem {
+    /// Returns the number of sectors covered by a buffer, which must hold one or more whole
+    /// sectors.
+    fn sectors_in(buffer_len: usize) -> Result<u32, Error> {
+        if buffer_len == 0 || !buffer_len.is_multiple_of(FF_MAX_SS as usize) {
+            return Err(Error::InvalidParameter)
+        }
+        Ok((buffer_len / FF_MAX_SS as usize) as u32)
+    }
+
+    /// Reads whole sectors from the drive, starting at the given logical block address counted
+    /// from the start of the drive, for partition tools, recovery and diagnostics. The length
+    /// of the buffer must be a non-zero multiple of the sector size. A sector held modified in
+    /// the window of the file system object is returned as FatFs sees it rather than as it is
+    /// on the drive.
+    pub fn read_sectors(&self, sector: u32, buffer: &mut [u8]) -> Result<(), Error> {
+        let count = Self::sectors_in(buffer.len())?;
+        let end = sector.checked_add(count).ok_or(Error::InvalidParameter)?;
+        diskio::read_sectors(self.fs().pdrv, buffer, sector)?;
+        let winsect = self.fs().winsect;
+        if self.fs().wflag != 0 && (sector..end).contains(&winsect) {
+            let offset = ((winsect - sector) * FF_MAX_SS) as usize;
+            buffer[offset..offset + FF_MAX_SS as usize].copy_from_slice(&self.fs().win);
+        }
+        Ok(())
+    }
+
+    /// Writes whole sectors to the drive, starting at the given logical block address counted
+    /// from the start of the drive. The length of the data must be a non-zero multiple of the
+    /// sector size. Writing to a mounted volume bypasses FatFs, so sectors in use by the file
+    /// system are only safe to write while no files or directories are open. A sector held in
+    /// the window of the file system object is discarded from it, including any change not
+    /// yet written back, so that FatFs reads the new data.
+    pub fn write_sectors(&self, sector: u32, data: &[u8]) -> Result<(), Error> {
+        let count = Self::sectors_in(data.len())?;
+        let end = sector.checked_add(count).ok_or(Error::InvalidParameter)?;
+        self.invalidate_stat_cache();
+        self.invalidate_dir_cache();
+        if (sector..end).contains(&self.fs().winsect) {
+            let fs = self.fs.get();
+            //FatFs reloads the window when the sector number does not match.
+            unsafe {
+                (*fs).wflag = 0;
+                (*fs).winsect = LBA_t::MAX;
+            }
+        }
+        diskio::write_sectors(self.fs().pdrv, data, sector)
+    }
+}
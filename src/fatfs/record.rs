@@ -0,0 +1,97 @@
+//! Files of fixed-size records, such as sample logs and event stores.
+//!
+//! ```ignore
+//! let mut samples: RecordFile<16> = RecordFile::open(&locked_fs, "samples.bin", true)?;
+//! let index = samples.append(&locked_fs, &sample)?;
+//! samples.read(&locked_fs, index, &mut sample)?;
+//! samples.close(&locked_fs)?;
+//! ```
+
+use crate::fatfs::{Error, File, FileOptions, RawFileSystem};
+use crate::fatfs::crc::crc32;
+
+/// A file holding records of `N` bytes each, addressed by index. When CRCs are enabled,
+/// each record is followed by a CRC-32 of its contents, which is checked when it is read.
+/// A partially written record at the end of the file, for example after a power loss,
+/// is not counted.
+pub struct RecordFile<const N: usize> {
+    file: File,
+    crc: bool
+}
+
+impl<const N: usize> RecordFile<N> {
+    /// Opens the record file at the given path, creating it if it does not exist.
+    /// Whether records carry a CRC must be the same every time the file is opened.
+    pub fn open(fs: &RawFileSystem, path: &str, crc: bool) -> Result<Self, Error> {
+        let file = fs.open_raw(path, FileOptions::OpenAlways | FileOptions::Read | FileOptions::Write)?;
+        Ok(Self { file, crc })
+    }
+
+    /// Size of each record as stored in the file, including its CRC.
+    pub fn record_size(&self) -> u32 {
+        N as u32 + if self.crc { 4 } else { 0 }
+    }
+
+    /// Returns the number of complete records in the file.
+    pub fn len(&self) -> u32 {
+        self.file.obj.objsize / self.record_size()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Reads the record at the given index. An index past the last record results in
+    /// `Error::InvalidParameter`, and a record that does not match its CRC in `Error::IntError`.
+    pub fn read(&mut self, fs: &RawFileSystem, index: u32, record: &mut [u8; N]) -> Result<(), Error> {
+        if index >= self.len() {
+            return Err(Error::InvalidParameter)
+        }
+        let offset = index * self.record_size();
+        fs.seek(&mut self.file, offset)?;
+        if fs.read(&mut self.file, record)? as usize != N {
+            return Err(Error::IntError)
+        }
+        if self.crc {
+            let mut stored = [0; 4];
+            if fs.read(&mut self.file, &mut stored)? != 4 || u32::from_le_bytes(stored) != crc32(0, record) {
+                return Err(Error::IntError)
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the record at the given index. An index equal to `len()` appends the record,
+    /// while an index beyond that results in `Error::InvalidParameter`.
+    pub fn write(&mut self, fs: &RawFileSystem, index: u32, record: &[u8; N]) -> Result<(), Error> {
+        if index > self.len() {
+            return Err(Error::InvalidParameter)
+        }
+        let offset = index * self.record_size();
+        fs.seek(&mut self.file, offset)?;
+        if fs.write(&mut self.file, record)? as usize != N {
+            return Err(Error::Denied)
+        }
+        if self.crc && fs.write(&mut self.file, &crc32(0, record).to_le_bytes())? != 4 {
+            return Err(Error::Denied)
+        }
+        Ok(())
+    }
+
+    /// Appends a record to the file and returns its index.
+    pub fn append(&mut self, fs: &RawFileSystem, record: &[u8; N]) -> Result<u32, Error> {
+        let index = self.len();
+        self.write(fs, index, record)?;
+        Ok(index)
+    }
+
+    /// Forces a write of all records to storage.
+    pub fn sync(&mut self, fs: &RawFileSystem) -> Result<(), Error> {
+        fs.sync(&mut self.file)
+    }
+
+    /// Closes the record file.
+    pub fn close(self, fs: &RawFileSystem) -> Result<(), Error> {
+        fs.close(self.file).map_err(Error::from)
+    }
+}
@@ -0,0 +1,131 @@
+use crate::fatfs::*;
+use crate::fatfs::crc::crc32;
+use crate::fatfs::path;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// An open file, identified by the handle given to it by `open_raw()`.
+pub(crate) struct OpenFile {
+    pub(crate) handle: u32,
+    /// The path as given to `open_raw()`, relative to `cdir` unless it is absolute.
+    pub(crate) path: String,
+    /// Start cluster of the current directory when the file was opened.
+    pub(crate) cdir: DWORD,
+    pub(crate) mode: FileOptions,
+    /// Set when the file is written to and cleared when it is synced.
+    pub(crate) dirty: bool,
+    /// The CRC-32 of the start of the file and the number of bytes it covers, kept while
+    /// checksums are enabled so that appending does not require the file to be read back.
+    pub(crate) crc: Option<(u32, u32)>,
+    /// The cluster link map table given to FatFs while fast seek is enabled.
+    pub(crate) link_map: Option<Vec<DWORD>>
+}
+
+/// Registry of the files currently open on the volume. FatFs hands out each file object by
+/// value, so files are tracked by their handle rather than by address. The lock slot is shared
+/// by every open of a file for reading and cannot tell them apart.
+pub(crate) struct Registry {
+    files: Vec<OpenFile>
+}
+
+impl Registry {
+    pub(crate) const fn new() -> Self {
+        Self { files: Vec::new() }
+    }
+
+    pub(crate) fn insert(&mut self, handle: u32, path: &str, cdir: DWORD, mode: FileOptions) {
+        self.files.push(OpenFile { handle, path: String::from(path), cdir, mode, dirty: false, crc: None, link_map: None });
+    }
+
+    pub(crate) fn remove(&mut self, handle: u32) {
+        self.files.retain(|file| file.handle != handle);
+    }
+
+    /// Sets whether the file has been modified since it was last synced. A modification
+    /// discards the running CRC of the file, which no longer matches its content.
+    pub(crate) fn set_dirty(&mut self, handle: u32, dirty: bool) {
+        if let Some(file) = self.files.iter_mut().find(|file| file.handle == handle) {
+            file.dirty = dirty;
+            if dirty {
+                file.crc = None;
+            }
+        }
+    }
+
+    /// Marks the file as modified by data written at the given position. The running CRC
+    /// is extended when the data directly follows the bytes it covers, and discarded when
+    /// the data overwrites them.
+    pub(crate) fn record_write(&mut self, handle: u32, position: u32, data: &[u8]) {
+        if let Some(file) = self.files.iter_mut().find(|file| file.handle == handle) {
+            file.dirty = true;
+            file.crc = match file.crc {
+                Some((crc, len)) if position == len => Some((crc32(crc, data), len + data.len() as u32)),
+                Some((crc, len)) if position > len => Some((crc, len)),
+                _ => None
+            };
+        }
+    }
+
+    pub(crate) fn set_crc(&mut self, handle: u32, crc: Option<(u32, u32)>) {
+        if let Some(file) = self.files.iter_mut().find(|file| file.handle == handle) {
+            file.crc = crc;
+        }
+    }
+
+    /// Replaces the cluster link map table of a file. Returns false if the file is not open.
+    pub(crate) fn set_link_map(&mut self, handle: u32, link_map: Option<Vec<DWORD>>) -> bool {
+        match self.files.iter_mut().find(|file| file.handle == handle) {
+            Some(file) => {
+                file.link_map = link_map;
+                true
+            },
+            None => false
+        }
+    }
+
+    pub(crate) fn get(&self, handle: u32) -> Option<&OpenFile> {
+        self.files.iter().find(|file| file.handle == handle)
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &OpenFile> {
+        self.files.iter()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.files.clear();
+    }
+}
+
+impl RawFileSystem {
+    /// Returns the open file that holds the FatFs lock on the given path, if any.
+    /// Paths are compared after resolving them against the current directory at the time
+    /// each file was opened, so an item opened through its short 8.3 name is not matched
+    /// against its long name.
+    pub(crate) fn lock_holder(&self, path: &str) -> Option<LockHolder> {
+        let target = self.resolve(self.fs().cdir, path)?;
+        let registry = self.registry.borrow();
+        let holder = registry.iter()
+            .find(|file| self.resolve(file.cdir, &file.path).is_some_and(|held| held.eq_ignore_ascii_case(&target)))
+            .map(|file| LockHolder { path: file.path.clone(), mode: file.mode });
+        holder
+    }
+
+    /// Converts a path relative to the directory starting at the given cluster into a
+    /// normalized absolute path.
+    pub(crate) fn resolve(&self, cdir: DWORD, path: &str) -> Option<String> {
+        let path = path.trim_end_matches('\0');
+        let path = path.split_once(':').map_or(path, |(_, path)| path);
+        if path.starts_with(['/', '\\']) || cdir == 0 {
+            return Some(path::normalize("", path))
+        }
+        let mut cwd = vec![0u8; 512];
+        let result = self.with_cdir(cdir, || unsafe { f_getcwd(cwd.as_mut_ptr().cast(), cwd.len() as UINT) });
+        if result != FRESULT_FR_OK {
+            return None
+        }
+        let len = cwd.iter().position(|c| *c == 0)?;
+        let cwd = core::str::from_utf8(&cwd[..len]).ok()?;
+        let cwd = cwd.split_once(':').map_or(cwd, |(_, cwd)| cwd);
+        Some(path::normalize(cwd, path))
+    }
+}
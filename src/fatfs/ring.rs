@@ -0,0 +1,342 @@
+//! Circular files for black-box and crash logging.
+//!
+//! ```ignore
+//! let mut black_box = RingFile::open(&locked_fs, "blackbox.bin", 64 * 1024)?;
+//! black_box.write(&locked_fs, b"motor stalled\n")?;
+//! black_box.sync(&locked_fs)?;
+//!
+//! let mut events: RingLog<32> = RingLog::open(&locked_fs, "events.bin", 1000)?;
+//! events.append(&locked_fs, &event)?;
+//! ```
+
+use crate::fatfs::{Error, File, FileOptions, RawFileSystem};
+use crate::fatfs::crc::crc32;
+
+const MAGIC: u32 = 0x474E_4952; //"RING"
+const HEADER_LEN: usize = 24;
+/// The two header copies are kept in separate sectors, so that a write interrupted by a
+/// power loss damages at most one of them.
+const HEADER_OFFSETS: [u32; 2] = [0, 512];
+const DATA_OFFSET: u32 = 1024;
+
+/// A file of fixed size that is written as a ring buffer. Once the ring is full, new data
+/// overwrites the oldest data, so the file never grows. The position of the ring is kept
+/// in a header that is written after each write, alternating between two copies
+/// protected by a CRC, so that the ring survives power cycles.
+pub struct RingFile {
+    file: File,
+    capacity: u32,
+    head: u32,
+    len: u32,
+    sequence: u32
+}
+
+impl RingFile {
+    /// Opens the ring file at the given path, holding up to `capacity` bytes of data.
+    /// If the file does not exist, or was not created with the same capacity, it is
+    /// (re)initialized as an empty ring and the space for it is allocated.
+    pub fn open(fs: &RawFileSystem, path: &str, capacity: u32) -> Result<Self, Error> {
+        if capacity == 0 {
+            return Err(Error::InvalidParameter)
+        }
+        let total = DATA_OFFSET.checked_add(capacity).ok_or(Error::InvalidParameter)?;
+        let file = fs.open_raw(path, FileOptions::OpenAlways | FileOptions::Read | FileOptions::Write)?;
+        let mut ring = Self { file, capacity, head: 0, len: 0, sequence: 0 };
+        let result = ring.load(fs, total);
+        match result {
+            Ok(()) => Ok(ring),
+            Err(error) => {
+                let _ = fs.close(ring.file);
+                Err(error)
+            }
+        }
+    }
+
+    fn load(&mut self, fs: &RawFileSystem, total: u32) -> Result<(), Error> {
+        if self.file.obj.objsize == total {
+            let mut newest: Option<[u32; 6]> = None;
+            for offset in HEADER_OFFSETS {
+                let mut bytes = [0; HEADER_LEN];
+                fs.seek(&mut self.file, offset)?;
+                if fs.read(&mut self.file, &mut bytes)? as usize != HEADER_LEN {
+                    continue
+                }
+                let mut header = [0; 6];
+                for (field, chunk) in header.iter_mut().zip(bytes.chunks_exact(4)) {
+                    *field = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+                }
+                let [magic, sequence, capacity, head, len, crc] = header;
+                let valid = magic == MAGIC && crc == crc32(0, &bytes[..HEADER_LEN - 4])
+                    && capacity == self.capacity && head < capacity && len <= capacity;
+                //Sequence numbers are compared with wrapping so that the newer copy is found after overflow.
+                if valid && newest.is_none_or(|newest| (sequence.wrapping_sub(newest[1]) as i32) > 0) {
+                    newest = Some(header);
+                }
+            }
+            if let Some([_, sequence, _, head, len, _]) = newest {
+                self.sequence = sequence;
+                self.head = head;
+                self.len = len;
+                return Ok(())
+            }
+        }
+        //Allocate the whole file up front. The content of the data area is undefined until written.
+        fs.seek(&mut self.file, 0)?;
+        fs.truncate(&mut self.file)?;
+        fs.seek(&mut self.file, total)?;
+        if self.file.obj.objsize != total {
+            return Err(Error::Denied)
+        }
+        self.clear(fs)
+    }
+
+    fn store_header(&mut self, fs: &RawFileSystem) -> Result<(), Error> {
+        self.sequence = self.sequence.wrapping_add(1);
+        let mut bytes = [0; HEADER_LEN];
+        let header = [MAGIC, self.sequence, self.capacity, self.head, self.len];
+        for (chunk, field) in bytes.chunks_exact_mut(4).zip(header) {
+            chunk.copy_from_slice(&field.to_le_bytes());
+        }
+        let crc = crc32(0, &bytes[..HEADER_LEN - 4]);
+        bytes[HEADER_LEN - 4..].copy_from_slice(&crc.to_le_bytes());
+        fs.seek(&mut self.file, HEADER_OFFSETS[(self.sequence % 2) as usize])?;
+        self.write_all(fs, &bytes)
+    }
+
+    fn write_all(&mut self, fs: &RawFileSystem, data: &[u8]) -> Result<(), Error> {
+        if fs.write(&mut self.file, data)? as usize != data.len() {
+            return Err(Error::Denied)
+        }
+        Ok(())
+    }
+
+    /// Appends data to the ring, overwriting the oldest data once the ring is full.
+    /// If more data than the capacity is supplied, only the newest `capacity` bytes are kept.
+    pub fn write(&mut self, fs: &RawFileSystem, data: &[u8]) -> Result<(), Error> {
+        let data = &data[data.len().saturating_sub(self.capacity as usize)..];
+        let first = data.len().min((self.capacity - self.head) as usize);
+        fs.seek(&mut self.file, DATA_OFFSET + self.head)?;
+        self.write_all(fs, &data[..first])?;
+        if first < data.len() {
+            fs.seek(&mut self.file, DATA_OFFSET)?;
+            self.write_all(fs, &data[first..])?;
+        }
+        self.head = ((self.head as u64 + data.len() as u64) % self.capacity as u64) as u32;
+        self.len = self.len.saturating_add(data.len() as u32).min(self.capacity);
+        self.store_header(fs)
+    }
+
+    /// Reads data from the ring, starting `offset` bytes after the oldest data held.
+    /// Returns the number of bytes read, which is 0 once the newest data has been read.
+    pub fn read(&mut self, fs: &RawFileSystem, offset: u32, buffer: &mut [u8]) -> Result<u32, Error> {
+        if offset >= self.len {
+            return Ok(0)
+        }
+        let count = (buffer.len() as u32).min(self.len - offset);
+        let capacity = self.capacity as u64;
+        let oldest = (self.head as u64 + capacity - self.len as u64) % capacity;
+        let start = ((oldest + offset as u64) % capacity) as u32;
+        let first = count.min(self.capacity - start);
+        fs.seek(&mut self.file, DATA_OFFSET + start)?;
+        let mut read = fs.read(&mut self.file, &mut buffer[..first as usize])?;
+        if first < count && read == first {
+            fs.seek(&mut self.file, DATA_OFFSET)?;
+            read += fs.read(&mut self.file, &mut buffer[first as usize..count as usize])?;
+        }
+        Ok(read)
+    }
+
+    /// Returns the number of bytes of data held in the ring.
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the maximum number of bytes of data the ring holds.
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    /// Discards all data held in the ring.
+    pub fn clear(&mut self, fs: &RawFileSystem) -> Result<(), Error> {
+        self.head = 0;
+        self.len = 0;
+        self.store_header(fs)
+    }
+
+    /// Forces a write of the ring and its header to storage.
+    pub fn sync(&mut self, fs: &RawFileSystem) -> Result<(), Error> {
+        fs.sync(&mut self.file)
+    }
+
+    /// Closes the ring file.
+    pub fn close(self, fs: &RawFileSystem) -> Result<(), Error> {
+        fs.close(self.file).map_err(Error::from)
+    }
+}
+
+/// Length of the header of each slot of a `RingLog`: the sequence number and the CRC.
+const SLOT_HEADER_LEN: u32 = 8;
+
+/// A file of fixed size holding the newest records of `N` bytes each, such as a sample or
+/// event log. Records are written to a ring of slots, each carrying a sequence number and a
+/// CRC, so no separate header is written. When the log is opened, the slots are scanned for
+/// the newest record and the unbroken run of records before it. The file is allocated as a
+/// contiguous block when created, so appending never allocates clusters.
+pub struct RingLog<const N: usize> {
+    file: File,
+    capacity: u32,
+    /// Slot the next record is written to.
+    head: u32,
+    len: u32,
+    /// Sequence number of the next record.
+    sequence: u32
+}
+
+impl<const N: usize> RingLog<N> {
+    /// Opens the ring log at the given path, holding up to `capacity` records. If the file
+    /// does not exist, or was not created with the same capacity, it is (re)created as an
+    /// empty log, allocated contiguously and cleared.
+    pub fn open(fs: &RawFileSystem, path: &str, capacity: u32) -> Result<Self, Error> {
+        let total = Self::SLOT_LEN.checked_mul(capacity).filter(|total| *total > 0).ok_or(Error::InvalidParameter)?;
+        let file = fs.open_raw(path, FileOptions::OpenAlways | FileOptions::Read | FileOptions::Write)?;
+        let mut log = Self { file, capacity, head: 0, len: 0, sequence: 0 };
+        let result = if log.file.obj.objsize == total {
+            log.scan(fs)
+        } else {
+            log.create(fs, total)
+        };
+        match result {
+            Ok(()) => Ok(log),
+            Err(error) => {
+                let _ = fs.close(log.file);
+                Err(error)
+            }
+        }
+    }
+
+    /// Size of each slot in the file.
+    const SLOT_LEN: u32 = SLOT_HEADER_LEN + N as u32;
+
+    fn create(&mut self, fs: &RawFileSystem, total: u32) -> Result<(), Error> {
+        fs.seek(&mut self.file, 0)?;
+        fs.truncate(&mut self.file)?;
+        fs.expand(&mut self.file, total)?;
+        //Clear the slots, so that data left in the clusters is not mistaken for records.
+        fs.zero_range(&mut self.file, 0, total)?;
+        fs.sync(&mut self.file)
+    }
+
+    /// Reads the record in the given slot, returning its sequence number if it is valid.
+    fn read_slot(&mut self, fs: &RawFileSystem, slot: u32, record: &mut [u8; N]) -> Result<Option<u32>, Error> {
+        let mut header = [0; SLOT_HEADER_LEN as usize];
+        fs.seek(&mut self.file, slot * Self::SLOT_LEN)?;
+        if fs.read(&mut self.file, &mut header)? != SLOT_HEADER_LEN || fs.read(&mut self.file, record)? as usize != N {
+            return Ok(None)
+        }
+        let [s0, s1, s2, s3, c0, c1, c2, c3] = header;
+        let crc = crc32(crc32(0, &header[..4]), record);
+        if u32::from_le_bytes([c0, c1, c2, c3]) != crc {
+            return Ok(None)
+        }
+        Ok(Some(u32::from_le_bytes([s0, s1, s2, s3])))
+    }
+
+    /// Finds the newest record, then counts the records before it with consecutive sequence
+    /// numbers.
+    fn scan(&mut self, fs: &RawFileSystem) -> Result<(), Error> {
+        let mut record = [0; N];
+        let mut newest: Option<(u32, u32)> = None;
+        for slot in 0..self.capacity {
+            if let Some(sequence) = self.read_slot(fs, slot, &mut record)? {
+                //Sequence numbers are compared with wrapping so that the newest is found after overflow.
+                if newest.is_none_or(|(_, newest)| (sequence.wrapping_sub(newest) as i32) > 0) {
+                    newest = Some((slot, sequence));
+                }
+            }
+        }
+        let (slot, sequence) = match newest {
+            Some(newest) => newest,
+            None => return Ok(())
+        };
+        self.head = (slot + 1) % self.capacity;
+        self.sequence = sequence.wrapping_add(1);
+        self.len = 1;
+        while self.len < self.capacity {
+            let previous = (slot + self.capacity - self.len) % self.capacity;
+            if self.read_slot(fs, previous, &mut record)? != Some(sequence.wrapping_sub(self.len)) {
+                break
+            }
+            self.len += 1;
+        }
+        Ok(())
+    }
+
+    /// Appends a record, overwriting the oldest record once the log is full. Returns the
+    /// sequence number of the record.
+    pub fn append(&mut self, fs: &RawFileSystem, record: &[u8; N]) -> Result<u32, Error> {
+        let sequence = self.sequence;
+        let mut header = [0; SLOT_HEADER_LEN as usize];
+        header[..4].copy_from_slice(&sequence.to_le_bytes());
+        let crc = crc32(crc32(0, &header[..4]), record);
+        header[4..].copy_from_slice(&crc.to_le_bytes());
+        fs.seek(&mut self.file, self.head * Self::SLOT_LEN)?;
+        if fs.write(&mut self.file, &header)? != SLOT_HEADER_LEN || fs.write(&mut self.file, record)? as usize != N {
+            return Err(Error::Denied)
+        }
+        self.head = (self.head + 1) % self.capacity;
+        self.len = (self.len + 1).min(self.capacity);
+        self.sequence = sequence.wrapping_add(1);
+        Ok(sequence)
+    }
+
+    /// Reads the record `index` records after the oldest record held. An index past the
+    /// newest record results in `Error::InvalidParameter`, and a record that does not match
+    /// its CRC in `Error::IntError`.
+    pub fn read(&mut self, fs: &RawFileSystem, index: u32, record: &mut [u8; N]) -> Result<(), Error> {
+        if index >= self.len {
+            return Err(Error::InvalidParameter)
+        }
+        let slot = (self.head + self.capacity - self.len + index) % self.capacity;
+        let expected = self.sequence.wrapping_sub(self.len - index);
+        match self.read_slot(fs, slot, record)? {
+            Some(sequence) if sequence == expected => Ok(()),
+            _ => Err(Error::IntError)
+        }
+    }
+
+    /// Returns the sequence number of the newest record, or `None` if the log is empty.
+    pub fn newest_sequence(&self) -> Option<u32> {
+        if self.len == 0 {
+            return None
+        }
+        Some(self.sequence.wrapping_sub(1))
+    }
+
+    /// Returns the number of records held.
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the maximum number of records held.
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    /// Forces a write of the records to storage.
+    pub fn sync(&mut self, fs: &RawFileSystem) -> Result<(), Error> {
+        fs.sync(&mut self.file)
+    }
+
+    /// Closes the ring log.
+    pub fn close(self, fs: &RawFileSystem) -> Result<(), Error> {
+        fs.close(self.file).map_err(Error::from)
+    }
+}
@@ -0,0 +1,126 @@
+//! File handles borrowed from the file system lock.
+//!
+//! A `ScopedFile` borrows the file system from the guard it was opened through, so it is
+//! a compile error to use the file after the lock has been released, or to move it to
+//! another task. Because the lock is known to be held, the file is closed when dropped.
+//!
+//! ```ignore
+//! let locked_fs = fatfs::FS.lock().await;
+//! let mut file = locked_fs.open("config.bin", FileOptions::Read)?;
+//! file.read(&mut buffer)?;
+//! drop(locked_fs); //Error: `locked_fs` is still borrowed by `file`.
+//! ```
+
+use core::mem;
+use core::ops::{Deref, DerefMut};
+use crate::fatfs::*;
+
+/// A file that may only be used while the file system lock it was opened through is held.
+/// The file is closed when dropped, discarding any error. Use `close()` to report errors.
+/// Dereferences to the underlying file object, so it may be passed to the file system methods
+/// that take `&mut File`.
+pub struct ScopedFile<'a> {
+    fs: &'a RawFileSystem,
+    file: File
+}
+
+impl<'a> ScopedFile<'a> {
+    /// Read data from the file. The length of the provided buffer determines the length of data read.
+    pub fn read(&mut self, buffer: &mut [u8]) -> Result<u32, Error> {
+        self.fs.read(&mut self.file, buffer)
+    }
+
+    /// Write data to the file. The length of the provided buffer determines the length of data written.
+    pub fn write(&mut self, buffer: &[u8]) -> Result<u32, Error> {
+        self.fs.write(&mut self.file, buffer)
+    }
+
+    /// Move to an offset in the file.
+    pub fn seek(&mut self, offset: u32) -> Result<(), Error> {
+        self.fs.seek(&mut self.file, offset)
+    }
+
+    /// Truncates the file at the current offset.
+    pub fn truncate(&mut self) -> Result<(), Error> {
+        self.fs.truncate(&mut self.file)
+    }
+
+    /// Forces a write of all data to storage.
+    pub fn sync(&mut self) -> Result<(), Error> {
+        self.fs.sync(&mut self.file)
+    }
+
+    /// Returns the current offset from the start of the file.
+    pub fn position(&self) -> u32 {
+        self.file.position()
+    }
+
+    /// Returns the size of the file in bytes.
+    pub fn len(&self) -> u32 {
+        self.file.len()
+    }
+
+    /// Returns true if the file is empty.
+    pub fn is_empty(&self) -> bool {
+        self.file.is_empty()
+    }
+
+    /// Returns true if the current position is at or beyond the end of the file.
+    pub fn is_eof(&self) -> bool {
+        self.file.is_eof()
+    }
+
+    /// Returns information about the file.
+    pub fn stat(&self) -> Result<FileInfo, Error> {
+        self.fs.fstat(&self.file)
+    }
+
+    /// Closes the file. If closing fails, the file is kept open and closed again when dropped.
+    pub fn close(mut self) -> Result<(), Error> {
+        match self.fs.close(mem::take(&mut self.file)) {
+            Ok(()) => Ok(()),
+            Err((error, file)) => {
+                self.file = file;
+                Err(error)
+            }
+        }
+    }
+
+    /// Returns the file without closing it. It must then be closed manually.
+    pub fn into_inner(mut self) -> File {
+        mem::take(&mut self.file)
+    }
+}
+
+impl Deref for ScopedFile<'_> {
+    type Target = File;
+
+    fn deref(&self) -> &File {
+        &self.file
+    }
+}
+
+impl DerefMut for ScopedFile<'_> {
+    fn deref_mut(&mut self) -> &mut File {
+        &mut self.file
+    }
+}
+
+impl Drop for ScopedFile<'_> {
+    fn drop(&mut self) {
+        //FatFs clears the file system pointer of a file when it is closed.
+        if !self.file.obj.fs.is_null() {
+            let _ = self.fs.close(mem::take(&mut self.file));
+        }
+    }
+}
+
+impl RawFileSystem {
+    /// Opens the file at the given path in the given mode. FileOption flags may be OR'd together.
+    /// The returned file borrows the file system, and so cannot outlive the lock guard. Use
+    /// `open_raw()` for a file object that is not tied to the guard.
+    pub fn open(&self, path: &str, mode: FileOptions) -> Result<ScopedFile<'_>, Error> {
+        let file = self.open_raw(path, mode)?;
+        Ok(ScopedFile { fs: self, file })
+    }
+}
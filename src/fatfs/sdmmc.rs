@@ -0,0 +1,251 @@
+//! Mirrors the shape of the `embedded-sdmmc` `VolumeManager`, `Volume`, `Directory` and `File`
+//! API on top of this crate, so that projects moving over for long file name support need only
+//! minimal changes at each call site. exFAT volumes additionally need `FF_FS_EXFAT` enabled in
+//! ffconf.h, which is off by default. Errors are reported with this crate's `Error`, and names
+//! are long file names rather than `ShortFileName`s.
+//!
+//! ```
+//! #[path = "../tests/simulated_driver.rs"]
+//! mod simulated_driver;
+//!
+//! use fatfs_embedded::fatfs::{self, FormatOptions, MkfsOptions};
+//! use fatfs_embedded::fatfs::sdmmc::{Mode, VolumeIdx, VolumeManager};
+//! use embassy_futures::block_on;
+//!
+//! block_on(fatfs::diskio::install(simulated_driver::RamBlockStorage::new()));
+//! let mut locked_fs = block_on(fatfs::FS.lock());
+//! locked_fs.mkfs("", MkfsOptions::new().format(FormatOptions::FAT32)).unwrap();
+//!
+//! let mut volume_mgr = VolumeManager::new(&mut locked_fs);
+//! let volume = volume_mgr.open_volume(VolumeIdx(0)).unwrap();
+//! let root_dir = volume.open_root_dir().unwrap();
+//! let mut file = root_dir.open_file_in_dir("README.TXT", Mode::ReadWriteCreateOrTruncate).unwrap();
+//! file.write(b"Hello world!").unwrap();
+//! file.close().unwrap();
+//! ```
+
+use crate::fatfs::{self, *};
+use crate::fatfs::path::PathBuffer;
+use core::mem::{self, ManuallyDrop};
+
+pub use crate::fatfs::DirEntry;
+
+/// Index of a volume. Only volume 0 is supported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VolumeIdx(pub usize);
+
+/// The mode in which a file is opened.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// Open an existing file for reading.
+    ReadOnly,
+    /// Open an existing file for reading and writing, positioned at the end.
+    ReadWriteAppend,
+    /// Open an existing file for reading and writing, discarding its contents.
+    ReadWriteTruncate,
+    /// Create a new file for reading and writing. Fails if the file exists.
+    ReadWriteCreate,
+    /// Create a file for reading and writing, discarding the contents of any existing file.
+    ReadWriteCreateOrTruncate,
+    /// Create a file for reading and writing, or open an existing file positioned at the end.
+    ReadWriteCreateOrAppend
+}
+
+/// Entry point of the facade, borrowed from a locked file system.
+pub struct VolumeManager<'a> {
+    fs: &'a mut RawFileSystem
+}
+
+impl<'a> VolumeManager<'a> {
+    pub fn new(fs: &'a mut RawFileSystem) -> Self {
+        Self { fs }
+    }
+
+    /// Mounts the volume with the given index.
+    pub fn open_volume(&mut self, volume_idx: VolumeIdx) -> Result<Volume<'_>, Error> {
+        if volume_idx.0 != 0 {
+            return Err(Error::InvalidDrive)
+        }
+        self.fs.mount()?;
+        Ok(Volume { fs: self.fs })
+    }
+}
+
+/// A mounted volume.
+pub struct Volume<'a> {
+    fs: &'a RawFileSystem
+}
+
+impl<'a> Volume<'a> {
+    /// Opens the root directory of the volume.
+    pub fn open_root_dir(&self) -> Result<Directory<'a>, Error> {
+        Ok(Directory { fs: self.fs, path: PathBuffer::new("/") })
+    }
+
+    /// Unmounts the volume.
+    pub fn close(self) -> Result<(), Error> {
+        self.fs.unmount("")
+    }
+}
+
+/// A directory within a volume. No FatFs directory object is held open between calls.
+pub struct Directory<'a> {
+    fs: &'a RawFileSystem,
+    path: PathBuffer
+}
+
+impl<'a> Directory<'a> {
+    fn child(&self, name: &str) -> PathBuffer {
+        let mut path = PathBuffer::new(self.path.as_str());
+        path.push(name);
+        path
+    }
+
+    /// Opens the sub-directory with the given name.
+    pub fn open_dir(&self, name: &str) -> Result<Directory<'a>, Error> {
+        let path = self.child(name);
+        let info = self.fs.stat(path.as_str())?;
+        if info.fattrib & FileAttributes::Directory.as_u8() == 0 {
+            return Err(Error::NoPath)
+        }
+        Ok(Directory { fs: self.fs, path })
+    }
+
+    /// Opens the file with the given name in the given mode.
+    pub fn open_file_in_dir(&self, name: &str, mode: Mode) -> Result<File<'a>, Error> {
+        let path = self.child(name);
+        let read_write = FileOptions::Read | FileOptions::Write;
+        let options = match mode {
+            Mode::ReadOnly => FileOptions::Read | FileOptions::OpenExisting,
+            Mode::ReadWriteAppend | Mode::ReadWriteTruncate => read_write | FileOptions::OpenExisting,
+            Mode::ReadWriteCreate => read_write | FileOptions::CreateNew,
+            Mode::ReadWriteCreateOrTruncate => read_write | FileOptions::CreateAlways,
+            Mode::ReadWriteCreateOrAppend => read_write | FileOptions::OpenAppend
+        };
+        let mut file = File { fs: self.fs, file: self.fs.open_raw(path.as_str(), options)? };
+        match mode {
+            Mode::ReadWriteAppend => file.seek_from_end(0)?,
+            Mode::ReadWriteTruncate => self.fs.truncate(&mut file.file)?,
+            _ => ()
+        }
+        Ok(file)
+    }
+
+    /// Calls the given function for each item in the directory.
+    pub fn iterate_dir(&self, mut func: impl FnMut(&DirEntry)) -> Result<(), Error> {
+        let mut dir = self.fs.opendir(self.path.as_str())?;
+        let result = loop {
+            let info = match self.fs.readdir(&mut dir) {
+                Ok(info) => info,
+                Err(error) => break Err(error)
+            };
+            if info.fname[0] == 0 {
+                break Ok(())
+            }
+            match DirEntry::new(&info) {
+                Ok(entry) => func(&entry),
+                Err(error) => break Err(error)
+            }
+        };
+        self.fs.closedir(dir)?;
+        result
+    }
+
+    /// Returns the item with the given name.
+    pub fn find_directory_entry(&self, name: &str) -> Result<DirEntry, Error> {
+        let path = self.child(name);
+        let mut entry = DirEntry::new(&self.fs.stat(path.as_str())?)?;
+        entry.name = String::from(name);
+        Ok(entry)
+    }
+
+    /// Creates a sub-directory with the given name.
+    pub fn make_dir_in_dir(&self, name: &str) -> Result<(), Error> {
+        self.fs.mkdir(self.child(name).as_str())
+    }
+
+    /// Deletes the file with the given name.
+    pub fn delete_file_in_dir(&self, name: &str) -> Result<(), Error> {
+        let path = self.child(name);
+        let info = self.fs.stat(path.as_str())?;
+        if info.fattrib & FileAttributes::Directory.as_u8() != 0 {
+            return Err(Error::Denied)
+        }
+        self.fs.unlink(path.as_str())
+    }
+
+    /// Closes the directory.
+    pub fn close(self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// An open file. The file is closed when it is dropped, discarding any error, or by
+/// calling `close()`.
+pub struct File<'a> {
+    fs: &'a RawFileSystem,
+    file: fatfs::File
+}
+
+impl<'a> File<'a> {
+    /// Reads data into the buffer and returns the number of bytes read.
+    pub fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        Ok(self.fs.read(&mut self.file, buffer)? as usize)
+    }
+
+    /// Writes all of the buffer to the file.
+    pub fn write(&mut self, buffer: &[u8]) -> Result<(), Error> {
+        Ok(self.fs.write_all(&mut self.file, buffer)?)
+    }
+
+    /// Returns true if the current position is at the end of the file.
+    pub fn is_eof(&self) -> bool {
+        self.file.is_eof()
+    }
+
+    /// Moves to the given offset from the start of the file.
+    pub fn seek_from_start(&mut self, offset: u32) -> Result<(), Error> {
+        self.fs.seek(&mut self.file, offset)
+    }
+
+    /// Moves by the given offset from the current position.
+    pub fn seek_from_current(&mut self, offset: i32) -> Result<(), Error> {
+        let position = self.file.position().checked_add_signed(offset).ok_or(Error::InvalidParameter)?;
+        self.fs.seek(&mut self.file, position)
+    }
+
+    /// Moves to the given offset back from the end of the file.
+    pub fn seek_from_end(&mut self, offset: u32) -> Result<(), Error> {
+        let position = self.file.len().checked_sub(offset).ok_or(Error::InvalidParameter)?;
+        self.fs.seek(&mut self.file, position)
+    }
+
+    /// Returns the length of the file in bytes.
+    pub fn length(&self) -> u32 {
+        self.file.len()
+    }
+
+    /// Returns the current position within the file.
+    pub fn offset(&self) -> u32 {
+        self.file.position()
+    }
+
+    /// Writes any cached data for the file to storage.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.fs.sync(&mut self.file)
+    }
+
+    /// Closes the file, returning any error.
+    pub fn close(self) -> Result<(), Error> {
+        let mut file = ManuallyDrop::new(self);
+        file.fs.close(mem::take(&mut file.file)).map_err(Error::from)
+    }
+}
+
+//The borrow of the file system is held for the lifetime of the file, so closing it here
+//does not need to acquire the file system lock.
+impl<'a> Drop for File<'a> {
+    fn drop(&mut self) {
+        let _ = self.fs.close(mem::take(&mut self.file));
+    }
+}
@@ -0,0 +1,87 @@
+use alloc::vec::Vec;
+use crate::fatfs::*;
+use crate::fatfs::path::PathBuffer;
+
+/// Returns the number in a name of the form `<prefix><digits>.<extension>`, compared without
+/// regard to case, or `None` if the name does not match.
+fn sequence_number(name: &str, prefix: &str, digits: usize, extension: &str) -> Option<u32> {
+    let stem = if extension.is_empty() {
+        name
+    } else {
+        let (stem, suffix) = name.rsplit_once('.')?;
+        if !suffix.eq_ignore_ascii_case(extension) {
+            return None
+        }
+        stem
+    };
+    if stem.len() != prefix.len() + digits || !stem.is_char_boundary(prefix.len()) {
+        return None
+    }
+    let (start, number) = stem.split_at(prefix.len());
+    if !start.eq_ignore_ascii_case(prefix) || !number.bytes().all(|c| c.is_ascii_digit()) {
+        return None
+    }
+    number.parse().ok()
+}
+
+impl RawFileSystem {
+    /// Returns the path of the next file in a numbered sequence in the directory at the given
+    /// path, such as `LOG_0042.CSV` for a prefix of `LOG_`, 4 digits and an extension of `CSV`.
+    /// The number follows the highest number found, so gaps left by deleted files are not
+    /// reused. Once the highest number possible has been used, the sequence wraps around to
+    /// the lowest number that is not in use. Fails with `Error::Denied` if every number is in
+    /// use, and with `Error::InvalidParameter` unless `digits` is between 1 and 9. The path
+    /// may be passed straight to `open()` or `create_new()`.
+    pub fn next_sequential_name(&self, dir: &str, prefix: &str, digits: usize, extension: &str) -> Result<String, Error> {
+        if !(1..=9).contains(&digits) {
+            return Err(Error::InvalidParameter)
+        }
+        let prefix = prefix.trim_end_matches('\0');
+        let extension = extension.trim_end_matches('\0');
+        let last = 10u32.pow(digits as u32) - 1;
+        let mut used: Vec<u32> = Vec::new();
+        let mut dir_object = self.opendir(dir)?;
+        let result = loop {
+            let info = match self.readdir(&mut dir_object) {
+                Ok(info) => info,
+                Err(error) => break Err(error)
+            };
+            if info.fname[0] == 0 {
+                break Ok(())
+            }
+            //Names that are not valid UTF-8 cannot be part of the sequence.
+            if let Some(number) = info.name().ok().and_then(|name| sequence_number(name, prefix, digits, extension)) {
+                used.push(number);
+            }
+        };
+        self.closedir(dir_object)?;
+        result?;
+        used.sort_unstable();
+        let next = match used.last() {
+            None => 0,
+            Some(&highest) if highest < last => highest + 1,
+            //Wrap around to the first gap in the sequence.
+            Some(_) => {
+                let mut next = 0;
+                for &number in &used {
+                    if number > next {
+                        break
+                    }
+                    next = number + 1;
+                }
+                if next > last {
+                    return Err(Error::Denied)
+                }
+                next
+            }
+        };
+        let mut name = alloc::format!("{}{:0width$}", prefix, next, width = digits);
+        if !extension.is_empty() {
+            name.push('.');
+            name.push_str(extension);
+        }
+        let mut path = PathBuffer::new(dir);
+        path.push(&name);
+        Ok(String::from(path.as_str()))
+    }
+}
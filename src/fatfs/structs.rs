@@ -0,0 +1,82 @@
+use alloc::vec::Vec;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use crate::fatfs::*;
+
+/// Error returned when reading or writing a struct to a file.
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum StructError {
+    /// The value could not be serialized.
+    Serialize,
+    /// The contents of the file could not be deserialized as the requested type.
+    Deserialize,
+    /// The file holds more data than the value that was deserialized from it.
+    TrailingData,
+    Other(Error)
+}
+
+impl From<Error> for StructError {
+    fn from(error: Error) -> Self {
+        StructError::Other(error)
+    }
+}
+
+impl From<StructError> for Error {
+    fn from(error: StructError) -> Self {
+        match error {
+            StructError::Other(error) => error,
+            _ => Error::InvalidParameter
+        }
+    }
+}
+
+impl RawFileSystem {
+    /// Serializes the value with `postcard` and writes it to the file at the given path with
+    /// `write_atomic()`, replacing any existing file.
+    #[cfg(feature = "postcard")]
+    pub fn write_struct<T: Serialize + ?Sized>(&self, path: &str, value: &T) -> Result<(), StructError> {
+        let data = postcard::to_allocvec(value).map_err(|_| StructError::Serialize)?;
+        Ok(self.write_atomic(path, &data)?)
+    }
+
+    /// Reads the file at the given path and deserializes it with `postcard`. The whole file
+    /// must be taken up by the value.
+    #[cfg(feature = "postcard")]
+    pub fn read_struct<T: DeserializeOwned>(&self, path: &str) -> Result<T, StructError> {
+        let data = self.read_file(path)?;
+        match postcard::take_from_bytes(&data) {
+            Ok((value, [])) => Ok(value),
+            Ok(_) => Err(StructError::TrailingData),
+            Err(_) => Err(StructError::Deserialize)
+        }
+    }
+
+    /// Serializes the value as JSON with `serde-json-core` and writes it to the file at the
+    /// given path with `write_atomic()`, replacing any existing file.
+    #[cfg(feature = "json")]
+    pub fn write_json<T: Serialize + ?Sized>(&self, path: &str, value: &T) -> Result<(), StructError> {
+        //The serialized length is not known in advance, so grow the buffer until it fits.
+        let mut buffer: Vec<u8> = alloc::vec![0; 256];
+        let len = loop {
+            match serde_json_core::to_slice(value, &mut buffer) {
+                Ok(len) => break len,
+                Err(_) if buffer.len() < u32::MAX as usize / 2 => buffer.resize(buffer.len() * 2, 0),
+                Err(_) => return Err(StructError::Serialize)
+            }
+        };
+        Ok(self.write_atomic(path, &buffer[..len])?)
+    }
+
+    /// Reads the file at the given path and deserializes it as JSON with `serde-json-core`.
+    /// Only whitespace may follow the value.
+    #[cfg(feature = "json")]
+    pub fn read_json<T: DeserializeOwned>(&self, path: &str) -> Result<T, StructError> {
+        let data = self.read_file(path)?;
+        match serde_json_core::from_slice(&data) {
+            Ok((value, len)) if data[len..].iter().all(u8::is_ascii_whitespace) => Ok(value),
+            Ok(_) => Err(StructError::TrailingData),
+            Err(_) => Err(StructError::Deserialize)
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+use crate::fatfs::*;
+use crate::fatfs::path::{self, PathBuffer};
+
+/// The number tried first for the next temporary file.
+static NEXT_TEMP: AtomicU32 = AtomicU32::new(0);
+
+impl RawFileSystem {
+    /// Creates a new file with a unique name in the directory at the given path, opened for
+    /// reading and writing. The name is formed from the prefix, of at most 4 letters, digits
+    /// or underscores, followed by 4 hexadecimal digits and `.TMP`, so is a valid 8.3 name.
+    /// Numbers are tried in turn, skipping names that already exist. Returns the file and
+    /// its path.
+    pub fn create_temp(&self, dir: &str, prefix: &str) -> Result<(File, String), Error> {
+        let prefix = prefix.trim_end_matches('\0');
+        if prefix.len() > 4 || !prefix.bytes().all(|c| c.is_ascii_alphanumeric() || c == b'_') {
+            return Err(Error::InvalidName)
+        }
+        let mut path = PathBuffer::new(dir);
+        for _ in 0..=u16::MAX {
+            //The lock is held, so the counter need not be updated atomically.
+            let number = NEXT_TEMP.load(Ordering::Relaxed) as u16;
+            NEXT_TEMP.store(number.wrapping_add(1) as u32, Ordering::Relaxed);
+            let len = path.push(&alloc::format!("{}{:04X}.TMP", prefix, number));
+            match self.open_raw(path.as_str(), FileOptions::Read | FileOptions::Write | FileOptions::CreateNew) {
+                Ok(file) => return Ok((file, String::from(path.as_str()))),
+                Err(Error::Exists) => path.truncate(len),
+                Err(error) => return Err(error)
+            }
+        }
+        Err(Error::Denied)
+    }
+
+    /// Replaces the contents of the file at the given path. The data is written to a temporary
+    /// file in the same directory, which is synced before it is renamed over the file with
+    /// `rename_replace()`. If power is lost before the rename, the old file is left intact. The
+    /// only window in which neither file is at the path is between the old file being deleted
+    /// and the rename, when the complete new contents are already in a `.TMP` file beginning
+    /// with `ATOM`. On failure the temporary file is deleted.
+    pub fn write_atomic(&self, path: &str, data: &[u8]) -> Result<(), Error> {
+        let (mut file, temp) = self.create_temp(path::parent(path), "ATOM")?;
+        let result = self.write_all(&mut file, data).map_err(Error::from)
+            .and_then(|_| self.sync(&mut file));
+        let closed = self.close(file).map_err(Error::from);
+        let result = result.and(closed)
+            .and_then(|_| self.rename_replace(&temp, path));
+        if result.is_err() {
+            let _ = self.unlink(&temp);
+        }
+        result
+    }
+}
@@ -0,0 +1,178 @@
+use crate::fatfs::*;
+use crate::fatfs::path::{self, PathBuffer};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+#[cfg(feature = "chrono")]
+use chrono::NaiveDateTime;
+
+/// The outcome of an operation applied across a directory tree. Errors affecting individual
+/// items do not stop the operation; they are counted here instead.
+#[derive(Debug, Default, PartialEq)]
+pub struct TreeSummary {
+    /// Number of items the operation was applied to successfully.
+    pub succeeded: u32,
+    /// Number of items the operation failed for.
+    pub failed: u32,
+    /// The first error encountered, if any.
+    pub first_error: Option<Error>
+}
+
+impl TreeSummary {
+    fn record(&mut self, result: Result<(), Error>) {
+        match result {
+            Ok(()) => self.succeeded += 1,
+            Err(error) => {
+                self.failed += 1;
+                self.first_error.get_or_insert(error);
+            }
+        }
+    }
+}
+
+impl RawFileSystem {
+    /// Visits every item beneath the directory at `path`, depth first. Each directory is
+    /// closed before its sub-directories are visited, so the number of open directories
+    /// does not grow with the depth of the tree. Items with names that are not valid UTF-8
+    /// are reported to the visitor as `Error::InvalidName` against the enclosing directory,
+    /// and are not descended into.
+    pub(crate) fn visit_tree(&self, path: &mut PathBuffer, visitor: &mut dyn FnMut(&str, Result<&FileInfo, Error>)) -> Result<(), Error> {
+        let mut dir = self.opendir(path.as_str())?;
+        let mut subdirs: Vec<String> = Vec::new();
+        let result = loop {
+            let info = match self.readdir(&mut dir) {
+                Ok(info) => info,
+                Err(error) => break Err(error)
+            };
+            if info.fname[0] == 0 {
+                break Ok(())
+            }
+            match info.name() {
+                Ok(name) => {
+                    let len = path.push(name);
+                    visitor(path.as_str(), Ok(&info));
+                    path.truncate(len);
+                    if info.fattrib & FileAttributes::Directory.as_u8() != 0 {
+                        subdirs.push(String::from(name));
+                    }
+                }
+                Err(error) => visitor(path.as_str(), Err(error))
+            }
+        };
+        self.closedir(dir)?;
+        result?;
+        for name in subdirs {
+            let len = path.push(&name);
+            let result = self.visit_tree(path, visitor);
+            path.truncate(len);
+            result?;
+        }
+        Ok(())
+    }
+
+    /// Applies an operation to the item at the given path and everything beneath it.
+    fn apply_recursive(&self, path: &str, operation: &mut dyn FnMut(&str) -> Result<(), Error>, progress: &mut dyn FnMut(&str, &Result<(), Error>)) -> Result<TreeSummary, Error> {
+        let mut summary = TreeSummary::default();
+        let mut path = PathBuffer::new(path);
+        if !path::is_root(path.as_str()) {
+            let info = self.stat(path.as_str())?;
+            let result = operation(path.as_str());
+            progress(path.as_str(), &result);
+            summary.record(result);
+            if info.fattrib & FileAttributes::Directory.as_u8() == 0 {
+                return Ok(summary)
+            }
+        }
+        self.visit_tree(&mut path, &mut |item_path, item| {
+            let result = item.and_then(|_| operation(item_path));
+            progress(item_path, &result);
+            summary.record(result);
+        })?;
+        Ok(summary)
+    }
+
+    /// Deletes the directory at the given path and everything beneath it. Files are deleted
+    /// as they are found, while each sub-directory is emptied before it is deleted itself. Only
+    /// one directory is held open at a time, and the only state kept per level of the tree is
+    /// the length of its path. Refuses to delete the root directory of a volume, returning
+    /// `Error::InvalidParameter`. Stops at the first error, leaving the rest of the tree in place.
+    pub fn remove_dir_all(&self, path: &str) -> Result<(), Error> {
+        self.remove_dir_all_cancellable(path, &CancelToken::new())
+    }
+
+    /// Deletes a directory tree in the same way as `remove_dir_all()`, checking the token
+    /// before each item is deleted. Once cancelled, `Error::Cancelled` is returned and the
+    /// items not yet deleted are left in place, each directory remaining valid.
+    pub fn remove_dir_all_cancellable(&self, path: &str, cancel: &CancelToken) -> Result<(), Error> {
+        let mut path = PathBuffer::new(path);
+        if path::is_root(path.as_str()) {
+            return Err(Error::InvalidParameter)
+        }
+        let mut parents: Vec<usize> = Vec::new();
+        loop {
+            let mut dir = self.opendir(path.as_str())?;
+            //FatFs does not return the dot entries, so the walk cannot leave the tree.
+            let result = loop {
+                let info = match self.readdir(&mut dir) {
+                    Ok(info) => info,
+                    Err(error) => break Err(error)
+                };
+                if info.fname[0] == 0 {
+                    break Ok(None)
+                }
+                let name = match info.name() {
+                    Ok(name) => name,
+                    Err(error) => break Err(error)
+                };
+                let len = path.push(name);
+                if info.fattrib & FileAttributes::Directory.as_u8() != 0 {
+                    break Ok(Some(len))
+                }
+                let result = cancel.check().and_then(|_| self.unlink(path.as_str()));
+                path.truncate(len);
+                if let Err(error) = result {
+                    break Err(error)
+                }
+            };
+            self.closedir(dir)?;
+            match result? {
+                //Descend into the sub-directory, and start the parent again once it is deleted.
+                Some(len) => parents.push(len),
+                None => {
+                    cancel.check()?;
+                    self.unlink(path.as_str())?;
+                    match parents.pop() {
+                        Some(len) => path.truncate(len),
+                        None => return Ok(())
+                    }
+                }
+            }
+        }
+    }
+
+    /// Applies the given attributes, according to the supplied mask, to the item at the given
+    /// path and everything beneath it. The progress callback is invoked with the path and the
+    /// result for each item. Failures for individual items are aggregated in the returned
+    /// summary, while an error reading the tree itself is returned immediately.
+    pub fn chmod_recursive(&self, path: &str, attr: FileAttributes, mask: FileAttributes, mut progress: impl FnMut(&str, &Result<(), Error>)) -> Result<TreeSummary, Error> {
+        self.apply_recursive(path, &mut |item_path| self.chmod(item_path, attr, mask), &mut progress)
+    }
+
+    /// Applies a timestamp to the item at the given path and everything beneath it.
+    /// Progress and errors are reported in the same way as `chmod_recursive()`.
+    #[cfg(feature = "chrono")]
+    pub fn utime_recursive(&self, path: &str, timestamp: NaiveDateTime, mut progress: impl FnMut(&str, &Result<(), Error>)) -> Result<TreeSummary, Error> {
+        self.apply_recursive(path, &mut |item_path| self.utime(item_path, timestamp), &mut progress)
+    }
+
+    /// Applies a timestamp to each of the given paths. Failures for individual paths are
+    /// aggregated in the returned summary.
+    #[cfg(feature = "chrono")]
+    pub fn utime_all<'a>(&self, paths: impl IntoIterator<Item = &'a str>, timestamp: NaiveDateTime) -> TreeSummary {
+        let mut summary = TreeSummary::default();
+        for path in paths {
+            summary.record(self.utime(path, timestamp));
+        }
+        summary
+    }
+}
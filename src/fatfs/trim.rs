@@ -0,0 +1,124 @@
+use crate::fatfs::*;
+use crate::fatfs::fat::FatReader;
+
+/// Position of a background TRIM or scrub pass over the FAT, so that the pass may be spread
+/// over many calls to `trim_free()` or `scrub_free()`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TrimCursor {
+    next_cluster: u32
+}
+
+/// The outcome of a call to `trim_free()` or `scrub_free()`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TrimReport {
+    /// Number of FAT entries examined.
+    pub clusters_scanned: u32,
+    /// Number of free clusters the device was informed of, or that were overwritten.
+    pub clusters_trimmed: u32,
+    /// Set when the pass reached the end of the FAT. The cursor starts a new pass on the
+    /// next call.
+    pub complete: bool
+}
+
+/// How `scrub_free()` treats free clusters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScrubMode {
+    /// Overwrite each free cluster with zeros.
+    Zero,
+    /// Issue `CTRL_TRIM` for each free cluster, which erases it on devices that support it.
+    Trim,
+    /// Overwrite each free cluster with zeros, then issue `CTRL_TRIM` for it.
+    ZeroAndTrim
+}
+
+impl RawFileSystem {
+    /// Walks part of the FAT and issues `CTRL_TRIM` for each run of at least `min_extent`
+    /// free clusters, keeping managed flash devices and SD cards fast after many files have
+    /// been deleted. At most `max_clusters` FAT entries are examined per call, so that the
+    /// file system lock is only held briefly; the cursor records where to resume.
+    /// Free runs are not carried between calls, so clusters allocated in between are never trimmed.
+    pub fn trim_free(&self, cursor: &mut TrimCursor, max_clusters: u32, min_extent: u32) -> Result<TrimReport, Error> {
+        if self.fs().fs_type == 0 {
+            return Err(Error::NotEnabled)
+        }
+        if max_clusters == 0 || min_extent == 0 {
+            return Err(Error::InvalidParameter)
+        }
+        self.free_runs(cursor, max_clusters, |start, end| self.trim_run(start, end, min_extent))
+    }
+
+    /// Walks part of the FAT and overwrites or trims every free cluster, so that the contents
+    /// of deleted files cannot be recovered from the storage, for example before a device is
+    /// decommissioned. As with `trim_free()`, at most `max_clusters` FAT entries are examined
+    /// per call, and the cursor records where to resume; a pass is complete when the report
+    /// says so. Clusters freed after the cursor has passed them are not scrubbed until the
+    /// next pass. Writes are flushed to the device before returning.
+    pub fn scrub_free(&self, cursor: &mut TrimCursor, max_clusters: u32, mode: ScrubMode) -> Result<TrimReport, Error> {
+        if self.fs().fs_type == 0 {
+            return Err(Error::NotEnabled)
+        }
+        if max_clusters == 0 {
+            return Err(Error::InvalidParameter)
+        }
+        let report = self.free_runs(cursor, max_clusters, |start, end| {
+            let first = self.cluster_sector(start);
+            let count = (end - start) * self.fs().csize as u32;
+            if mode != ScrubMode::Trim {
+                for sector in first..first + count {
+                    diskio::write_sectors(self.fs().pdrv, &ZERO_SECTOR, sector)?;
+                }
+            }
+            if mode != ScrubMode::Zero {
+                diskio::trim_sectors(self.fs().pdrv, first, first + count - 1)?;
+            }
+            Ok(end - start)
+        })?;
+        diskio::sync_drive(self.fs().pdrv)?;
+        Ok(report)
+    }
+
+    /// Examines at most `max_clusters` FAT entries from the cursor, calling the given function
+    /// with the start and end of each run of free clusters found, and advances the cursor. The
+    /// function returns the number of clusters it acted on, which is added to the report.
+    fn free_runs(&self, cursor: &mut TrimCursor, max_clusters: u32, mut f: impl FnMut(u32, u32) -> Result<u32, Error>) -> Result<TrimReport, Error> {
+        let mut report = TrimReport::default();
+        let mut fat = FatReader::new(self);
+        let mut cluster = cursor.next_cluster.max(2);
+        let mut run_start = None;
+        while cluster < self.fs().n_fatent && report.clusters_scanned < max_clusters {
+            let free = fat.entry(cluster)? == 0;
+            match (free, run_start) {
+                (true, None) => run_start = Some(cluster),
+                (false, Some(start)) => {
+                    report.clusters_trimmed += f(start, cluster)?;
+                    run_start = None;
+                }
+                _ => ()
+            }
+            cluster += 1;
+            report.clusters_scanned += 1;
+        }
+        if let Some(start) = run_start {
+            report.clusters_trimmed += f(start, cluster)?;
+        }
+        if cluster >= self.fs().n_fatent {
+            report.complete = true;
+            cursor.next_cluster = 2;
+        } else {
+            cursor.next_cluster = cluster;
+        }
+        Ok(report)
+    }
+
+    /// Issues `CTRL_TRIM` for the free clusters from `start` up to but not including `end`,
+    /// if there are at least `min_extent` of them. Returns the number of clusters trimmed.
+    fn trim_run(&self, start: u32, end: u32, min_extent: u32) -> Result<u32, Error> {
+        let count = end - start;
+        if count < min_extent {
+            return Ok(0)
+        }
+        let first = self.cluster_sector(start);
+        diskio::trim_sectors(self.fs().pdrv, first, first + count * self.fs().csize as u32 - 1)?;
+        Ok(count)
+    }
+}
@@ -0,0 +1,94 @@
+use core::ptr;
+use crate::fatfs::*;
+use crate::fatfs::boot::FatType;
+use crate::fatfs::path::PathBuffer;
+
+/// The capacity of a mounted volume, returned by `stats()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FsStats {
+    /// Size of the data area of the volume, which holds files and directories.
+    pub total_bytes: u64,
+    pub free_bytes: u64,
+    pub cluster_size: u32,
+    pub fs_type: FatType
+}
+
+/// A description of a mounted volume, returned by `volume_info()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VolumeInfo {
+    /// The volume label, which is empty if the volume has none.
+    pub label: String,
+    pub serial: u32,
+    pub fs_type: FatType,
+    /// Size of the data area of the volume, which holds files and directories.
+    pub total_bytes: u64,
+    pub free_bytes: u64,
+    pub sector_size: u32,
+    pub cluster_size: u32
+}
+
+impl RawFileSystem {
+    /// Returns the type of FAT of the mounted volume.
+    pub(crate) fn fat_type(&self) -> Result<FatType, Error> {
+        match self.fs().fs_type as u32 {
+            FS_FAT12 => Ok(FatType::Fat12),
+            FS_FAT16 => Ok(FatType::Fat16),
+            FS_FAT32 => Ok(FatType::Fat32),
+            _ => Err(Error::NotEnabled)
+        }
+    }
+
+    /// Returns the total and free space of the volume at the given path in bytes, along with
+    /// its cluster size and type of FAT. The free space is found with `getfree()`, so the FAT
+    /// may be scanned the first time it is called after mounting.
+    pub fn stats(&self, path: &str) -> Result<FsStats, Error> {
+        let free_clusters = self.getfree(PathBuffer::new(path).as_str())?;
+        let cluster_size = self.fs().csize as u32 * FF_MAX_SS;
+        Ok(FsStats {
+            total_bytes: (self.fs().n_fatent - 2) as u64 * cluster_size as u64,
+            free_bytes: free_clusters as u64 * cluster_size as u64,
+            cluster_size,
+            fs_type: self.fat_type()?
+        })
+    }
+
+    /// Returns the serial number of the volume at the given path, which is set when the volume
+    /// is formatted. Comparing it with a stored value detects that a card has been swapped.
+    pub fn volume_serial(&self, path: &str) -> Result<u32, Error> {
+        let path = PathBuffer::new(path);
+        let result;
+        let mut serial = 0;
+        //FatFs skips reading the label when no buffer is given.
+        unsafe { result = f_getlabel(path.as_str().as_ptr().cast(), ptr::null_mut(), ptr::addr_of_mut!(serial)); }
+        if result == FRESULT_FR_OK {
+            return Ok(serial)
+        } else {
+            return Err(Error::from_result(result))
+        }
+    }
+
+    /// Returns the label, serial number, type of FAT, capacity and sector and cluster sizes of
+    /// the volume at the given path together, without the label buffer required by `getlabel()`.
+    pub fn volume_info(&self, path: &str) -> Result<VolumeInfo, Error> {
+        let path = PathBuffer::new(path);
+        let result;
+        let mut serial = 0;
+        //Large enough for any label, as for `getlabel()`.
+        let mut label = [0u8; 34];
+        unsafe { result = f_getlabel(path.as_str().as_ptr().cast(), label.as_mut_ptr().cast(), ptr::addr_of_mut!(serial)); }
+        if result != FRESULT_FR_OK {
+            return Err(Error::from_result(result))
+        }
+        let len = label.iter().position(|c| *c == 0).unwrap_or(label.len());
+        let stats = self.stats(path.as_str())?;
+        Ok(VolumeInfo {
+            label: String::from_utf8_lossy(&label[..len]).into_owned(),
+            serial,
+            fs_type: stats.fs_type,
+            total_bytes: stats.total_bytes,
+            free_bytes: stats.free_bytes,
+            sector_size: FF_MAX_SS,
+            cluster_size: stats.cluster_size
+        })
+    }
+}
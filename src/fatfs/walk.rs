@@ -0,0 +1,200 @@
+use crate::fatfs::*;
+use crate::fatfs::path::{self, PathBuffer};
+use alloc::vec::Vec;
+
+#[cfg(feature = "chrono")]
+use chrono::NaiveDateTime;
+#[cfg(feature = "chrono")]
+use crate::fatfs::entry::fat_timestamp;
+
+/// An item found by `walk()`.
+#[derive(Clone, Debug)]
+pub struct WalkEntry {
+    /// The path of the item, formed by joining its name onto the path given to `walk()`.
+    pub path: String,
+    pub info: FileInfo
+}
+
+/// The space taken up by a directory tree, returned by `du()`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DiskUsage {
+    /// Total size of the files.
+    pub bytes: u64,
+    /// Total size of the files, with each rounded up to a whole number of clusters. This is
+    /// the space the files take up on the volume, not counting directories.
+    pub allocated_bytes: u64,
+    pub files: u32,
+    pub directories: u32
+}
+
+/// A directory being walked, to be resumed once its sub-directory has been walked.
+struct Level {
+    /// Length of the path of the directory.
+    len: usize,
+    /// Number of items of the directory already read.
+    index: u32
+}
+
+/// Depth-first iterator over the items beneath a directory, created by `walk()`. Each item is
+/// yielded before the items beneath it.
+///
+/// Only one directory is held open at a time, and the only state kept per level of the tree
+/// is the number of items already read, so memory use grows with the depth of the tree and
+/// not with the number of items. When a sub-directory has been walked, its parent is opened
+/// again and the items already read are skipped.
+///
+/// Errors reading a directory are yielded and the rest of that directory is skipped. Items
+/// with names that are not valid UTF-8 are yielded as `Error::InvalidName` and not descended into.
+pub struct Walk<'a> {
+    fs: &'a RawFileSystem,
+    path: PathBuffer,
+    dir: Option<Directory>,
+    index: u32,
+    parents: Vec<Level>,
+    done: bool
+}
+
+impl Walk<'_> {
+    fn close(&mut self) {
+        if let Some(dir) = self.dir.take() {
+            let _ = self.fs.closedir(dir);
+        }
+    }
+
+    /// Opens the current directory, skipping the items already read.
+    fn open(&mut self) -> Result<(), Error> {
+        let mut dir = self.fs.opendir(self.path.as_str())?;
+        for _ in 0..self.index {
+            if let Err(error) = self.fs.readdir(&mut dir) {
+                let _ = self.fs.closedir(dir);
+                return Err(error)
+            }
+        }
+        self.dir = Some(dir);
+        Ok(())
+    }
+
+    /// Returns to the parent of the current directory, or ends the walk at the top.
+    fn ascend(&mut self) {
+        self.close();
+        match self.parents.pop() {
+            Some(level) => {
+                self.path.truncate(level.len);
+                self.index = level.index;
+            },
+            None => self.done = true
+        }
+    }
+
+    fn read(&mut self) -> Result<FileInfo, Error> {
+        if self.dir.is_none() {
+            self.open()?;
+        }
+        match self.dir.as_mut() {
+            Some(dir) => self.fs.readdir(dir),
+            None => Err(Error::IntError)
+        }
+    }
+}
+
+impl Iterator for Walk<'_> {
+    type Item = Result<WalkEntry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            let info = match self.read() {
+                Ok(info) => info,
+                Err(error) => {
+                    self.ascend();
+                    return Some(Err(error))
+                }
+            };
+            if info.fname[0] == 0 {
+                self.ascend();
+                continue
+            }
+            self.index += 1;
+            let name = match info.name() {
+                Ok(name) => name,
+                Err(error) => return Some(Err(error))
+            };
+            let len = self.path.push(name);
+            let entry = WalkEntry { path: String::from(self.path.as_str()), info: info.clone() };
+            if info.fattrib & FileAttributes::Directory.as_u8() != 0 {
+                self.close();
+                self.parents.push(Level { len, index: self.index });
+                self.index = 0;
+            } else {
+                self.path.truncate(len);
+            }
+            return Some(Ok(entry))
+        }
+        None
+    }
+}
+
+impl Drop for Walk<'_> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl RawFileSystem {
+    /// Walks the tree beneath the directory at the given path, depth first. See `Walk`.
+    pub fn walk(&self, path: &str) -> Result<Walk<'_>, Error> {
+        let mut walk = Walk { fs: self, path: PathBuffer::new(path), dir: None, index: 0, parents: Vec::new(), done: false };
+        walk.open()?;
+        Ok(walk)
+    }
+
+    /// Walks the tree beneath the directory at the given path, yielding the items whose
+    /// attributes, selected by `mask`, equal `attr`, in the same way as `findfirst_filtered()`.
+    /// Errors are yielded as they are by `walk()`.
+    pub fn find_with_attr(&self, path: &str, attr: FileAttributes, mask: FileAttributes) -> Result<impl Iterator<Item = Result<WalkEntry, Error>> + '_, Error> {
+        Ok(self.walk(path)?.filter(move |item| match item {
+            Ok(entry) => entry.info.fattrib & mask.as_u8() == attr.as_u8(),
+            Err(_) => true
+        }))
+    }
+
+    /// Walks the tree beneath the directory at the given path, yielding the files modified at
+    /// or after the given time. Directories and items with invalid timestamps are skipped.
+    /// Errors are yielded as they are by `walk()`.
+    #[cfg(feature = "chrono")]
+    pub fn find_modified_since(&self, path: &str, timestamp: NaiveDateTime) -> Result<impl Iterator<Item = Result<WalkEntry, Error>> + '_, Error> {
+        Ok(self.walk(path)?.filter(move |item| match item {
+            Ok(entry) => entry.info.fattrib & FileAttributes::Directory.as_u8() == 0 &&
+                fat_timestamp(entry.info.fdate, entry.info.ftime).is_some_and(|modified| modified >= timestamp),
+            Err(_) => true
+        }))
+    }
+
+    /// Adds up the sizes of the files beneath the directory at the given path, walking it with
+    /// `walk()`, so memory use grows with the depth of the tree and not with the number of
+    /// items. Given the path of a file, returns the size of that file. Stops at the first error.
+    pub fn du(&self, path: &str) -> Result<DiskUsage, Error> {
+        let cluster_size = self.fs().csize as u64 * FF_MAX_SS as u64;
+        let mut usage = DiskUsage::default();
+        let mut add = |info: &FileInfo| {
+            if info.fattrib & FileAttributes::Directory.as_u8() != 0 {
+                usage.directories += 1;
+            } else {
+                usage.files += 1;
+                usage.bytes += info.fsize as u64;
+                usage.allocated_bytes += (info.fsize as u64).div_ceil(cluster_size) * cluster_size;
+            }
+        };
+        let path = PathBuffer::new(path);
+        if !path::is_root(path.as_str()) {
+            let info = self.stat(path.as_str())?;
+            if info.fattrib & FileAttributes::Directory.as_u8() == 0 {
+                add(&info);
+                return Ok(usage)
+            }
+        }
+        for entry in self.walk(path.as_str())? {
+            add(&entry?.info);
+        }
+        Ok(usage)
+    }
+}
@@ -0,0 +1,122 @@
+//! A deferred write queue for logging from interrupt context.
+//!
+//! The file system mutex can never be taken from an interrupt handler. Instead, an
+//! interrupt handler pushes records into a `WriteQueue` without blocking, and a
+//! normal-priority task periodically drains the queue into an open file.
+//!
+//! ```ignore
+//! static LOG_QUEUE: WriteQueue<1024> = WriteQueue::new();
+//!
+//! //From an interrupt handler:
+//! let _ = LOG_QUEUE.push(b"ADC overrun\n");
+//!
+//! //From a task:
+//! LOG_QUEUE.drain_to(&fatfs::FS, &mut log_file).await?;
+//! ```
+
+use core::cell::UnsafeCell;
+use core::ptr;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use crate::fatfs::{Error, File, FileSystem, RawFileSystem};
+
+/// Returned by `WriteQueue::push()` when a record does not fit in the free space of the queue.
+#[derive(Debug, PartialEq)]
+pub struct QueueFull;
+
+/// A lock-free single-producer, single-consumer byte queue with a capacity of `N - 1` bytes.
+/// Records are pushed whole or not at all.
+///
+/// Only one context may push at a time. If records are pushed from more than one interrupt
+/// priority, the caller must serialize the calls to `push()`. `N` must be at least 2.
+pub struct WriteQueue<const N: usize> {
+    buffer: UnsafeCell<[u8; N]>,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    dropped: AtomicU32
+}
+
+unsafe impl<const N: usize> Sync for WriteQueue<N> {}
+
+impl<const N: usize> WriteQueue<N> {
+    pub const fn new() -> Self {
+        const { assert!(N >= 2, "a WriteQueue must have a capacity of at least 2") }
+        Self {
+            buffer: UnsafeCell::new([0; N]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            dropped: AtomicU32::new(0)
+        }
+    }
+
+    /// Queues a record to be written. This never blocks and is safe to call from an interrupt handler.
+    /// If the record does not fit, it is discarded and counted in `dropped()`.
+    pub fn push(&self, record: &[u8]) -> Result<(), QueueFull> {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+        if record.len() > (tail + N - head - 1) % N {
+            //Only the producer updates the counter, so a load and store is sufficient.
+            self.dropped.store(self.dropped.load(Ordering::Relaxed).wrapping_add(1), Ordering::Relaxed);
+            return Err(QueueFull)
+        }
+        let first = record.len().min(N - head);
+        let buffer: *mut u8 = self.buffer.get().cast();
+        unsafe {
+            ptr::copy_nonoverlapping(record.as_ptr(), buffer.add(head), first);
+            ptr::copy_nonoverlapping(record[first..].as_ptr(), buffer, record.len() - first);
+        }
+        self.head.store((head + record.len()) % N, Ordering::Release);
+        Ok(())
+    }
+
+    /// Returns the number of bytes waiting to be written.
+    pub fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+        (head + N - tail) % N
+    }
+
+    /// Returns true if no bytes are waiting to be written.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of records discarded because the queue was full.
+    pub fn dropped(&self) -> u32 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Writes all queued bytes to the given file and returns the number of bytes written.
+    /// Bytes that could not be written, for example because the drive is full, remain queued.
+    pub fn drain(&self, fs: &RawFileSystem, file: &mut File) -> Result<u32, Error> {
+        let head = self.head.load(Ordering::Acquire);
+        let mut tail = self.tail.load(Ordering::Relaxed);
+        let mut total = 0;
+        while tail != head {
+            let end = if head > tail { head } else { N };
+            //The producer never writes to the region between the tail and the head.
+            let chunk = unsafe {
+                &*ptr::slice_from_raw_parts(self.buffer.get().cast::<u8>().add(tail), end - tail)
+            };
+            let written = fs.write(file, chunk)?;
+            total += written;
+            tail = (tail + written as usize) % N;
+            self.tail.store(tail, Ordering::Release);
+            if (written as usize) < chunk.len() {
+                break
+            }
+        }
+        Ok(total)
+    }
+
+    /// Acquires a lock on the file system and writes all queued bytes to the given file.
+    pub async fn drain_to(&self, fs: &FileSystem, file: &mut File) -> Result<u32, Error> {
+        let locked_fs = fs.lock().await;
+        self.drain(&locked_fs, file)
+    }
+}
+
+impl<const N: usize> Default for WriteQueue<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -8,8 +8,8 @@
 //! testing purposes when targeting an OS.
 //! * Thread safe - The choice was made to have a dependency on the Embassy
 //! framework for concurrency support which is suitable for embedded systems. A global
-//! file system mutex is implemented in favor of the `FF_FS_REENTRANT` option, which is
-//! more suitable to a Rust implementation.
+//! file system read-write lock is implemented in favor of the `FF_FS_REENTRANT` option,
+//! which is more suitable to a Rust implementation.
 //! * Portable - Implement the `FatFsDriver` trait to add support for any block device.
 //! To support this implementation, `alloc` support is unfortunately required due to the 
 //! structure of FatFs. A simulated block storage driver implementation is included for 
@@ -21,19 +21,72 @@
 //! which can easily cause a lockup condition.
 //! Files and directories must be manually closed. (The file system object itself is 
 //! implemented as a static singleton and thus is never dropped.)
+//! Alternatively, a file may be wrapped in an `AutoCloseFile`, which is queued to be closed
+//! when dropped. Queued files are closed the next time the lock is acquired with `lock()`,
+//! or by calling `process_pending_closes()`.
+//! Files returned by `open()` borrow the lock guard instead, and so are closed directly
+//! when dropped. `open_raw()` returns a bare file object that must be closed manually.
+//! 
+//! ## Async
+//! Acquiring the file system lock is asynchronous, but each FatFs call then runs to
+//! completion, including the driver operations it makes. FatFs is a synchronous C library
+//! and cannot be suspended part way through a call. Drivers built on completion futures may
+//! implement `AsyncFatFsDriver` and be installed with `install_async()`. `read_async()` and
+//! `write_async()` then await the driver for the whole sectors of a transfer, which FatFs
+//! would otherwise read or write directly, so that other tasks run while a long transfer is
+//! in flight. The FAT, directories and partial sectors are still read and written by FatFs,
+//! which polls the driver to completion from within the call.
 //! 
 //! # FatFs Configuration
 //! Most features of FatFs are enabled with a few exceptions:
 //! * `FF_USE_FORWARD` is disabled to avoid using additional `unsafe` code.
 //! * `FF_CODE_PAGE` is set to 0 and thus must be set via a call to `setcp()`.
 //! * `FF_VOLUMES` is currently set to 1 limiting the number of volumes supported to 1.
-//! * `FF_MULTI_PARTITION` is not currently supported.
+//! * `FF_MULTI_PARTITION` is enabled so that a partition may be selected with `select_partition()`,
+//! `mount_by_label()` or `mount_by_serial()`, and a drive divided into partitions with
+//! `partition()`. `mount()` and `mkfs()` use the first FAT volume on the drive unless a
+//! partition has been selected.
 //! * `FF_FS_LOCK` is configured to support 10 simultaneous open files.
-//! * An implementation of the `f_printf()` function is not provided.
+//! * `FF_USE_FIND` is set to 2 so that `findfirst()` and `findnext()` also match patterns
+//! against the short 8.3 name of each item.
+//! * An implementation of the `f_printf()` function is not provided. Use `write!()` with a
+//! `buffered::BufWriter` instead, which implements `core::fmt::Write`.
+//!
+//! # Panics
+//! Errors from FatFs and the installed driver, including invalid requests from FatFs to the
+//! driver, are returned as an `Error` rather than causing a panic. Explicit panics are kept out
+//! of the crate by denying the `clippy` lints for `unwrap()`, `expect()`, `panic!()` and the
+//! like. This is not a guarantee that no call panics: slice indexing, arithmetic overflow in
+//! debug builds and a `RefCell` borrowed twice may still panic, and there is no link-time check
+//! that the panic handler is unreachable. Allocation failures are handled by the global
+//! allocator of the application.
 //! 
 //! # Features
 //! * `chrono` (default) - Enables time support in the library. Access to an RTC may be 
 //! provided via an implementation of the `FatFsDriver` trait.
+//! * `time` - Enables timeouts for block device operations using `embassy-time`. See
+//! `diskio::timeout`.
+//! * `heapless` - Enables `readdir_names()`, which yields item names as fixed capacity
+//! `heapless::String`s.
+//! * `embedded-io` - Enables streaming images of the volume to and from `embedded-io`
+//! readers and writers with `export_image()` and `import_image()`, and `io::IoFile`, which
+//! implements the `embedded-io` traits for a file.
+//! * `embedded-io-async` - Enables `io::AsyncIoFile`, which implements the `embedded-io-async`
+//! traits for a file.
+//! * `embedded-storage` - Enables `diskio::storage::StorageDriver`, which adapts an
+//! `embedded_storage::Storage` device to a `FatFsDriver`.
+//! * `embedded-storage-async` - Enables `diskio::storage::FlashDriver`, for async NOR flash.
+//! * `sd-spi` - Enables `diskio::sd_spi::SdSpi`, a driver for SD cards over an `embedded-hal`
+//! SPI bus and chip select pin.
+//! * `ufmt` - Implements `ufmt::uWrite` for `buffered::BufWriter`, for formatted writes
+//! without the code size of `core::fmt`.
+//! * `kv` - Enables `kv::KvStore`, a small key-value store kept in a file.
+//! * `postcard` - Enables `write_struct()` and `read_struct()`, which store `serde` types in
+//! files in the `postcard` format.
+//! * `json` - Enables `write_json()` and `read_json()`, which store `serde` types in files as
+//! JSON with `serde-json-core`.
+//! * `sha256` - Adds SHA-256 to the algorithms of `checksum()`, using `sha2`, and enables
+//! `load_firmware()`, which streams a firmware image to a callback while checking its digest.
 //! 
 //! # Examples
 //! A brief example that formats and mounts a simulated drive, writes a string to a file, 
@@ -42,7 +95,7 @@
 //! #[path = "../tests/simulated_driver.rs"]
 //! mod simulated_driver;
 //! 
-//! use fatfs_embedded::fatfs::{self, File, FileOptions, FormatOptions};
+//! use fatfs_embedded::fatfs::{self, FileOptions, FormatOptions, MkfsOptions};
 //! use embassy_futures::block_on;
 //! 
 //! const TEST_STRING: &[u8] = b"Hello world!";
@@ -55,13 +108,13 @@
 //! let mut locked_fs = block_on(fatfs::FS.lock());
 //! 
 //! //Format the drive.
-//! locked_fs.mkfs("", FormatOptions::FAT32, 0, 0, 0, 0);
+//! locked_fs.mkfs("", MkfsOptions::new().format(FormatOptions::FAT32));
 //! 
 //! //Mount the drive.
 //! locked_fs.mount();
 //! 
 //! //Create a new file.
-//! let mut test_file: File = locked_fs.open("test.txt", 
+//! let mut test_file = locked_fs.open("test.txt", 
 //!     FileOptions::CreateAlways | 
 //!     FileOptions::Read | 
 //!     FileOptions::Write).unwrap();
@@ -77,29 +130,130 @@
 //! locked_fs.read(&mut test_file, &mut read_back);
 //! assert_eq!(TEST_STRING, read_back);
 //! 
-//! //Close the file when done.
-//! locked_fs.close(&mut test_file);
+//! //Close the file when done. It is also closed when dropped.
+//! test_file.close();
 //! ```
 
 #![no_std]
+#![cfg_attr(not(test), deny(clippy::unwrap_used, clippy::expect_used, clippy::panic, clippy::unreachable, clippy::todo, clippy::unimplemented))]
 
 pub mod fatfs {
 
     /// Block storage I/O objects are located here.
     pub mod diskio;
+    /// Deferred writes from interrupt context are located here.
+    pub mod write_queue;
+    /// A double-buffered writer for high-rate logging is located here.
+    pub mod double_buffer;
+    /// A facade mirroring the `embedded-sdmmc` API is located here.
+    pub mod sdmmc;
+    /// Files of fixed-size records are located here.
+    pub mod record;
+    /// Circular files for black-box logging are located here.
+    pub mod ring;
+    /// Byte budgets for directories are located here.
+    pub mod quota;
+    /// Buffered readers and writers for files are located here.
+    pub mod buffered;
+    /// Settings files protected by a CRC are located here.
+    pub mod config;
+    /// A key-value store kept in a file is located here.
+    #[cfg(feature = "kv")]
+    pub mod kv;
+    /// Decoding of boot sectors is located here.
+    pub mod boot;
+    /// `embedded-io` adapters for files are located here.
+    #[cfg(feature = "embedded-io")]
+    pub mod io;
     mod inc_bindings;
+    mod lock;
+    mod async_file;
+    mod cache;
+    mod path;
+    mod registry;
+    mod tree;
+    mod entry;
+    mod named_lock;
+    mod trim;
+    mod crc;
+    mod fat;
+    mod clone;
+    mod mount_info;
+    mod checksum;
+    mod batch;
+    mod partition;
+    mod deferred_close;
+    mod scoped;
+    mod open_options;
+    mod mkfs_options;
+    mod file;
+    mod walk;
+    mod copy;
+    mod temp;
+    mod sequence;
+    mod cancel;
+    mod prealloc;
+    mod fast_seek;
+    mod extent;
+    mod raw;
+    mod fsck;
+    mod volume;
+    #[cfg(feature = "embedded-io")]
+    mod image;
+    #[cfg(any(feature = "postcard", feature = "json"))]
+    mod structs;
+    #[cfg(feature = "sha256")]
+    mod firmware;
+
+    pub use tree::TreeSummary;
+    pub use entry::{EntryMeta, DirEntry, DirIter, FindIter};
+    pub use named_lock::NamedLock;
+    pub use trim::{TrimCursor, TrimReport, ScrubMode};
+    pub use clone::CloneMode;
+    pub use mount_info::MountInfo;
+    pub use partition::PartitionSpec;
+    pub use deferred_close::AutoCloseFile;
+    pub use scoped::ScopedFile;
+    pub use open_options::{OpenOptions, OpenOptionsError};
+    pub use mkfs_options::{MkfsOptions, MkfsOptionsError, MkfsReport};
+    pub use file::{SeekFrom, WriteAllError, ReadExactError};
+    pub use walk::{Walk, WalkEntry, DiskUsage};
+    pub use checksum::{Checksum, ChecksumAlgorithm};
+    pub use cancel::CancelToken;
+    pub use prealloc::PreallocatedFile;
+    pub use extent::{SectorExtent, ClusterRun, Fragmentation};
+    pub use fsck::{FsckIssue, FsckReport, LostChainAction, RecoveryReport};
+    pub use volume::{FsStats, VolumeInfo};
+    #[cfg(feature = "embedded-io")]
+    pub use image::ImageError;
+    #[cfg(any(feature = "postcard", feature = "json"))]
+    pub use structs::StructError;
+    #[cfg(feature = "sha256")]
+    pub use firmware::FirmwareError;
+    #[cfg(feature = "heapless")]
+    pub use entry::DirNames;
+
+    pub use lock::{FileSystem, FileSystemGuard, ReadGuard, ReadOnlyFileSystem};
 
     extern crate alloc;
 
     use core::ptr;
+    use core::cell::{Cell, RefCell, UnsafeCell};
+    use core::ops::{Deref, DerefMut};
     use alloc::string::String;
+    use alloc::vec::Vec;
     use bitflags::bitflags;
-    use embassy_sync::{mutex::Mutex, blocking_mutex::raw::ThreadModeRawMutex};
     use crate::fatfs::inc_bindings::*;
+    use crate::fatfs::cache::{DirCache, StatCache};
+    use crate::fatfs::registry::Registry;
+    use crate::fatfs::path::PathBuffer;
     
     #[cfg(feature = "chrono")]
     use chrono::{NaiveDateTime, Timelike, Datelike};
 
+    #[cfg(feature = "time")]
+    use embassy_time::Duration;
+
     #[derive(Debug)]
     #[derive(PartialEq)]
     pub enum Error {
@@ -121,7 +275,12 @@ pub mod fatfs {
         Locked = FRESULT_FR_LOCKED as isize,
         NotEnoughCore = FRESULT_FR_NOT_ENOUGH_CORE as isize,
         TooManyOpenFiles = FRESULT_FR_TOO_MANY_OPEN_FILES as isize,
-        InvalidParameter = FRESULT_FR_INVALID_PARAMETER as isize
+        InvalidParameter = FRESULT_FR_INVALID_PARAMETER as isize,
+        //Not returned by FatFs, only when an operation is stopped with a `CancelToken`.
+        Cancelled = FRESULT_FR_INVALID_PARAMETER as isize + 1,
+        //Not returned by FatFs, only when a file has been synced while checksums are enabled
+        //but its sidecar could not be written. The data of the file is safe.
+        ChecksumMissing = FRESULT_FR_INVALID_PARAMETER as isize + 2
     }
 
     impl TryFrom<u32> for Error {
@@ -153,6 +312,77 @@ pub mod fatfs {
         }
     }
 
+    impl Error {
+        /// Converts a failed result from FatFs or the driver. A driver operation that overran
+        /// its deadline is reported to FatFs as a disk error, so a pending timeout is returned
+        /// as `Error::Timeout` instead, and cleared.
+        pub(crate) fn from_result(result: u32) -> Self {
+            #[cfg(feature = "time")]
+            if diskio::timeout::take_expired() {
+                return Error::Timeout
+            }
+            Error::try_from(result).unwrap_or(Error::IntError)
+        }
+    }
+
+    /// Error returned by `create_new()`. An existing item at the path is reported separately
+    /// from all other errors, so that it can be handled as an expected outcome.
+    #[derive(Debug)]
+    #[derive(PartialEq)]
+    pub enum CreateError {
+        AlreadyExists,
+        Other(Error)
+    }
+
+    impl From<Error> for CreateError {
+        fn from(error: Error) -> Self {
+            match error {
+                Error::Exists => CreateError::AlreadyExists,
+                error => CreateError::Other(error)
+            }
+        }
+    }
+
+    /// An open file that holds the FatFs lock on a path.
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct LockHolder {
+        /// The path the file was opened with.
+        pub path: String,
+        pub mode: FileOptions
+    }
+
+    /// Error returned by `open_reporting_holder()`. A sharing violation is reported
+    /// together with the open file that caused it, where it can be identified.
+    #[derive(Debug)]
+    #[derive(PartialEq)]
+    pub enum OpenError {
+        Locked(Option<LockHolder>),
+        Other(Error)
+    }
+
+    /// Discards the handle returned by a failed `close()`.
+    impl From<(Error, File)> for Error {
+        fn from((error, _): (Error, File)) -> Self {
+            error
+        }
+    }
+
+    /// Discards the handle returned by a failed `closedir()`.
+    impl From<(Error, Directory)> for Error {
+        fn from((error, _): (Error, Directory)) -> Self {
+            error
+        }
+    }
+
+    impl From<OpenError> for Error {
+        fn from(error: OpenError) -> Self {
+            match error {
+                OpenError::Locked(_) => Error::Locked,
+                OpenError::Other(error) => error
+            }
+        }
+    }
+
     impl Default for FATFS {
         fn default() -> FATFS {
             FATFS {
@@ -240,7 +470,43 @@ pub mod fatfs {
         }
     }
 
+    impl FILINFO {
+        /// Returns the name of the item. Names that are not valid UTF-8 in the configured code
+        /// page result in `Error::InvalidName`.
+        pub fn name(&self) -> Result<&str, Error> {
+            let name = unsafe { &*ptr::addr_of!(self.fname).cast::<[u8; 256]>() };
+            let len = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+            core::str::from_utf8(&name[..len]).map_err(|_| Error::InvalidName)
+        }
+
+        /// Returns the short 8.3 name of the item. FatFs only records a separate short name
+        /// for items that have a long name, so the name itself is returned for items that
+        /// do not. exFAT volumes have no short names, so the long name is always returned.
+        pub fn alt_name(&self) -> Result<&str, Error> {
+            let altname = unsafe { &*ptr::addr_of!(self.altname).cast::<[u8; 13]>() };
+            let len = altname.iter().position(|c| *c == 0).unwrap_or(altname.len());
+            if len == 0 {
+                return self.name()
+            }
+            core::str::from_utf8(&altname[..len]).map_err(|_| Error::InvalidName)
+        }
+    }
+
+    impl Clone for FILINFO {
+        fn clone(&self) -> Self {
+            Self {
+                fsize: self.fsize,
+                fdate: self.fdate,
+                ftime: self.ftime,
+                fattrib: self.fattrib,
+                fname: self.fname,
+                altname: self.altname,
+            }
+        }
+    }
+
     bitflags! {
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
         pub struct FileOptions: u8 {
             const Read = FA_READ as u8;
             const Write = FA_WRITE as u8;
@@ -253,6 +519,7 @@ pub mod fatfs {
     }
 
     bitflags! {
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
         pub struct FileAttributes: u8 {
             const ReadOnly = AM_RDO as u8;
             const Hidden = AM_HID as u8;
@@ -263,6 +530,7 @@ pub mod fatfs {
     }
 
     bitflags! {
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
         pub struct FormatOptions: u8 {
             const FAT = FM_FAT as u8;
             const FAT32 = FM_FAT32 as u8;
@@ -289,15 +557,40 @@ pub mod fatfs {
         }
     }
 
-    pub type FileSystem = Mutex<ThreadModeRawMutex, RawFileSystem>;
-    pub type File = FIL;
+    /// An open file. Files are handed out by value, so each open is given a handle under which
+    /// the state kept for it is found. The FatFs lock slot cannot serve, as it is shared by
+    /// every open of a file for reading.
+    #[derive(Debug, Default)]
+    pub struct File {
+        fil: FIL,
+        handle: u32
+    }
+
+    impl Deref for File {
+        type Target = FIL;
+
+        fn deref(&self) -> &FIL {
+            &self.fil
+        }
+    }
+
+    impl DerefMut for File {
+        fn deref_mut(&mut self) -> &mut FIL {
+            &mut self.fil
+        }
+    }
+
     pub type Directory = DIR;
     pub type FileInfo = FILINFO;
 
+    /// A sector of zeros, shared by operations that fill storage with zeros.
+    static ZERO_SECTOR: [u8; FF_MAX_SS as usize] = [0; FF_MAX_SS as usize];
+
     /// This is the file system singleton object. Access the file system
-    /// API by acquiring a lock on this object.
-    pub static FS: FileSystem = Mutex::new(
-        RawFileSystem { fs:
+    /// API by acquiring a lock on this object. Use `read()` instead of `lock()`
+    /// to allow other tasks that only read to proceed at the same time.
+    pub static FS: FileSystem = FileSystem::new(
+        RawFileSystem { fs: UnsafeCell::new(
             FATFS {
                 fs_type: 0, 
                 pdrv: 0, 
@@ -320,37 +613,165 @@ pub mod fatfs {
                 win: [0; 512],
                 lfnbuf: ptr::null_mut(),
                 cdir: 0,
-            }
+            }),
+            stat_cache: RefCell::new(StatCache::new()),
+            dir_cache: RefCell::new(DirCache::new()),
+            registry: RefCell::new(Registry::new()),
+            next_handle: Cell::new(1),
+            find_patterns: RefCell::new(Vec::new()),
+            mount_tracking: false,
+            mount_info: Cell::new(None),
+            checksums: false
     });
 
     /// The file system API is located here.
     pub struct RawFileSystem {
-        fs: FATFS
+        //FatFs modifies the file system object through the pointer given to `f_mount()`.
+        fs: UnsafeCell<FATFS>,
+        stat_cache: RefCell<StatCache>,
+        dir_cache: RefCell<DirCache>,
+        registry: RefCell<Registry>,
+        //Handle given to the next file opened.
+        next_handle: Cell<u32>,
+        //Patterns of open `findfirst()` searches, which FatFs reads on every `findnext()`.
+        find_patterns: RefCell<Vec<PathBuffer>>,
+        mount_tracking: bool,
+        mount_info: Cell<Option<MountInfo>>,
+        checksums: bool
     }
 
     unsafe impl Send for RawFileSystem {}
 
     impl RawFileSystem {
-        /// Opens the file at the given path in the given mode. FileOption flags may be OR'd together.
-        pub fn open(&self, path: &str, mode: FileOptions) -> Result<File, Error> {
+        /// Returns the file system object. The reference must not be held across a call into
+        /// FatFs, which may modify the object.
+        pub(crate) fn fs(&self) -> &FATFS {
+            unsafe { &*self.fs.get() }
+        }
+
+        /// Opens the file at the given path in the given mode, in the same way as `open()`, but
+        /// returns the bare file object. The file is not tied to the lock guard and must be closed
+        /// with `close()` while the lock is held.
+        /// When the directory cache is enabled with `set_dir_cache_capacity()`, the parent directory
+        /// of an absolute path is located from the cache instead of traversing every path component.
+        pub fn open_raw(&self, path: &str, mode: FileOptions) -> Result<File, Error> {
+            if mode.as_u8() & !FileOptions::Read.as_u8() != 0 {
+                self.invalidate_stat_cache();
+            }
+            let buffer = PathBuffer::new(path);
+            let path = buffer.as_str();
             let result;
-            let mut file = Default::default(); 
-            unsafe { result = f_open(ptr::addr_of_mut!(file), path.as_ptr().cast(), mode.as_u8());}
+            let mut file = File::default(); 
+            if let Some((cluster, name)) = self.cached_parent(path) {
+                result = self.with_cdir(cluster, || unsafe { f_open(ptr::addr_of_mut!(file.fil), name.as_ptr().cast(), mode.as_u8()) });
+            } else {
+                unsafe { result = f_open(ptr::addr_of_mut!(file.fil), path.as_ptr().cast(), mode.as_u8());}
+            }
             if result == FRESULT_FR_OK {
+                //Handle 0 is left to files that are not open.
+                file.handle = self.next_handle.get();
+                self.next_handle.set(file.handle.checked_add(1).unwrap_or(1));
+                self.registry.borrow_mut().insert(file.handle, path, self.fs().cdir, mode);
+                if self.checksums && file.obj.objsize == 0 {
+                    self.registry.borrow_mut().set_crc(file.handle, Some((0, 0)));
+                }
                 return Ok(file)
             } else {
-                return Err(Error::try_from(result).unwrap())
+                return Err(Error::from_result(result))
+            }
+        }
+
+        /// Creates a new file at the given path and opens it for reading and writing.
+        /// Fails with `CreateError::AlreadyExists` if an item already exists at the path, which
+        /// makes it suitable for lock files and initialization that must only happen once.
+        pub fn create_new(&self, path: &str) -> Result<File, CreateError> {
+            Ok(self.open_raw(path, FileOptions::CreateNew | FileOptions::Read | FileOptions::Write)?)
+        }
+
+        /// Opens the file at the given path in the given mode, in the same way as `open_raw()`.
+        /// If the file is already open in a conflicting mode, the error identifies the open file
+        /// that holds the lock on it.
+        pub fn open_reporting_holder(&self, path: &str, mode: FileOptions) -> Result<File, OpenError> {
+            match self.open_raw(path, mode) {
+                Ok(file) => Ok(file),
+                Err(Error::Locked) => Err(OpenError::Locked(self.lock_holder(path))),
+                Err(error) => Err(OpenError::Other(error))
+            }
+        }
+
+        /// Enables caching of the start clusters of up to the given number of directories, so that
+        /// files opened by absolute path are located without traversing every parent directory.
+        /// The cache is cleared when files are deleted or renamed, or the volume is mounted, unmounted
+        /// or formatted. A capacity of 0 disables the cache, which is the default.
+        pub fn set_dir_cache_capacity(&self, capacity: usize) {
+            self.dir_cache.borrow_mut().set_capacity(capacity);
+        }
+
+        /// Splits an absolute path into the start cluster of its parent directory and the
+        /// remaining name, resolving and caching the parent directory if necessary.
+        fn cached_parent<'a>(&self, path: &'a str) -> Option<(DWORD, &'a str)> {
+            if self.dir_cache.borrow().capacity() == 0 || !path.starts_with('/') {
+                return None
+            }
+            let (parent, name) = path.rsplit_once('/')?;
+            if parent.is_empty() {
+                return None
+            }
+            if let Some(cluster) = self.dir_cache.borrow_mut().get(parent) {
+                return Some((cluster, name))
             }
+            let parent_path = PathBuffer::new(parent);
+            let mut cluster = 0;
+            let result = self.with_cdir(0, || unsafe {
+                let result = f_chdir(parent_path.as_str().as_ptr().cast());
+                cluster = self.fs().cdir;
+                result
+            });
+            if result != FRESULT_FR_OK {
+                return None
+            }
+            self.dir_cache.borrow_mut().insert(parent, cluster);
+            Some((cluster, name))
+        }
+
+        /// Runs a FatFs call with the current directory temporarily set to the directory starting
+        /// at the given cluster, so that relative paths are resolved from it.
+        fn with_cdir<T>(&self, cluster: DWORD, f: impl FnOnce() -> T) -> T {
+            let fs = self.fs.get();
+            let previous;
+            unsafe {
+                previous = (*fs).cdir;
+                (*fs).cdir = cluster;
+            }
+            let result = f();
+            unsafe { (*fs).cdir = previous; }
+            result
+        }
+
+        fn invalidate_dir_cache(&self) {
+            self.dir_cache.borrow_mut().clear();
         }
 
-        /// Closes the given file.
-        pub fn close(&self, file: &mut File) -> Result<(), Error> {
+        /// Closes the given file. If closing fails, the file is returned along with the error
+        /// so that closing may be retried. While checksums are enabled, a file whose sidecar
+        /// cannot be written is left open and returned with `Error::ChecksumMissing`, so that
+        /// the sidecar is written by a retry once room has been made for it.
+        #[allow(clippy::result_large_err)]
+        pub fn close(&self, mut file: File) -> Result<(), (Error, File)> {
+            self.invalidate_stat_cache();
             let result;
-            unsafe { result = f_close(ptr::addr_of_mut!(*file)); }
+            let handle = file.handle;
+            if self.checksums && self.registry.borrow().get(handle).is_some_and(|open| open.dirty) {
+                if let Err(error) = self.sync(&mut file) {
+                    return Err((error, file))
+                }
+            }
+            unsafe { result = f_close(ptr::addr_of_mut!(file.fil)); }
             if result == FRESULT_FR_OK {
+                self.registry.borrow_mut().remove(handle);
                 return Ok(())
             } else {
-                return Err(Error::try_from(result).unwrap())
+                return Err((Error::from_result(result), file))
             }
         }
 
@@ -358,79 +779,155 @@ pub mod fatfs {
         pub fn read(&self, file: &mut File, buffer: &mut [u8]) -> Result<u32, Error> {
             let result;
             let mut bytes_read: UINT = 0;
-            unsafe { result = f_read(ptr::addr_of_mut!(*file), buffer.as_mut_ptr().cast(), buffer.len() as u32, ptr::addr_of_mut!(bytes_read)); }
+            unsafe { result = f_read(ptr::addr_of_mut!(file.fil), buffer.as_mut_ptr().cast(), buffer.len() as u32, ptr::addr_of_mut!(bytes_read)); }
             if result == FRESULT_FR_OK {
                 return Ok(bytes_read)
             } else {
-                return Err(Error::try_from(result).unwrap())
+                return Err(Error::from_result(result))
             }
         }
 
         /// Write data to the given file. The length of the provided buffer determines the length of data written.
         pub fn write(&self, file: &mut File, buffer: &[u8]) -> Result<u32, Error> {
+            self.invalidate_stat_cache();
             let result;
+            let position = file.fptr;
             let mut bytes_written: UINT = 0;
-            unsafe { result = f_write(ptr::addr_of_mut!(*file), buffer.as_ptr().cast(), buffer.len() as u32, ptr::addr_of_mut!(bytes_written)); }
+            unsafe { result = f_write(ptr::addr_of_mut!(file.fil), buffer.as_ptr().cast(), buffer.len() as u32, ptr::addr_of_mut!(bytes_written)); }
             if result == FRESULT_FR_OK {
+                self.registry.borrow_mut().record_write(file.handle, position, &buffer[..bytes_written as usize]);
                 return Ok(bytes_written)
             } else {
-                return Err(Error::try_from(result).unwrap())
+                self.registry.borrow_mut().set_dirty(file.handle, true);
+                return Err(Error::from_result(result))
             }
         }
 
         /// Move to an offset in the given file. This represents the location within the file for where data is read or written.
         pub fn seek(&self, file: &mut File, offset: u32) -> Result<(), Error> {
             let result;
-            unsafe { result = f_lseek(ptr::addr_of_mut!(*file), offset); }
+            unsafe { result = f_lseek(ptr::addr_of_mut!(file.fil), offset); }
             if result == FRESULT_FR_OK {
                 return Ok(())
             } else {
-                return Err(Error::try_from(result).unwrap())
+                return Err(Error::from_result(result))
             }
         }
 
         /// Truncates the given file.
         pub fn truncate(&self, file: &mut File) -> Result<(), Error> {
+            self.invalidate_stat_cache();
+            self.registry.borrow_mut().set_dirty(file.handle, true);
             let result;
-            unsafe { result = f_truncate(ptr::addr_of_mut!(*file)); }
+            unsafe { result = f_truncate(ptr::addr_of_mut!(file.fil)); }
             if result == FRESULT_FR_OK {
                 return Ok(())
             } else {
-                return Err(Error::try_from(result).unwrap())
+                return Err(Error::from_result(result))
             }
         }
 
         /// Forces a write of all data to storage. Whether this has any effect depends on the driver implementation.
+        /// While checksums are enabled, `Error::ChecksumMissing` is returned if the data was written but the
+        /// sidecar of the file could not be.
         pub fn sync(&self, file: &mut File) -> Result<(), Error> {
+            self.invalidate_stat_cache();
             let result;
-            unsafe { result = f_sync(ptr::addr_of_mut!(*file)); }
+            let dirty = self.registry.borrow().get(file.handle).is_some_and(|open| open.dirty);
+            unsafe { result = f_sync(ptr::addr_of_mut!(file.fil)); }
             if result == FRESULT_FR_OK {
+                //The file stays dirty until its sidecar is written, so that a failed update is
+                //retried by the next sync or close.
+                if self.checksums && dirty {
+                    self.update_checksum(file)?;
+                }
+                self.registry.borrow_mut().set_dirty(file.handle, false);
                 return Ok(())
             } else {
-                return Err(Error::try_from(result).unwrap())
+                return Err(Error::from_result(result))
+            }
+        }
+
+        /// Forces a write of all cached data to storage, for example ahead of a power loss.
+        /// Each of the given files is synced, then the FAT and directory data held by the file
+        /// system object is written back and the driver is asked to complete pending writes.
+        /// Data written to a file is held in that file object until it is synced, so every
+        /// file that has been written to must be supplied. All steps are attempted even if one
+        /// fails, in which case the first error is returned. On success the number of open files
+        /// that have been written to but remain unsynced, because they were not supplied, is returned.
+        pub fn sync_all(&self, files: &mut [&mut File]) -> Result<usize, Error> {
+            let mut first_error = None;
+            for file in files.iter_mut() {
+                if let Err(error) = self.sync(file) {
+                    first_error.get_or_insert(error);
+                }
             }
+            if let Err(error) = self.sync_fs() {
+                first_error.get_or_insert(error);
+            }
+            match first_error {
+                Some(error) => Err(error),
+                None => Ok(self.registry.borrow().iter().filter(|file| file.dirty).count())
+            }
+        }
+
+        /// Writes back the sector window of the file system object and, on FAT32 volumes, the
+        /// FSInfo sector, then issues `CTRL_SYNC`. This mirrors `sync_fs()` in FatFs, which is
+        /// otherwise only reached through `f_sync()` on a modified file.
+        fn sync_fs(&self) -> Result<(), Error> {
+            if self.fs().fs_type == 0 {
+                return Err(Error::NotEnabled)
+            }
+            let fs = self.fs.get();
+            unsafe {
+                if (*fs).wflag != 0 {
+                    diskio::write_sectors((*fs).pdrv, &(*fs).win, (*fs).winsect)?;
+                    (*fs).wflag = 0;
+                    //Reflect a sector of the first FAT to the second FAT.
+                    if (*fs).winsect.wrapping_sub((*fs).fatbase) < (*fs).fsize && (*fs).n_fats == 2 {
+                        diskio::write_sectors((*fs).pdrv, &(*fs).win, (*fs).winsect + (*fs).fsize)?;
+                    }
+                }
+                if (*fs).fs_type == FS_FAT32 as u8 && (*fs).fsi_flag == 1 {
+                    let win = &mut (*fs).win;
+                    win.fill(0);
+                    win[0..4].copy_from_slice(&0x41615252u32.to_le_bytes()); //FSI_LeadSig
+                    win[484..488].copy_from_slice(&0x61417272u32.to_le_bytes()); //FSI_StrucSig
+                    win[488..492].copy_from_slice(&(*fs).free_clst.to_le_bytes()); //FSI_Free_Count
+                    win[492..496].copy_from_slice(&(*fs).last_clst.to_le_bytes()); //FSI_Nxt_Free
+                    win[510..512].copy_from_slice(&0xAA55u16.to_le_bytes()); //BS_55AA
+                    (*fs).winsect = (*fs).volbase + 1;
+                    diskio::write_sectors((*fs).pdrv, &(*fs).win, (*fs).winsect)?;
+                    (*fs).fsi_flag = 0;
+                }
+            }
+            diskio::sync_drive(self.fs().pdrv)
         }
 
         /// Opens a directory. On success, the Directory object is returned.
         pub fn opendir(&self, path: &str) -> Result<Directory, Error> {
             let result;
             let mut dir: Directory = Default::default();
-            unsafe { result = f_opendir(ptr::addr_of_mut!(dir), path.as_ptr().cast()); }
+            unsafe { result = f_opendir(ptr::addr_of_mut!(dir), PathBuffer::new(path).as_str().as_ptr().cast()); }
             if result == FRESULT_FR_OK {
                 return Ok(dir)
             } else {
-                return Err(Error::try_from(result).unwrap())
+                return Err(Error::from_result(result))
             }
         }
 
-        /// Closes the given directory.
-        pub fn closedir(&self, dir: &mut Directory) -> Result<(), Error> {
+        /// Closes the given directory. If closing fails, the directory is returned along with
+        /// the error.
+        pub fn closedir(&self, mut dir: Directory) -> Result<(), (Error, Directory)> {
             let result;
-            unsafe { result = f_closedir(ptr::addr_of_mut!(*dir)); }
+            unsafe { result = f_closedir(ptr::addr_of_mut!(dir)); }
             if result == FRESULT_FR_OK {
+                if !dir.pat.is_null() {
+                    self.find_patterns.borrow_mut().retain(|pattern| pattern.as_str().as_ptr().cast() != dir.pat);
+                }
                 return Ok(())
             } else {
-                return Err(Error::try_from(result).unwrap())
+                return Err((Error::from_result(result), dir))
             }
         }
 
@@ -443,21 +940,37 @@ pub mod fatfs {
             if result == FRESULT_FR_OK {
                 return Ok(info)
             } else {
-                return Err(Error::try_from(result).unwrap())
+                return Err(Error::from_result(result))
+            }
+        }
+
+        /// Moves back to the first item of the given directory, so that the next call to
+        /// `readdir()` returns it again.
+        pub fn rewinddir(&self, dir: &mut Directory) -> Result<(), Error> {
+            let result;
+            unsafe { result = f_readdir(ptr::addr_of_mut!(*dir), ptr::null_mut()); }
+            if result == FRESULT_FR_OK {
+                return Ok(())
+            } else {
+                return Err(Error::from_result(result))
             }
         }
 
         /// Find the first item that matches the given pattern.
         /// On success a tuple is returned containing file information and the enclosing directory.
+        /// FatFs reads the pattern again on each call to `findnext()`, so a copy of it is kept
+        /// until the directory is passed to `closedir()` or the volume is unmounted.
         pub fn findfirst(&self, path: &str, pattern: &str) -> Result<(Directory, FileInfo), Error> {
             let result;
             let mut info: FileInfo = Default::default();
             let mut dir: Directory = Default::default();
-            unsafe { result = f_findfirst(ptr::addr_of_mut!(dir), ptr::addr_of_mut!(info), path.as_ptr().cast(), pattern.as_ptr().cast()); }
+            let pattern = PathBuffer::new(pattern);
+            unsafe { result = f_findfirst(ptr::addr_of_mut!(dir), ptr::addr_of_mut!(info), PathBuffer::new(path).as_str().as_ptr().cast(), pattern.as_str().as_ptr().cast()); }
             if result == FRESULT_FR_OK {
+                self.find_patterns.borrow_mut().push(pattern);
                 return Ok((dir, info))
             } else {
-                return Err(Error::try_from(result).unwrap())
+                return Err(Error::from_result(result))
             }
         }
 
@@ -469,70 +982,269 @@ pub mod fatfs {
             if result == FRESULT_FR_OK {
                 return Ok(info)
             } else {
-                return Err(Error::try_from(result).unwrap())
+                return Err(Error::from_result(result))
+            }
+        }
+
+        /// Find the first item that matches the given pattern and whose attributes, selected by
+        /// `mask`, equal `attr`. For example, all directories that are not hidden are found with
+        /// an `attr` of `Directory` and a `mask` of `Directory | Hidden`.
+        /// On success a tuple is returned containing file information and the enclosing directory.
+        pub fn findfirst_filtered(&self, path: &str, pattern: &str, attr: FileAttributes, mask: FileAttributes) -> Result<(Directory, FileInfo), Error> {
+            let (mut dir, info) = self.findfirst(path, pattern)?;
+            if info.fname[0] == 0 || info.fattrib & mask.as_u8() == attr.as_u8() {
+                return Ok((dir, info))
+            }
+            match self.findnext_filtered(&mut dir, attr, mask) {
+                Ok(info) => Ok((dir, info)),
+                Err(error) => {
+                    let _ = self.closedir(dir);
+                    Err(error)
+                }
+            }
+        }
+
+        /// Returns the next item that matches a pattern and attribute filter following a call to
+        /// `findfirst_filtered()`.
+        pub fn findnext_filtered(&self, dir: &mut Directory, attr: FileAttributes, mask: FileAttributes) -> Result<FileInfo, Error> {
+            loop {
+                let info = self.findnext(dir)?;
+                if info.fname[0] == 0 || info.fattrib & mask.as_u8() == attr.as_u8() {
+                    return Ok(info)
+                }
             }
         }
 
         /// Create a directory at the specified path.
         pub fn mkdir(&self, path: &str) -> Result<(), Error> {
+            self.invalidate_stat_cache();
             let result;
-            unsafe { result = f_mkdir(path.as_ptr().cast()); }
+            unsafe { result = f_mkdir(PathBuffer::new(path).as_str().as_ptr().cast()); }
             if result == FRESULT_FR_OK {
                 return Ok(())
             } else {
-                return Err(Error::try_from(result).unwrap())
+                return Err(Error::from_result(result))
             }
         }
 
         /// Deletes a file at the specified path.
         pub fn unlink(&self, path: &str) -> Result<(), Error> {
+            self.invalidate_stat_cache();
+            self.invalidate_dir_cache();
             let result;
-            unsafe { result = f_unlink(path.as_ptr().cast()); }
+            unsafe { result = f_unlink(PathBuffer::new(path).as_str().as_ptr().cast()); }
             if result == FRESULT_FR_OK {
+                if self.checksums {
+                    self.remove_checksum(path);
+                }
                 return Ok(())
             } else {
-                return Err(Error::try_from(result).unwrap())
+                return Err(Error::from_result(result))
+            }
+        }
+
+        /// Deletes a file at the specified path after overwriting every cluster allocated to it
+        /// with the given byte, including the unused space after the end of the data. The
+        /// overwritten data is synced to storage before the directory entry is removed.
+        pub fn unlink_secure(&self, path: &str, pattern: u8) -> Result<(), Error> {
+            self.shred(path, &[pattern])
+        }
+
+        /// Deletes a file at the specified path after overwriting every cluster allocated to it
+        /// once with each of the given bytes in turn, in the same way as `unlink_secure()`. Each
+        /// pass is synced to storage before the next begins. Fails with `Error::InvalidParameter`
+        /// if no patterns are given.
+        pub fn shred(&self, path: &str, patterns: &[u8]) -> Result<(), Error> {
+            if patterns.is_empty() {
+                return Err(Error::InvalidParameter)
+            }
+            let mut file = self.open_raw(path, FileOptions::Write | FileOptions::OpenExisting)?;
+            let cluster_size = self.fs().csize as u32 * FF_MAX_SS;
+            let allocated = file.obj.objsize.div_ceil(cluster_size) * cluster_size;
+            let mut result = Ok(());
+            for pattern in patterns {
+                let buffer = [*pattern; FF_MAX_SS as usize];
+                let mut written = 0;
+                result = self.seek(&mut file, 0);
+                while written < allocated && result.is_ok() {
+                    let len = (allocated - written).min(FF_MAX_SS) as usize;
+                    result = match self.write(&mut file, &buffer[..len]) {
+                        Ok(count) if count as usize == len => Ok(()),
+                        Ok(_) => Err(Error::Denied),
+                        Err(error) => Err(error)
+                    };
+                    written += len as u32;
+                }
+                result = result.and_then(|_| self.sync(&mut file));
+                if result.is_err() {
+                    break
+                }
             }
+            let closed = self.close(file);
+            result?;
+            closed?;
+            self.unlink(path)
         }
 
         /// Renames a file at the old path to the new path.
         pub fn rename(&self, old_path: &str, new_path: &str) -> Result<(), Error> {
+            self.invalidate_stat_cache();
+            self.invalidate_dir_cache();
             let result;
-            unsafe { result = f_rename(old_path.as_ptr().cast(), new_path.as_ptr().cast()); }
+            unsafe { result = f_rename(PathBuffer::new(old_path).as_str().as_ptr().cast(), PathBuffer::new(new_path).as_str().as_ptr().cast()); }
             if result == FRESULT_FR_OK {
+                if self.checksums {
+                    self.rename_checksum(old_path, new_path);
+                }
                 return Ok(())
             } else {
-                return Err(Error::try_from(result).unwrap())
+                return Err(Error::from_result(result))
+            }
+        }
+
+        /// Renames a file or directory at the old path to the new path, first deleting any file
+        /// or empty directory at the new path. No other task can observe the new path missing,
+        /// as the lock is held throughout. The old path is kept open while the new path is
+        /// deleted, so that FatFs refuses to delete it if both paths refer to the same item.
+        pub fn rename_replace(&self, old_path: &str, new_path: &str) -> Result<(), Error> {
+            let info = self.stat(old_path)?;
+            let replaced = if info.fattrib & FileAttributes::Directory.as_u8() != 0 {
+                let dir = self.opendir(old_path)?;
+                let result = self.unlink(new_path);
+                self.closedir(dir)?;
+                result
+            } else {
+                let file = self.open_raw(old_path, FileOptions::Read | FileOptions::OpenExisting)?;
+                let result = self.unlink(new_path);
+                self.close(file)?;
+                result
+            };
+            match replaced {
+                //The new path is either missing or the same item, which FatFs renames in place.
+                Ok(()) | Err(Error::NoFile) | Err(Error::Locked) => self.rename(old_path, new_path),
+                Err(error) => Err(error)
             }
         }
 
         /// Returns information about a file at the given path.
+        /// Results are served from the stat cache when it is enabled with `set_stat_cache_capacity()`.
         pub fn stat(&self, path: &str) -> Result<FileInfo, Error> {
+            if let Some(cached) = self.stat_cache.borrow_mut().get(path) {
+                return cached.ok_or(Error::NoFile)
+            }
             let result;
             let mut info: FileInfo = Default::default();
-            unsafe { result = f_stat(path.as_ptr().cast(), ptr::addr_of_mut!(info)); }
+            unsafe { result = f_stat(PathBuffer::new(path).as_str().as_ptr().cast(), ptr::addr_of_mut!(info)); }
             if result == FRESULT_FR_OK {
+                self.stat_cache.borrow_mut().insert(path, Some(info.clone()));
                 return Ok(info)
+            } else if result == FRESULT_FR_NO_FILE {
+                self.stat_cache.borrow_mut().insert(path, None);
+                return Err(Error::NoFile)
             } else {
-                return Err(Error::try_from(result).unwrap())
+                return Err(Error::from_result(result))
+            }
+        }
+
+        /// Returns information about the item at the given path, or `None` if neither the item
+        /// nor its parent directory exists.
+        pub fn try_stat(&self, path: &str) -> Result<Option<FileInfo>, Error> {
+            match self.stat(path) {
+                Ok(info) => Ok(Some(info)),
+                Err(Error::NoFile) | Err(Error::NoPath) => Ok(None),
+                Err(error) => Err(error)
+            }
+        }
+
+        /// Returns true if a file or directory exists at the given path. A missing parent
+        /// directory is reported as the item not existing, while other errors are returned.
+        pub fn exists(&self, path: &str) -> Result<bool, Error> {
+            if path::is_root(path) {
+                return Ok(true)
             }
+            return Ok(self.try_stat(path)?.is_some())
+        }
+
+        /// Returns true if a file exists at the given path, in the same way as `exists()`.
+        pub fn is_file(&self, path: &str) -> Result<bool, Error> {
+            if path::is_root(path) {
+                return Ok(false)
+            }
+            let info = self.try_stat(path)?;
+            return Ok(info.is_some_and(|info| info.fattrib & FileAttributes::Directory.as_u8() == 0))
+        }
+
+        /// Returns true if a directory exists at the given path, in the same way as `exists()`.
+        /// The root directory of a volume always exists.
+        pub fn is_dir(&self, path: &str) -> Result<bool, Error> {
+            if path::is_root(path) {
+                return Ok(true)
+            }
+            let info = self.try_stat(path)?;
+            return Ok(info.is_some_and(|info| info.fattrib & FileAttributes::Directory.as_u8() != 0))
+        }
+
+        /// Returns information about an open file without looking up its path. The size is the
+        /// current size of the open file, including data that has not been synced yet. Attributes
+        /// and timestamps are read from the directory entry of the file, which is updated when
+        /// the file is synced. The name fields of the returned object are left empty.
+        pub fn fstat(&self, file: &File) -> Result<FileInfo, Error> {
+            if file.obj.fs.is_null() || file.obj.id != self.fs().id {
+                return Err(Error::InvalidObject)
+            }
+            //The directory entry was located in the window of the file system object when the file was opened.
+            let offset = (file.dir_ptr as usize).wrapping_sub(ptr::addr_of!(self.fs().win) as usize);
+            let end = match offset.checked_add(32) {
+                Some(end) if end <= self.fs().win.len() => end,
+                _ => return Err(Error::InvalidObject)
+            };
+            let mut sector = [0; FF_MAX_SS as usize];
+            let entry = if self.fs().winsect == file.dir_sect {
+                &self.fs().win[offset..end]
+            } else {
+                diskio::read_sectors(self.fs().pdrv, &mut sector, file.dir_sect)?;
+                &sector[offset..end]
+            };
+            let mut info = FileInfo::default();
+            info.fsize = file.obj.objsize;
+            info.fattrib = entry[11] & 0x3F; //DIR_Attr, masked to the attribute bits.
+            info.ftime = u16::from_le_bytes([entry[22], entry[23]]); //DIR_ModTime
+            info.fdate = u16::from_le_bytes([entry[24], entry[25]]); //DIR_ModDate
+            Ok(info)
+        }
+
+        /// Enables caching of `stat()` results, including missing paths, for up to the given
+        /// number of paths. The cache is cleared by any operation that may modify the volume
+        /// or change the current directory. A capacity of 0 disables the cache, which is the default.
+        pub fn set_stat_cache_capacity(&self, capacity: usize) {
+            self.stat_cache.borrow_mut().set_capacity(capacity);
+        }
+
+        fn invalidate_stat_cache(&self) {
+            self.stat_cache.borrow_mut().clear();
         }
 
         /// Applies the given attributes to the file according to the supplied mask.
         pub fn chmod(&self, path: &str, attr: FileAttributes, mask: FileAttributes) -> Result<(), Error> {
+            self.invalidate_stat_cache();
             let result;
-            unsafe { result = f_chmod(path.as_ptr().cast(), attr.as_u8(), mask.as_u8()); }
+            unsafe { result = f_chmod(PathBuffer::new(path).as_str().as_ptr().cast(), attr.as_u8(), mask.as_u8()); }
             if result == FRESULT_FR_OK {
                 return Ok(())
             } else {
-                return Err(Error::try_from(result).unwrap())
+                return Err(Error::from_result(result))
             }
         }
 
         /// Applies a timestamp to the given file.
         #[cfg(feature = "chrono")]
         pub fn utime(&self, path: &str, timestamp: NaiveDateTime) -> Result<(), Error> {
+            self.invalidate_stat_cache();
             let result;
+            //FAT timestamps hold the years 1980 to 2107.
+            if !(1980..=2107).contains(&timestamp.year()) {
+                return Err(Error::InvalidParameter)
+            }
             let year = timestamp.year() as u32;
             let month = timestamp.month();
             let day = timestamp.day();
@@ -542,33 +1254,35 @@ pub mod fatfs {
             let mut info = FileInfo::default();
             info.fdate = (((year - 1980) * 512) | month * 32 | day) as u16;
             info.ftime = (hour * 2048 | minute * 32 | second / 2) as u16;
-            unsafe { result = f_utime(path.as_ptr().cast(), ptr::addr_of_mut!(info)); }
+            unsafe { result = f_utime(PathBuffer::new(path).as_str().as_ptr().cast(), ptr::addr_of_mut!(info)); }
             if result == FRESULT_FR_OK {
                 return Ok(())
             } else {
-                return Err(Error::try_from(result).unwrap())
+                return Err(Error::from_result(result))
             }
         }
 
         /// Change the current directory to the given path.
         pub fn chdir(&self, path: &str) -> Result<(), Error> {
+            self.invalidate_stat_cache();
             let result;
-            unsafe { result = f_chdir(path.as_ptr().cast()); }
+            unsafe { result = f_chdir(PathBuffer::new(path).as_str().as_ptr().cast()); }
             if result == FRESULT_FR_OK {
                 return Ok(())
             } else {
-                return Err(Error::try_from(result).unwrap())
+                return Err(Error::from_result(result))
             }
         }
 
         /// Change the current drive.
         pub fn chdrive(&self, path: &str) -> Result<(), Error> {
+            self.invalidate_stat_cache();
             let result;
-            unsafe { result = f_chdrive(path.as_ptr().cast()); }
+            unsafe { result = f_chdrive(PathBuffer::new(path).as_str().as_ptr().cast()); }
             if result == FRESULT_FR_OK {
                 return Ok(())
             } else {
-                return Err(Error::try_from(result).unwrap())
+                return Err(Error::from_result(result))
             }
         }
 
@@ -580,90 +1294,157 @@ pub mod fatfs {
             if result == FRESULT_FR_OK {
                 return Ok(())
             } else {
-                return Err(Error::try_from(result).unwrap())
+                return Err(Error::from_result(result))
             }
         }
 
-        /// Get number of free clusters on the drive.
+        /// Get number of free clusters on the drive. `stats()` returns the free space in bytes.
         pub fn getfree(&self, path: &str) -> Result<u32, Error> {
             let result;
             let mut num_clusters = 0;
             let mut fs_ptr: *mut FATFS = ptr::null_mut();
-            unsafe { result = f_getfree(path.as_ptr().cast(), ptr::addr_of_mut!(num_clusters), ptr::addr_of_mut!(fs_ptr)); }
+            unsafe { result = f_getfree(PathBuffer::new(path).as_str().as_ptr().cast(), ptr::addr_of_mut!(num_clusters), ptr::addr_of_mut!(fs_ptr)); }
             if result == FRESULT_FR_OK {
                 return Ok(num_clusters)
             } else {
-                return Err(Error::try_from(result).unwrap())
+                return Err(Error::from_result(result))
             }
         }
 
         /// Get the volume label.
-        /// The supplied String buffer must have sufficient capacity to read the entire label.
+        /// The supplied String buffer must have sufficient capacity to read the entire label. Returns
+        /// the volume serial number, which `volume_serial()` returns on its own.
         pub fn getlabel(&self, path: &str, label: &mut String) -> Result<u32, Error> {
             let result;
             let mut vsn = 0;
             if label.capacity() < 34 { //From FATFS documentation, this is the max length required for this parameter.
                 return Err(Error::InvalidParameter)
             }
-            unsafe { result = f_getlabel(path.as_ptr().cast(), label.as_mut_ptr().cast(), ptr::addr_of_mut!(vsn)); }
+            unsafe { result = f_getlabel(PathBuffer::new(path).as_str().as_ptr().cast(), label.as_mut_ptr().cast(), ptr::addr_of_mut!(vsn)); }
             if result == FRESULT_FR_OK {
                 return Ok(vsn)
             } else {
-                return Err(Error::try_from(result).unwrap())
+                return Err(Error::from_result(result))
             }
         }
 
         /// Set the volume label.
         pub fn setlabel(&self, label: &str) -> Result<(), Error> {
+            self.invalidate_stat_cache();
             let result;
-            unsafe { result = f_setlabel(label.as_ptr().cast()); }
+            unsafe { result = f_setlabel(PathBuffer::new(label).as_str().as_ptr().cast()); }
             if result == FRESULT_FR_OK {
                 return Ok(())
             } else {
-                return Err(Error::try_from(result).unwrap())
+                return Err(Error::from_result(result))
+            }
+        }
+
+        /// Writes `len` zeros to the given file starting at `offset`, extending the file if
+        /// necessary. Writes are split at sector boundaries so that whole sectors are written
+        /// directly to storage. The file position is left at the end of the zeroed range.
+        pub fn zero_range(&self, file: &mut File, offset: u32, len: u32) -> Result<(), Error> {
+            self.seek(file, offset)?;
+            let mut position = offset;
+            let end = offset.checked_add(len).ok_or(Error::InvalidParameter)?;
+            while position < end {
+                let chunk = (FF_MAX_SS - position % FF_MAX_SS).min(end - position);
+                if self.write(file, &ZERO_SECTOR[..chunk as usize])? != chunk {
+                    return Err(Error::Denied)
+                }
+                position += chunk;
             }
+            Ok(())
         }
-        
+
         /// Allocate a contiguous block to the given file.
         pub fn expand(&self, file: &mut File, size: u32) ->Result<(), Error> {
+            self.invalidate_stat_cache();
+            self.registry.borrow_mut().set_dirty(file.handle, true);
+            let result;
+            unsafe { result = f_expand(ptr::addr_of_mut!(file.fil), size, 1); }
+            if result == FRESULT_FR_OK {
+                return Ok(())
+            } else {
+                return Err(Error::from_result(result))
+            }
+        }
+
+        /// Find a contiguous block of the given size for the given empty file without
+        /// allocating it. The file is left unchanged, and the next cluster allocation on the
+        /// volume starts at the block, so a file then written sequentially is contiguous as
+        /// long as nothing else is allocated in between.
+        pub fn prepare_expand(&self, file: &mut File, size: u32) -> Result<(), Error> {
             let result;
-            unsafe { result = f_expand(ptr::addr_of_mut!(*file), size, 1); }
+            unsafe { result = f_expand(ptr::addr_of_mut!(file.fil), size, 0); }
             if result == FRESULT_FR_OK {
                 return Ok(())
             } else {
-                return Err(Error::try_from(result).unwrap())
+                return Err(Error::from_result(result))
             }
         }
 
+        /// Runs the supplied operations with the given timeout applied to each block
+        /// device operation, in place of the timeout configured with
+        /// `diskio::timeout::set_timeout()`.
+        #[cfg(feature = "time")]
+        pub fn with_timeout<T>(&mut self, timeout: Duration, f: impl FnOnce(&mut Self) -> T) -> T {
+            let previous = diskio::timeout::timeout();
+            diskio::timeout::set_timeout(Some(timeout));
+            let result = f(self);
+            diskio::timeout::set_timeout(previous);
+            result
+        }
+
         /// Mount the drive.
         pub fn mount(&mut self) -> Result<(), Error> {
-            self.fs = FATFS::default();
-            let file_path = "";
+            self.invalidate_stat_cache();
+            self.invalidate_dir_cache();
+            self.registry.borrow_mut().clear();
+            self.find_patterns.borrow_mut().clear();
+            *self.fs.get_mut() = FATFS::default();
+            let file_path = PathBuffer::new("");
             let result;
-            unsafe { result = f_mount(ptr::addr_of_mut!(self.fs), file_path.as_ptr().cast(), 1); }
+            unsafe { result = f_mount(self.fs.get(), file_path.as_str().as_ptr().cast(), 1); }
             if result == FRESULT_FR_OK {
+                self.track_mount();
                 return Ok(())
             } else {
-                return Err(Error::try_from(result).unwrap())
+                return Err(Error::from_result(result))
             }
         }
 
-        /// Format the drive according to the supplied options.
-        pub fn mkfs(&self, path: &str, format: FormatOptions, copies: u8, alignment: u32, au_size: u32, root_entries: u32) -> Result<(), Error> {
-            let result;
+        /// Format the drive according to the supplied options. Fails with `Error::InvalidParameter`
+        /// if `MkfsOptions::validate()` rejects the options. The OEM name and volume serial number
+        /// are written once FatFs has formatted the volume, if they are set. Returns the layout of
+        /// the volume, including the cluster size and alignment chosen for options left at 0.
+        /// A work buffer of one sector is placed on the stack; `mkfs_with_buffer()` takes one
+        /// from the caller instead.
+        pub fn mkfs(&self, path: &str, options: &MkfsOptions) -> Result<MkfsReport, Error> {
             let mut work: [u8; FF_MAX_SS as usize] = [0; FF_MAX_SS as usize];
-            let parameters = MKFS_PARM {
-                fmt: format.as_u8(),
-                n_fat: copies,
-                align: alignment,
-                n_root: root_entries,
-                au_size: au_size,
-            };
-            unsafe { result = f_mkfs(path.as_ptr().cast(), ptr::addr_of!(parameters), work.as_mut_ptr().cast(), work.len() as u32); }
+            self.mkfs_with_buffer(path, options, &mut work)
+        }
+
+        /// Format the drive in the same way as `mkfs()`, using the given work buffer, which may be
+        /// a static buffer to spare a small task stack. The buffer must hold at least one sector,
+        /// or `Error::InvalidParameter` is returned. FatFs writes as many whole sectors at once as
+        /// the buffer holds, so a larger buffer formats faster.
+        pub fn mkfs_with_buffer(&self, path: &str, options: &MkfsOptions, work: &mut [u8]) -> Result<MkfsReport, Error> {
+            if work.len() < FF_MAX_SS as usize {
+                return Err(Error::InvalidParameter)
+            }
+            let parameters = options.parameters(self.fs().pdrv)?;
+            self.invalidate_stat_cache();
+            self.invalidate_dir_cache();
+            self.registry.borrow_mut().clear();
+            self.find_patterns.borrow_mut().clear();
+            let result;
+            let len = work.len().min(u32::MAX as usize) as u32;
+            unsafe { result = f_mkfs(PathBuffer::new(path).as_str().as_ptr().cast(), ptr::addr_of!(parameters), work.as_mut_ptr().cast(), len); }
             if result == FRESULT_FR_OK {
-                return Ok(())
+                return options.finish(self, &parameters)
             } else {
-                return Err(Error::try_from(result).unwrap())
+                return Err(Error::from_result(result))
             }
         }
 
@@ -674,14 +1455,16 @@ pub mod fatfs {
             if result == FRESULT_FR_OK {
                 return Ok(())
             } else {
-                return Err(Error::try_from(result).unwrap())
+                return Err(Error::from_result(result))
             }
         }
 
         /// Write a character to the file.
         pub fn putc(&self, file: &mut File, char: u8) -> Result<i32, Error> {
+            self.invalidate_stat_cache();
+            self.registry.borrow_mut().set_dirty(file.handle, true);
             let result;
-            unsafe { result = f_putc(char as TCHAR, ptr::addr_of_mut!(*file)); }
+            unsafe { result = f_putc(char as TCHAR, ptr::addr_of_mut!(file.fil)); }
             if result >= 0 {
                 return Ok(result)
             } else {
@@ -691,8 +1474,10 @@ pub mod fatfs {
 
         /// Write a string to the file.
         pub fn puts(&self, file: &mut File, string: &str) -> Result<i32, Error> {
+            self.invalidate_stat_cache();
+            self.registry.borrow_mut().set_dirty(file.handle, true);
             let result;
-            unsafe { result = f_puts(string.as_ptr().cast(), ptr::addr_of_mut!(*file)); }
+            unsafe { result = f_puts(PathBuffer::new(string).as_str().as_ptr().cast(), ptr::addr_of_mut!(file.fil)); }
             if result >= 0 {
                 return Ok(result)
             } else {
@@ -704,7 +1489,7 @@ pub mod fatfs {
         /// The capacity of the supplied String buffer determines the maximum length of data read.
         pub fn gets(&self, file: &mut File, buffer: &mut String) -> Result<(), Error> {
             let result;
-            unsafe { result = f_gets(buffer.as_mut_ptr().cast(), buffer.capacity() as i32, ptr::addr_of_mut!(*file)); }
+            unsafe { result = f_gets(buffer.as_mut_ptr().cast(), buffer.capacity() as i32, ptr::addr_of_mut!(file.fil)); }
             if result != ptr::null_mut() {
                 return Ok(())
             } else {
@@ -714,12 +1499,17 @@ pub mod fatfs {
 
         /// Unmount the drive at the supplied path.
         pub fn unmount(&self, path: &str) -> Result<(), Error> {
+            self.track_unmount();
+            self.invalidate_stat_cache();
+            self.invalidate_dir_cache();
+            self.registry.borrow_mut().clear();
+            self.find_patterns.borrow_mut().clear();
             let result;
-            unsafe { result = f_mount(ptr::null_mut(), path.as_ptr().cast(), 0); }
+            unsafe { result = f_mount(ptr::null_mut(), PathBuffer::new(path).as_str().as_ptr().cast(), 0); }
             if result == FRESULT_FR_OK {
                 return Ok(())
             } else {
-                return Err(Error::try_from(result).unwrap())
+                return Err(Error::from_result(result))
             }
         }
     }
@@ -0,0 +1,116 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+use fatfs_embedded::fatfs::{self, FileOptions, FormatOptions, MkfsOptions};
+use fatfs_embedded::fatfs::diskio::{self, AsyncFatFsDriver, DiskResult, IoctlCommand};
+use embassy_futures::{block_on, yield_now};
+
+const STORAGE_SIZE: usize = 1024 * 1000 * 64;
+const SECTOR_SIZE: usize = 512;
+
+//Number of transfers of more than one sector, which only `read_async()` and `write_async()` make.
+static MULTI_SECTOR_TRANSFERS: AtomicU32 = AtomicU32::new(0);
+
+struct AsyncRamBlockStorage {
+    memory: Vec<u8>
+}
+
+impl AsyncRamBlockStorage {
+    fn range(buffer_len: usize, sector: u32) -> core::ops::Range<usize> {
+        if buffer_len > SECTOR_SIZE {
+            MULTI_SECTOR_TRANSFERS.fetch_add(1, Ordering::Relaxed);
+        }
+        let offset = sector as usize * SECTOR_SIZE;
+        offset..offset + buffer_len
+    }
+}
+
+impl AsyncFatFsDriver for AsyncRamBlockStorage {
+    async fn disk_status(&self, _drive: u8) -> u8 {
+        0
+    }
+
+    async fn disk_initialize(&mut self, _drive: u8) -> u8 {
+        self.memory.resize(STORAGE_SIZE, 0);
+        0
+    }
+
+    async fn disk_read(&mut self, _drive: u8, buffer: &mut [u8], sector: u32) -> DiskResult {
+        yield_now().await;
+        buffer.copy_from_slice(&self.memory[Self::range(buffer.len(), sector)]);
+        DiskResult::Ok
+    }
+
+    async fn disk_write(&mut self, _drive: u8, buffer: &[u8], sector: u32) -> DiskResult {
+        yield_now().await;
+        self.memory[Self::range(buffer.len(), sector)].copy_from_slice(buffer);
+        DiskResult::Ok
+    }
+
+    async fn disk_ioctl(&self, data: &mut IoctlCommand) -> DiskResult {
+        match data {
+            IoctlCommand::CtrlSync(_) => (),
+            IoctlCommand::GetSectorCount(count) => *count = (self.memory.len() / SECTOR_SIZE) as u32,
+            IoctlCommand::GetSectorSize(size) => *size = SECTOR_SIZE as u16,
+            IoctlCommand::GetBlockSize(size) => *size = 1,
+            IoctlCommand::CtrlTrim(_, _) => ()
+        }
+        DiskResult::Ok
+    }
+
+    fn get_fattime(&self) -> chrono::NaiveDateTime {
+        chrono::offset::Local::now().naive_local()
+    }
+}
+
+//Test function must be called "main" to satisfy ThreadModeRawMutex.
+#[test]
+fn main() {
+    block_on(diskio::install_async(AsyncRamBlockStorage { memory: Vec::new() }));
+    let mut locked_fs = block_on(fatfs::FS.lock());
+    locked_fs.mkfs("", MkfsOptions::new().format(FormatOptions::FAT).au_size(4096)).expect("Formatting drive failed.");
+    locked_fs.mount().expect("Mounting drive failed.");
+
+    let data: Vec<u8> = (0..100_000u32).map(|i| (i * 7 + i / 251) as u8).collect();
+    let mut file = locked_fs.open("async.bin", FileOptions::CreateAlways | FileOptions::Read | FileOptions::Write).expect("Opening failed.");
+    //A partial sector left in the sector buffer of the file is written over by the sectors
+    //written directly.
+    locked_fs.write(&mut file, &data[..700]).expect("Writing failed.");
+    locked_fs.seek(&mut file, 100).expect("Seeking failed.");
+    let written = block_on(locked_fs.write_async(&mut file, &data[100..])).expect("Writing failed.");
+    assert_eq!(written, data.len() as u32 - 100);
+    assert!(MULTI_SECTOR_TRANSFERS.load(Ordering::Relaxed) > 0);
+    assert_eq!(file.obj.objsize, data.len() as u32);
+
+    //Reads from unaligned offsets match, through the sector buffer as well as directly.
+    for start in [0, 1, 511, 512, 4095, 4096, 12345, 99_000] {
+        let mut read_back = vec![0; data.len() - start];
+        locked_fs.seek(&mut file, start as u32).expect("Seeking failed.");
+        let read = block_on(locked_fs.read_async(&mut file, &mut read_back)).expect("Reading failed.");
+        assert_eq!(read as usize, read_back.len());
+        assert_eq!(read_back, &data[start..]);
+    }
+    //Reading past the end of the file stops there.
+    let mut read_back = vec![0; 1000];
+    locked_fs.seek(&mut file, 99_500).expect("Seeking failed.");
+    assert_eq!(block_on(locked_fs.read_async(&mut file, &mut read_back)).expect("Reading failed."), 500);
+
+    //A partial sector written through FatFs and not yet synced is read back directly.
+    locked_fs.seek(&mut file, 2048).expect("Seeking failed.");
+    locked_fs.write(&mut file, b"dirty").expect("Writing failed.");
+    locked_fs.seek(&mut file, 0).expect("Seeking failed.");
+    let mut read_back = vec![0; 8192];
+    block_on(locked_fs.read_async(&mut file, &mut read_back)).expect("Reading failed.");
+    assert_eq!(&read_back[2048..2053], b"dirty");
+    assert_eq!(&read_back[2053..], &data[2053..8192]);
+    file.close().expect("Closing failed.");
+
+    //The data is read back by FatFs after the volume is mounted again.
+    locked_fs.mount().expect("Mounting drive failed.");
+    let mut file = locked_fs.open("async.bin", FileOptions::Read).expect("Opening failed.");
+    let mut read_back = vec![0; data.len()];
+    assert_eq!(locked_fs.read(&mut file, &mut read_back).expect("Reading failed."), data.len() as u32);
+    assert_eq!(&read_back[..2048], &data[..2048]);
+    assert_eq!(&read_back[2048..2053], b"dirty");
+    assert_eq!(&read_back[2053..], &data[2053..]);
+    assert_eq!(block_on(locked_fs.write_async(&mut file, b"denied")).err(), Some(fatfs::Error::Denied));
+    file.close().expect("Closing failed.");
+}
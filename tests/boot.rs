@@ -0,0 +1,124 @@
+mod simulated_driver;
+
+use fatfs_embedded::fatfs::{self, Error, FormatOptions, MkfsOptions};
+use fatfs_embedded::fatfs::boot::{self, BootSector, FatType};
+use embassy_futures::block_on;
+
+fn put_u16(sector: &mut [u8], offset: usize, value: u16) {
+    sector[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(sector: &mut [u8], offset: usize, value: u32) {
+    sector[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+//The boot sector of a 1.44 MB floppy disk.
+fn floppy() -> [u8; 512] {
+    let mut sector = [0; 512];
+    sector[..3].copy_from_slice(&[0xEB, 0x3C, 0x90]);
+    sector[3..11].copy_from_slice(b"MSDOS5.0");
+    put_u16(&mut sector, 11, 512);
+    sector[13] = 1;
+    put_u16(&mut sector, 14, 1);
+    sector[16] = 2;
+    put_u16(&mut sector, 17, 224);
+    put_u16(&mut sector, 19, 2880);
+    sector[21] = 0xF0;
+    put_u16(&mut sector, 22, 9);
+    sector[38] = 0x29;
+    put_u32(&mut sector, 39, 0x1234_5678);
+    sector[43..54].copy_from_slice(b"FLOPPY     ");
+    sector[510..].copy_from_slice(&[0x55, 0xAA]);
+    sector
+}
+
+//The boot sector of a FAT32 volume of 1,000,000 sectors, 2048 sectors into the drive.
+fn fat32() -> [u8; 512] {
+    let mut sector = [0; 512];
+    sector[..3].copy_from_slice(&[0xEB, 0x58, 0x90]);
+    sector[3..11].copy_from_slice(b"MSWIN4.1");
+    put_u16(&mut sector, 11, 512);
+    sector[13] = 8;
+    put_u16(&mut sector, 14, 32);
+    sector[16] = 2;
+    sector[21] = 0xF8;
+    put_u32(&mut sector, 28, 2048);
+    put_u32(&mut sector, 32, 1_000_000);
+    put_u32(&mut sector, 36, 1000);
+    put_u32(&mut sector, 44, 2);
+    put_u16(&mut sector, 48, 1);
+    sector[66] = 0x29;
+    put_u32(&mut sector, 67, 0xCAFE_F00D);
+    sector[71..82].copy_from_slice(b"DATA       ");
+    sector[510..].copy_from_slice(&[0x55, 0xAA]);
+    sector
+}
+
+//Test function must be called "main" to satisfy ThreadModeRawMutex.
+#[test]
+fn main() {
+    let boot = BootSector::parse(&floppy()).expect("Parsing failed.");
+    assert_eq!(&boot.oem_name, b"MSDOS5.0");
+    assert_eq!((boot.bytes_per_sector, boot.sectors_per_cluster, boot.reserved_sectors), (512, 1, 1));
+    assert_eq!((boot.fat_count, boot.root_entries, boot.total_sectors), (2, 224, 2880));
+    assert_eq!((boot.media, boot.sectors_per_fat, boot.hidden_sectors), (0xF0, 9, 0));
+    //The data area starts after the reserved sector, 2 FATs of 9 sectors and 14 root directory sectors.
+    assert_eq!((boot.fat_type, boot.cluster_count), (FatType::Fat12, 2880 - 33));
+    assert_eq!((boot.root_cluster, boot.fs_info_sector), (None, None));
+    assert_eq!(boot.serial, Some(0x1234_5678));
+    assert_eq!(boot.label_str(), Some("FLOPPY"));
+    assert_eq!(boot.fs_info, None);
+
+    let boot = BootSector::parse(&fat32()).expect("Parsing failed.");
+    assert_eq!((boot.total_sectors, boot.sectors_per_fat, boot.hidden_sectors), (1_000_000, 1000, 2048));
+    assert_eq!((boot.fat_type, boot.cluster_count), (FatType::Fat32, (1_000_000 - 2032) / 8));
+    assert_eq!((boot.root_cluster, boot.fs_info_sector), (Some(2), Some(1)));
+    assert_eq!(boot.serial, Some(0xCAFE_F00D));
+    assert_eq!(boot.label_str(), Some("DATA"));
+
+    //Without the extended boot signature, there is no serial number or label.
+    let mut sector = floppy();
+    sector[38] = 0;
+    let boot = BootSector::parse(&sector).expect("Parsing failed.");
+    assert_eq!((boot.serial, boot.label, boot.label_str()), (None, None, None));
+
+    //Sectors that are not boot sectors are rejected.
+    assert_eq!(BootSector::parse(&[0; 512]), Err(Error::NoFileSystem));
+    assert_eq!(BootSector::parse(&floppy()[..511]), Err(Error::NoFileSystem));
+    let damaged: [fn(&mut [u8; 512]); 7] = [
+        |sector| sector[511] = 0,
+        |sector| sector[0] = 0,
+        |sector| put_u16(sector, 11, 1000),
+        |sector| sector[13] = 3,
+        |sector| sector[16] = 0,
+        |sector| put_u16(sector, 19, 0),
+        //The FATs end past the end of the volume.
+        |sector| put_u16(sector, 22, 2000)
+    ];
+    for damage in damaged {
+        let mut sector = floppy();
+        damage(&mut sector);
+        assert_eq!(BootSector::parse(&sector), Err(Error::NoFileSystem));
+    }
+
+    //A volume formatted by FatFs is read back from the drive, FSInfo sector included.
+    let driver = simulated_driver::RamBlockStorage::new();
+    block_on(fatfs::diskio::install(driver));
+    let mut locked_fs = block_on(fatfs::FS.lock());
+    locked_fs.mkfs("", MkfsOptions::new().format(FormatOptions::FAT32)).expect("Formatting drive failed.");
+    locked_fs.mount().expect("Mounting drive failed.");
+    let mut label = String::with_capacity(34);
+    let serial = locked_fs.getlabel("", &mut label).expect("Getting label failed.");
+    let boot = boot::read(&locked_fs).expect("Reading the boot sector failed.");
+    assert_eq!(boot.fat_type, FatType::Fat32);
+    assert_eq!(boot.serial, Some(serial));
+    assert!(boot.fs_info.is_some());
+    assert_eq!(boot::read_at(&locked_fs, boot.hidden_sectors).expect("Reading the boot sector failed."), boot);
+
+    //The identity set after formatting is read back, and by FatFs once mounted again.
+    boot::set_identity(&locked_fs, Some(b"UNIT0042"), Some(42)).expect("Setting the identity failed.");
+    let boot = boot::read(&locked_fs).expect("Reading the boot sector failed.");
+    assert_eq!((&boot.oem_name, boot.serial), (b"UNIT0042", Some(42)));
+    locked_fs.mount().expect("Mounting drive failed.");
+    assert_eq!(locked_fs.getlabel("", &mut label).expect("Getting label failed."), 42);
+}
@@ -0,0 +1,76 @@
+mod simulated_driver;
+
+use fatfs_embedded::fatfs::{self, Error, FileOptions, FormatOptions, MkfsOptions, RawFileSystem};
+use fatfs_embedded::fatfs::buffered::{AsyncLines, BufReader, BufWriter};
+use embassy_futures::block_on;
+
+//Opening a file for writing fails while it is open elsewhere, so succeeds once it is closed.
+fn assert_closed(fs: &RawFileSystem, path: &str) {
+    let file = fs.open(path, FileOptions::Write).expect("The file was left open.");
+    file.close().expect("Closing failed.");
+}
+
+//Test function must be called "main" to satisfy ThreadModeRawMutex.
+#[test]
+fn main() {
+    let driver = simulated_driver::RamBlockStorage::new();
+    block_on(fatfs::diskio::install(driver));
+    {
+        let mut locked_fs = block_on(fatfs::FS.lock());
+        locked_fs.mkfs("", MkfsOptions::new().format(FormatOptions::FAT32)).expect("Formatting drive failed.");
+        locked_fs.mount().expect("Mounting drive failed.");
+
+        //A dropped writer writes its buffered data and closes its file.
+        let file = locked_fs.open_raw("lines.txt", FileOptions::CreateAlways | FileOptions::Write).expect("Opening failed.");
+        let mut writer: BufWriter<'_, 64> = BufWriter::new(&locked_fs, file);
+        writer.write(b"first\r\nsecond\n").expect("Writing failed.");
+        writer.write(b"third").expect("Writing failed.");
+        assert_eq!(locked_fs.open("lines.txt", FileOptions::Write).err(), Some(Error::Locked));
+        drop(writer);
+        assert_closed(&locked_fs, "lines.txt");
+        assert_eq!(locked_fs.stat("lines.txt").expect("Stat failed.").fsize, 19);
+
+        //A writer taken apart with `into_inner()` leaves its file open.
+        let file = locked_fs.open_raw("lines.txt", FileOptions::Read).expect("Opening failed.");
+        let writer: BufWriter<'_, 64> = BufWriter::new(&locked_fs, file);
+        let file = writer.into_inner().expect("Flushing failed.");
+        assert_eq!(locked_fs.open("lines.txt", FileOptions::Write).err(), Some(Error::Locked));
+        locked_fs.close(file).expect("Closing failed.");
+
+        //A dropped reader, and the line iterator built on it, close their files.
+        let file = locked_fs.open_raw("lines.txt", FileOptions::Read).expect("Opening failed.");
+        let mut reader: BufReader<'_, 4> = BufReader::new(&locked_fs, file);
+        assert_eq!(reader.read_byte().expect("Reading failed."), Some(b'f'));
+        drop(reader);
+        assert_closed(&locked_fs, "lines.txt");
+        let file = locked_fs.open_raw("lines.txt", FileOptions::Read).expect("Opening failed.");
+        let mut lines = BufReader::<'_, 4>::new(&locked_fs, file).lines();
+        assert_eq!(lines.next().map(|line| line.expect("Reading failed.")), Some(String::from("first")));
+        drop(lines);
+        assert_closed(&locked_fs, "lines.txt");
+
+        //A reader taken apart with `into_inner()` leaves its file open at the first byte
+        //not read.
+        let file = locked_fs.open_raw("lines.txt", FileOptions::Read).expect("Opening failed.");
+        let mut reader: BufReader<'_, 4> = BufReader::new(&locked_fs, file);
+        reader.read_byte().expect("Reading failed.");
+        let file = reader.into_inner();
+        assert_eq!(file.position(), 1);
+        locked_fs.close(file).expect("Closing failed.");
+    }
+
+    //A dropped asynchronous line iterator leaves its file to be closed when the lock is next
+    //acquired.
+    let file = block_on(fatfs::FS.lock()).open_raw("lines.txt", FileOptions::Read).expect("Opening failed.");
+    let mut lines: AsyncLines<'_, 4> = AsyncLines::new(&fatfs::FS, file);
+    assert_eq!(block_on(lines.next_line()).map(|line| line.expect("Reading failed.")), Some(String::from("first")));
+    assert_eq!(block_on(lines.next_line()).map(|line| line.expect("Reading failed.")), Some(String::from("second")));
+    drop(lines);
+    assert_closed(&block_on(fatfs::FS.lock()), "lines.txt");
+    let file = block_on(fatfs::FS.lock()).open_raw("lines.txt", FileOptions::Read).expect("Opening failed.");
+    let lines: AsyncLines<'_, 4> = AsyncLines::new(&fatfs::FS, file);
+    let file = block_on(lines.into_inner());
+    let locked_fs = block_on(fatfs::FS.lock());
+    assert_eq!(locked_fs.open("lines.txt", FileOptions::Write).err(), Some(Error::Locked));
+    locked_fs.close(file).expect("Closing failed.");
+}
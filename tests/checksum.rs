@@ -0,0 +1,63 @@
+mod simulated_driver;
+
+use fatfs_embedded::fatfs::{self, Error, FileOptions, FormatOptions, MkfsOptions, RawFileSystem};
+use embassy_futures::block_on;
+
+fn write_file(fs: &RawFileSystem, path: &str, data: &[u8]) {
+    let mut file = fs.open(path, FileOptions::CreateAlways | FileOptions::Write).expect("Opening failed.");
+    fs.write(&mut file, data).expect("Writing failed.");
+    file.close().expect("Closing failed.");
+}
+
+//Test function must be called "main" to satisfy ThreadModeRawMutex.
+#[test]
+fn main() {
+    let driver = simulated_driver::RamBlockStorage::new();
+    block_on(fatfs::diskio::install(driver));
+    let mut locked_fs = block_on(fatfs::FS.lock());
+    //The root directory holds 16 entries.
+    locked_fs.mkfs("", MkfsOptions::new().format(FormatOptions::FAT).root_entries(16)).expect("Formatting drive failed.");
+    locked_fs.mount().expect("Mounting drive failed.");
+
+    //Files written while checksums are disabled have no sidecar.
+    write_file(&locked_fs, "plain", b"data");
+    assert_eq!(locked_fs.verify("plain"), Err(Error::ChecksumMissing));
+    assert_eq!(locked_fs.verify("none"), Err(Error::NoFile));
+
+    locked_fs.set_checksums(true);
+    write_file(&locked_fs, "plain", b"data");
+    assert_eq!(locked_fs.verify("plain"), Ok(true));
+    //Content changed while checksums are disabled no longer matches the sidecar.
+    locked_fs.set_checksums(false);
+    write_file(&locked_fs, "plain", b"atad");
+    assert_eq!(locked_fs.verify("plain"), Ok(false));
+    locked_fs.set_checksums(true);
+    locked_fs.unlink("plain").expect("Deleting failed.");
+
+    //A file with a name too long for a sidecar is written without one.
+    locked_fs.mkdir("dir").expect("Creating directory failed.");
+    let long_name = format!("dir/{}", "x".repeat(251));
+    write_file(&locked_fs, &long_name, b"data");
+    assert_eq!(locked_fs.verify(&long_name), Err(Error::ChecksumMissing));
+    locked_fs.unlink(&long_name).expect("Deleting failed.");
+    locked_fs.unlink("dir").expect("Deleting failed.");
+
+    //With one entry left in the root directory, there is no room for the sidecar, which takes
+    //two. The data is synced, but the file stays open until the sidecar has been written.
+    let mut file = locked_fs.open_raw("data", FileOptions::CreateAlways | FileOptions::Write).expect("Opening failed.");
+    for index in 0..14 {
+        let filler = locked_fs.open(&format!("f{index}"), FileOptions::CreateAlways | FileOptions::Write).expect("Opening failed.");
+        filler.close().expect("Closing failed.");
+    }
+    locked_fs.write(&mut file, b"data").expect("Writing failed.");
+    assert_eq!(locked_fs.sync(&mut file), Err(Error::ChecksumMissing));
+    assert_eq!(locked_fs.stat("data").expect("Stat failed.").fsize, 4);
+    assert_eq!(locked_fs.verify("data"), Err(Error::ChecksumMissing));
+    let (error, file) = locked_fs.close(file).expect_err("Closing succeeded.");
+    assert_eq!(error, Error::ChecksumMissing);
+    for index in 0..14 {
+        locked_fs.unlink(&format!("f{index}")).expect("Deleting failed.");
+    }
+    locked_fs.close(file).expect("Closing failed.");
+    assert_eq!(locked_fs.verify("data"), Ok(true));
+}
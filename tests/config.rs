@@ -0,0 +1,72 @@
+mod simulated_driver;
+
+use fatfs_embedded::fatfs::{self, Error, FileOptions, FormatOptions, MkfsOptions, RawFileSystem};
+use fatfs_embedded::fatfs::config::{self, ConfigError};
+use embassy_futures::block_on;
+
+//Overwrites bytes of the file at the given offset, as a corruption of the card would.
+fn corrupt(fs: &RawFileSystem, path: &str, offset: u32, data: &[u8]) {
+    let mut file = fs.open(path, FileOptions::Write).expect("Opening failed.");
+    fs.seek(&mut file, offset).expect("Seeking failed.");
+    fs.write(&mut file, data).expect("Writing failed.");
+    file.close().expect("Closing failed.");
+}
+
+//Test function must be called "main" to satisfy ThreadModeRawMutex.
+#[test]
+fn main() {
+    let driver = simulated_driver::RamBlockStorage::new();
+    block_on(fatfs::diskio::install(driver));
+    let mut locked_fs = block_on(fatfs::FS.lock());
+    locked_fs.mkfs("", MkfsOptions::new().format(FormatOptions::FAT32)).expect("Formatting drive failed.");
+    locked_fs.mount().expect("Mounting drive failed.");
+
+    //Settings are read back as written, including empty settings.
+    assert_eq!(config::read(&locked_fs, "settings.bin"), Err(ConfigError::Other(Error::NoFile)));
+    config::write(&locked_fs, "settings.bin", b"brightness=7").expect("Writing settings failed.");
+    assert_eq!(config::read(&locked_fs, "settings.bin").expect("Reading settings failed."), b"brightness=7");
+    assert_eq!(locked_fs.stat("settings.bin").expect("Stat failed.").fsize, config::HEADER_LEN as u32 + 12);
+    config::write(&locked_fs, "empty.bin", b"").expect("Writing settings failed.");
+    assert_eq!(config::read(&locked_fs, "empty.bin").expect("Reading settings failed."), b"");
+
+    //A changed byte of data, a changed length and a file shorter than its header are all
+    //reported as corrupt.
+    corrupt(&locked_fs, "settings.bin", config::HEADER_LEN as u32 + 3, b"X");
+    assert_eq!(config::read(&locked_fs, "settings.bin"), Err(ConfigError::Corrupt));
+    config::write(&locked_fs, "settings.bin", b"brightness=7").expect("Writing settings failed.");
+    corrupt(&locked_fs, "settings.bin", 0, &[11]);
+    assert_eq!(config::read(&locked_fs, "settings.bin"), Err(ConfigError::Corrupt));
+    let mut file = locked_fs.open("settings.bin", FileOptions::CreateAlways | FileOptions::Write).expect("Opening failed.");
+    locked_fs.write(&mut file, b"abc").expect("Writing failed.");
+    file.close().expect("Closing failed.");
+    assert_eq!(config::read(&locked_fs, "settings.bin"), Err(ConfigError::Corrupt));
+    assert_eq!(Error::from(ConfigError::Corrupt), Error::IntError);
+
+    //Dual settings alternate between the two files, and the newest copy is read.
+    assert_eq!(config::read_dual(&locked_fs, "a.bin", "b.bin"), Err(ConfigError::Other(Error::NoFile)));
+    assert_eq!(config::write_dual(&locked_fs, "a.bin", "b.bin", b"one").expect("Writing settings failed."), 1);
+    assert!(locked_fs.exists("a.bin").expect("Checking failed."));
+    assert!(!locked_fs.exists("b.bin").expect("Checking failed."));
+    assert_eq!(config::read_dual(&locked_fs, "a.bin", "b.bin").expect("Reading settings failed."), (b"one".to_vec(), 1));
+    assert_eq!(config::write_dual(&locked_fs, "a.bin", "b.bin", b"two").expect("Writing settings failed."), 2);
+    assert_eq!(config::read_dual(&locked_fs, "a.bin", "b.bin").expect("Reading settings failed."), (b"two".to_vec(), 2));
+    assert_eq!(config::write_dual(&locked_fs, "a.bin", "b.bin", b"three").expect("Writing settings failed."), 3);
+    assert_eq!(config::read_dual(&locked_fs, "a.bin", "b.bin").expect("Reading settings failed."), (b"three".to_vec(), 3));
+    assert_eq!(config::read(&locked_fs, "b.bin").expect("Reading settings failed."), [&2u32.to_le_bytes()[..], b"two"].concat());
+
+    //A corrupt newest copy, as left by an interrupted write, falls back to the older one,
+    //which the next write then keeps.
+    corrupt(&locked_fs, "a.bin", config::HEADER_LEN as u32 + 4, b"X");
+    assert_eq!(config::read_dual(&locked_fs, "a.bin", "b.bin").expect("Reading settings failed."), (b"two".to_vec(), 2));
+    assert_eq!(config::write_dual(&locked_fs, "a.bin", "b.bin", b"four").expect("Writing settings failed."), 3);
+    assert_eq!(config::read_dual(&locked_fs, "a.bin", "b.bin").expect("Reading settings failed."), (b"four".to_vec(), 3));
+    assert_eq!(config::read(&locked_fs, "b.bin").expect("Reading settings failed."), [&2u32.to_le_bytes()[..], b"two"].concat());
+
+    //Only when both copies are corrupt is the error reported.
+    corrupt(&locked_fs, "b.bin", config::HEADER_LEN as u32 + 4, b"X");
+    assert_eq!(config::read_dual(&locked_fs, "a.bin", "b.bin").expect("Reading settings failed."), (b"four".to_vec(), 3));
+    corrupt(&locked_fs, "a.bin", config::HEADER_LEN as u32 + 4, b"X");
+    assert_eq!(config::read_dual(&locked_fs, "a.bin", "b.bin"), Err(ConfigError::Corrupt));
+    assert_eq!(config::write_dual(&locked_fs, "a.bin", "b.bin", b"fresh").expect("Writing settings failed."), 1);
+    assert_eq!(config::read_dual(&locked_fs, "a.bin", "b.bin").expect("Reading settings failed."), (b"fresh".to_vec(), 1));
+}
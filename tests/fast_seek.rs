@@ -0,0 +1,61 @@
+mod simulated_driver;
+
+use fatfs_embedded::fatfs::{self, FileOptions, FormatOptions, MkfsOptions, RawFileSystem};
+use embassy_futures::block_on;
+
+const CHUNK_LEN: usize = 4096;
+
+fn chunk(index: usize) -> Vec<u8> {
+    vec![index as u8 + 1; CHUNK_LEN]
+}
+
+fn read_chunk(fs: &RawFileSystem, file: &mut fatfs::File, index: usize) -> Vec<u8> {
+    fs.seek(file, (index * CHUNK_LEN) as u32).expect("Seeking failed.");
+    let mut data = vec![0; CHUNK_LEN];
+    assert_eq!(fs.read(file, &mut data).expect("Reading failed."), CHUNK_LEN as u32);
+    data
+}
+
+//Test function must be called "main" to satisfy ThreadModeRawMutex.
+#[test]
+fn main() {
+    let driver = simulated_driver::RamBlockStorage::new();
+    block_on(fatfs::diskio::install(driver));
+    let mut locked_fs = block_on(fatfs::FS.lock());
+    locked_fs.mkfs("", MkfsOptions::new().format(FormatOptions::FAT32)).expect("Formatting drive failed.");
+    locked_fs.mount().expect("Mounting drive failed.");
+
+    //Writing two files in turn leaves each of them in several fragments.
+    let mut data_file = locked_fs.open("data", FileOptions::CreateAlways | FileOptions::Write).expect("Opening failed.");
+    let mut other_file = locked_fs.open("other", FileOptions::CreateAlways | FileOptions::Write).expect("Opening failed.");
+    for index in 0..8 {
+        locked_fs.write(&mut data_file, &chunk(index)).expect("Writing failed.");
+        locked_fs.sync(&mut data_file).expect("Syncing failed.");
+        locked_fs.write(&mut other_file, &chunk(index)).expect("Writing failed.");
+        locked_fs.sync(&mut other_file).expect("Syncing failed.");
+    }
+    data_file.close().expect("Closing failed.");
+    other_file.close().expect("Closing failed.");
+
+    //The same file opened twice for reading keeps a table for each open.
+    let mut first = locked_fs.open("data", FileOptions::Read).expect("Opening failed.");
+    let mut second = locked_fs.open("data", FileOptions::Read).expect("Opening failed.");
+    locked_fs.enable_fast_seek(&mut first).expect("Enabling fast seek failed.");
+    locked_fs.enable_fast_seek(&mut second).expect("Enabling fast seek failed.");
+    assert_eq!(read_chunk(&locked_fs, &mut first, 5), chunk(5));
+    assert_eq!(read_chunk(&locked_fs, &mut second, 2), chunk(2));
+
+    //Closing one open leaves the table of the other in place, even when its memory is reused.
+    first.close().expect("Closing failed.");
+    let mut third = locked_fs.open("data", FileOptions::Read).expect("Opening failed.");
+    let reused: Vec<Vec<u32>> = (0..8).map(|_| vec![u32::MAX; 32]).collect();
+    for index in (0..8).rev() {
+        assert_eq!(read_chunk(&locked_fs, &mut second, index), chunk(index));
+    }
+    drop(reused);
+    locked_fs.disable_fast_seek(&mut second);
+    assert_eq!(read_chunk(&locked_fs, &mut second, 7), chunk(7));
+    assert_eq!(read_chunk(&locked_fs, &mut third, 3), chunk(3));
+    second.close().expect("Closing failed.");
+    third.close().expect("Closing failed.");
+}
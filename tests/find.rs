@@ -0,0 +1,58 @@
+mod simulated_driver;
+
+use fatfs_embedded::fatfs::{self, FileAttributes, FileOptions, FormatOptions, MkfsOptions};
+use embassy_futures::block_on;
+
+//Test function must be called "main" to satisfy ThreadModeRawMutex.
+#[test]
+fn main() {
+    let driver = simulated_driver::RamBlockStorage::new();
+    block_on(fatfs::diskio::install(driver));
+    let mut locked_fs = block_on(fatfs::FS.lock());
+    locked_fs.mkfs("", MkfsOptions::new().format(FormatOptions::FAT32)).expect("Formatting drive failed.");
+    locked_fs.mount().expect("Mounting drive failed.");
+    for i in 0..20 {
+        let extension = if i % 2 == 0 { "txt" } else { "bin" };
+        let file = locked_fs.open(&format!("file{}.{}", i, extension), FileOptions::CreateAlways | FileOptions::Write).expect("Creating failed.");
+        file.close().expect("Closing failed.");
+    }
+    locked_fs.mkdir("dir.txt").expect("Creating directory failed.");
+
+    //The pattern is a slice of a longer string, without a terminating NUL, and the string
+    //is dropped and its memory reused before the search continues.
+    let pattern = String::from("*.txtGARBAGE");
+    let mut found = locked_fs.find("", &pattern[..5]).expect("Finding failed.");
+    drop(pattern);
+    let _reused = String::from("?????????????????????");
+    let names: Vec<String> = found.by_ref().map(|info| {
+        let info = info.expect("Finding failed.");
+        let len = info.fname.iter().position(|c| *c == 0).unwrap_or(info.fname.len());
+        info.fname[..len].iter().map(|c| *c as u8 as char).collect()
+    }).collect();
+    drop(found);
+    assert_eq!(names.len(), 11);
+    assert!(names.iter().all(|name| name.ends_with(".txt")));
+
+    //The same holds for the searches made with `findfirst()` and `findfirst_filtered()`.
+    let pattern = String::from("*.binGARBAGE");
+    let (mut dir, mut info) = locked_fs.findfirst("", &pattern[..5]).expect("Finding failed.");
+    drop(pattern);
+    let _reused = String::from("?????????????????????");
+    let mut count = 0;
+    while info.fname[0] != 0 {
+        count += 1;
+        info = locked_fs.findnext(&mut dir).expect("Finding failed.");
+    }
+    locked_fs.closedir(dir).expect("Closing directory failed.");
+    assert_eq!(count, 10);
+    let pattern = String::from("*.txtGARBAGE");
+    let (mut dir, mut info) = locked_fs.findfirst_filtered("", &pattern[..5], FileAttributes::Directory, FileAttributes::Directory).expect("Finding failed.");
+    drop(pattern);
+    let mut count = 0;
+    while info.fname[0] != 0 {
+        count += 1;
+        info = locked_fs.findnext_filtered(&mut dir, FileAttributes::Directory, FileAttributes::Directory).expect("Finding failed.");
+    }
+    locked_fs.closedir(dir).expect("Closing directory failed.");
+    assert_eq!(count, 1);
+}
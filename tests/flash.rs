@@ -0,0 +1,95 @@
+#![cfg(feature="embedded-storage-async")]
+
+use embedded_storage::nor_flash::{ErrorType, NorFlashError, NorFlashErrorKind};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+use fatfs_embedded::fatfs::{self, FileOptions, FormatOptions, MkfsOptions};
+use fatfs_embedded::fatfs::diskio::{self, storage::FlashDriver};
+use embassy_futures::block_on;
+
+const CAPACITY: usize = 8 * 1024 * 1024;
+const ERASE_SIZE: usize = 4096;
+
+#[derive(Debug)]
+struct FlashError;
+
+impl NorFlashError for FlashError {
+    fn kind(&self) -> NorFlashErrorKind {
+        NorFlashErrorKind::Other
+    }
+}
+
+//NOR flash held in memory. Writing only clears bits, so data written over bytes that were not
+//erased is corrupted, as it would be on a real device.
+struct RamFlash {
+    memory: Vec<u8>
+}
+
+impl ErrorType for RamFlash {
+    type Error = FlashError;
+}
+
+impl ReadNorFlash for RamFlash {
+    const READ_SIZE: usize = 1;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), FlashError> {
+        let offset = offset as usize;
+        bytes.copy_from_slice(&self.memory[offset..offset + bytes.len()]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.memory.len()
+    }
+}
+
+impl NorFlash for RamFlash {
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = ERASE_SIZE;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), FlashError> {
+        assert!((from as usize).is_multiple_of(ERASE_SIZE) && (to as usize).is_multiple_of(ERASE_SIZE));
+        self.memory[from as usize..to as usize].fill(0xFF);
+        Ok(())
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), FlashError> {
+        let offset = offset as usize;
+        for (byte, data) in self.memory[offset..offset + bytes.len()].iter_mut().zip(bytes) {
+            *byte &= data;
+        }
+        Ok(())
+    }
+}
+
+//Test function must be called "main" to satisfy ThreadModeRawMutex.
+#[test]
+fn main() {
+    let flash = RamFlash { memory: vec![0; CAPACITY + 100] };
+    block_on(diskio::install_async(FlashDriver::new(flash)));
+    let mut locked_fs = block_on(fatfs::FS.lock());
+    //The data area is aligned to the erase blocks of 8 sectors, and the partial block at the
+    //end of the device is left unused.
+    let report = locked_fs.mkfs("", MkfsOptions::new().format(FormatOptions::FAT)).expect("Formatting drive failed.");
+    assert_eq!(report.alignment, 8);
+    assert!(report.sector_count <= (CAPACITY / 512) as u32);
+    locked_fs.mount().expect("Mounting drive failed.");
+
+    //Sectors written one at a time keep the rest of their erase block intact.
+    let data: Vec<u8> = (0..20_000u32).map(|i| (i % 251) as u8).collect();
+    let mut file = locked_fs.open("data", FileOptions::CreateAlways | FileOptions::Write).expect("Opening failed.");
+    for chunk in data.chunks(700) {
+        locked_fs.write(&mut file, chunk).expect("Writing failed.");
+    }
+    file.close().expect("Closing failed.");
+    let mut file = locked_fs.open("other", FileOptions::CreateAlways | FileOptions::Write).expect("Opening failed.");
+    locked_fs.write(&mut file, b"other").expect("Writing failed.");
+    file.close().expect("Closing failed.");
+
+    locked_fs.mount().expect("Mounting drive failed.");
+    let mut file = locked_fs.open("data", FileOptions::Read).expect("Opening failed.");
+    let mut read_back = vec![0; data.len()];
+    assert_eq!(locked_fs.read(&mut file, &mut read_back).expect("Reading failed."), data.len() as u32);
+    file.close().expect("Closing failed.");
+    assert_eq!(read_back, data);
+    assert_eq!(locked_fs.stat("other").expect("Stat failed.").fsize, 5);
+}
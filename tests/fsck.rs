@@ -0,0 +1,138 @@
+mod simulated_driver;
+
+use fatfs_embedded::fatfs::{self, Error, FileOptions, FormatOptions, FsckIssue, LostChainAction, MkfsOptions, RawFileSystem};
+use fatfs_embedded::fatfs::boot::{self, BootSector};
+use embassy_futures::block_on;
+
+const END_OF_CHAIN: u32 = 0x0FFF_FFFF;
+const LONG_NAME: &str = "/Long directory name";
+
+//Sets an entry of the first FAT of a FAT32 volume directly, as a crash or faulty media would.
+fn set_fat(fs: &RawFileSystem, boot: &BootSector, cluster: u32, value: u32) {
+    let offset = cluster * 4;
+    let sector = boot.hidden_sectors + boot.reserved_sectors as u32 + offset / 512;
+    let mut buffer = [0; 512];
+    fs.read_sectors(sector, &mut buffer).expect("Reading sector failed.");
+    let index = (offset % 512) as usize;
+    buffer[index..index + 4].copy_from_slice(&value.to_le_bytes());
+    fs.write_sectors(sector, &buffer).expect("Writing sector failed.");
+}
+
+//Returns the first sector of the root directory.
+fn root_sector(boot: &BootSector) -> u32 {
+    let data_start = boot.hidden_sectors + boot.reserved_sectors as u32 + boot.fat_count as u32 * boot.sectors_per_fat;
+    data_start + (boot.root_cluster.expect("The volume is not FAT32.") - 2) * boot.sectors_per_cluster as u32
+}
+
+//Counts the long name entries in the first sector of the root directory that are not deleted.
+fn long_name_entries(fs: &RawFileSystem, boot: &BootSector) -> usize {
+    let mut buffer = [0; 512];
+    fs.read_sectors(root_sector(boot), &mut buffer).expect("Reading sector failed.");
+    buffer.chunks(32).filter(|entry| entry[0] != 0 && entry[0] != 0xE5 && entry[11] == 0x0F).count()
+}
+
+fn first_cluster(fs: &RawFileSystem, path: &str) -> u32 {
+    fs.cluster_chain(path).expect("Getting the cluster chain failed.")[0].first_cluster
+}
+
+fn write_file(fs: &RawFileSystem, path: &str, data: &[u8]) {
+    let mut file = fs.open(path, FileOptions::CreateAlways | FileOptions::Write).expect("Opening failed.");
+    fs.write(&mut file, data).expect("Writing failed.");
+    file.close().expect("Closing failed.");
+}
+
+//Test function must be called "main" to satisfy ThreadModeRawMutex.
+#[test]
+fn main() {
+    let driver = simulated_driver::RamBlockStorage::new();
+    block_on(fatfs::diskio::install(driver));
+    let mut locked_fs = block_on(fatfs::FS.lock());
+    locked_fs.mkfs("", MkfsOptions::new().format(FormatOptions::FAT32)).expect("Formatting drive failed.");
+    locked_fs.mount().expect("Mounting drive failed.");
+    let boot = boot::read(&locked_fs).expect("Reading the boot sector failed.");
+    let cluster_size = boot.sectors_per_cluster as u32 * 512;
+    let free = locked_fs.getfree("").expect("Getting free clusters failed.");
+
+    locked_fs.mkdir("d").expect("Creating directory failed.");
+    write_file(&locked_fs, "d/a", &vec![2; 3 * cluster_size as usize]);
+    write_file(&locked_fs, "b", &vec![1; 3 * cluster_size as usize]);
+    locked_fs.mkdir(LONG_NAME).expect("Creating directory failed.");
+    let report = locked_fs.fsck(false, |path, issue| panic!("{path}: {issue:?}")).expect("Checking failed.");
+    assert!(report.is_clean());
+    assert_eq!((report.files, report.directories), (2, 2));
+    assert_eq!(long_name_entries(&locked_fs, &boot), 2);
+
+    //The chain of "b" leads into the chain of "d/a" after its first cluster, leaving the rest
+    //of its chain lost. The chain of the directory with a long name starts at a free cluster.
+    //A lost chain of two clusters is left at the end of the volume.
+    let a_first = first_cluster(&locked_fs, "d/a");
+    let b_first = first_cluster(&locked_fs, "b");
+    set_fat(&locked_fs, &boot, b_first, a_first);
+    set_fat(&locked_fs, &boot, first_cluster(&locked_fs, LONG_NAME), 0);
+    let last = boot.cluster_count + 1;
+    set_fat(&locked_fs, &boot, last - 1, last);
+    set_fat(&locked_fs, &boot, last, END_OF_CHAIN);
+    let mut issues = Vec::new();
+    let report = locked_fs.fsck(false, |path, issue| issues.push((String::from(path), *issue))).expect("Checking failed.");
+    assert!(!report.is_clean());
+    assert_eq!((report.cross_links, report.invalid_clusters, report.size_mismatches), (1, 1, 1));
+    assert_eq!((report.lost_chains, report.lost_clusters, report.repaired), (2, 4, 0));
+    assert!(issues.contains(&(String::from("/D/A"), FsckIssue::CrossLinked { cluster: a_first })));
+    assert!(issues.contains(&(String::from(""), FsckIssue::LostChain { first_cluster: last - 1, clusters: 2 })));
+
+    //Repairs need the volume to have no open files.
+    let file = locked_fs.open("b", FileOptions::Read).expect("Opening failed.");
+    assert_eq!(locked_fs.fsck(true, |_, _| ()).err(), Some(Error::Denied));
+    assert_eq!(locked_fs.recover_lost(LostChainAction::Free).err(), Some(Error::Denied));
+    file.close().expect("Closing failed.");
+
+    //"b" keeps the clusters read first, cut to fit its size, "d/a" is emptied, and the
+    //directory is deleted along with its long name. The cluster cut off from "b" is lost, like
+    //the lost chains found before.
+    let report = locked_fs.fsck(true, |_, _| ()).expect("Repairing failed.");
+    assert_eq!(report.repaired, 3);
+    let report = locked_fs.fsck(false, |_, _| ()).expect("Checking failed.");
+    assert_eq!((report.cross_links, report.invalid_clusters, report.size_mismatches), (0, 0, 0));
+    assert_eq!((report.files, report.directories), (2, 1));
+    assert_eq!((report.lost_chains, report.lost_clusters), (3, 5));
+    assert_eq!(locked_fs.stat("b").expect("Stat failed.").fsize, 3 * cluster_size);
+    assert_eq!(locked_fs.stat("d/a").expect("Stat failed.").fsize, 0);
+    assert_eq!(locked_fs.stat(LONG_NAME).err(), Some(Error::NoFile));
+    assert_eq!(long_name_entries(&locked_fs, &boot), 0);
+    let mut file = locked_fs.open("b", FileOptions::Read).expect("Opening failed.");
+    let mut data = vec![0; 3 * cluster_size as usize];
+    assert_eq!(locked_fs.read(&mut file, &mut data).expect("Reading failed."), 3 * cluster_size);
+    file.close().expect("Closing failed.");
+    assert_eq!(data, [vec![1; cluster_size as usize], vec![2; 2 * cluster_size as usize]].concat());
+
+    //Lost chains are saved as files holding their clusters, and a lost cycle is freed.
+    set_fat(&locked_fs, &boot, last - 3, last - 2);
+    set_fat(&locked_fs, &boot, last - 2, last - 3);
+    let report = locked_fs.recover_lost(LostChainAction::Save("found")).expect("Recovering failed.");
+    assert_eq!((report.lost_chains, report.lost_clusters, report.saved, report.freed), (3, 7, 3, 2));
+    assert!(locked_fs.fsck(false, |_, _| ()).expect("Checking failed.").is_clean());
+    assert_eq!(first_cluster(&locked_fs, "found/FILE0000.CHK"), a_first + 2);
+    assert_eq!(first_cluster(&locked_fs, "found/FILE0001.CHK"), b_first + 1);
+    assert_eq!(locked_fs.stat("found/FILE0001.CHK").expect("Stat failed.").fsize, 2 * cluster_size);
+    let mut file = locked_fs.open("found/FILE0001.CHK", FileOptions::Read).expect("Opening failed.");
+    let mut data = vec![0; 2 * cluster_size as usize];
+    assert_eq!(locked_fs.read(&mut file, &mut data).expect("Reading failed."), 2 * cluster_size);
+    file.close().expect("Closing failed.");
+    assert_eq!(data, vec![1; 2 * cluster_size as usize]);
+    assert_eq!(first_cluster(&locked_fs, "found/FILE0002.CHK"), last - 1);
+
+    //Freeing lost chains restores the free space they took up.
+    locked_fs.unlink("found/FILE0000.CHK").expect("Deleting failed.");
+    locked_fs.unlink("found/FILE0001.CHK").expect("Deleting failed.");
+    locked_fs.unlink("found/FILE0002.CHK").expect("Deleting failed.");
+    locked_fs.unlink("found").expect("Deleting failed.");
+    locked_fs.unlink("d/a").expect("Deleting failed.");
+    locked_fs.unlink("d").expect("Deleting failed.");
+    set_fat(&locked_fs, &boot, last - 1, last);
+    set_fat(&locked_fs, &boot, last, END_OF_CHAIN);
+    let report = locked_fs.recover_lost(LostChainAction::Free).expect("Recovering failed.");
+    assert_eq!((report.lost_chains, report.lost_clusters, report.saved, report.freed), (1, 2, 0, 2));
+    assert!(locked_fs.fsck(false, |_, _| ()).expect("Checking failed.").is_clean());
+    locked_fs.unlink("b").expect("Deleting failed.");
+    assert_eq!(locked_fs.getfree("").expect("Getting free clusters failed."), free);
+}
@@ -0,0 +1,47 @@
+#![cfg(feature="embedded-io")]
+
+mod simulated_driver;
+
+use fatfs_embedded::fatfs::{self, CloneMode, FileOptions, FormatOptions, ImageError, MkfsOptions};
+use embassy_futures::block_on;
+
+//Builds a sparse image holding a single run of sectors.
+fn sparse_image(first: u32, count: u32) -> Vec<u8> {
+    let mut image = Vec::from(*b"FSIM");
+    image.extend_from_slice(&512u32.to_le_bytes());
+    image.extend_from_slice(&first.to_le_bytes());
+    image.extend_from_slice(&count.to_le_bytes());
+    image.extend(vec![0xAA; 2 * 512]);
+    image.extend_from_slice(&[0; 8]);
+    image
+}
+
+//Test function must be called "main" to satisfy ThreadModeRawMutex.
+#[test]
+fn main() {
+    let driver = simulated_driver::RamBlockStorage::new();
+    block_on(fatfs::diskio::install(driver));
+    let mut locked_fs = block_on(fatfs::FS.lock());
+    locked_fs.mkfs("", MkfsOptions::new().format(FormatOptions::FAT32)).expect("Formatting drive failed.");
+    locked_fs.mount().expect("Mounting drive failed.");
+    let mut file = locked_fs.open("data", FileOptions::CreateAlways | FileOptions::Write).expect("Opening failed.");
+    locked_fs.write(&mut file, b"data").expect("Writing failed.");
+    file.close().expect("Closing failed.");
+
+    //A sparse image restores the volume it was taken of.
+    let mut image = vec![0; 4 * 1024 * 1024];
+    let mut writer = &mut image[..];
+    locked_fs.export_image(&mut writer, CloneMode::UsedSectors).expect("Exporting failed.");
+    let len = 4 * 1024 * 1024 - writer.len();
+    locked_fs.mkfs("", MkfsOptions::new().format(FormatOptions::FAT32)).expect("Formatting drive failed.");
+    locked_fs.import_image(&mut &image[..len], CloneMode::UsedSectors).expect("Importing failed.");
+    locked_fs.mount().expect("Mounting drive failed.");
+    assert_eq!(locked_fs.stat("data").expect("Stat failed.").fsize, 4);
+
+    //Runs that overflow or reach past the end of the device are rejected.
+    let sector_count = 64 * 1000 * 1024 / 512;
+    for (first, count) in [(u32::MAX, 2), (sector_count - 1, 2), (sector_count, 1)] {
+        let result = locked_fs.import_image(&mut &sparse_image(first, count)[..], CloneMode::UsedSectors);
+        assert_eq!(result.err(), Some(ImageError::InvalidImage));
+    }
+}
@@ -0,0 +1,90 @@
+#![cfg(feature = "kv")]
+
+mod simulated_driver;
+
+use fatfs_embedded::fatfs::{self, Error, FileOptions, FormatOptions, MkfsOptions, RawFileSystem};
+use fatfs_embedded::fatfs::kv::KvStore;
+use embassy_futures::block_on;
+
+const PATH: &str = "store/settings.kv";
+
+fn file_size(fs: &RawFileSystem) -> u32 {
+    fs.stat(PATH).expect("Stat failed.").fsize
+}
+
+//Test function must be called "main" to satisfy ThreadModeRawMutex.
+#[test]
+fn main() {
+    let driver = simulated_driver::RamBlockStorage::new();
+    block_on(fatfs::diskio::install(driver));
+    let mut locked_fs = block_on(fatfs::FS.lock());
+    locked_fs.mkfs("", MkfsOptions::new().format(FormatOptions::FAT32)).expect("Formatting drive failed.");
+    locked_fs.mount().expect("Mounting drive failed.");
+    locked_fs.mkdir("store").expect("Creating directory failed.");
+
+    //Values are replaced and removed, with the records made obsolete counted as garbage.
+    let mut store = KvStore::open(&locked_fs, PATH).expect("Opening the store failed.");
+    assert!(store.is_empty());
+    store.set(&locked_fs, "volume", &[3]).expect("Setting failed.");
+    store.set(&locked_fs, "name", b"logger").expect("Setting failed.");
+    store.set(&locked_fs, "volume", &[7]).expect("Setting failed.");
+    store.set(&locked_fs, "empty", b"").expect("Setting failed.");
+    assert_eq!(store.get(&locked_fs, "volume").expect("Getting failed."), Some(vec![7]));
+    assert!(store.remove(&locked_fs, "name").expect("Removing failed."));
+    assert!(!store.remove(&locked_fs, "name").expect("Removing failed."));
+    assert_eq!(store.get(&locked_fs, "name").expect("Getting failed."), None);
+    assert_eq!(store.set(&locked_fs, "", b"value"), Err(Error::InvalidParameter));
+    assert_eq!(store.set(&locked_fs, &"k".repeat(256), b"value"), Err(Error::InvalidParameter));
+    //Each record is a header of 8 bytes followed by the key and value.
+    assert_eq!(store.garbage(), (8 + 6 + 1) + (8 + 4 + 6) + (8 + 4));
+    assert_eq!(store.len(), 2);
+    let garbage = store.garbage();
+    store.close(&locked_fs).expect("Closing the store failed.");
+
+    //A partial record left by a power loss is removed when the store is opened again.
+    let size = file_size(&locked_fs);
+    let mut file = locked_fs.open(PATH, FileOptions::OpenAppend | FileOptions::Write).expect("Opening failed.");
+    locked_fs.write(&mut file, &[5, 0, 1, 0, 0xAA]).expect("Writing failed.");
+    file.close().expect("Closing failed.");
+    let mut store = KvStore::open(&locked_fs, PATH).expect("Opening the store failed.");
+    assert_eq!(file_size(&locked_fs), size);
+    assert_eq!(store.len(), 2);
+    assert_eq!(store.garbage(), garbage);
+    assert_eq!(store.get(&locked_fs, "volume").expect("Getting failed."), Some(vec![7]));
+    assert_eq!(store.get(&locked_fs, "empty").expect("Getting failed."), Some(vec![]));
+
+    //A record whose CRC does not match is discarded together with every record after it.
+    store.set(&locked_fs, "damaged", b"value").expect("Setting failed.");
+    store.set(&locked_fs, "after", b"value").expect("Setting failed.");
+    store.close(&locked_fs).expect("Closing the store failed.");
+    let mut file = locked_fs.open(PATH, FileOptions::Write).expect("Opening failed.");
+    locked_fs.seek(&mut file, size + 8 + 7 + 2).expect("Seeking failed.");
+    locked_fs.write(&mut file, b"V").expect("Writing failed.");
+    file.close().expect("Closing failed.");
+    let mut store = KvStore::open(&locked_fs, PATH).expect("Opening the store failed.");
+    assert_eq!(file_size(&locked_fs), size);
+    assert!(!store.contains_key("damaged"));
+    assert!(!store.contains_key("after"));
+    assert_eq!(store.len(), 2);
+
+    //Compacting keeps only the current records, through a temporary file that is renamed
+    //over the store.
+    store.set(&locked_fs, "large", &[9; 1000]).expect("Setting failed.");
+    store.compact(&locked_fs).expect("Compacting failed.");
+    assert_eq!(store.garbage(), 0);
+    assert_eq!(file_size(&locked_fs), (8 + 6 + 1) + (8 + 5) + (8 + 5 + 1000));
+    assert_eq!(locked_fs.read_dir("store").expect("Reading directory failed.").len(), 1);
+    let mut keys: Vec<&str> = store.keys().collect();
+    keys.sort();
+    assert_eq!(keys, ["empty", "large", "volume"]);
+    assert_eq!(store.get(&locked_fs, "volume").expect("Getting failed."), Some(vec![7]));
+    store.close(&locked_fs).expect("Closing the store failed.");
+
+    //The compacted store is read back after the volume is mounted again.
+    locked_fs.mount().expect("Mounting drive failed.");
+    let mut store = KvStore::open(&locked_fs, PATH).expect("Opening the store failed.");
+    assert_eq!(store.garbage(), 0);
+    assert_eq!(store.get(&locked_fs, "large").expect("Getting failed."), Some(vec![9; 1000]));
+    assert_eq!(store.get(&locked_fs, "empty").expect("Getting failed."), Some(vec![]));
+    store.close(&locked_fs).expect("Closing the store failed.");
+}
@@ -0,0 +1,60 @@
+mod simulated_driver;
+
+use fatfs_embedded::fatfs::{self, FileOptions, FormatOptions, MkfsOptions};
+use embassy_futures::{block_on, join::join, poll_once};
+use core::cell::RefCell;
+use core::pin::pin;
+use core::task::Poll;
+
+//Test function must be called "main" to satisfy ThreadModeRawMutex.
+#[test]
+fn main() {
+    const TEST_STRING: &[u8] = b"Hello world!";
+    let driver = simulated_driver::RamBlockStorage::new();
+    block_on(fatfs::diskio::install(driver));
+    {
+        let mut locked_fs = block_on(fatfs::FS.lock());
+        locked_fs.mkfs("", MkfsOptions::new().format(FormatOptions::FAT32)).expect("Formatting drive failed.");
+        locked_fs.mount().expect("Mounting drive failed.");
+        let mut test_file = locked_fs.open("test.txt", FileOptions::CreateAlways | FileOptions::Write).expect("Opening failed.");
+        locked_fs.write(&mut test_file, TEST_STRING).expect("Writing to the file failed.");
+        test_file.close().expect("Closing the file failed.");
+    }
+    //Two readers may hold the lock at the same time.
+    let reader = block_on(fatfs::FS.read());
+    let other_reader = block_on(fatfs::FS.read());
+    assert_eq!(reader.stat("test.txt").expect("Stat failed.").fsize, TEST_STRING.len() as u32);
+    let mut test_file = other_reader.open("test.txt").expect("Opening failed.");
+    let mut read_back: [u8; TEST_STRING.len()] = [0; TEST_STRING.len()];
+    other_reader.read(&mut test_file, &mut read_back).expect("Reading the file failed.");
+    test_file.close().expect("Closing the file failed.");
+    assert_eq!(TEST_STRING, read_back);
+    drop(reader);
+    //A writer waits until the remaining reader releases the lock.
+    let order = RefCell::new(Vec::new());
+    block_on(join(
+        async { let _writer = fatfs::FS.lock().await; order.borrow_mut().push("writer"); },
+        async { drop(other_reader); order.borrow_mut().push("reader"); }
+    ));
+    assert_eq!(*order.borrow(), ["reader", "writer"]);
+
+    //Readers give way to every waiting writer, not only the first.
+    let reader = block_on(fatfs::FS.read());
+    let mut first = pin!(fatfs::FS.lock());
+    let mut second = pin!(fatfs::FS.lock());
+    assert!(poll_once(first.as_mut()).is_pending());
+    assert!(poll_once(second.as_mut()).is_pending());
+    drop(reader);
+    let Poll::Ready(writer) = poll_once(first.as_mut()) else { panic!("Locking failed.") };
+    assert!(poll_once(fatfs::FS.read()).is_pending());
+    drop(writer);
+    let Poll::Ready(writer) = poll_once(second.as_mut()) else { panic!("Locking failed.") };
+    drop(writer);
+    assert!(poll_once(fatfs::FS.read()).is_ready());
+
+    //A writer that stops waiting no longer holds back readers.
+    let reader = block_on(fatfs::FS.read());
+    assert!(poll_once(fatfs::FS.lock()).is_pending());
+    assert!(poll_once(fatfs::FS.read()).is_ready());
+    drop(reader);
+}
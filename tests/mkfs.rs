@@ -0,0 +1,79 @@
+mod simulated_driver;
+
+use fatfs_embedded::fatfs::{self, Error, FormatOptions, MkfsOptions, MkfsOptionsError};
+use fatfs_embedded::fatfs::boot::{self, FatType};
+use embassy_futures::block_on;
+
+//Test function must be called "main" to satisfy ThreadModeRawMutex.
+#[test]
+fn main() {
+    //Invalid options are reported rather than replaced by FatFs defaults.
+    assert_eq!(MkfsOptions::new().validate(), Ok(()));
+    assert_eq!(MkfsOptions::new().fat_copies(0).validate(), Err(MkfsOptionsError::InvalidFatCopies));
+    assert_eq!(MkfsOptions::new().fat_copies(3).validate(), Err(MkfsOptionsError::InvalidFatCopies));
+    assert_eq!(MkfsOptions::new().alignment(3).validate(), Err(MkfsOptionsError::InvalidAlignment));
+    assert_eq!(MkfsOptions::new().alignment(0x10000).validate(), Err(MkfsOptionsError::InvalidAlignment));
+    assert_eq!(MkfsOptions::new().au_size(3000).validate(), Err(MkfsOptionsError::InvalidAuSize));
+    assert_eq!(MkfsOptions::new().au_size(256).validate(), Err(MkfsOptionsError::InvalidAuSize));
+    assert_eq!(MkfsOptions::new().au_size(0x2000000).validate(), Err(MkfsOptionsError::InvalidAuSize));
+    assert_eq!(MkfsOptions::new().root_entries(10).validate(), Err(MkfsOptionsError::InvalidRootEntries));
+    assert_eq!(MkfsOptions::new().root_entries(0).validate(), Err(MkfsOptionsError::InvalidRootEntries));
+
+    let driver = simulated_driver::RamBlockStorage::new();
+    block_on(fatfs::diskio::install(driver));
+    let mut locked_fs = block_on(fatfs::FS.lock());
+    assert_eq!(locked_fs.mkfs("", MkfsOptions::new().au_size(100)), Err(Error::InvalidParameter));
+
+    //The report describes the volume as written to the boot sector.
+    let report = locked_fs.mkfs("", MkfsOptions::new().format(FormatOptions::FAT).fat_copies(2).au_size(2048).alignment(64).root_entries(1024))
+        .expect("Formatting drive failed.");
+    assert_eq!((report.fat_type, report.cluster_size, report.alignment), (FatType::Fat16, 2048, 64));
+    locked_fs.mount().expect("Mounting drive failed.");
+    let boot = boot::read(&locked_fs).expect("Reading the boot sector failed.");
+    assert_eq!((boot.fat_count, boot.sectors_per_cluster, boot.root_entries), (2, 4, 1024));
+    assert_eq!((report.sector_count, report.cluster_count), (boot.total_sectors, boot.cluster_count));
+    assert_eq!(&boot.oem_name, b"MSDOS5.0");
+
+    //An alignment of 0 is taken from the driver.
+    let report = locked_fs.mkfs("", MkfsOptions::new().format(FormatOptions::FAT32)).expect("Formatting drive failed.");
+    assert_eq!((report.fat_type, report.alignment), (FatType::Fat32, 512));
+    locked_fs.mount().expect("Mounting drive failed.");
+    let boot = boot::read(&locked_fs).expect("Reading the boot sector failed.");
+    assert_eq!(boot.fat_count, 1);
+    let data_start = boot.reserved_sectors as u32 + boot.sectors_per_fat;
+    assert_eq!((boot.hidden_sectors + data_start) % 512, 0);
+
+    //The OEM name and serial number are written in place of those chosen by FatFs, and the
+    //serial number is read back by FatFs.
+    locked_fs.mkfs("", MkfsOptions::new().format(FormatOptions::FAT32).oem_name(*b"UNIT0042").serial(42)).expect("Formatting drive failed.");
+    locked_fs.mount().expect("Mounting drive failed.");
+    let boot = boot::read(&locked_fs).expect("Reading the boot sector failed.");
+    assert_eq!((&boot.oem_name, boot.serial), (b"UNIT0042", Some(42)));
+    let mut label = String::with_capacity(34);
+    assert_eq!(locked_fs.getlabel("", &mut label).expect("Getting label failed."), 42);
+
+    //Cards are formatted as the SD Association specifies for their capacity class, with the
+    //largest class used for cards over 32 GB.
+    const MB: u32 = 2048;
+    let classes = [
+        (8 * MB, FormatOptions::FAT, 8192, 16),
+        (8 * MB + 1, FormatOptions::FAT, 16384, 32),
+        (64 * MB, FormatOptions::FAT, 16384, 32),
+        (256 * MB, FormatOptions::FAT, 16384, 64),
+        (1024 * MB, FormatOptions::FAT, 16384, 128),
+        (2048 * MB, FormatOptions::FAT, 32768, 128),
+        (2048 * MB + 1, FormatOptions::FAT32, 32768, 8192),
+        (u32::MAX, FormatOptions::FAT32, 32768, 8192)
+    ];
+    for (sector_count, format, au_size, alignment) in classes {
+        let expected = *MkfsOptions::new().format(format).fat_copies(2).au_size(au_size).alignment(alignment);
+        assert_eq!(MkfsOptions::sd_standard(sector_count), expected);
+        assert_eq!(MkfsOptions::sd_standard(sector_count).validate(), Ok(()));
+    }
+    //The simulated drive of 64 MB, just under 64 MiB, is in the class of cards up to 64 MiB.
+    let report = locked_fs.mkfs_sd_standard("").expect("Formatting drive failed.");
+    assert_eq!((report.fat_type, report.cluster_size, report.alignment), (FatType::Fat12, 16384, 32));
+    locked_fs.mount().expect("Mounting drive failed.");
+    let boot = boot::read(&locked_fs).expect("Reading the boot sector failed.");
+    assert_eq!((boot.fat_count, boot.sectors_per_cluster, boot.cluster_count), (2, 32, report.cluster_count));
+}
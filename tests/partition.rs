@@ -0,0 +1,73 @@
+mod simulated_driver;
+
+use fatfs_embedded::fatfs::{self, Error, FileOptions, FormatOptions, MkfsOptions, PartitionSpec, RawFileSystem};
+use embassy_futures::block_on;
+
+fn write_file(fs: &RawFileSystem, path: &str, data: &[u8]) {
+    let mut file = fs.open(path, FileOptions::CreateAlways | FileOptions::Write).expect("Opening failed.");
+    fs.write(&mut file, data).expect("Writing failed.");
+    file.close().expect("Closing failed.");
+}
+
+//Test function must be called "main" to satisfy ThreadModeRawMutex.
+#[test]
+fn main() {
+    let driver = simulated_driver::RamBlockStorage::new();
+    block_on(fatfs::diskio::install(driver));
+    let mut locked_fs = block_on(fatfs::FS.lock());
+    //FatFs formats the whole drive as a single partition.
+    locked_fs.mkfs("", MkfsOptions::new().format(FormatOptions::FAT32)).expect("Formatting drive failed.");
+    locked_fs.mount().expect("Mounting drive failed.");
+    locked_fs.setlabel("DATA").expect("Setting label failed.");
+    write_file(&locked_fs, "x", b"data");
+
+    //Volumes are found by label without regard to case, and by serial number.
+    assert_eq!(locked_fs.mount_by_label("data"), Ok(1));
+    let mut label = String::with_capacity(34);
+    let serial = locked_fs.getlabel("", &mut label).expect("Getting label failed.");
+    assert_eq!(locked_fs.mount_by_serial(serial), Ok(1));
+    assert_eq!(locked_fs.stat("x").expect("Stat failed.").fsize, 4);
+
+    //Without a match, the volume mounted before is mounted again, while files opened on it
+    //are invalidated.
+    let file = locked_fs.open_raw("x", FileOptions::Read).expect("Opening failed.");
+    assert_eq!(locked_fs.mount_by_label("other"), Err(Error::NoFileSystem));
+    assert_eq!(locked_fs.mount_by_serial(serial ^ 1), Err(Error::NoFileSystem));
+    assert_eq!(locked_fs.stat("x").expect("Stat failed.").fsize, 4);
+    assert!(locked_fs.close(file).is_err());
+    //A volume that was not mounted is left unmounted.
+    locked_fs.unmount("").expect("Unmounting failed.");
+    assert_eq!(locked_fs.mount_by_label("other"), Err(Error::NoFileSystem));
+    assert_eq!(locked_fs.stat("x").err(), Some(Error::NotEnabled));
+
+    assert_eq!(locked_fs.partition(0, &[]), Err(Error::InvalidParameter));
+    assert_eq!(locked_fs.partition(0, &[PartitionSpec::Percent(50); 5]), Err(Error::InvalidParameter));
+    assert_eq!(locked_fs.partition(0, &[PartitionSpec::Sectors(100)]), Err(Error::InvalidParameter));
+    assert_eq!(locked_fs.partition(0, &[PartitionSpec::Percent(0)]), Err(Error::InvalidParameter));
+    assert_eq!(locked_fs.partition(0, &[PartitionSpec::Percent(101)]), Err(Error::InvalidParameter));
+    assert_eq!(locked_fs.select_partition(5), Err(Error::InvalidParameter));
+
+    //Each partition is formatted in turn, leaving the others intact.
+    locked_fs.partition(0, &[PartitionSpec::Sectors(40_000), PartitionSpec::Percent(100)]).expect("Partitioning failed.");
+    for (partition, label) in [(1, "ALPHA"), (2, "BETA")] {
+        locked_fs.select_partition(partition).expect("Selecting partition failed.");
+        let report = locked_fs.mkfs("", MkfsOptions::new().format(FormatOptions::FAT)).expect("Formatting partition failed.");
+        if partition == 1 {
+            assert!(report.sector_count <= 40_000);
+        }
+        locked_fs.mount().expect("Mounting partition failed.");
+        locked_fs.setlabel(label).expect("Setting label failed.");
+        write_file(&locked_fs, "x", label.as_bytes());
+    }
+    assert_eq!(locked_fs.mount_by_label("alpha"), Ok(1));
+    assert_eq!(locked_fs.stat("x").expect("Stat failed.").fsize, 5);
+    assert_eq!(locked_fs.mount_by_label("beta"), Ok(2));
+    assert_eq!(locked_fs.stat("x").expect("Stat failed.").fsize, 4);
+    //The partition found last is mounted again by `mount()`, even after a failed search.
+    assert_eq!(locked_fs.mount_by_label("data"), Err(Error::NoFileSystem));
+    locked_fs.mount().expect("Mounting partition failed.");
+    assert_eq!(locked_fs.stat("x").expect("Stat failed.").fsize, 4);
+    locked_fs.select_partition(1).expect("Selecting partition failed.");
+    locked_fs.mount().expect("Mounting partition failed.");
+    assert_eq!(locked_fs.stat("x").expect("Stat failed.").fsize, 5);
+}
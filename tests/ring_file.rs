@@ -0,0 +1,86 @@
+mod simulated_driver;
+
+use fatfs_embedded::fatfs::{self, FileOptions, FormatOptions, MkfsOptions, RawFileSystem};
+use fatfs_embedded::fatfs::ring::RingFile;
+use embassy_futures::block_on;
+
+const CAPACITY: u32 = 100;
+
+fn contents(fs: &RawFileSystem, ring: &mut RingFile) -> Vec<u8> {
+    let mut data = vec![0; ring.len() as usize];
+    let mut offset = 0;
+    while offset < ring.len() {
+        offset += ring.read(fs, offset, &mut data[offset as usize..]).expect("Reading the ring failed.");
+    }
+    data
+}
+
+//Test function must be called "main" to satisfy ThreadModeRawMutex.
+#[test]
+fn main() {
+    let driver = simulated_driver::RamBlockStorage::new();
+    block_on(fatfs::diskio::install(driver));
+    let mut locked_fs = block_on(fatfs::FS.lock());
+    locked_fs.mkfs("", MkfsOptions::new().format(FormatOptions::FAT32)).expect("Formatting drive failed.");
+    locked_fs.mount().expect("Mounting drive failed.");
+
+    //Writes of varying length wrap around the ring, which keeps the newest bytes.
+    let mut ring = RingFile::open(&locked_fs, "blackbox.bin", CAPACITY).expect("Opening the ring failed.");
+    assert!(ring.is_empty());
+    let mut expected: Vec<u8> = Vec::new();
+    for i in 0..50u32 {
+        let chunk: Vec<u8> = (0..(i * 7 % 45) as u8).map(|byte| byte.wrapping_add(i as u8)).collect();
+        ring.write(&locked_fs, &chunk).expect("Writing the ring failed.");
+        expected.extend_from_slice(&chunk);
+        expected.drain(..expected.len().saturating_sub(CAPACITY as usize));
+        assert_eq!(contents(&locked_fs, &mut ring), expected);
+    }
+    //Only the newest bytes of a write larger than the ring are kept.
+    ring.write(&locked_fs, &(0..250u8).collect::<Vec<u8>>()).expect("Writing the ring failed.");
+    expected = (150..250u8).collect();
+    assert_eq!(contents(&locked_fs, &mut ring), expected);
+    ring.sync(&locked_fs).expect("Syncing the ring failed.");
+
+    //The ring survives a power cycle: the file is never closed and the volume is mounted again.
+    locked_fs.mount().expect("Mounting drive failed.");
+    let mut ring = RingFile::open(&locked_fs, "blackbox.bin", CAPACITY).expect("Opening the ring failed.");
+    assert_eq!(contents(&locked_fs, &mut ring), expected);
+    let before_last_write = expected.clone();
+    ring.write(&locked_fs, b"last").expect("Writing the ring failed.");
+    expected.drain(..4);
+    expected.extend_from_slice(b"last");
+    assert_eq!(contents(&locked_fs, &mut ring), expected);
+    ring.close(&locked_fs).expect("Closing the ring failed.");
+
+    //A damaged header copy falls back to the other copy, which describes the ring as it was
+    //before the last write. The data of the last write stays where it was written, over the
+    //oldest bytes of the full ring.
+    let mut newest = None;
+    for offset in [0, 512] {
+        let mut file = locked_fs.open("blackbox.bin", FileOptions::Read | FileOptions::Write).expect("Opening failed.");
+        let mut header = [0; 24];
+        locked_fs.seek(&mut file, offset).expect("Seeking failed.");
+        locked_fs.read(&mut file, &mut header).expect("Reading failed.");
+        let sequence = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        if newest.is_none_or(|(_, newest)| sequence > newest) {
+            newest = Some((offset, sequence));
+        }
+        file.close().expect("Closing failed.");
+    }
+    let (offset, _) = newest.expect("No header was found.");
+    let mut file = locked_fs.open("blackbox.bin", FileOptions::Write).expect("Opening failed.");
+    locked_fs.seek(&mut file, offset + 20).expect("Seeking failed.");
+    locked_fs.write(&mut file, &[0; 4]).expect("Writing failed.");
+    file.close().expect("Closing failed.");
+    let mut ring = RingFile::open(&locked_fs, "blackbox.bin", CAPACITY).expect("Opening the ring failed.");
+    assert_eq!(contents(&locked_fs, &mut ring), [b"last", &before_last_write[4..]].concat());
+    ring.close(&locked_fs).expect("Closing the ring failed.");
+
+    //Opening with another capacity starts an empty ring of that size.
+    let ring = RingFile::open(&locked_fs, "blackbox.bin", 2 * CAPACITY).expect("Opening the ring failed.");
+    assert!(ring.is_empty());
+    assert_eq!(ring.capacity(), 2 * CAPACITY);
+    ring.close(&locked_fs).expect("Closing the ring failed.");
+    assert_eq!(locked_fs.stat("blackbox.bin").expect("Stat failed.").fsize, 1024 + 2 * CAPACITY);
+    assert_eq!(RingFile::open(&locked_fs, "empty.bin", 0).err(), Some(fatfs::Error::InvalidParameter));
+}
@@ -0,0 +1,75 @@
+mod simulated_driver;
+
+use fatfs_embedded::fatfs::{self, Error, FileOptions, FormatOptions, MkfsOptions, RawFileSystem};
+use fatfs_embedded::fatfs::ring::RingLog;
+use embassy_futures::block_on;
+
+const CAPACITY: u32 = 4;
+//Each slot holds an 8 byte header of sequence number and CRC before the record.
+const SLOT_LEN: u32 = 8 + 5;
+
+fn records(fs: &RawFileSystem, log: &mut RingLog<5>) -> Vec<[u8; 5]> {
+    (0..log.len()).map(|index| {
+        let mut record = [0; 5];
+        log.read(fs, index, &mut record).expect("Reading the log failed.");
+        record
+    }).collect()
+}
+
+//Test function must be called "main" to satisfy ThreadModeRawMutex.
+#[test]
+fn main() {
+    let driver = simulated_driver::RamBlockStorage::new();
+    block_on(fatfs::diskio::install(driver));
+    let mut locked_fs = block_on(fatfs::FS.lock());
+    locked_fs.mkfs("", MkfsOptions::new().format(FormatOptions::FAT32)).expect("Formatting drive failed.");
+    locked_fs.mount().expect("Mounting drive failed.");
+
+    let mut log: RingLog<5> = RingLog::open(&locked_fs, "events.log", CAPACITY).expect("Opening the log failed.");
+    assert!(log.is_empty());
+    assert_eq!(log.newest_sequence(), None);
+    for i in 0..3u8 {
+        assert_eq!(log.append(&locked_fs, &[i; 5]).expect("Appending failed."), i as u32);
+    }
+    log.close(&locked_fs).expect("Closing the log failed.");
+
+    //The records are found again when the log is opened.
+    let mut log: RingLog<5> = RingLog::open(&locked_fs, "events.log", CAPACITY).expect("Opening the log failed.");
+    assert_eq!(log.len(), 3);
+    assert_eq!(log.newest_sequence(), Some(2));
+    assert_eq!(records(&locked_fs, &mut log), [[0; 5], [1; 5], [2; 5]]);
+
+    //Appending past the capacity overwrites the oldest records.
+    for i in 3..10u8 {
+        assert_eq!(log.append(&locked_fs, &[i; 5]).expect("Appending failed."), i as u32);
+    }
+    assert_eq!(log.len(), CAPACITY);
+    assert_eq!(records(&locked_fs, &mut log), [[6; 5], [7; 5], [8; 5], [9; 5]]);
+    assert_eq!(log.read(&locked_fs, CAPACITY, &mut [0; 5]).err(), Some(Error::InvalidParameter));
+    log.sync(&locked_fs).expect("Syncing the log failed.");
+
+    //The log survives a power cycle: the file is never closed and the volume is mounted again.
+    locked_fs.mount().expect("Mounting drive failed.");
+    let mut log: RingLog<5> = RingLog::open(&locked_fs, "events.log", CAPACITY).expect("Opening the log failed.");
+    assert_eq!(log.newest_sequence(), Some(9));
+    assert_eq!(records(&locked_fs, &mut log), [[6; 5], [7; 5], [8; 5], [9; 5]]);
+    log.close(&locked_fs).expect("Closing the log failed.");
+
+    //A damaged record ends the run of records before the newest. Record 7 is in slot 3.
+    let mut file = locked_fs.open("events.log", FileOptions::Write).expect("Opening failed.");
+    locked_fs.seek(&mut file, 3 * SLOT_LEN + 8).expect("Seeking failed.");
+    locked_fs.write(&mut file, &[0xFF]).expect("Writing failed.");
+    file.close().expect("Closing failed.");
+    let mut log: RingLog<5> = RingLog::open(&locked_fs, "events.log", CAPACITY).expect("Opening the log failed.");
+    assert_eq!(records(&locked_fs, &mut log), [[8; 5], [9; 5]]);
+    assert_eq!(log.append(&locked_fs, &[10; 5]).expect("Appending failed."), 10);
+    log.close(&locked_fs).expect("Closing the log failed.");
+
+    //Opening with another capacity starts an empty log of that size.
+    let log: RingLog<5> = RingLog::open(&locked_fs, "events.log", 2 * CAPACITY).expect("Opening the log failed.");
+    assert!(log.is_empty());
+    assert_eq!(log.capacity(), 2 * CAPACITY);
+    log.close(&locked_fs).expect("Closing the log failed.");
+    assert_eq!(locked_fs.stat("events.log").expect("Stat failed.").fsize, 2 * CAPACITY * SLOT_LEN);
+    assert_eq!(RingLog::<5>::open(&locked_fs, "empty.log", 0).err(), Some(Error::InvalidParameter));
+}
@@ -1,6 +1,6 @@
 mod simulated_driver;
 
-use fatfs_embedded::fatfs::{self, File, FileOptions, FormatOptions};
+use fatfs_embedded::fatfs::{self, FileOptions, FormatOptions, MkfsOptions};
 use embassy_futures::block_on;
 
 //Test function must be called "main" to satisfy ThreadModeRawMutex.
@@ -10,14 +10,14 @@ fn main() {
     //Create an instance of the simulated block storage device.
     let driver = simulated_driver::RamBlockStorage::new();
     //Install the driver.
-    block_on(fatfs::diskio::install(driver));
+    let installed = block_on(fatfs::diskio::install(driver));
     let mut locked_fs = block_on(fatfs::FS.lock());
     //Format the drive.
-    locked_fs.mkfs("", FormatOptions::FAT32, 0, 0, 0, 0).expect("Formatting drive failed.");
+    locked_fs.mkfs("", MkfsOptions::new().format(FormatOptions::FAT32)).expect("Formatting drive failed.");
     //Mount the drive.
     locked_fs.mount().expect("Mounting drive failed.");
     //Create a new test file.
-    let mut test_file: File = locked_fs.open("test.txt", FileOptions::CreateAlways | FileOptions::Read | FileOptions::Write).expect("Opening failed.");
+    let mut test_file = locked_fs.open("test.txt", FileOptions::CreateAlways | FileOptions::Read | FileOptions::Write).expect("Opening failed.");
     //Write a test string to the file.
     locked_fs.write(&mut test_file, TEST_STRING).expect("Writing to the file failed.");
     //Seek back to the beginning of the file.
@@ -26,4 +26,10 @@ fn main() {
     let mut read_back: [u8; TEST_STRING.len()] = [0; TEST_STRING.len()];
     locked_fs.read(&mut test_file, &mut read_back).expect("Reading the file failed.");
     assert_eq!(TEST_STRING, read_back);
+    //Close the file and unmount the drive.
+    test_file.close().expect("Closing the file failed.");
+    locked_fs.unmount("").expect("Unmounting drive failed.");
+    drop(locked_fs);
+    //Reclaim the driver.
+    assert!(block_on(installed.take()).is_some());
 }
\ No newline at end of file